@@ -0,0 +1,55 @@
+#!/usr/bin/env bash
+#
+# Load the server over the network by `oha`, or `wrk` when not installed,
+# serving a static file of `SIZE` bytes from a temporary directory.
+#
+# Usage: benches/load.sh [DURATION] [CONNECTIONS] [SIZE]
+#
+# e.g. `benches/load.sh 30s 256 1048576`.
+
+set -euo pipefail
+
+DURATION="${1:-10s}"
+CONNECTIONS="${2:-64}"
+SIZE="${3:-1048576}"
+LISTEN="127.0.0.1:17080"
+
+cd "$(dirname "$0")/.."
+
+cargo build --release --features bench
+
+WORKDIR="$(mktemp -d)"
+trap 'kill "${SERVER_PID:-}" 2>/dev/null || true; rm -rf "$WORKDIR"' EXIT
+
+head -c "$SIZE" /dev/urandom > "$WORKDIR/segment.m4s"
+
+cat > "$WORKDIR/config.toml" <<EOF
+listen = "$LISTEN"
+
+[log]
+level = "warn"
+
+[[static]]
+prefix = "/static"
+root = "$WORKDIR"
+EOF
+
+./target/release/mikufans-bvc-server --config "$WORKDIR/config.toml" &
+SERVER_PID=$!
+
+# Till listening
+for _ in $(seq 50); do
+    curl -fso /dev/null "http://$LISTEN/static/segment.m4s" && break
+    sleep 0.1
+done
+
+URL="http://$LISTEN/static/segment.m4s"
+
+if command -v oha > /dev/null; then
+    oha --no-tui -z "$DURATION" -c "$CONNECTIONS" "$URL"
+elif command -v wrk > /dev/null; then
+    wrk -d "$DURATION" -c "$CONNECTIONS" -t "$(nproc)" "$URL"
+else
+    echo "Neither oha nor wrk installed" >&2
+    exit 1
+fi
@@ -0,0 +1,154 @@
+//! Benchmarks of the HTTP engine: requests parsed, response heads written and
+//! files streamed, over streams in memory, see `mikufans_bvc_server::bench`.
+//!
+//! Run with `cargo bench --features bench`, and see `benches/load.sh` of the
+//! server under load over the network.
+
+use std::io::Write;
+
+use bytes::Bytes;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_main};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use mikufans_bvc_server::{AnyStream, bench};
+use tokio::{
+    io::{BufReader, DuplexStream},
+    runtime::Runtime,
+    sync::Mutex,
+};
+
+/// Bytes buffered each way of the streams in memory.
+const BUFFER_SIZE: usize = 256 * 1024;
+
+/// A request of a media segment, alike those of players.
+const SEGMENT_REQUEST: &[u8] = b"GET /upgcxcode/12/34/567890/567890-1-30280.m4s?e=ig8euxZM2rNcNbdlhoNvNC8BqJIzNbfqXBvEuENvNC8aNEVEtEvE9IMvXBvE2ENvNCImNEVEIj0Y2J_aug859r1qXg8gNEVE5XREto8z5JZC2X2gkX5L5F1eTX1jkXlsTXHeux_f2o859IB_&uipk=5&nbs=1&deadline=1700000000&gen=playurlv2&os=bcache&oi=0&trid=0000abcdef&mid=0&platform=pc&upsig=0123456789abcdef0123456789abcdef&uparams=e,uipk,nbs,deadline,gen,os,oi,trid,mid,platform&bvc=vod&nettype=0&orderid=0,3&buvid=&build=0&f=u_0_0&agrr=1&bw=100000&logo=80000000 HTTP/1.1\r\n\
+Host: upos-sz-mirrorcos.bilivideo.com\r\n\
+User-Agent: Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36\r\n\
+Accept: */*\r\n\
+Accept-Language: zh-CN,zh;q=0.9,en;q=0.8\r\n\
+Accept-Encoding: identity\r\n\
+Origin: https://www.bilibili.com\r\n\
+Referer: https://www.bilibili.com/video/BV1xx411c7mD\r\n\
+Range: bytes=0-1048575\r\n\
+Connection: keep-alive\r\n\
+Sec-Fetch-Dest: empty\r\n\
+Sec-Fetch-Mode: cors\r\n\
+Sec-Fetch-Site: cross-site\r\n\
+\r\n";
+
+/// The least request.
+const MINIMAL_REQUEST: &[u8] = b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+/// A runtime of the current thread, the benchmarks run on.
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Runtime")
+}
+
+/// A stream in memory of `runtime`, of which the client end is drained.
+///
+/// Shared by the iterations behind a [`Mutex`], never contended.
+fn drained(runtime: &Runtime) -> AnyStream {
+    let (mut client, server) = tokio::io::duplex(BUFFER_SIZE);
+
+    runtime.spawn(async move {
+        let _ = tokio::io::copy(&mut client, &mut tokio::io::sink()).await;
+    });
+
+    AnyStream::Memory(BufReader::<DuplexStream>::new(server))
+}
+
+/// Request heads parsed, see `Request::handle`.
+fn parse_request(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("parse_request");
+
+    for (name, head) in [("minimal", MINIMAL_REQUEST), ("segment", SEGMENT_REQUEST)] {
+        group.throughput(Throughput::Bytes(head.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), head, |b, head| {
+            b.to_async(&runtime).iter(|| async {
+                let mut reader = head;
+
+                bench::parse_request(&mut reader)
+                    .await
+                    .expect("Parsed")
+                    .expect("Request")
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// Response heads of as many headers written, of an empty body.
+fn write_head(c: &mut Criterion) {
+    let runtime = runtime();
+    let stream = Mutex::new(drained(&runtime));
+    let mut group = c.benchmark_group("write_head");
+
+    for count in [0, 8, 32] {
+        let headers: HeaderMap = (0..count)
+            .map(|i| {
+                (
+                    HeaderName::try_from(format!("x-header-{i}")).expect("Header name"),
+                    HeaderValue::from_static("a value of a header, of some length"),
+                )
+            })
+            .collect();
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(count),
+            &headers,
+            |b, headers| {
+                b.to_async(&runtime).iter(|| async {
+                    bench::write_response(headers.clone(), Bytes::new(), &mut *stream.lock().await)
+                        .await
+                        .expect("Written");
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+/// Files of as many bytes streamed piece by piece, see
+/// `TransferConfig::chunk_size`.
+fn stream_file(c: &mut Criterion) {
+    let runtime = runtime();
+    let stream = Mutex::new(drained(&runtime));
+    let mut group = c.benchmark_group("stream_file");
+
+    let path = std::env::temp_dir().join(format!("mikufans-bench-{}", std::process::id()));
+
+    for len in [64 * 1024, 1024 * 1024, 16 * 1024 * 1024] {
+        std::fs::File::create(&path)
+            .and_then(|mut file| file.write_all(&vec![0x5a; len]))
+            .expect("File written");
+
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(len), &len, |b, &len| {
+            b.to_async(&runtime).iter(|| async {
+                let file = tokio::fs::File::open(&path).await.expect("File opened");
+
+                bench::write_file(file, 0, len as u64, &mut *stream.lock().await)
+                    .await
+                    .expect("Written");
+            });
+        });
+    }
+
+    group.finish();
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[allow(missing_docs, reason = "Of the functions generated")]
+mod group {
+    use super::{parse_request, stream_file, write_head};
+
+    criterion::criterion_group!(benches, parse_request, write_head, stream_file);
+}
+
+criterion_main!(group::benches);
@@ -0,0 +1,25 @@
+//! Arbitrary `Range` header values resolved against arbitrary content
+//! lengths, see `mikufans_bvc_server::fuzz::resolve_range`.
+//!
+//! Run with `cargo +nightly fuzz run range`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mikufans_bvc_server::{Error, fuzz};
+
+fuzz_target!(|input: (u64, &str)| {
+    let (length, range) = input;
+
+    match fuzz::resolve_range(range, length) {
+        Ok(Some((start, end))) => {
+            // Satisfiable, within the content
+            assert!(start <= end, "{range:?} of {length}: {start} > {end}");
+            assert!(end < length, "{range:?} of {length}: {end} past the end");
+        }
+        Ok(None) => {}
+        // Answered of the length
+        Err(Error::RangeNotSatisfiable(of)) => assert_eq!(of, length, "{range:?}"),
+        Err(e) => panic!("{range:?} of {length}: {e}"),
+    }
+});
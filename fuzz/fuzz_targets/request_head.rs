@@ -0,0 +1,13 @@
+//! Arbitrary bytes parsed as a request head, coming in pieces of the size
+//! told by the first byte, see `mikufans_bvc_server::fuzz::parse_request`.
+//!
+//! Run with `cargo +nightly fuzz run request_head`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mikufans_bvc_server::fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = fuzz::parse_request(data);
+});
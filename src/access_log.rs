@@ -0,0 +1,324 @@
+//! Access log, a line per request, see [`AccessLogConfig`].
+//!
+//! Lines are written apart from the tracing logs, to stdout or the file
+//! configured, in the format configured, see [`AccessLogFormat`]. JSON lines
+//! are like
+//!
+//! ```json
+//! {"time":"2026-10-16T08:00:00.000Z","client":"127.0.0.1","method":"GET","path":"/resource/mikufans/a","status":206,"bytes":1024,"duration":0.002,"range":"bytes=0-1023","request_id":"19a0c3e5b0012c4-1f"}
+//! ```
+//!
+//! The query is left out of the path, carrying tokens of signed URLs and
+//! sessions.
+//!
+//! The file is reopened once asked, e.g. once moved away by logrotate, see
+//! [`reopen`].
+
+use std::{
+    cell::RefCell,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Instant, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use http::{Method, StatusCode, header::RANGE};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+
+use crate::{
+    client,
+    config::{AccessLogConfig, AccessLogFormat, Config},
+    error::Error,
+    proto, request_id, transfer,
+};
+
+/// Max lines queued to be written, past which lines are dropped.
+const MAX_QUEUED_LINES: usize = 4096;
+
+/// Abbreviated month names, as of CLF.
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Queue of lines to be written, see [`init`].
+static LINES: OnceLock<mpsc::Sender<String>> = OnceLock::new();
+
+/// Whether to reopen the file once written next, see [`reopen`].
+static REOPEN: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    /// The request being logged, see [`observe`].
+    static CURRENT: RefCell<Current>;
+}
+
+#[derive(Debug, Default)]
+/// The request being logged, as known so far.
+struct Current {
+    method: Option<Method>,
+
+    path: String,
+
+    /// `Range` request header
+    range: Option<String>,
+
+    status: Option<StatusCode>,
+}
+
+#[derive(Debug)]
+#[derive(Serialize)]
+/// A line of the access log.
+struct Entry<'a> {
+    /// RFC 3339, in UTC
+    time: String,
+
+    client: IpAddr,
+
+    method: &'a str,
+
+    path: &'a str,
+
+    status: u16,
+
+    /// Body bytes sent
+    bytes: u64,
+
+    /// In seconds
+    duration: f64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    range: Option<&'a str>,
+
+    /// See [`request_id`]
+    request_id: Option<String>,
+}
+
+/// Open the access log, and spawn the writer of it.
+pub(crate) fn init(config: &AccessLogConfig) -> Result<()> {
+    let writer: Box<dyn AsyncWrite + Send + Unpin> = match &config.file {
+        Some(path) => Box::new(open(path)?),
+        None => Box::new(tokio::io::stdout()),
+    };
+
+    let (sender, receiver) = mpsc::channel(MAX_QUEUED_LINES);
+
+    if LINES.set(sender).is_ok() {
+        tokio::spawn(write(writer, config.file.clone(), receiver));
+    }
+
+    Ok(())
+}
+
+/// Reopen the file, when written next.
+pub(crate) fn reopen() {
+    REOPEN.store(true, Ordering::Relaxed);
+}
+
+/// Open the file at `path` for appending.
+fn open(path: &Path) -> Result<tokio::fs::File> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Open access log {}", path.display()))?;
+
+    Ok(tokio::fs::File::from_std(file))
+}
+
+/// Write lines queued, to the file at `path` if any, flushing once the queue
+/// drained.
+async fn write(
+    mut writer: Box<dyn AsyncWrite + Send + Unpin>,
+    path: Option<PathBuf>,
+    mut receiver: mpsc::Receiver<String>,
+) {
+    while let Some(mut lines) = receiver.recv().await {
+        while let Ok(line) = receiver.try_recv() {
+            lines.push_str(&line);
+        }
+
+        if REOPEN.swap(false, Ordering::Relaxed) {
+            if let Some(path) = &path {
+                // Keep writing to the current file, not to lose lines.
+                match open(path) {
+                    Ok(file) => writer = Box::new(file),
+                    Err(e) => tracing::error!("Reopen access log error: {e:#}"),
+                }
+            }
+        }
+
+        if let Err(e) = async {
+            writer.write_all(lines.as_bytes()).await?;
+            writer.flush().await
+        }
+        .await
+        {
+            tracing::error!("Write access log error: {e}");
+        }
+    }
+}
+
+/// Run the handler `future` of a request from `client`, logging it once
+/// done, by the client told by a trusted proxy if any, see
+/// [`client`](crate::client). Requests the handler fails without responding are
+/// taken as of the status of the error, as responded, see [`Error::status`].
+pub(crate) async fn observe<F>(client: IpAddr, future: F) -> Result<bool, Error>
+where
+    F: Future<Output = Result<bool, Error>>,
+{
+    let Some(lines) = LINES.get() else {
+        return future.await;
+    };
+
+    CURRENT
+        .scope(RefCell::new(Current::default()), async move {
+            let time = SystemTime::now();
+            let started = Instant::now();
+            let (result, bytes) = transfer::counted(future).await;
+            let duration = started.elapsed().as_secs_f64();
+
+            let line = CURRENT.with(|current| {
+                let current = current.borrow();
+
+                let status = match (&result, current.status) {
+                    (_, Some(status)) => status.as_u16(),
+                    (Err(e), None) => e.status().as_u16(),
+                    // Nothing requested, e.g. the connection closed
+                    (Ok(_), None) => return None,
+                };
+
+                let entry = Entry {
+                    time: rfc3339(time),
+                    client: client::current().unwrap_or(client),
+                    method: current.method.as_ref().map_or("-", Method::as_str),
+                    path: if current.path.is_empty() {
+                        "-"
+                    } else {
+                        &current.path
+                    },
+                    status,
+                    bytes,
+                    duration,
+                    range: current.range.as_deref(),
+                    request_id: request_id::current()
+                        .and_then(|id| id.to_str().ok().map(str::to_owned)),
+                };
+
+                Some(render(&entry, time))
+            });
+
+            if let Some(line) = line {
+                if lines.try_send(line).is_err() {
+                    tracing::warn!("Access log queue full, line dropped");
+                }
+            }
+
+            result
+        })
+        .await
+}
+
+/// Set the request being logged, see [`observe`].
+pub(crate) fn request(request: &proto::Request) {
+    let _ = CURRENT.try_with(|current| {
+        let mut current = current.borrow_mut();
+
+        current.method = Some(request.method.clone());
+        request
+            .request_uri
+            .path()
+            .as_str()
+            .clone_into(&mut current.path);
+        current.range = request
+            .headers
+            .get(RANGE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+    });
+}
+
+/// Set the status responded to the request being logged, see [`observe`].
+pub(crate) fn status(status: StatusCode) {
+    let _ = CURRENT.try_with(|current| current.borrow_mut().status = Some(status));
+}
+
+/// The line of `entry`, in the format configured.
+fn render(entry: &Entry<'_>, time: SystemTime) -> String {
+    let format = Config::current()
+        .access_log
+        .as_ref()
+        .map(|config| config.format)
+        .unwrap_or_default();
+
+    match format {
+        AccessLogFormat::Clf => {
+            let (year, month, day, hour, minute, second) = civil(time);
+
+            format!(
+                "{} - - [{day:02}/{}/{year}:{hour:02}:{minute:02}:{second:02} +0000] \"{} {} \
+                 HTTP/1.1\" {} {} \"{}\" {:.3} {}\n",
+                entry.client,
+                MONTHS[usize::from(month - 1)],
+                entry.method,
+                entry.path,
+                entry.status,
+                entry.bytes,
+                entry.range.unwrap_or("-").replace('"', "\\\""),
+                entry.duration,
+                entry.request_id.as_deref().unwrap_or("-"),
+            )
+        }
+        AccessLogFormat::Json => {
+            let mut line = serde_json::to_string(entry).unwrap_or_default();
+            line.push('\n');
+            line
+        }
+    }
+}
+
+/// `time` in RFC 3339, in UTC with milliseconds.
+fn rfc3339(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = civil(time);
+    let millis = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.subsec_millis());
+
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{millis:03}Z")
+}
+
+/// Year, month, day, hour, minute and second of `time`, in UTC.
+fn civil(time: SystemTime) -> (i64, u8, u8, u8, u8, u8) {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+
+    // Civil from days, of Howard Hinnant
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (
+        year,
+        month,
+        day,
+        (secs_of_day / 3600) as u8,
+        (secs_of_day % 3600 / 60) as u8,
+        (secs_of_day % 60) as u8,
+    )
+}
@@ -0,0 +1,161 @@
+//! Alerts of error bursts by webhook, see [`AlertConfig`], so that an
+//! unattended deployment notifies by e.g. Telegram or Discord.
+//!
+//! Upstream fetches failed and `5xx` responded are counted within
+//! [`AlertConfig::window`], see [`upstream_error`] and [`status`]. Once
+//! either reaches the threshold configured, an alert is posted to the
+//! webhook, at most once per [`AlertConfig::cooldown`].
+
+use std::{
+    collections::VecDeque,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use http::{HeaderMap, HeaderValue, Method, StatusCode, header::CONTENT_TYPE};
+
+use crate::{
+    config::{AlertConfig, Config},
+    upstream::{self, Priority},
+};
+
+/// Errors within the window, and when last alerted.
+static STATE: LazyLock<Mutex<State>> = LazyLock::new(|| Mutex::new(State::default()));
+
+tokio::task_local! {
+    /// Set while posting an alert, whose errors are not counted.
+    static ALERTING: ();
+}
+
+#[derive(Debug, Default)]
+/// See [`STATE`].
+struct State {
+    /// When upstream fetches failed
+    upstream_errors: VecDeque<Instant>,
+
+    /// When `5xx` responded
+    server_errors: VecDeque<Instant>,
+
+    last_alerted: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Kind of errors counted.
+enum Kind {
+    Upstream,
+    Server,
+}
+
+/// Count an upstream fetch failed.
+pub(crate) fn upstream_error() {
+    if ALERTING.try_with(|()| ()).is_ok() {
+        return;
+    }
+
+    record(Kind::Upstream);
+}
+
+/// Count the status responded if `5xx`.
+pub(crate) fn status(status: StatusCode) {
+    if status.is_server_error() {
+        record(Kind::Server);
+    }
+}
+
+/// Count an error of `kind`, alerting if reached the threshold.
+fn record(kind: Kind) {
+    let config = Config::current();
+
+    let Some(alert_config) = &config.alert else {
+        return;
+    };
+
+    let threshold = match kind {
+        Kind::Upstream => alert_config.upstream_errors,
+        Kind::Server => alert_config.server_errors,
+    };
+
+    if threshold == 0 {
+        return;
+    }
+
+    let now = Instant::now();
+    let window = Duration::from_secs(alert_config.window);
+
+    let count = {
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+
+        let cooling = state.last_alerted.is_some_and(|last_alerted| {
+            now.duration_since(last_alerted) < Duration::from_secs(alert_config.cooldown)
+        });
+
+        let errors = match kind {
+            Kind::Upstream => &mut state.upstream_errors,
+            Kind::Server => &mut state.server_errors,
+        };
+
+        errors.push_back(now);
+        while errors
+            .front()
+            .is_some_and(|error| now.duration_since(*error) > window)
+        {
+            errors.pop_front();
+        }
+
+        let count = errors.len();
+
+        if count < threshold || cooling {
+            return;
+        }
+
+        errors.clear();
+        state.last_alerted = Some(now);
+
+        count
+    };
+
+    let message = match kind {
+        Kind::Upstream => format!(
+            "mikufans-bvc-server: {count} upstream fetches failed within {}s",
+            alert_config.window
+        ),
+        Kind::Server => format!(
+            "mikufans-bvc-server: {count} 5xx responses within {}s",
+            alert_config.window
+        ),
+    };
+
+    tracing::warn!("Alert: {message}");
+
+    tokio::spawn(ALERTING.scope((), post(alert_config.clone(), message)));
+}
+
+/// Post `message` to the webhook.
+async fn post(alert_config: AlertConfig, message: String) {
+    let escaped = serde_json::to_string(&message).unwrap_or_default();
+    let body = alert_config
+        .body
+        .replace("{message}", escaped.trim_matches('"'));
+
+    let mut headers = HeaderMap::new();
+    if let Ok(content_type) = HeaderValue::from_str(&alert_config.content_type) {
+        headers.insert(CONTENT_TYPE, content_type);
+    }
+
+    match upstream::send(
+        &alert_config.webhook,
+        &Method::POST,
+        &alert_config.path,
+        &headers,
+        body.as_bytes(),
+        Priority::Background,
+    )
+    .await
+    {
+        Ok(response) if response.status.is_success() => {
+            tracing::debug!("Alert posted");
+        }
+        Ok(response) => tracing::error!("Post alert error: webhook responded {}", response.status),
+        Err(e) => tracing::error!("Post alert error: {e:#}"),
+    }
+}
@@ -0,0 +1,143 @@
+//! API keys of the admin API and uploads, see [`AdminConfig`].
+//!
+//! Requests carry a key as `Authorization: Bearer {key}` or
+//! `X-API-Key: {key}`, compared in constant time against those configured,
+//! of [`AdminConfig::token`], [`AdminConfig::api_keys`] and the file of
+//! [`AdminConfig::api_keys_file`], see [`authenticate`].
+//!
+//! Admin actions and uploads are logged by the name of the key to target
+//! `audit`, see [`audit`], so that they can be told apart, e.g. by
+//! `RUST_LOG=info,audit=info` or a file of their own.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{Arc, LazyLock},
+};
+
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use http::{HeaderName, header::AUTHORIZATION};
+
+use crate::{
+    client,
+    config::{AdminConfig, Config},
+    proto, utils,
+};
+
+/// Header `X-API-Key`.
+const X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Name of the key of [`AdminConfig::token`].
+const TOKEN_NAME: &str = "token";
+
+/// API keys by name loaded of [`AdminConfig::api_keys_file`], see [`load`].
+static FILE_KEYS: LazyLock<ArcSwap<BTreeMap<String, String>>> =
+    LazyLock::new(|| ArcSwap::from_pointee(BTreeMap::new()));
+
+/// Load the API keys of [`AdminConfig::api_keys_file`] if any.
+pub(crate) fn init(config: &AdminConfig) -> Result<()> {
+    load(config)
+}
+
+/// Load the API keys of the file of the current config again, e.g. once
+/// keys rotated, keeping the loaded ones if failed.
+pub(crate) fn reload() {
+    let config = Config::current();
+
+    let Some(admin_config) = &config.admin else {
+        return;
+    };
+
+    match load(admin_config) {
+        Ok(()) => {
+            if admin_config.api_keys_file.is_some() {
+                tracing::info!("API keys reloaded");
+            }
+        }
+        Err(e) => tracing::error!("Reload API keys error, keeping the loaded ones: {e:#}"),
+    }
+}
+
+/// Name of the API key `request` carries, if any of those configured.
+pub(crate) fn authenticate(request: &proto::Request, config: &AdminConfig) -> Option<String> {
+    let key = request
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            request
+                .headers
+                .get(X_API_KEY)
+                .and_then(|value| value.to_str().ok())
+        })?
+        .trim();
+
+    if key.is_empty() {
+        return None;
+    }
+
+    let file_keys = FILE_KEYS.load();
+
+    // All compared, not to tell by timing which one matched
+    config
+        .token
+        .iter()
+        .map(|token| (TOKEN_NAME, &**token))
+        .chain(
+            config
+                .api_keys
+                .iter()
+                .map(|(name, key)| (name.as_str(), &**key)),
+        )
+        .chain(
+            file_keys
+                .iter()
+                .map(|(name, key)| (name.as_str(), key.as_str())),
+        )
+        .fold(None, |matched, (name, expected)| {
+            if utils::constant_time_eq(key.as_bytes(), expected.as_bytes()) {
+                Some(name.to_owned())
+            } else {
+                matched
+            }
+        })
+}
+
+/// Log an action of `request` on `target` by the API key of `name`.
+pub(crate) fn audit(name: &str, request: &proto::Request, target: &str) {
+    tracing::info!(
+        target: "audit",
+        key = name,
+        client = client::current().map(tracing::field::display),
+        "{} {target}",
+        request.method,
+    );
+}
+
+/// Load the API keys of [`AdminConfig::api_keys_file`], none if not set.
+fn load(config: &AdminConfig) -> Result<()> {
+    let keys = match &config.api_keys_file {
+        Some(path) => read(path)?,
+        None => BTreeMap::new(),
+    };
+
+    FILE_KEYS.store(Arc::new(keys));
+
+    Ok(())
+}
+
+/// Read the API keys file at `path`, refused if accessible by group or
+/// others.
+fn read(path: &Path) -> Result<BTreeMap<String, String>> {
+    utils::check_private(path, "API keys file")?;
+
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Read API keys file {}", path.display()))?;
+
+    toml::from_str(&content).with_context(|| format!("Parse API keys file {}", path.display()))
+}
@@ -0,0 +1,113 @@
+//! Keys taken of either header and told by name, of the token, those inline
+//! and those of the file, the file refused if not private.
+
+use super::{authenticate, load};
+use crate::{config::AdminConfig, proto};
+
+/// The config of the token and the key `ci`, and the keys file at `file` if
+/// any.
+fn config(file: Option<&str>) -> AdminConfig {
+    let file = file.map_or_else(String::new, |file| format!("api_keys_file = {file:?}"));
+
+    toml::from_str(&format!(
+        r#"
+            token = "secret token"
+            {file}
+
+            [api_keys]
+            ci = "secret of ci"
+        "#
+    ))
+    .expect("Admin config")
+}
+
+/// A request of the header lines given.
+async fn request(headers: &str) -> proto::Request {
+    let head = format!("POST /admin/purge HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n");
+
+    proto::Request::handle(&mut head.as_bytes())
+        .await
+        .expect("Parsed")
+        .expect("Request")
+}
+
+/// Name of the key the request of the header lines given carries.
+async fn authenticated(config: &AdminConfig, headers: &str) -> Option<String> {
+    authenticate(&request(headers).await, config)
+}
+
+#[tokio::test]
+/// Keys of `Authorization: Bearer` or `X-API-Key` told by name.
+async fn headers() {
+    let config = config(None);
+
+    assert_eq!(
+        authenticated(&config, "Authorization: Bearer secret token\r\n").await,
+        Some("token".to_owned())
+    );
+    assert_eq!(
+        authenticated(&config, "X-API-Key: secret of ci\r\n").await,
+        Some("ci".to_owned())
+    );
+    assert_eq!(
+        authenticated(&config, "X-API-Key:  secret of ci \r\n").await,
+        Some("ci".to_owned())
+    );
+}
+
+#[tokio::test]
+/// Keys wrong, empty or of another scheme refused.
+async fn refused() {
+    let config = config(None);
+
+    for headers in [
+        "",
+        "Authorization: Bearer secret\r\n",
+        "Authorization: Bearer secret token and more\r\n",
+        "Authorization: Bearer \r\n",
+        "Authorization: Basic c2VjcmV0IHRva2Vu\r\n",
+        "Authorization: secret token\r\n",
+        "X-API-Key: \r\n",
+        "X-API-Key: secret of CI\r\n",
+    ] {
+        assert_eq!(authenticated(&config, headers).await, None, "{headers:?}");
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+/// Keys of the file told by name, the file refused if accessible by group or
+/// others.
+async fn file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("api-keys-{}.toml", std::process::id()));
+    std::fs::write(&path, "deploy = \"secret of deploy\"\n").expect("File written");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+        .expect("Permissions set");
+
+    let config = config(path.to_str());
+    load(&config).expect("Loaded");
+
+    assert_eq!(
+        authenticated(&config, "X-API-Key: secret of deploy\r\n").await,
+        Some("deploy".to_owned())
+    );
+    assert_eq!(
+        authenticated(&config, "Authorization: Bearer secret of ci\r\n").await,
+        Some("ci".to_owned())
+    );
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644))
+        .expect("Permissions set");
+    let loaded = load(&config);
+
+    std::fs::remove_file(&path).expect("File removed");
+    loaded.expect_err("Readable by others");
+
+    // Kept once failed
+    assert_eq!(
+        authenticated(&config, "X-API-Key: secret of deploy\r\n").await,
+        Some("deploy".to_owned())
+    );
+}
@@ -0,0 +1,59 @@
+//! Internals of the HTTP engine exposed to the benchmarks in `benches`, of
+//! the `bench` feature only.
+//!
+//! Responses are written to any [`Stream`], e.g. [`AnyStream::Memory`] of
+//! which the client end is drained, so that the engine is measured rather
+//! than the network.
+//!
+//! [`AnyStream::Memory`]: crate::AnyStream::Memory
+
+use anyhow::Result;
+use bytes::Bytes;
+use http::{HeaderMap, StatusCode};
+use tokio::{fs::File, io::AsyncRead};
+
+use crate::proto::{Body, Request, Response, Stream};
+
+/// Parse a request head of `reader`, see [`Request::handle`].
+///
+/// # Errors
+///
+/// If the head is invalid, or reading failed.
+pub async fn parse_request<R>(reader: &mut R) -> Result<Option<Request>>
+where
+    R: AsyncRead + Unpin,
+{
+    Request::handle(reader).await
+}
+
+/// Write a `200 OK` response of `headers` and `body` to `stream`, the body in
+/// memory written along with the head.
+///
+/// # Errors
+///
+/// If writing failed.
+pub async fn write_response(
+    headers: HeaderMap,
+    body: Bytes,
+    stream: &mut impl Stream,
+) -> Result<()> {
+    let mut response = Response::status(StatusCode::OK);
+    response.headers_mut().extend(headers);
+
+    response.with_body(body).write_to_stream(stream).await
+}
+
+/// Write `len` bytes of `file` from `offset` as the body of a `200 OK`
+/// response to `stream`, see [`Body::File`]. Those to a stream not plain TCP
+/// are streamed piece by piece, see
+/// [`copy_chunked`](crate::transfer::copy_chunked).
+///
+/// # Errors
+///
+/// If reading the file or writing failed.
+pub async fn write_file(file: File, offset: u64, len: u64, stream: &mut impl Stream) -> Result<()> {
+    Response::status(StatusCode::OK)
+        .with_body(Body::File { file, offset, len })
+        .write_to_stream(stream)
+        .await
+}
@@ -0,0 +1,135 @@
+//! Byte buffers pooled by size, reused across requests rather than allocated
+//! for each, see [`Buf`].
+//!
+//! - [`Size::Head`] of request and response heads, of the parser and the
+//!   upstream fetcher.
+//! - [`Size::Chunk`] of body pieces, of the streaming loops.
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Mutex,
+};
+
+use bytes::{Bytes, BytesMut};
+
+use crate::config;
+
+/// Max idle buffers kept in a pool of a [`Size`].
+const MAX_POOLED: usize = 256;
+
+/// Bytes of a [`Size::Head`] buffer, enough for most heads. Those longer grow
+/// the buffer, which is not pooled again if grown much.
+pub(crate) const HEAD_SIZE: usize = 8 * 1024;
+
+/// Idle buffers of [`Size::Head`].
+static HEADS: Mutex<Vec<BytesMut>> = Mutex::new(Vec::new());
+
+/// Idle buffers of [`Size::Chunk`].
+static CHUNKS: Mutex<Vec<BytesMut>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Size of pooled buffers.
+pub(crate) enum Size {
+    /// [`HEAD_SIZE`]
+    Head,
+
+    /// [`TransferConfig::chunk_size`](config::TransferConfig::chunk_size)
+    Chunk,
+}
+
+impl Size {
+    /// Bytes of the buffers.
+    fn bytes(self) -> usize {
+        match self {
+            Self::Head => HEAD_SIZE,
+            Self::Chunk => config::Config::current().transfer.chunk_size.max(1),
+        }
+    }
+
+    /// Pool of the buffers.
+    fn pool(self) -> &'static Mutex<Vec<BytesMut>> {
+        match self {
+            Self::Head => &HEADS,
+            Self::Chunk => &CHUNKS,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A buffer taken from the pool of its [`Size`], returned when dropped, e.g.
+/// once the [`Bytes`] of [`Buf::freeze`] and all sliced of them are.
+pub(crate) struct Buf {
+    buf: BytesMut,
+    size: Size,
+}
+
+impl Buf {
+    /// Take an empty buffer of `size` bytes of capacity, to be appended to.
+    pub(crate) fn take(size: Size) -> Self {
+        let bytes = size.bytes();
+
+        let buf = size
+            .pool()
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .filter(|buf| buf.capacity() >= bytes)
+            .unwrap_or_else(|| BytesMut::with_capacity(bytes));
+
+        Self { buf, size }
+    }
+
+    /// Take a buffer of [`Size::Chunk`], zero-filled, to be read into.
+    pub(crate) fn chunk() -> Self {
+        let mut chunk = Self::take(Size::Chunk);
+        chunk.buf.resize(Size::Chunk.bytes(), 0);
+
+        chunk
+    }
+
+    /// Freeze into [`Bytes`], of which the buffer is returned to the pool
+    /// once all dropped.
+    pub(crate) fn freeze(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl Deref for Buf {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for Buf {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+impl AsRef<[u8]> for Buf {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for Buf {
+    fn drop(&mut self) {
+        let mut buf = std::mem::take(&mut self.buf);
+        let bytes = self.size.bytes();
+
+        // Split off, or grown much
+        if !(bytes..=bytes * 2).contains(&buf.capacity()) {
+            return;
+        }
+
+        buf.clear();
+
+        let mut pool = self.size.pool().lock().unwrap_or_else(|e| e.into_inner());
+
+        if pool.len() < MAX_POOLED {
+            pool.push(buf);
+        }
+    }
+}
@@ -0,0 +1,1744 @@
+//! Disk cache of resources.
+//!
+//! Objects are content-addressed: each one is stored as
+//! `objects/{hash[0..2]}/{hash[2..4]}/{hash}` where `hash` is the hex SHA-256
+//! of its content, and the index maps keys to hashes. Identical objects
+//! referenced by multiple keys are therefore stored only once.
+//!
+//! Objects may be spread over several storage roots, e.g. a small SSD plus a
+//! big HDD, see [`StorageRootConfig`]. Each new object is placed on the root
+//! with the most weighted free space, and read from wherever it is.
+//!
+//! The total size of objects is limited, the least recently used keys are
+//! evicted when exceeded. The index is persisted as `index.json` so that it
+//! survives restarts.
+//!
+//! Each object may carry metadata in a sidecar, see [`metadata`].
+//!
+//! The hottest objects may also be kept in memory, see [`hot`].
+//!
+//! Objects may also be cached partially, span by span, see [`partial`], and
+//! read while being written, see [`filling`].
+//!
+//! Stored objects may be verified periodically in background, see [`scrub`],
+//! and watched for external changes, see [`watch`].
+
+mod filling;
+mod hot;
+mod metadata;
+mod partial;
+mod scrub;
+mod watch;
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+use std::{
+    collections::HashSet,
+    sync::{Condvar, atomic::AtomicU64},
+};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex, MutexGuard, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{fs::File, io::AsyncWriteExt};
+#[cfg(feature = "upstream")]
+use tokio::{fs::OpenOptions, io::AsyncSeekExt};
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+use self::filling::Progress;
+#[cfg(feature = "upstream")]
+use self::partial::ByteMap;
+pub(crate) use self::{
+    filling::FillingObject, hot::HotObject, metadata::Metadata, partial::PartialObject,
+};
+use self::{
+    filling::Fillings,
+    hot::HotCache,
+    metadata::{Sidecars, sidecar_path},
+    partial::{PARTIAL_DIR, PartialEntry},
+};
+#[cfg(any(feature = "upstream", feature = "admin"))]
+use crate::metrics;
+use crate::{
+    config::{CacheConfig, FsyncPolicy, StorageRootConfig},
+    upgrade,
+};
+
+/// The global [`Cache`], set when enabled.
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+/// Index file name
+const INDEX_FILE: &str = "index.json";
+
+/// Objects directory name
+const OBJECTS_DIR: &str = "objects";
+
+/// Directory name of objects being written
+const TMP_DIR: &str = "tmp";
+
+/// Interval of persisting the index when changed.
+const PERSIST_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Interval of persisting the index when keys are only accessed, not worth
+/// rewriting it as often for the order of eviction.
+const ACCESS_PERSIST_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug)]
+/// Disk cache, see the [module-level documentation](self).
+pub(crate) struct Cache {
+    /// Cache directory, holding the index and partial objects.
+    dir: PathBuf,
+
+    /// Storage roots of objects, never empty.
+    roots: Vec<StorageRootConfig>,
+
+    /// Max total size of objects, in bytes.
+    max_size: u64,
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// See [`CacheConfig::min_free_space`].
+    min_free_space: Option<u64>,
+
+    fsync: FsyncPolicy,
+
+    /// In-memory cache of hot objects, when enabled.
+    hot: Option<HotCache>,
+
+    sidecars: Sidecars,
+
+    /// Objects being written, see [`filling`].
+    fillings: Fillings,
+
+    index: Mutex<Index>,
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Notified once stored objects are no more being moved, see
+    /// [`Index::moving`].
+    moved: Condvar,
+
+    /// Whether the index has been changed since last persisted.
+    dirty: AtomicBool,
+
+    /// Whether keys have been accessed since last persisted, see
+    /// [`ACCESS_PERSIST_INTERVAL`].
+    accessed: AtomicBool,
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Names temporary files of [`CacheWriter`].
+    next_tmp_id: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+#[derive(Serialize, Deserialize)]
+/// Cache index
+struct Index {
+    /// Logical clock for LRU
+    clock: u64,
+
+    /// Key to entry
+    entries: HashMap<String, Entry>,
+
+    #[serde(default)]
+    /// Key to partial object entry
+    partials: HashMap<String, PartialEntry>,
+
+    #[serde(skip)]
+    /// Hash to stored object, rebuilt from `entries` when loaded.
+    objects: HashMap<String, StoredObject>,
+
+    #[serde(skip)]
+    /// Entries and partial object entries by last access, rebuilt when
+    /// loaded.
+    lru: Lru,
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    #[serde(skip)]
+    /// Hashes of stored objects being moved into or out of place with the
+    /// index unlocked, neither added nor removed meanwhile, see [`Cache::add`]
+    /// and [`Cache::release`].
+    moving: HashSet<String>,
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    #[serde(skip)]
+    /// Keys of partial objects removed, their files being removed with the
+    /// index unlocked, see [`Cache::remove_partial`].
+    removing: HashSet<String>,
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    #[serde(skip)]
+    /// Files to be removed once the index unlocked, see
+    /// [`Cache::remove_released`].
+    released: Vec<Released>,
+
+    #[serde(skip)]
+    /// Total size of all stored objects and spans of partial objects, and
+    /// space reserved by writers, see [`CacheWriter::preallocate`].
+    total_size: u64,
+}
+
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+/// Cache entry
+struct Entry {
+    /// Hex SHA-256 of the object content.
+    hash: String,
+
+    /// Object size
+    size: u64,
+
+    /// [`Index::clock`] of last access
+    last_access: u64,
+
+    #[serde(default)]
+    /// When stored, in seconds since UNIX epoch.
+    created_at: u64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// When no longer fresh, in seconds since UNIX epoch, see
+    /// [`CacheWriter::set_expires`].
+    expires: Option<u64>,
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+#[derive(Debug)]
+/// Files released, see [`Index::released`].
+enum Released {
+    /// Hash and path of a stored object, see [`Cache::release`].
+    Object(String, PathBuf),
+
+    /// Key of a partial object, see [`Cache::remove_partial`].
+    Partial(String),
+}
+
+#[derive(Debug, Default)]
+/// Keys of the [`Index`] ordered by [`Index::clock`] of last access, least
+/// recently used first, and whether of a partial object.
+struct Lru(BTreeSet<(u64, bool, String)>);
+
+impl Lru {
+    fn insert(&mut self, last_access: u64, partial: bool, key: String) {
+        self.0.insert((last_access, partial, key));
+    }
+
+    fn remove(&mut self, last_access: u64, partial: bool, key: &str) {
+        self.0.remove(&(last_access, partial, key.to_owned()));
+    }
+
+    /// Move `key` last accessed at `from` to `to`.
+    fn touch(&mut self, from: u64, to: u64, partial: bool, key: &str) {
+        if let Some((_, partial, key)) = self.0.take(&(from, partial, key.to_owned())) {
+            self.0.insert((to, partial, key));
+        }
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// The least recently used key `matches(partial, key)`.
+    fn find<F>(&self, mut matches: F) -> Option<(u64, bool, String)>
+    where
+        F: FnMut(bool, &str) -> bool,
+    {
+        self.0
+            .iter()
+            .find(|(_, partial, key)| matches(*partial, key))
+            .cloned()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// An object stored on disk
+struct StoredObject {
+    size: u64,
+
+    /// Index of the storage root holding it
+    root: usize,
+
+    /// Number of keys referencing it
+    refs: usize,
+}
+
+#[cfg(feature = "admin")]
+#[derive(Debug)]
+#[derive(Serialize)]
+/// Cache usage, see [`Cache::usage`].
+pub(crate) struct CacheUsage {
+    /// Number of keys
+    keys: usize,
+
+    /// Number of stored objects
+    objects: usize,
+
+    /// Number of partial objects
+    partials: usize,
+
+    /// Total size of stored objects and spans of partial objects
+    total_size: u64,
+
+    /// See [`CacheConfig::max_size`].
+    max_size: u64,
+
+    roots: Vec<RootUsage>,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Cache occupancy, see [`Cache::occupancy`].
+pub(crate) struct CacheOccupancy {
+    /// Number of keys, complete and partial
+    pub(crate) keys: usize,
+
+    /// Total size of stored objects and spans of partial objects
+    pub(crate) size: u64,
+
+    /// See [`CacheConfig::max_size`].
+    pub(crate) max_size: u64,
+}
+
+#[cfg(feature = "admin")]
+#[derive(Debug)]
+#[derive(Serialize)]
+/// Storage root usage
+struct RootUsage {
+    dir: PathBuf,
+
+    /// Free space of the file system, `None` when unknown.
+    free_space: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+/// A cached object found by [`Cache::get`].
+pub(crate) struct CachedObject {
+    /// Hex SHA-256 of the object content.
+    pub hash: String,
+
+    /// Path of the object file.
+    pub path: PathBuf,
+
+    /// Object size
+    pub size: u64,
+
+    /// When no longer fresh, in seconds since UNIX epoch, see
+    /// [`CacheWriter::set_expires`].
+    pub expires: Option<u64>,
+}
+
+impl CachedObject {
+    /// Whether no longer fresh, i.e. should be fetched again rather than
+    /// served.
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| unix_timestamp() >= expires)
+    }
+
+    /// Whether expired for `window` already, i.e. not even to be served
+    /// stale.
+    pub(crate) fn is_expired_for(&self, window: Duration) -> bool {
+        self.expires
+            .is_some_and(|expires| unix_timestamp() >= expires.saturating_add(window.as_secs()))
+    }
+}
+
+impl Cache {
+    /// Initialize the global [`Cache`] and spawn the index persisting task.
+    ///
+    /// Must be called within tokio runtime.
+    pub(crate) fn init(config: &CacheConfig) -> Result<()> {
+        let partial_dir = config.dir.join(PARTIAL_DIR);
+
+        std::fs::create_dir_all(&partial_dir)
+            .with_context(|| format!("Create cache directory {}", partial_dir.display()))?;
+
+        let roots = if config.roots.is_empty() {
+            vec![StorageRootConfig {
+                dir: config.dir.clone(),
+                weight: 1,
+            }]
+        } else {
+            config.roots.clone()
+        };
+
+        for root in &roots {
+            let objects_dir = root.dir.join(OBJECTS_DIR);
+            let tmp_dir = root.dir.join(TMP_DIR);
+
+            std::fs::create_dir_all(&objects_dir)
+                .with_context(|| format!("Create cache directory {}", objects_dir.display()))?;
+
+            // Objects left by an interrupted writer, or being written by the
+            // process upgraded from.
+            if tmp_dir.exists() && upgrade::upgrading_from().is_none() {
+                std::fs::remove_dir_all(&tmp_dir)
+                    .with_context(|| format!("Clean up {}", tmp_dir.display()))?;
+            }
+            std::fs::create_dir_all(&tmp_dir)
+                .with_context(|| format!("Create cache directory {}", tmp_dir.display()))?;
+        }
+
+        let mut index: Index = match std::fs::read(config.dir.join(INDEX_FILE)) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                tracing::warn!("Invalid cache index, starting over: {e:?}");
+                Index::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Index::default(),
+            Err(e) => return Err(e).context("Read cache index"),
+        };
+
+        // Locate objects, dropping entries whose object has gone.
+        let mut object_roots = HashMap::new();
+
+        index.entries.retain(|key, entry| {
+            let root = object_roots.get(&entry.hash).copied().or_else(|| {
+                roots
+                    .iter()
+                    .position(|root| object_path(&root.dir, &entry.hash).is_file())
+            });
+
+            let Some(root) = root else {
+                tracing::warn!("Cached object of {key:?} has gone");
+                return false;
+            };
+
+            object_roots.insert(entry.hash.clone(), root);
+
+            true
+        });
+
+        index.partials.retain(|key, _| {
+            let exists = partial_path(&config.dir, key).is_file();
+
+            if !exists {
+                tracing::warn!("Partial object of {key:?} has gone");
+            }
+
+            exists
+        });
+
+        for (key, entry) in &index.entries {
+            index.lru.insert(entry.last_access, false, key.clone());
+        }
+        for (key, entry) in &index.partials {
+            index.lru.insert(entry.last_access, true, key.clone());
+        }
+
+        for entry in index.entries.values() {
+            index
+                .objects
+                .entry(entry.hash.clone())
+                .or_insert(StoredObject {
+                    size: entry.size,
+                    root: object_roots.get(&entry.hash).copied().unwrap_or_default(),
+                    refs: 0,
+                })
+                .refs += 1;
+        }
+        index.total_size = index
+            .objects
+            .values()
+            .map(|object| object.size)
+            .sum::<u64>()
+            + index
+                .partials
+                .values()
+                .map(|entry| entry.ranges.len())
+                .sum::<u64>();
+
+        tracing::info!(
+            "Cache loaded, {} keys, {} objects, {} partial objects, {} bytes",
+            index.entries.len(),
+            index.objects.len(),
+            index.partials.len(),
+            index.total_size
+        );
+
+        let cache = Self {
+            dir: config.dir.clone(),
+            roots,
+            max_size: config.max_size,
+            #[cfg(any(feature = "upstream", feature = "admin"))]
+            min_free_space: config.min_free_space,
+            fsync: config.fsync,
+            hot: config.hot.as_ref().map(HotCache::new),
+            sidecars: Sidecars::new(),
+            fillings: Fillings::default(),
+            index: Mutex::new(index),
+            #[cfg(any(feature = "upstream", feature = "admin"))]
+            moved: Condvar::new(),
+            dirty: AtomicBool::new(false),
+            accessed: AtomicBool::new(false),
+            #[cfg(any(feature = "upstream", feature = "admin"))]
+            next_tmp_id: AtomicU64::new(0),
+        };
+
+        if CACHE.set(cache).is_err() {
+            anyhow::bail!("Cache has been initialized");
+        }
+
+        if let (Some(cache), Some(scrub_config)) = (Self::global(), config.scrub) {
+            for root in &cache.roots {
+                std::fs::create_dir_all(root.dir.join(scrub::QUARANTINE_DIR))
+                    .context("Create quarantine directory")?;
+            }
+
+            scrub::spawn(cache, scrub_config);
+        }
+
+        if let (Some(cache), true) = (Self::global(), config.watch) {
+            watch::watch(cache).context("Watch cache storage roots")?;
+        }
+
+        tokio::spawn(async {
+            let Some(cache) = Self::global() else {
+                return;
+            };
+
+            let mut accessed_persisted = Instant::now();
+
+            loop {
+                tokio::time::sleep(PERSIST_INTERVAL).await;
+
+                let accessed = accessed_persisted.elapsed() >= ACCESS_PERSIST_INTERVAL;
+                if accessed {
+                    accessed_persisted = Instant::now();
+                }
+
+                if let Err(e) = cache.persist_if(accessed).await {
+                    tracing::error!("Persist cache index error: {e:?}");
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    #[inline]
+    /// Get the global [`Cache`], `None` when not enabled.
+    pub(crate) fn global() -> Option<&'static Self> {
+        CACHE.get()
+    }
+
+    /// Look up a cached object by key, marking it as recently used.
+    pub(crate) fn get(&self, key: &str) -> Option<CachedObject> {
+        let mut index = self.index();
+
+        index.clock += 1;
+        let clock = index.clock;
+
+        let entry = index.entries.get_mut(key)?;
+        let last_access = std::mem::replace(&mut entry.last_access, clock);
+
+        let (hash, size, expires) = (entry.hash.clone(), entry.size, entry.expires);
+        index.lru.touch(last_access, clock, false, key);
+
+        let object = CachedObject {
+            path: self.stored_path(index.objects.get(&hash)?.root, &hash),
+            hash,
+            size,
+            expires,
+        };
+
+        self.accessed.store(true, Ordering::Release);
+
+        Some(object)
+    }
+
+    #[cfg(feature = "playurl")]
+    /// Keys of the complete objects starting with `prefix`, in no particular
+    /// order, not marking them as used.
+    pub(crate) fn keys(&self, prefix: &str) -> Vec<String> {
+        self.index()
+            .entries
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Get the in-memory copy of a cached object, `None` when not enabled.
+    ///
+    /// It may hold only the first bytes of the object, see [`HotObject`].
+    pub(crate) async fn get_hot(&self, object: &CachedObject) -> Option<HotObject> {
+        self.hot.as_ref()?.get(&object.path, object.size).await
+    }
+
+    /// Get the metadata of a cached object, `None` when it has no sidecar.
+    pub(crate) async fn metadata(&self, object: &CachedObject) -> Option<Arc<Metadata>> {
+        self.sidecars.get(&object.hash, &object.path).await
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Create a [`CacheWriter`] to store a new object under the given key.
+    ///
+    /// The object becomes visible once [`CacheWriter::commit`]ed.
+    ///
+    /// Fails with [`io::ErrorKind::StorageFull`] when there's no space left,
+    /// as does [`CacheWriter::write_all`]. The caller should then go on
+    /// without caching rather than failing the request.
+    pub(crate) async fn writer(&'static self, key: &str) -> io::Result<CacheWriter> {
+        let root = self.place().await?;
+        let tmp_path = self.tmp_path(root);
+
+        let file = File::create(&tmp_path).await?;
+
+        Ok(CacheWriter {
+            cache: self,
+            key: key.to_owned(),
+            root,
+            metadata: None,
+            tmp_path,
+            file: Some(file),
+            hasher: Sha256::new(),
+            written: 0,
+            expires: None,
+            filling: None,
+            reserved: 0,
+        })
+    }
+
+    /// Look up an object of `key` being written, see [`filling`].
+    pub(crate) fn filling(&self, key: &str) -> Option<FillingObject> {
+        self.fillings.get(key)
+    }
+
+    /// Look up a partial object by key, marking it as recently used.
+    pub(crate) fn get_partial(&self, key: &str) -> Option<PartialObject> {
+        let mut index = self.index();
+
+        index.clock += 1;
+        let clock = index.clock;
+
+        let entry = index
+            .partials
+            .get_mut(key)
+            .filter(|entry| !entry.finalizing)?;
+        let last_access = std::mem::replace(&mut entry.last_access, clock);
+
+        let object = PartialObject {
+            path: partial_path(&self.dir, key),
+            size: entry.size,
+            ranges: entry.ranges.clone(),
+        };
+        index.lru.touch(last_access, clock, true, key);
+
+        self.accessed.store(true, Ordering::Release);
+
+        Some(object)
+    }
+
+    #[cfg(feature = "upstream")]
+    /// Write a span of the object of `key`, whose full size is `size`, at
+    /// `offset` into its partial object, creating it if not exists.
+    ///
+    /// The object is moved into the store once all spans are present.
+    pub(crate) async fn fill_partial(
+        &'static self,
+        key: &str,
+        size: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let end = offset + data.len() as u64;
+
+        if end > size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Span exceeds the object size",
+            ));
+        }
+
+        let path = partial_path(&self.dir, key);
+
+        let id = {
+            let mut index = self.index();
+
+            // Its file not removed yet.
+            if index.removing.contains(key) {
+                return Ok(());
+            }
+
+            match index.partials.get(key) {
+                // All spans present already.
+                Some(entry) if entry.finalizing => return Ok(()),
+                // Upstream object changed, start over with the next span.
+                Some(entry) if entry.size != size => {
+                    self.remove_partial(&mut index, key);
+                    self.spawn_remove_released(index);
+                    return Ok(());
+                }
+                Some(entry) => entry.id,
+                None => {
+                    index.clock += 1;
+                    let clock = index.clock;
+
+                    let entry = PartialEntry {
+                        size,
+                        ranges: ByteMap::default(),
+                        last_access: clock,
+                        created_at: unix_timestamp(),
+                        finalizing: false,
+                        id: clock,
+                    };
+                    index.partials.insert(key.to_owned(), entry);
+                    index.lru.insert(clock, true, key.to_owned());
+
+                    clock
+                }
+            }
+        };
+
+        let written = async {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(false)
+                .open(&path)
+                .await?;
+
+            if file.metadata().await?.len() != size {
+                file.set_len(size).await?;
+            }
+
+            file.seek(io::SeekFrom::Start(offset)).await?;
+            file.write_all(data).await?;
+            file.flush().await?;
+
+            Ok::<_, io::Error>(file)
+        }
+        .await;
+
+        let file = match written {
+            Ok(file) => file,
+            Err(e) => {
+                let mut index = self.index();
+
+                if index
+                    .partials
+                    .get(key)
+                    .is_some_and(|entry| entry.id == id && entry.ranges.len() == 0)
+                {
+                    self.remove_partial(&mut index, key);
+                }
+                self.spawn_remove_released(index);
+
+                return Err(e);
+            }
+        };
+
+        metrics::cache_written(data.len() as u64);
+
+        let complete = {
+            let mut index = self.index();
+
+            index.clock += 1;
+            let clock = index.clock;
+
+            // Removed meanwhile, the file written into possibly gone, or
+            // completed by another fill.
+            let Some(entry) = index
+                .partials
+                .get_mut(key)
+                .filter(|entry| entry.id == id && !entry.finalizing)
+            else {
+                return Ok(());
+            };
+
+            let present = entry.ranges.len();
+            entry.ranges.insert(offset..end);
+            let last_access = std::mem::replace(&mut entry.last_access, clock);
+
+            let added = entry.ranges.len() - present;
+            let complete = entry.ranges.covers(0..size);
+            entry.finalizing = complete;
+
+            index.lru.touch(last_access, clock, true, key);
+            index.total_size += added;
+            self.dirty.store(true, Ordering::Release);
+
+            if !complete {
+                self.evict(&mut index, key);
+            }
+
+            self.spawn_remove_released(index);
+
+            complete
+        };
+
+        if complete {
+            tracing::debug!("Partial object of {key:?} completed");
+
+            // Not to be left finalizing if the request is cancelled.
+            tokio::spawn(self.finalize_partial(key.to_owned(), size, file, path))
+                .await
+                .map_err(io::Error::other)??;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "upstream")]
+    /// Move the complete partial object of `key` at `path` into the store,
+    /// dropping the partial object along with its file if failed.
+    async fn finalize_partial(
+        &'static self,
+        key: String,
+        size: u64,
+        file: File,
+        path: PathBuf,
+    ) -> io::Result<()> {
+        let finalized = async {
+            if self.fsync != FsyncPolicy::Never {
+                file.sync_data().await?;
+            }
+
+            let hash = partial::hash_file(&path).await?;
+
+            // Partial objects live in the cache directory, move it onto the
+            // chosen root first in case that's on another file system.
+            let root = self.place().await?;
+            let tmp_path = self.tmp_path(root);
+
+            if let Err(e) = tokio::fs::rename(&path, &tmp_path).await {
+                if e.kind() != io::ErrorKind::CrossesDevices {
+                    return Err(e);
+                }
+
+                if let Err(e) = tokio::fs::copy(&path, &tmp_path).await {
+                    remove_file(&tmp_path);
+                    return Err(e);
+                }
+                remove_file(&path);
+            }
+
+            // The partial object is dropped once the object published.
+            if let Err(e) = self
+                .add(key.clone(), tmp_path.clone(), root, hash, size, None)
+                .await
+            {
+                remove_file(&tmp_path);
+                return Err(e);
+            }
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = &finalized {
+            tracing::warn!("Failed to store the partial object of {key:?}: {e}");
+
+            let mut index = self.index();
+
+            if index
+                .partials
+                .get(&key)
+                .is_some_and(|entry| entry.finalizing)
+            {
+                self.remove_partial(&mut index, &key);
+            }
+            self.spawn_remove_released(index);
+        }
+
+        finalized
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Drop the partial object of `key` if any.
+    ///
+    /// Its file is removed once the index unlocked, see
+    /// [`Cache::remove_released`], not filled till then, see
+    /// [`Index::removing`].
+    fn remove_partial(&self, index: &mut Index, key: &str) {
+        if let Some(entry) = index.partials.remove(key) {
+            index.total_size -= entry.ranges.len();
+            index.lru.remove(entry.last_access, true, key);
+
+            index.removing.insert(key.to_owned());
+            index.released.push(Released::Partial(key.to_owned()));
+
+            self.dirty.store(true, Ordering::Release);
+        }
+    }
+
+    #[cfg(feature = "admin")]
+    /// Remove all keys `matches(key, age)`, returns the number of keys
+    /// removed.
+    pub(crate) fn purge<F>(&'static self, mut matches: F) -> usize
+    where
+        F: FnMut(&str, Duration) -> bool,
+    {
+        let now = unix_timestamp();
+
+        let mut index = self.index();
+
+        let purged: Vec<_> = index
+            .entries
+            .iter()
+            .filter(|(key, entry)| {
+                matches(
+                    key,
+                    Duration::from_secs(now.saturating_sub(entry.created_at)),
+                )
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in &purged {
+            if let Some(entry) = index.entries.remove(key) {
+                index.lru.remove(entry.last_access, false, key);
+                self.release(&mut index, &entry.hash);
+            }
+        }
+
+        let purged_partials: Vec<_> = index
+            .partials
+            .iter()
+            .filter(|(key, entry)| {
+                matches(
+                    key,
+                    Duration::from_secs(now.saturating_sub(entry.created_at)),
+                )
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in &purged_partials {
+            self.remove_partial(&mut index, key);
+        }
+
+        self.dirty.store(true, Ordering::Release);
+
+        self.spawn_remove_released(index);
+
+        purged.len() + purged_partials.len()
+    }
+
+    /// Persist the index if changed or accessed.
+    pub(crate) async fn persist(&self) -> io::Result<()> {
+        self.persist_if(true).await
+    }
+
+    /// Persist the index if changed, or if `accessed` and keys have been
+    /// accessed.
+    async fn persist_if(&self, accessed: bool) -> io::Result<()> {
+        let dirty = self.dirty.swap(false, Ordering::AcqRel);
+        let accessed = (dirty || accessed) && self.accessed.swap(false, Ordering::AcqRel);
+
+        if !dirty && !accessed {
+            return Ok(());
+        }
+
+        let persisted = self.write_index().await;
+
+        // Retried next time
+        if persisted.is_err() {
+            self.dirty.fetch_or(dirty, Ordering::AcqRel);
+            self.accessed.fetch_or(accessed, Ordering::AcqRel);
+        }
+
+        persisted
+    }
+
+    /// Write the index into [`INDEX_FILE`].
+    async fn write_index(&self) -> io::Result<()> {
+        let content = {
+            let index = self.index();
+            serde_json::to_vec(&*index)?
+        };
+
+        let tmp_path = self.dir.join(".index.json.tmp");
+
+        let mut file = File::create(&tmp_path).await?;
+        file.write_all(&content).await?;
+
+        if self.fsync != FsyncPolicy::Never {
+            file.sync_data().await?;
+        }
+        drop(file);
+
+        tokio::fs::rename(&tmp_path, self.dir.join(INDEX_FILE)).await?;
+
+        if self.fsync == FsyncPolicy::Full {
+            File::open(&self.dir).await?.sync_all().await?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Choose the storage root for a new object, the one with the most free
+    /// space multiplied by [`StorageRootConfig::weight`].
+    ///
+    /// Roots having less free space than
+    /// [`CacheConfig::min_free_space`] get their least recently used objects
+    /// evicted first, and are skipped if still short. Fails with
+    /// [`io::ErrorKind::StorageFull`] when no root is left.
+    async fn place(&'static self) -> io::Result<usize> {
+        if self.roots.len() == 1 && self.min_free_space.is_none() {
+            return Ok(0);
+        }
+
+        let mut best = None;
+
+        for (idx, root) in self.roots.iter().enumerate() {
+            let mut free = root_free_space(&root.dir).await;
+
+            if let (Some(min_free_space), Some(available)) = (self.min_free_space, free) {
+                if available < min_free_space {
+                    let freed = self.evict_root(idx, min_free_space - available);
+
+                    if available + freed < min_free_space {
+                        tracing::warn!("Cache storage root {} is full", root.dir.display());
+                        continue;
+                    }
+
+                    free = Some(available + freed);
+                }
+            }
+
+            // Unknown, compare weights only.
+            let score = u128::from(free.unwrap_or(1)) * u128::from(root.weight);
+
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((idx, score));
+            }
+        }
+
+        best.map(|(idx, _)| idx).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::StorageFull,
+                "All cache storage roots are full",
+            )
+        })
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Evict least recently used keys whose object is on the given storage
+    /// root, until at least `want` bytes are freed. Returns the bytes freed.
+    fn evict_root(&'static self, root: usize, want: u64) -> u64 {
+        let mut index = self.index();
+
+        let mut freed = 0;
+
+        while freed < want {
+            let Some((last_access, _, key)) = index.lru.find(|partial, key| {
+                !partial
+                    && index
+                        .entries
+                        .get(key)
+                        .and_then(|entry| index.objects.get(&entry.hash))
+                        .is_some_and(|object| object.root == root)
+            }) else {
+                break;
+            };
+
+            index.lru.remove(last_access, false, &key);
+
+            if let Some(entry) = index.entries.remove(&key) {
+                tracing::debug!("Evict cached object {key:?} for free space");
+                freed += self.release(&mut index, &entry.hash);
+                metrics::cache_evictions(1);
+            }
+        }
+
+        self.dirty.store(true, Ordering::Release);
+
+        self.spawn_remove_released(index);
+
+        freed
+    }
+
+    #[cfg(feature = "admin")]
+    /// Get the current usage, e.g. for monitoring.
+    pub(crate) async fn usage(&self) -> CacheUsage {
+        let mut usage = {
+            let index = self.index();
+
+            CacheUsage {
+                keys: index.entries.len(),
+                objects: index.objects.len(),
+                partials: index.partials.len(),
+                total_size: index.total_size,
+                max_size: self.max_size,
+                roots: Vec::with_capacity(self.roots.len()),
+            }
+        };
+
+        for root in &self.roots {
+            usage.roots.push(RootUsage {
+                dir: root.dir.clone(),
+                free_space: root_free_space(&root.dir).await,
+            });
+        }
+
+        usage
+    }
+
+    /// Get the current occupancy, cheaper than [`Cache::usage`].
+    pub(crate) fn occupancy(&self) -> CacheOccupancy {
+        let index = self.index();
+
+        CacheOccupancy {
+            keys: index.entries.len() + index.partials.len(),
+            size: index.total_size,
+            max_size: self.max_size,
+        }
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// A new temporary file path on the given storage root.
+    fn tmp_path(&self, root: usize) -> PathBuf {
+        self.roots[root]
+            .dir
+            .join(TMP_DIR)
+            .join(self.next_tmp_id.fetch_add(1, Ordering::Relaxed).to_string())
+    }
+
+    #[inline]
+    /// Path of the stored object on the given storage root.
+    fn stored_path(&self, root: usize, hash: &str) -> PathBuf {
+        object_path(&self.roots[root].dir, hash)
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Move a fully written temporary file on the given storage root into the
+    /// store and reference it by key, evicting least recently used keys if
+    /// exceeding the max size.
+    ///
+    /// The file is moved off the runtime, see [`Cache::store`].
+    ///
+    /// Returns the path of the stored object.
+    async fn add(
+        &'static self,
+        key: String,
+        tmp_path: PathBuf,
+        root: usize,
+        hash: String,
+        size: u64,
+        expires: Option<u64>,
+    ) -> io::Result<PathBuf> {
+        // Done even if the caller gives up, not to leave the object moving
+        tokio::task::spawn_blocking(move || self.store(key, &tmp_path, root, hash, size, expires))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// See [`Cache::add`], blocking.
+    ///
+    /// The index is locked only to look up the object and to publish it, not
+    /// while it is moved into place. An object being moved, into or out of
+    /// place, is waited for, see [`Index::moving`], so that one being removed
+    /// cannot race with the same one being added back.
+    fn store(
+        &self,
+        key: String,
+        tmp_path: &Path,
+        root: usize,
+        hash: String,
+        size: u64,
+        expires: Option<u64>,
+    ) -> io::Result<PathBuf> {
+        let mut index = self
+            .moved
+            .wait_while(self.index(), |index| index.moving.contains(&hash))
+            .unwrap_or_else(|e| e.into_inner());
+
+        let deduplicated = index.objects.contains_key(&hash);
+
+        let path = if let Some(object) = index.objects.get_mut(&hash) {
+            tracing::debug!("Deduplicated cached object {key:?}: {hash}");
+
+            object.refs += 1;
+
+            self.stored_path(object.root, &hash)
+        } else {
+            index.moving.insert(hash.clone());
+            drop(index);
+
+            let path = self.stored_path(root, &hash);
+
+            let moved = path
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::rename(tmp_path, &path))
+                .and_then(|()| {
+                    // Durable before published
+                    if self.fsync == FsyncPolicy::Full {
+                        sync_parent(&path)?;
+                    }
+
+                    Ok(())
+                });
+
+            index = self.index();
+            index.moving.remove(&hash);
+            self.moved.notify_all();
+
+            moved?;
+
+            index.objects.insert(
+                hash.clone(),
+                StoredObject {
+                    size,
+                    root,
+                    refs: 1,
+                },
+            );
+            index.total_size += size;
+
+            path
+        };
+
+        index.clock += 1;
+        let entry = Entry {
+            hash,
+            size,
+            last_access: index.clock,
+            created_at: unix_timestamp(),
+            expires,
+        };
+
+        let clock = index.clock;
+        if let Some(replaced) = index.entries.insert(key.clone(), entry) {
+            index.lru.remove(replaced.last_access, false, &key);
+            self.release(&mut index, &replaced.hash);
+        }
+        index.lru.insert(clock, false, key.clone());
+
+        // Superseded by the complete one.
+        self.remove_partial(&mut index, &key);
+
+        self.evict(&mut index, &key);
+
+        self.dirty.store(true, Ordering::Release);
+
+        let released = std::mem::take(&mut index.released);
+        drop(index);
+
+        if deduplicated {
+            remove_file(tmp_path);
+        }
+        self.remove_released(released);
+
+        Ok(path)
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Evict least recently used keys, both complete and partial, except the
+    /// given one, until not exceeding the max size.
+    fn evict(&self, index: &mut Index, keep: &str) {
+        if index.total_size <= self.max_size {
+            return;
+        }
+
+        while index.total_size > self.max_size {
+            let Some((last_access, partial, key)) = index.lru.find(|partial, key| {
+                key != keep
+                    && !(partial
+                        && index
+                            .partials
+                            .get(key)
+                            .is_some_and(|entry| entry.finalizing))
+            }) else {
+                break;
+            };
+
+            if partial {
+                tracing::debug!("Evict partial object {key:?}");
+                self.remove_partial(index, &key);
+                metrics::cache_evictions(1);
+                continue;
+            }
+
+            index.lru.remove(last_access, false, &key);
+
+            if let Some(entry) = index.entries.remove(&key) {
+                tracing::debug!("Evict cached object {key:?}");
+                self.release(index, &entry.hash);
+                metrics::cache_evictions(1);
+            }
+        }
+
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Drop a reference to the stored object, removing it when no more
+    /// referenced. Returns the bytes freed.
+    ///
+    /// The files are removed once the index unlocked, see
+    /// [`Cache::remove_released`], the object being moved out of place till
+    /// then, see [`Index::moving`].
+    fn release(&self, index: &mut Index, hash: &str) -> u64 {
+        let Some(object) = index.objects.get_mut(hash) else {
+            return 0;
+        };
+
+        object.refs = object.refs.saturating_sub(1);
+
+        if object.refs == 0 {
+            let StoredObject { size, root, .. } = *object;
+
+            index.objects.remove(hash);
+            index.total_size -= size;
+
+            let path = self.stored_path(root, hash);
+
+            if let Some(hot) = &self.hot {
+                hot.invalidate(&path);
+            }
+            self.sidecars.invalidate(hash);
+
+            index.moving.insert(hash.to_owned());
+            index.released.push(Released::Object(hash.to_owned(), path));
+
+            return size;
+        }
+
+        0
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Remove the files `released`, see [`Cache::release`] and
+    /// [`Cache::remove_partial`], with the index unlocked, then let them be
+    /// added back.
+    fn remove_released(&self, released: Vec<Released>) {
+        if released.is_empty() {
+            return;
+        }
+
+        for released in &released {
+            match released {
+                Released::Object(_, path) => {
+                    remove_file(path);
+                    remove_file(&sidecar_path(path));
+                }
+                Released::Partial(key) => remove_file(&partial_path(&self.dir, key)),
+            }
+        }
+
+        let mut index = self.index();
+
+        for released in &released {
+            match released {
+                Released::Object(hash, _) => {
+                    index.moving.remove(hash);
+                }
+                Released::Partial(key) => {
+                    index.removing.remove(key);
+                }
+            }
+        }
+
+        drop(index);
+        self.moved.notify_all();
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Unlock the `index`, then [`Cache::remove_released`] off the runtime.
+    fn spawn_remove_released(&'static self, mut index: MutexGuard<'_, Index>) {
+        let released = std::mem::take(&mut index.released);
+        drop(index);
+
+        if !released.is_empty() {
+            tokio::task::spawn_blocking(move || self.remove_released(released));
+        }
+    }
+
+    /// Move a corrupt object into quarantine, dropping all keys referencing
+    /// it.
+    ///
+    /// Blocking, called on tokio's blocking pool, see [`scrub`].
+    fn quarantine(&self, hash: &str) {
+        let Some(object) = self.forget(&mut self.index(), hash, "is corrupt") else {
+            return;
+        };
+
+        let root = &self.roots[object.root].dir;
+        let path = object_path(root, hash);
+        let quarantine_path = root.join(scrub::QUARANTINE_DIR).join(hash);
+
+        if let Some(hot) = &self.hot {
+            hot.invalidate(&path);
+        }
+
+        if let Err(e) = std::fs::rename(&path, &quarantine_path) {
+            tracing::error!("Quarantine cached object {hash} error: {e:?}");
+        }
+
+        self.sidecars.invalidate(hash);
+        remove_file(&sidecar_path(&path));
+    }
+
+    /// Check the object file at `path` after it has been changed externally,
+    /// forgetting the object if it's gone or its size no longer matches.
+    ///
+    /// The in-memory copy is dropped anyway.
+    fn revalidate(&self, path: &Path) {
+        let Some(hash) = path.file_name().and_then(|name| name.to_str()) else {
+            return;
+        };
+
+        if let Some(hash) = hash.strip_suffix(".json") {
+            self.sidecars.invalidate(hash);
+            return;
+        }
+
+        if let Some(hot) = &self.hot {
+            hot.invalidate(path);
+        }
+
+        let mut index = self.index();
+
+        let Some(object) = index.objects.get(hash).copied() else {
+            return;
+        };
+
+        if self.stored_path(object.root, hash) != path
+            || std::fs::metadata(path).is_ok_and(|metadata| metadata.len() == object.size)
+        {
+            return;
+        }
+
+        if self
+            .forget(&mut index, hash, "has been changed externally")
+            .is_some()
+        {
+            remove_file(path);
+        }
+    }
+
+    /// Forget a stored object and all keys referencing it, without touching
+    /// the file.
+    fn forget(&self, index: &mut Index, hash: &str, reason: &str) -> Option<StoredObject> {
+        let object = index.objects.remove(hash)?;
+        index.total_size -= object.size;
+
+        let lru = &mut index.lru;
+        index.entries.retain(|key, entry| {
+            if entry.hash == hash {
+                tracing::warn!("Cached object of {key:?} {reason}");
+                lru.remove(entry.last_access, false, key);
+                false
+            } else {
+                true
+            }
+        });
+
+        self.dirty.store(true, Ordering::Release);
+
+        Some(object)
+    }
+
+    #[inline]
+    fn index(&self) -> MutexGuard<'_, Index> {
+        self.index.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+#[derive(Debug)]
+/// Writes a new object into the [`Cache`].
+///
+/// The partially written object is removed if dropped without being
+/// committed.
+pub(crate) struct CacheWriter {
+    cache: &'static Cache,
+    key: String,
+
+    /// Storage root the object is written onto
+    root: usize,
+
+    /// Written into the sidecar on commit, see [`CacheWriter::set_metadata`].
+    metadata: Option<Metadata>,
+
+    tmp_path: PathBuf,
+    file: Option<File>,
+    hasher: Sha256,
+    written: u64,
+
+    /// See [`CacheWriter::set_expires`].
+    expires: Option<u64>,
+
+    /// Where to report the progress if shared, see [`CacheWriter::share`].
+    filling: Option<tokio::sync::watch::Sender<Progress>>,
+
+    /// Bytes counted into the total size till committed or dropped, see
+    /// [`CacheWriter::preallocate`].
+    reserved: u64,
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+impl CacheWriter {
+    /// Append data to the object.
+    pub(crate) async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let Some(file) = &mut self.file else {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        };
+
+        file.write_all(buf).await?;
+        self.hasher.update(buf);
+        self.written += buf.len() as u64;
+
+        metrics::cache_written(buf.len() as u64);
+
+        if let Some(filling) = &self.filling {
+            // Make it readable before reporting
+            file.flush().await?;
+
+            filling.send_replace(Progress::Written(self.written));
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    /// Bytes written so far
+    pub(crate) const fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Reserve disk space for an object of the given size, known beforehand
+    /// e.g. from `Content-Length`, avoiding fragmentation and failing fast
+    /// with [`io::ErrorKind::StorageFull`] before streaming it.
+    ///
+    /// The space is counted into the size of the cache till committed or
+    /// dropped, evicting others if exceeded, so that writers in progress are
+    /// not left out of it.
+    ///
+    /// The file size is not changed. Not allocated where `fallocate(2)` is
+    /// not supported.
+    pub(crate) fn preallocate(&mut self, size: u64) -> io::Result<()> {
+        if size > self.cache.max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                "Object larger than the cache",
+            ));
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(file) = &self.file {
+            use std::os::fd::AsRawFd;
+
+            let Ok(length) = libc::off_t::try_from(size) else {
+                return Err(io::ErrorKind::InvalidInput.into());
+            };
+
+            #[allow(unsafe_code, reason = "FFI")]
+            // SAFETY: the fd is valid for the duration of the call.
+            let ret =
+                unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, length) };
+
+            if ret != 0 {
+                let e = io::Error::last_os_error();
+
+                // Not supported by the file system
+                if e.raw_os_error() != Some(libc::EOPNOTSUPP) {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut index = self.cache.index();
+
+        index.total_size = index.total_size - self.reserved + size;
+        self.reserved = size;
+        self.cache.evict(&mut index, &self.key);
+
+        self.cache.spawn_remove_released(index);
+
+        Ok(())
+    }
+
+    /// Set the metadata written into the sidecar of the object, replacing the
+    /// existing one if the same content has been stored.
+    pub(crate) fn set_metadata(&mut self, metadata: Metadata) {
+        self.metadata = Some(metadata);
+    }
+
+    #[cfg(feature = "upstream")]
+    /// Set when the object is no longer fresh, in seconds since UNIX epoch,
+    /// e.g. of the `deadline` of a signed URL it's fetched from. Recorded in
+    /// the index for the key, see [`CachedObject::is_expired`].
+    pub(crate) const fn set_expires(&mut self, expires: u64) {
+        self.expires = Some(expires);
+    }
+
+    #[cfg(feature = "upstream")]
+    /// Let readers follow the object while written, see [`filling`]. `size`
+    /// is of the whole object, known beforehand.
+    ///
+    /// Call after [`CacheWriter::set_metadata`] for readers to get the
+    /// metadata as well. Does nothing if another object of the same key is
+    /// shared already.
+    pub(crate) fn share(&mut self, size: u64) {
+        self.filling = self.cache.fillings.insert(
+            &self.key,
+            self.tmp_path.clone(),
+            size,
+            self.metadata.clone(),
+        );
+    }
+
+    /// Finish writing, making the object visible in the [`Cache`].
+    pub(crate) async fn commit(mut self) -> io::Result<()> {
+        // Counted as stored instead
+        self.unreserve();
+
+        if let Some(mut file) = self.file.take() {
+            file.flush().await?;
+
+            if self.cache.fsync != FsyncPolicy::Never {
+                file.sync_data().await?;
+            }
+        }
+
+        let hash = format!("{:x}", std::mem::take(&mut self.hasher).finalize());
+
+        let key = std::mem::take(&mut self.key);
+
+        let path = self
+            .cache
+            .add(
+                key.clone(),
+                self.tmp_path.clone(),
+                self.root,
+                hash.clone(),
+                self.written,
+                self.expires,
+            )
+            .await?;
+
+        // Committed, nothing to clean up.
+        self.tmp_path = PathBuf::new();
+
+        if let Some(filling) = self.filling.take() {
+            self.cache
+                .fillings
+                .remove(&key, &filling, Progress::Committed);
+        }
+
+        if let Some(metadata) = &self.metadata {
+            self.cache.sidecars.write(&hash, &path, metadata).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop counting the space reserved, see [`CacheWriter::preallocate`].
+    fn unreserve(&mut self) {
+        if self.reserved > 0 {
+            self.cache.index().total_size -= std::mem::take(&mut self.reserved);
+        }
+    }
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        self.unreserve();
+
+        if let Some(filling) = self.filling.take() {
+            self.cache
+                .fillings
+                .remove(&self.key, &filling, Progress::Failed);
+        }
+
+        if !self.tmp_path.as_os_str().is_empty() {
+            remove_file(&self.tmp_path);
+        }
+    }
+}
+
+/// Path of the stored object of the given hash.
+fn object_path(dir: &Path, hash: &str) -> PathBuf {
+    let mut path = dir.join(OBJECTS_DIR);
+
+    if let (Some(level_1), Some(level_2)) = (hash.get(0..2), hash.get(2..4)) {
+        path.push(level_1);
+        path.push(level_2);
+    }
+
+    path.push(hash);
+    path
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+/// Free space of the file system of a storage root, in bytes.
+async fn root_free_space(dir: &Path) -> Option<u64> {
+    let dir = dir.to_owned();
+
+    tokio::task::spawn_blocking(move || free_space(&dir))
+        .await
+        .ok()
+        .flatten()
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+/// Free space of the file system containing `path`, in bytes.
+fn free_space(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+
+        #[allow(unsafe_code, reason = "FFI")]
+        // SAFETY: `path` is a valid C string, `stat` is written by the call.
+        let stat = unsafe {
+            let mut stat = std::mem::zeroed::<libc::statvfs>();
+
+            if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+                return None;
+            }
+
+            stat
+        };
+
+        #[allow(clippy::useless_conversion, reason = "Types differ across platforms")]
+        Some(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+
+        None
+    }
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+/// Sync the directory containing `path`, making a rename into it durable.
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => std::fs::File::open(parent)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+/// Path of the partial object of the given key.
+fn partial_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(PARTIAL_DIR)
+        .join(format!("{:x}", Sha256::digest(key.as_bytes())))
+}
+
+/// Current time in seconds since UNIX epoch.
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Remove a file, logging errors.
+fn remove_file(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            tracing::error!("Remove cache file {} error: {e:?}", path.display());
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Objects being written into the cache, readable while written.
+//!
+//! A [`CacheWriter`](super::CacheWriter) of an object of known size may be
+//! shared, see [`CacheWriter::share`](super::CacheWriter::share), so that
+//! readers find it by [`Cache::filling`](super::Cache::filling) and follow
+//! the temporary file as bytes arrive, e.g. a client requesting a segment
+//! being prefetched is served right away rather than fetching it again.
+
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::watch;
+
+use super::Metadata;
+
+#[cfg_attr(
+    not(any(feature = "upstream", feature = "admin")),
+    allow(dead_code, reason = "Written by the cache writers only")
+)]
+#[derive(Debug, Clone, Copy)]
+/// Progress of writing a shared object.
+pub(super) enum Progress {
+    /// Bytes written so far
+    Written(u64),
+
+    /// All written and in place
+    Committed,
+
+    /// Writing has been given up
+    Failed,
+}
+
+#[derive(Debug, Default)]
+/// Shared objects being written, by key.
+pub(super) struct Fillings {
+    objects: Mutex<HashMap<String, FillingObject>>,
+}
+
+#[derive(Debug, Clone)]
+/// An object being written, see [`Cache::filling`](super::Cache::filling).
+pub(crate) struct FillingObject {
+    /// Path of the temporary file. The file is renamed or removed once
+    /// written, so it should be opened right away.
+    pub path: PathBuf,
+
+    /// Size of the whole object
+    pub size: u64,
+
+    pub metadata: Option<Arc<Metadata>>,
+
+    progress: watch::Receiver<Progress>,
+}
+
+impl FillingObject {
+    /// Wait until bytes past `offset` have been written, returning how many
+    /// bytes have been written.
+    ///
+    /// Fails if writing has been given up.
+    pub(crate) async fn available(&mut self, offset: u64) -> io::Result<u64> {
+        let progress = self
+            .progress
+            .wait_for(
+                |progress| !matches!(progress, Progress::Written(written) if *written <= offset),
+            )
+            .await
+            .map(|progress| *progress)
+            .unwrap_or(Progress::Failed);
+
+        match progress {
+            Progress::Written(written) => Ok(written),
+            Progress::Committed => Ok(self.size),
+            Progress::Failed => Err(io::Error::other("Writing into the cache given up")),
+        }
+    }
+}
+
+impl Fillings {
+    #[cfg(feature = "upstream")]
+    /// Share the object of `key` being written to `path`, returning where to
+    /// report the progress. `None` if another one of `key` is shared already.
+    pub(super) fn insert(
+        &self,
+        key: &str,
+        path: PathBuf,
+        size: u64,
+        metadata: Option<Metadata>,
+    ) -> Option<watch::Sender<Progress>> {
+        let mut objects = self.objects.lock().unwrap_or_else(|e| e.into_inner());
+
+        if objects.contains_key(key) {
+            return None;
+        }
+
+        let (sender, progress) = watch::channel(Progress::Written(0));
+
+        objects.insert(
+            key.to_owned(),
+            FillingObject {
+                path,
+                size,
+                metadata: metadata.map(Arc::new),
+                progress,
+            },
+        );
+
+        Some(sender)
+    }
+
+    /// The shared object of `key` being written, if any.
+    pub(super) fn get(&self, key: &str) -> Option<FillingObject> {
+        self.objects
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(key)
+            .cloned()
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Report the final progress of the object of `key` shared with `sender`
+    /// and stop sharing it.
+    pub(super) fn remove(&self, key: &str, sender: &watch::Sender<Progress>, progress: Progress) {
+        sender.send_replace(progress);
+
+        let mut objects = self.objects.lock().unwrap_or_else(|e| e.into_inner());
+
+        if objects
+            .get(key)
+            .is_some_and(|object| object.progress.same_channel(&sender.subscribe()))
+        {
+            objects.remove(key);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! In-memory cache of hot objects.
+//!
+//! Holds the first [`HotCacheConfig::max_object_size`] bytes of objects, so
+//! that small objects like init segments and playlists, as well as the first
+//! chunk of popular segments, are served without touching the file system.
+//! Stored objects are immutable, so entries never go stale.
+//!
+//! Admission and eviction are left to [`moka`], which only keeps objects
+//! frequently accessed enough.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio::{fs::File, io::AsyncReadExt};
+
+use crate::config::HotCacheConfig;
+
+#[derive(Debug)]
+/// In-memory cache of hot objects, see the [module-level
+/// documentation](self).
+pub(super) struct HotCache {
+    /// Object path to object
+    objects: moka::sync::Cache<PathBuf, HotObject>,
+
+    max_object_size: u64,
+}
+
+#[derive(Debug, Clone)]
+/// An object found by [`Cache::get_hot`](super::Cache::get_hot).
+pub(crate) struct HotObject {
+    /// The first bytes of the object, may be all of it.
+    pub head: Arc<[u8]>,
+
+    /// Object size
+    pub size: u64,
+}
+
+impl HotObject {
+    /// Whether the inclusive byte range is held in memory.
+    pub(crate) fn holds(&self, (_, end): (u64, u64)) -> bool {
+        end < self.head.len() as u64
+    }
+
+    #[inline]
+    /// Whether the whole object is held in memory.
+    pub(crate) fn is_complete(&self) -> bool {
+        self.head.len() as u64 == self.size
+    }
+}
+
+impl HotCache {
+    /// Create a new [`HotCache`].
+    pub(super) fn new(config: &HotCacheConfig) -> Self {
+        Self {
+            objects: moka::sync::Cache::builder()
+                .max_capacity(config.max_size)
+                .weigher(|_, object: &HotObject| {
+                    u32::try_from(object.head.len()).unwrap_or(u32::MAX)
+                })
+                .build(),
+            max_object_size: config.max_object_size,
+        }
+    }
+
+    /// Get the object stored at `path` of `size` bytes, reading its head into
+    /// memory when not present.
+    pub(super) async fn get(&self, path: &Path, size: u64) -> Option<HotObject> {
+        if let Some(object) = self.objects.get(path) {
+            return Some(object);
+        }
+
+        let length = usize::try_from(size.min(self.max_object_size)).ok()?;
+        let mut head = vec![0; length];
+
+        let read = async {
+            File::open(path).await?.read_exact(&mut head).await?;
+
+            std::io::Result::Ok(())
+        };
+
+        if let Err(e) = read.await {
+            tracing::debug!("Load hot object {} error: {e:?}", path.display());
+            return None;
+        }
+
+        let object = HotObject {
+            head: head.into(),
+            size,
+        };
+
+        self.objects.insert(path.to_owned(), object.clone());
+
+        Some(object)
+    }
+
+    #[inline]
+    /// Drop the object stored at `path`, e.g. when found corrupt.
+    pub(super) fn invalidate(&self, path: &Path) {
+        self.objects.invalidate(path);
+    }
+}
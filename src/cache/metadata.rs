@@ -0,0 +1,157 @@
+//! Per-object metadata sidecars.
+//!
+//! A stored object may come with a JSON sidecar next to it, i.e.
+//! `objects/{hash[0..2]}/{hash[2..4]}/{hash}.json`, written on ingestion or
+//! by external tools, like:
+//!
+//! ```json
+//! {
+//!     "content_type": "video/mp4",
+//!     "url": "https://upos-sz-mirrorcos.bilivideo.com/...",
+//!     "checksums": { "md5": "..." },
+//!     "expires": 1735689600,
+//!     "headers": { "x-bvc-upos": "cos" }
+//! }
+//! ```
+//!
+//! All fields are optional. Sidecars are read lazily and kept in memory.
+
+use std::{collections::BTreeMap, io, path::Path, sync::Arc, time::SystemTime};
+
+use http::{HeaderMap, HeaderName, HeaderValue, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+
+/// Max number of sidecars kept in memory.
+const CACHED_SIDECARS: u64 = 16 * 1024;
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+/// Metadata of a stored object, see the [module-level
+/// documentation](self).
+pub(crate) struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// `Content-Type` to respond with.
+    pub content_type: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Where the object was fetched from.
+    pub url: Option<String>,
+
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    /// Checksums by algorithm name, besides the SHA-256 naming the object.
+    pub checksums: BTreeMap<String, String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// When the object is no longer served, in seconds since UNIX epoch.
+    pub expires: Option<u64>,
+
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    /// Extra response headers.
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    /// Whether the object has expired.
+    pub(crate) fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .is_ok_and(|now| now.as_secs() >= expires)
+        })
+    }
+
+    /// Set `Content-Type` and extra headers of the response.
+    pub(crate) fn apply(&self, headers: &mut HeaderMap) {
+        if let Some(content_type) = &self.content_type {
+            match HeaderValue::from_str(content_type) {
+                Ok(content_type) => {
+                    headers.insert(CONTENT_TYPE, content_type);
+                }
+                Err(_) => tracing::warn!("Invalid content type in sidecar: {content_type:?}"),
+            }
+        }
+
+        for (name, value) in &self.headers {
+            match (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    headers.insert(name, value);
+                }
+                _ => tracing::warn!("Invalid header in sidecar: {name:?}: {value:?}"),
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Sidecars kept in memory, by object hash.
+pub(super) struct Sidecars {
+    cached: moka::sync::Cache<String, Option<Arc<Metadata>>>,
+}
+
+impl Sidecars {
+    /// Create a new [`Sidecars`].
+    pub(super) fn new() -> Self {
+        Self {
+            cached: moka::sync::Cache::new(CACHED_SIDECARS),
+        }
+    }
+
+    /// Get the metadata of the object of `hash` stored at `path`, `None` when
+    /// it has no sidecar.
+    pub(super) async fn get(&self, hash: &str, path: &Path) -> Option<Arc<Metadata>> {
+        if let Some(metadata) = self.cached.get(hash) {
+            return metadata;
+        }
+
+        let metadata = match tokio::fs::read(sidecar_path(path)).await {
+            Ok(content) => match serde_json::from_slice(&content) {
+                Ok(metadata) => Some(Arc::new(metadata)),
+                Err(e) => {
+                    tracing::warn!("Invalid sidecar of {hash}: {e:?}");
+                    None
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                // Not cached, may be transient.
+                tracing::error!("Read sidecar of {hash} error: {e:?}");
+                return None;
+            }
+        };
+
+        self.cached.insert(hash.to_owned(), metadata.clone());
+
+        metadata
+    }
+
+    #[cfg(any(feature = "upstream", feature = "admin"))]
+    /// Write the sidecar of the object of `hash` stored at `path`.
+    pub(super) async fn write(
+        &self,
+        hash: &str,
+        path: &Path,
+        metadata: &Metadata,
+    ) -> io::Result<()> {
+        tokio::fs::write(sidecar_path(path), serde_json::to_vec_pretty(metadata)?).await?;
+
+        self.invalidate(hash);
+
+        Ok(())
+    }
+
+    #[inline]
+    /// Drop the in-memory copy of the sidecar of `hash`.
+    pub(super) fn invalidate(&self, hash: &str) {
+        self.cached.invalidate(hash);
+    }
+}
+
+#[inline]
+/// Path of the sidecar of the object stored at `path`.
+pub(super) fn sidecar_path(path: &Path) -> std::path::PathBuf {
+    path.with_extension("json")
+}
@@ -0,0 +1,165 @@
+//! Partially cached objects.
+//!
+//! Range requests may be cached before the whole object is. A partial object
+//! is stored as a sparse file of the full size under `partial/`, named after
+//! the hex SHA-256 of its key, with a [`ByteMap`] of the spans present kept in
+//! the index. Spans are filled in as they are fetched, and once the whole
+//! object is present, it's hashed and moved into the store like any other one.
+
+#[cfg(test)]
+mod tests;
+
+#[cfg(feature = "upstream")]
+use std::{io, path::Path};
+use std::{ops::Range, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "upstream")]
+use sha2::{Digest, Sha256};
+#[cfg(feature = "upstream")]
+use tokio::{fs::File, io::AsyncReadExt};
+
+#[cfg(feature = "upstream")]
+use crate::buf::Buf;
+
+/// Directory name of partial objects
+pub(super) const PARTIAL_DIR: &str = "partial";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+/// Byte spans present in a partial object, as sorted, non-overlapping and
+/// non-adjacent half-open ranges.
+pub(crate) struct ByteMap(Vec<(u64, u64)>);
+
+impl ByteMap {
+    #[cfg(feature = "upstream")]
+    /// Mark the given span as present, merging it with overlapping or adjacent
+    /// ones.
+    pub(crate) fn insert(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let (mut start, mut end) = (range.start, range.end);
+
+        self.0.retain(|&(span_start, span_end)| {
+            if span_end < start || span_start > end {
+                return true;
+            }
+
+            start = start.min(span_start);
+            end = end.max(span_end);
+
+            false
+        });
+
+        let idx = self
+            .0
+            .partition_point(|&(span_start, _)| span_start < start);
+        self.0.insert(idx, (start, end));
+    }
+
+    /// Spans within the given range which are not present.
+    pub(crate) fn missing(&self, range: Range<u64>) -> Vec<Range<u64>> {
+        let mut missing = Vec::new();
+        let mut cursor = range.start;
+
+        for &(start, end) in &self.0 {
+            if end <= cursor {
+                continue;
+            }
+
+            if start >= range.end {
+                break;
+            }
+
+            if start > cursor {
+                missing.push(cursor..start);
+            }
+
+            cursor = end;
+        }
+
+        if cursor < range.end {
+            missing.push(cursor..range.end);
+        }
+
+        missing
+    }
+
+    #[inline]
+    /// Whether the given range is fully present.
+    pub(crate) fn covers(&self, range: Range<u64>) -> bool {
+        self.missing(range).is_empty()
+    }
+
+    /// Total bytes present
+    pub(super) fn len(&self) -> u64 {
+        self.0.iter().map(|(start, end)| end - start).sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+/// Partial object entry of the index
+pub(super) struct PartialEntry {
+    /// Full object size
+    pub(super) size: u64,
+
+    /// Spans present
+    pub(super) ranges: ByteMap,
+
+    /// [`Index::clock`] of last access
+    pub(super) last_access: u64,
+
+    /// When first filled, in seconds since UNIX epoch.
+    pub(super) created_at: u64,
+
+    #[serde(skip)]
+    /// All spans present, the object being moved into the store.
+    ///
+    /// Kept in the index till then, so that no span is written to it nor it
+    /// is evicted meanwhile.
+    pub(super) finalizing: bool,
+
+    #[cfg(feature = "upstream")]
+    #[serde(skip)]
+    /// [`Index::clock`] when added, telling it from one of the same key added
+    /// back once removed.
+    pub(super) id: u64,
+}
+
+#[derive(Debug, Clone)]
+/// A partial object found by [`Cache::get_partial`](super::Cache::get_partial).
+pub(crate) struct PartialObject {
+    /// Path of the sparse file, as long as the full object.
+    pub path: PathBuf,
+
+    /// Full object size
+    pub size: u64,
+
+    /// Spans present, only these may be served.
+    pub ranges: ByteMap,
+}
+
+#[cfg(feature = "upstream")]
+/// Hex SHA-256 of the file content.
+pub(super) async fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path).await?;
+
+    let mut hasher = Sha256::new();
+    let mut chunk = Buf::chunk();
+
+    loop {
+        let read = file.read(&mut chunk).await?;
+
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&chunk[..read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
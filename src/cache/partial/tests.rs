@@ -0,0 +1,82 @@
+//! Spans of a [`ByteMap`] merged once inserted, and those missing of a range
+//! told.
+
+use std::ops::Range;
+
+use super::ByteMap;
+
+/// Spans of `map` missing within `range`, as pairs.
+fn spans_missing(map: &ByteMap, range: Range<u64>) -> Vec<(u64, u64)> {
+    map.missing(range)
+        .into_iter()
+        .map(|span| (span.start, span.end))
+        .collect()
+}
+
+#[cfg(feature = "upstream")]
+/// A map of the spans inserted in order.
+fn inserted(spans: &[(u64, u64)]) -> ByteMap {
+    let mut map = ByteMap::default();
+
+    for &(start, end) in spans {
+        map.insert(start..end);
+    }
+
+    map
+}
+
+#[cfg(feature = "upstream")]
+#[test]
+/// Spans kept sorted, merged with overlapping or adjacent ones, and empty
+/// ones ignored.
+fn insert() {
+    assert_eq!(inserted(&[(10, 20), (0, 5)]).0, [(0, 5), (10, 20)]);
+    assert_eq!(inserted(&[(0, 5), (5, 10)]).0, [(0, 10)]);
+    assert_eq!(inserted(&[(5, 10), (0, 5)]).0, [(0, 10)]);
+    assert_eq!(inserted(&[(0, 10), (5, 15)]).0, [(0, 15)]);
+    assert_eq!(inserted(&[(0, 10), (2, 8)]).0, [(0, 10)]);
+    assert_eq!(inserted(&[(2, 8), (0, 10)]).0, [(0, 10)]);
+    assert_eq!(
+        inserted(&[(0, 2), (4, 6), (8, 10), (20, 30), (1, 9)]).0,
+        [(0, 10), (20, 30)]
+    );
+    assert_eq!(inserted(&[(0, 5), (7, 7), (6, 6)]).0, [(0, 5)]);
+    assert_eq!(inserted(&[(3, 3)]), ByteMap::default());
+}
+
+#[test]
+/// Bytes present summed.
+fn len() {
+    assert_eq!(ByteMap::default().len(), 0);
+    assert_eq!(ByteMap(vec![(0, 5), (10, 20)]).len(), 15);
+}
+
+#[test]
+/// Spans missing within the range, clipped to it.
+fn missing() {
+    let map = ByteMap(vec![(10, 20), (30, 40)]);
+
+    assert_eq!(spans_missing(&map, 0..50), [(0, 10), (20, 30), (40, 50)]);
+    assert_eq!(spans_missing(&map, 10..20), []);
+    assert_eq!(spans_missing(&map, 15..35), [(20, 30)]);
+    assert_eq!(spans_missing(&map, 12..18), []);
+    assert_eq!(spans_missing(&map, 0..5), [(0, 5)]);
+    assert_eq!(spans_missing(&map, 45..50), [(45, 50)]);
+    assert_eq!(spans_missing(&map, 20..30), [(20, 30)]);
+    assert_eq!(spans_missing(&map, 5..5), []);
+    assert_eq!(spans_missing(&ByteMap::default(), 0..8), [(0, 8)]);
+}
+
+#[test]
+/// Ranges covered only if present in full.
+fn covers() {
+    let map = ByteMap(vec![(10, 20), (30, 40)]);
+
+    assert!(map.covers(10..20));
+    assert!(map.covers(12..18));
+    assert!(map.covers(35..35));
+    assert!(!map.covers(5..15));
+    assert!(!map.covers(15..35));
+    assert!(!map.covers(10..21));
+    assert!(!ByteMap::default().covers(0..1));
+}
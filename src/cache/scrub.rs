@@ -0,0 +1,101 @@
+//! Background integrity verification of stored objects.
+//!
+//! Objects are content-addressed, so re-hashing one and comparing against its
+//! name tells whether it has rotted. Corrupt objects are moved into
+//! `quarantine/` and every key referencing them is dropped, so that they never
+//! get served.
+
+use std::{
+    io::{self, Read},
+    path::Path,
+    time::Duration,
+};
+
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+
+use super::Cache;
+use crate::{buf::Buf, config::ScrubConfig};
+
+/// Quarantine directory name
+pub(super) const QUARANTINE_DIR: &str = "quarantine";
+
+/// Spawn the scrubber, verifying all stored objects every
+/// [`ScrubConfig::interval`].
+pub(super) fn spawn(cache: &'static Cache, config: ScrubConfig) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(config.interval)).await;
+
+            let objects: Vec<_> = cache
+                .index()
+                .objects
+                .iter()
+                .map(|(hash, object)| (hash.clone(), cache.stored_path(object.root, hash)))
+                .collect();
+
+            tracing::info!("Scrubbing {} cached objects", objects.len());
+
+            let mut corrupt = 0usize;
+
+            for (hash, path) in objects {
+                match verify(&path, &hash, config.rate).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        corrupt += 1;
+
+                        // Renames the file
+                        if let Err(e) =
+                            tokio::task::spawn_blocking(move || cache.quarantine(&hash)).await
+                        {
+                            tracing::error!("Quarantine task error: {e:?}");
+                        }
+                    }
+                    // Evicted meanwhile
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => tracing::error!("Verify cached object {hash} error: {e:?}"),
+                }
+            }
+
+            tracing::info!("Scrubbing done, {corrupt} corrupt objects quarantined");
+        }
+    });
+}
+
+/// Re-hash the object at `path` and compare against its hash, reading at
+/// most `rate` bytes per second to not compete with serving.
+///
+/// Each chunk is read and hashed on tokio's blocking pool.
+async fn verify(path: &Path, hash: &str, rate: u64) -> io::Result<bool> {
+    let file = File::open(path).await?.into_std().await;
+
+    let mut state = (file, Sha256::new(), Buf::chunk());
+
+    loop {
+        let (read, returned) = tokio::task::spawn_blocking(move || {
+            let (file, hasher, chunk) = &mut state;
+
+            let read = file.read(&mut chunk[..]);
+
+            if let Ok(read) = read {
+                hasher.update(&chunk[..read]);
+            }
+
+            (read, state)
+        })
+        .await
+        .map_err(io::Error::other)?;
+
+        state = returned;
+
+        let read = read?;
+
+        if read == 0 {
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_secs_f64(read as f64 / rate.max(1) as f64)).await;
+    }
+
+    Ok(format!("{:x}", state.1.finalize()) == hash)
+}
@@ -0,0 +1,42 @@
+//! Watching storage roots for external changes.
+//!
+//! An object replaced or removed behind our back, e.g. by an out-of-band
+//! downloader, would otherwise keep being served with the length recorded in
+//! the index or held in memory, breaking range math.
+
+use std::sync::OnceLock;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::{Cache, OBJECTS_DIR};
+
+/// The watcher, kept alive for the lifetime of the process.
+static WATCHER: OnceLock<RecommendedWatcher> = OnceLock::new();
+
+/// Start watching the objects directory of all storage roots.
+pub(super) fn watch(cache: &'static Cache) -> notify::Result<()> {
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+            Ok(Event {
+                kind: EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_),
+                paths,
+                ..
+            }) => {
+                for path in &paths {
+                    cache.revalidate(path);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::error!("Watch cache storage error: {e:?}"),
+        })?;
+
+    for root in &cache.roots {
+        watcher.watch(&root.dir.join(OBJECTS_DIR), RecursiveMode::Recursive)?;
+    }
+
+    if WATCHER.set(watcher).is_err() {
+        tracing::warn!("Cache storage roots are being watched already");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Client address of the request being handled.
+//!
+//! That is the peer, or if it is a trusted proxy, see
+//! [`Config::trusted_proxies`], the address told by `Forwarded` or
+//! `X-Forwarded-For`, see [`accept`]. Taken by the access log and usage
+//! accounting; the headers are ignored if not from a trusted proxy.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    cell::Cell,
+    net::{IpAddr, SocketAddr},
+};
+
+use http::{HeaderName, header::FORWARDED};
+
+use crate::{config::Config, listener, proto};
+
+/// Header `X-Forwarded-For`.
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+tokio::task_local! {
+    /// Client of the request being handled, see [`scope`].
+    static CURRENT: Cell<IpAddr>;
+}
+
+/// Run the handler `future` of a request from `peer`.
+pub(crate) async fn scope<F>(peer: IpAddr, future: F) -> F::Output
+where
+    F: Future,
+{
+    CURRENT.scope(Cell::new(peer), future).await
+}
+
+/// Take the client told by `request` if from a trusted proxy.
+///
+/// Proxies append the address they are connected by, so the chain is walked
+/// from the last one, while told by a trusted proxy.
+pub(crate) fn accept(request: &proto::Request) {
+    let _ = CURRENT.try_with(|current| {
+        let config = Config::current();
+
+        let trusted = |ip: IpAddr| {
+            config
+                .trusted_proxies
+                .iter()
+                .any(|range| range.contains(ip))
+        };
+
+        let peer = current.get();
+
+        if !trusted(peer) {
+            return;
+        }
+
+        let client = walk(peer, forwarded_for(request), trusted);
+
+        if client != peer {
+            current.set(client);
+
+            tracing::Span::current().record("client", tracing::field::display(client));
+        }
+    });
+}
+
+/// Walk the addresses told, `hops`, from the last one while told by a
+/// `trusted` proxy, starting from `peer`.
+fn walk<F>(peer: IpAddr, hops: Vec<Option<IpAddr>>, trusted: F) -> IpAddr
+where
+    F: Fn(IpAddr) -> bool,
+{
+    let mut client = peer;
+
+    for hop in hops.into_iter().rev() {
+        if !trusted(client) {
+            break;
+        }
+
+        // Unknown or obfuscated, the client cannot be told further
+        let Some(hop) = hop else {
+            break;
+        };
+
+        client = hop;
+    }
+
+    client
+}
+
+#[inline]
+/// Client of the request being handled, if any.
+pub(crate) fn current() -> Option<IpAddr> {
+    CURRENT.try_with(Cell::get).ok()
+}
+
+/// Addresses told by `Forwarded`, or `X-Forwarded-For` if not set, in order,
+/// `None` for those unknown or obfuscated.
+fn forwarded_for(request: &proto::Request) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        request
+            .headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+    };
+
+    if request.headers.contains_key(FORWARDED) {
+        // Like `for=192.0.2.60;proto=http, for="[2001:db8:cafe::17]:4711"`
+        values(FORWARDED)
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node.trim_matches('"')))
+            })
+            .collect()
+    } else {
+        values(X_FORWARDED_FOR).map(parse_node).collect()
+    }
+}
+
+/// Parse a node, i.e. an address with the port or not, IPv6 ones in brackets
+/// if with the port.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let ip = node
+        .parse()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|node| node.strip_suffix(']'))
+                .and_then(|ip| ip.parse().ok())
+        })?;
+
+    Some(listener::canonical(SocketAddr::new(ip, 0)).ip())
+}
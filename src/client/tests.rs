@@ -0,0 +1,83 @@
+//! Addresses told by `Forwarded` or `X-Forwarded-For` parsed, and walked
+//! from the last one while told by a trusted proxy.
+
+use std::net::IpAddr;
+
+use super::{forwarded_for, parse_node, walk};
+use crate::proto;
+
+/// A request of the header lines given.
+async fn request(headers: &str) -> proto::Request {
+    let head = format!("GET / HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n");
+
+    proto::Request::handle(&mut head.as_bytes())
+        .await
+        .expect("Parsed")
+        .expect("Request")
+}
+
+/// Parse `ip`.
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().expect("Address")
+}
+
+#[test]
+/// Nodes with the port or not, IPv6 ones in brackets, IPv4-mapped ones
+/// taken as IPv4, and obfuscated ones not parsed.
+fn nodes() {
+    assert_eq!(parse_node("192.0.2.1"), Some(ip("192.0.2.1")));
+    assert_eq!(parse_node("192.0.2.1:4711"), Some(ip("192.0.2.1")));
+    assert_eq!(parse_node("2001:db8::17"), Some(ip("2001:db8::17")));
+    assert_eq!(parse_node("[2001:db8::17]"), Some(ip("2001:db8::17")));
+    assert_eq!(parse_node("[2001:db8::17]:4711"), Some(ip("2001:db8::17")));
+    assert_eq!(parse_node("::ffff:192.0.2.1"), Some(ip("192.0.2.1")));
+    assert_eq!(parse_node("unknown"), None);
+    assert_eq!(parse_node("_hidden"), None);
+}
+
+#[tokio::test]
+/// `Forwarded` elements of any parameters order and quoting, across header
+/// lines, and `X-Forwarded-For` only if `Forwarded` not sent.
+async fn headers() {
+    let forwarded = request(
+        "Forwarded: for=192.0.2.60;proto=http, For=\"[2001:db8:cafe::17]:4711\"\r\nForwarded: \
+         proto=https;for=unknown\r\nX-Forwarded-For: 198.51.100.1\r\n",
+    )
+    .await;
+
+    assert_eq!(
+        forwarded_for(&forwarded),
+        [Some(ip("192.0.2.60")), Some(ip("2001:db8:cafe::17")), None]
+    );
+
+    let x_forwarded_for = request("X-Forwarded-For: 198.51.100.1, 192.0.2.1:80, junk\r\n").await;
+
+    assert_eq!(
+        forwarded_for(&x_forwarded_for),
+        [Some(ip("198.51.100.1")), Some(ip("192.0.2.1")), None]
+    );
+}
+
+#[test]
+/// Walked while told by a trusted proxy, stopped at the first one not, or at
+/// one unknown.
+fn walked() {
+    let trusted = |ip: IpAddr| ip.to_string().starts_with("10.");
+
+    let hops = vec![
+        Some(ip("203.0.113.9")),
+        Some(ip("198.51.100.1")),
+        Some(ip("10.0.0.2")),
+    ];
+
+    // Spoofed by the client before the first untrusted hop
+    assert_eq!(
+        walk(ip("10.0.0.1"), hops.clone(), trusted),
+        ip("198.51.100.1")
+    );
+
+    let hops = vec![Some(ip("198.51.100.1")), None, Some(ip("10.0.0.2"))];
+    assert_eq!(walk(ip("10.0.0.1"), hops, trusted), ip("10.0.0.2"));
+
+    assert_eq!(walk(ip("10.0.0.1"), Vec::new(), trusted), ip("10.0.0.1"));
+}
@@ -0,0 +1,92 @@
+//! Address ranges parsed from CIDR notation or single addresses, matched by
+//! prefix, and filters of them.
+
+use std::net::IpAddr;
+
+use super::{IpFilterConfig, IpRange};
+
+/// Parse `range`.
+fn range(range: &str) -> IpRange {
+    IpRange::try_from(range.to_owned()).expect("IP range")
+}
+
+/// Parse `ip`.
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().expect("Address")
+}
+
+#[test]
+/// Addresses within the prefix matched only, of the same family, IPv4-mapped
+/// ones taken as IPv4.
+fn contains() {
+    let v4 = range("192.168.1.0/24");
+    assert!(v4.contains(ip("192.168.1.0")));
+    assert!(v4.contains(ip("192.168.1.255")));
+    assert!(!v4.contains(ip("192.168.2.1")));
+    assert!(v4.contains(ip("::ffff:192.168.1.7")));
+    assert!(!v4.contains(ip("::1")));
+
+    let v6 = range("2001:db8::/32");
+    assert!(v6.contains(ip("2001:db8:ffff::1")));
+    assert!(!v6.contains(ip("2001:db9::1")));
+    assert!(!v6.contains(ip("192.168.1.1")));
+
+    // Host bits set are ignored
+    assert!(range("10.1.2.3/8").contains(ip("10.200.0.1")));
+}
+
+#[test]
+/// Single addresses, and the prefix lengths at the bounds.
+fn prefix_lengths() {
+    let single = range("192.0.2.1");
+    assert!(single.contains(ip("192.0.2.1")));
+    assert!(!single.contains(ip("192.0.2.2")));
+    assert_eq!(single, range("192.0.2.1/32"));
+
+    assert!(range("0.0.0.0/0").contains(ip("203.0.113.1")));
+    assert!(!range("0.0.0.0/0").contains(ip("2001:db8::1")));
+    assert!(range("::/0").contains(ip("2001:db8::1")));
+    assert!(range("2001:db8::1/128").contains(ip("2001:db8::1")));
+    assert!(!range("2001:db8::1/128").contains(ip("2001:db8::2")));
+}
+
+#[test]
+/// Malformed ranges, or prefixes too long for the family, refused.
+fn invalid() {
+    for invalid in [
+        "192.168.1.0/33",
+        "2001:db8::/129",
+        "192.168.1.0/",
+        "192.168.1.0/-1",
+        "192.168.1/24",
+        "example.com",
+        "",
+    ] {
+        assert!(
+            IpRange::try_from(invalid.to_owned()).is_err(),
+            "{invalid:?}"
+        );
+    }
+}
+
+#[test]
+/// Denied ranges taking precedence over allowed ones, all allowed if none.
+fn filter() {
+    let filter = IpFilterConfig {
+        allow: vec![range("192.168.1.0/24"), range("10.8.0.0/24")],
+        deny: vec![range("192.168.1.13")],
+    };
+
+    assert!(filter.allows(ip("192.168.1.7")));
+    assert!(filter.allows(ip("10.8.0.2")));
+    assert!(!filter.allows(ip("192.168.1.13")));
+    assert!(!filter.allows(ip("203.0.113.1")));
+
+    let deny_only = IpFilterConfig {
+        allow: Vec::new(),
+        deny: vec![range("203.0.113.0/24")],
+    };
+
+    assert!(deny_only.allows(ip("192.0.2.1")));
+    assert!(!deny_only.allows(ip("203.0.113.1")));
+}
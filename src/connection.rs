@@ -0,0 +1,465 @@
+//! Live connections, listed and closed by the admin API.
+//!
+//! Each connection is registered while open, see [`register`], with the
+//! request being handled on it, see [`request`], and body bytes sent so far,
+//! counted as sent, see [`transfer::count`](crate::transfer::count).
+//!
+//! Connections past the limit configured, see [`ConnectionsConfig`], are
+//! rejected or wait for accepting, see [`acquire`]. So are those past the
+//! limit per client address, rejected.
+
+#[cfg(feature = "admin")]
+use std::time::Instant;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use http::{
+    HeaderValue, StatusCode,
+    header::{CONNECTION, RETRY_AFTER},
+};
+#[cfg(feature = "admin")]
+use serde::Serialize;
+use tokio::{
+    net::TcpStream,
+    sync::{Notify, OwnedSemaphorePermit, Semaphore},
+};
+
+use crate::{
+    config::{Config, ConnectionsConfig, OverLimit},
+    metrics, proto,
+};
+
+/// Time to write the response of rejecting a connection, see [`reject`].
+const REJECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connections open, by ID.
+static CONNECTIONS: LazyLock<Mutex<HashMap<u64, Arc<Connection>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// ID of the next connection.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Connections open by client address, of those limited, see
+/// [`ConnectionsConfig::max_per_ip`].
+static PER_IP: LazyLock<Mutex<HashMap<IpAddr, usize>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Connection slots, see [`slots`].
+static SLOTS: Mutex<Option<Slots>> = Mutex::new(None);
+
+tokio::task_local! {
+    /// The connection being handled, see [`Registered::scope`].
+    static CURRENT: Arc<Connection>;
+}
+
+#[derive(Debug)]
+/// A connection open.
+struct Connection {
+    id: u64,
+
+    #[cfg(feature = "admin")]
+    peer: SocketAddr,
+
+    #[cfg(feature = "admin")]
+    opened: Instant,
+
+    /// Body bytes sent
+    sent: AtomicU64,
+
+    /// The request being handled, if any
+    request: Mutex<Option<Request>>,
+
+    /// Notified to close the connection, see [`close`].
+    close: Notify,
+}
+
+#[derive(Debug)]
+/// A request being handled on a [`Connection`].
+struct Request {
+    method: String,
+
+    /// The query left out
+    path: String,
+
+    #[cfg(feature = "admin")]
+    started: Instant,
+
+    #[cfg(feature = "admin")]
+    /// [`Connection::sent`] when started
+    sent_before: u64,
+}
+
+#[derive(Debug, Default)]
+/// A connection slot taken before accepting, see [`acquire`].
+pub(crate) struct Slot(Option<OwnedSemaphorePermit>);
+
+#[derive(Debug)]
+/// Semaphore of connection slots, see [`slots`].
+struct Slots {
+    /// Number configured
+    max: usize,
+
+    semaphore: Arc<Semaphore>,
+
+    /// Slots to forget once released, taken when `max` shrank.
+    owed: usize,
+}
+
+#[derive(Debug)]
+/// A connection registered, unregistered once dropped, see [`register`].
+pub(crate) struct Registered {
+    connection: Arc<Connection>,
+
+    /// Counted in [`PER_IP`], if limited
+    limited_ip: Option<IpAddr>,
+
+    /// Freed once dropped
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+impl Registered {
+    /// Run `future` of handling the connection, so that the requests and
+    /// bytes sent are known.
+    pub(crate) fn scope<F>(&self, future: F) -> impl Future<Output = F::Output> + use<F>
+    where
+        F: Future,
+    {
+        CURRENT.scope(self.connection.clone(), future)
+    }
+
+    /// Wait until asked to close, see [`close`].
+    pub(crate) async fn closed(&self) {
+        self.connection.close.notified().await;
+    }
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        CONNECTIONS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.connection.id);
+
+        if let Some(ip) = self.limited_ip {
+            let mut per_ip = PER_IP.lock().unwrap_or_else(|e| e.into_inner());
+
+            if let Some(count) = per_ip.get_mut(&ip) {
+                *count -= 1;
+
+                if *count == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "admin")]
+#[derive(Debug)]
+#[derive(Serialize)]
+/// A connection open, see [`list`].
+pub(crate) struct ConnectionInfo {
+    id: u64,
+
+    peer: SocketAddr,
+
+    /// Seconds since opened
+    age: f64,
+
+    /// Body bytes sent
+    bytes_sent: u64,
+
+    /// The request being handled, `None` when idle
+    request: Option<RequestInfo>,
+}
+
+#[cfg(feature = "admin")]
+#[derive(Debug)]
+#[derive(Serialize)]
+/// A request being handled, see [`ConnectionInfo`].
+pub(crate) struct RequestInfo {
+    method: String,
+
+    path: String,
+
+    /// Seconds since started
+    duration: f64,
+
+    /// Body bytes sent
+    bytes_sent: u64,
+
+    /// Body bytes sent per second
+    throughput: u64,
+}
+
+/// Wait for a free connection slot before accepting, if limited and
+/// configured to backpressure, see [`OverLimit::Backpressure`].
+pub(crate) async fn acquire() -> Slot {
+    let config = Config::current();
+
+    match config.connections.max {
+        Some(max) if config.connections.over_limit == OverLimit::Backpressure => {
+            Slot(slots(max).acquire_owned().await.ok())
+        }
+        _ => Slot::default(),
+    }
+}
+
+/// Register a connection from `peer`, in the `slot` taken if any.
+///
+/// Returns `None` if either limit is reached, see [`reject`].
+pub(crate) fn register(peer: SocketAddr, slot: Slot) -> Option<Registered> {
+    let config = Config::current();
+
+    let slot = match (slot.0, config.connections.max) {
+        (Some(slot), _) => Some(slot),
+        (None, Some(max)) => Some(slots(max).try_acquire_owned().ok()?),
+        (None, None) => None,
+    };
+
+    let limited_ip = match config.connections.max_per_ip {
+        Some(max_per_ip) => count_ip(&config.connections, peer.ip(), max_per_ip)?,
+        None => None,
+    };
+
+    let connection = Arc::new(Connection {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        #[cfg(feature = "admin")]
+        peer,
+        #[cfg(feature = "admin")]
+        opened: Instant::now(),
+        sent: AtomicU64::new(0),
+        request: Mutex::new(None),
+        close: Notify::new(),
+    });
+
+    CONNECTIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(connection.id, connection.clone());
+
+    Some(Registered {
+        connection,
+        limited_ip,
+        _slot: slot,
+    })
+}
+
+/// Count a connection from `ip` unless unlimited, returning the address
+/// counted if any, or `None` if `max_per_ip` is reached.
+fn count_ip(config: &ConnectionsConfig, ip: IpAddr, max_per_ip: usize) -> Option<Option<IpAddr>> {
+    if config.unlimited_ips.iter().any(|range| range.contains(ip)) {
+        return Some(None);
+    }
+
+    let mut per_ip = PER_IP.lock().unwrap_or_else(|e| e.into_inner());
+    let count = per_ip.entry(ip).or_default();
+
+    if *count >= max_per_ip {
+        if *count == 0 {
+            per_ip.remove(&ip);
+        }
+
+        return None;
+    }
+
+    *count += 1;
+
+    Some(Some(ip))
+}
+
+/// Reject a connection past the limit, with `503 Service Unavailable` and
+/// `Retry-After`.
+pub(crate) async fn reject(mut tcp_stream: TcpStream, config: &ConnectionsConfig) {
+    metrics::connection_rejected();
+
+    let mut response = proto::Response::status(StatusCode::SERVICE_UNAVAILABLE).with_body(b"");
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(config.retry_after));
+    response
+        .headers_mut()
+        .insert(CONNECTION, HeaderValue::from_static("close"));
+
+    match tokio::time::timeout(REJECT_TIMEOUT, response.write_to_stream(&mut tcp_stream)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::debug!("Write rejection error: {e:?}"),
+        Err(_) => tracing::debug!("Write rejection timed out"),
+    }
+}
+
+/// The semaphore of `max` slots, resized once the config changed.
+///
+/// Slots taken beyond a smaller `max` are forgotten once released, by the
+/// calls following.
+fn slots(max: usize) -> Arc<Semaphore> {
+    let max = max.max(1);
+
+    let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+
+    let slots = slots.get_or_insert_with(|| Slots {
+        max,
+        semaphore: Arc::new(Semaphore::new(max)),
+        owed: 0,
+    });
+
+    if max > slots.max {
+        let grown = max - slots.max;
+        let repaid = grown.min(slots.owed);
+
+        slots.owed -= repaid;
+        slots.semaphore.add_permits(grown - repaid);
+    } else {
+        slots.owed += slots.max - max;
+    }
+    slots.max = max;
+
+    if slots.owed > 0 {
+        slots.owed -= slots.semaphore.forget_permits(slots.owed);
+    }
+
+    slots.semaphore.clone()
+}
+
+/// Set the request being handled on the current connection.
+pub(crate) fn request(request: &proto::Request) {
+    let _ = CURRENT.try_with(|connection| {
+        *connection.request.lock().unwrap_or_else(|e| e.into_inner()) = Some(Request {
+            method: request.method.to_string(),
+            path: request.request_uri.path().as_str().to_owned(),
+            #[cfg(feature = "admin")]
+            started: Instant::now(),
+            #[cfg(feature = "admin")]
+            sent_before: connection.sent.load(Ordering::Relaxed),
+        });
+    });
+}
+
+/// Mark the request being handled on the current connection done.
+pub(crate) fn done() {
+    let _ = CURRENT.try_with(|connection| {
+        connection
+            .request
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+    });
+}
+
+/// Method and path of the request being handled on the current connection,
+/// if any.
+pub(crate) fn current_request() -> Option<(String, String)> {
+    CURRENT
+        .try_with(|connection| {
+            connection
+                .request
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_ref()
+                .map(|request| (request.method.clone(), request.path.clone()))
+        })
+        .ok()
+        .flatten()
+}
+
+#[inline]
+/// Count `length` body bytes sent on the current connection.
+pub(crate) fn sent(length: u64) {
+    let _ = CURRENT.try_with(|connection| connection.sent.fetch_add(length, Ordering::Relaxed));
+}
+
+#[cfg(feature = "admin")]
+/// Connections open, oldest first.
+pub(crate) fn list() -> Vec<ConnectionInfo> {
+    let mut connections: Vec<_> = CONNECTIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .values()
+        .cloned()
+        .collect();
+    connections.sort_unstable_by_key(|connection| connection.id);
+
+    connections
+        .iter()
+        .map(|connection| {
+            let bytes_sent = connection.sent.load(Ordering::Relaxed);
+
+            let request = connection
+                .request
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_ref()
+                .map(|request| {
+                    let duration = request.started.elapsed().as_secs_f64();
+                    let request_sent = bytes_sent.saturating_sub(request.sent_before);
+
+                    RequestInfo {
+                        method: request.method.clone(),
+                        path: request.path.clone(),
+                        duration,
+                        bytes_sent: request_sent,
+                        throughput: if duration > 0.0 {
+                            (request_sent as f64 / duration) as u64
+                        } else {
+                            0
+                        },
+                    }
+                });
+
+            ConnectionInfo {
+                id: connection.id,
+                peer: connection.peer,
+                age: connection.opened.elapsed().as_secs_f64(),
+                bytes_sent,
+                request,
+            }
+        })
+        .collect()
+}
+
+/// Number of connections open.
+pub(crate) fn count() -> usize {
+    CONNECTIONS.lock().unwrap_or_else(|e| e.into_inner()).len()
+}
+
+/// Wait until all connections are closed, e.g. for exiting once upgraded, see
+/// [`upgrade`](crate::upgrade).
+pub(crate) async fn drained() {
+    for waited in 0_u64.. {
+        let open = count();
+
+        if open == 0 {
+            return;
+        }
+
+        if waited % 60 == 0 {
+            tracing::info!("Draining, {open} connections open");
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+#[cfg(feature = "admin")]
+/// Close the connection of `id`, returning whether found.
+pub(crate) fn close(id: u64) -> bool {
+    let Some(connection) = CONNECTIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&id)
+        .cloned()
+    else {
+        return false;
+    };
+
+    connection.close.notify_one();
+
+    true
+}
@@ -0,0 +1,246 @@
+//! Credentials of requests to the playurl API, see [`CredentialsConfig`].
+//!
+//! Cookies of a logged in web session, i.e. `SESSDATA` and so on, are sent
+//! along with the playurl API requests, see [`cookie`], and the access key of
+//! the app appended to their queries, see [`access_key`], so that higher
+//! qualities (1080P+, 4K) are given as to the account.
+//!
+//! Credentials may be kept in a file of their own, refused if accessible by
+//! group or others, and are reloaded from it every
+//! [`CredentialsConfig::check_interval`], when the login is checked as well
+//! against the nav API. Credentials found stale, i.e. `SESSDATA` expired or
+//! the API telling not logged in, are logged loudly.
+
+use std::{
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use arc_swap::ArcSwapOption;
+use http::HeaderValue;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    config::{Config, CredentialsConfig},
+    playurl, utils,
+};
+
+/// How long before `SESSDATA` expires to warn.
+const EXPIRY_WARNING: u64 = 7 * 24 * 60 * 60;
+
+/// Credentials loaded, see [`load`].
+static CREDENTIALS: ArcSwapOption<Credentials> = ArcSwapOption::const_empty();
+
+/// Whether the credentials were found stale by the last check.
+static STALE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Default)]
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Credentials, as given by [`CredentialsConfig`] or its file.
+struct Credentials {
+    /// `SESSDATA` cookie
+    sessdata: Option<String>,
+
+    /// `bili_jct` cookie, i.e. the CSRF token
+    bili_jct: Option<String>,
+
+    /// `DedeUserID` cookie, i.e. the UID
+    dede_user_id: Option<String>,
+
+    /// `access_key` of the app
+    access_key: Option<String>,
+}
+
+impl Credentials {
+    /// When `SESSDATA` expires, in seconds since UNIX epoch, as it tells
+    /// like `{hex}%2C{expires}%2C{...}`.
+    fn expires(&self) -> Option<u64> {
+        self.sessdata
+            .as_deref()?
+            .replace("%2C", ",")
+            .replace("%2c", ",")
+            .split(',')
+            .nth(1)?
+            .parse()
+            .ok()
+    }
+}
+
+/// Load the credentials configured, see [`load`], and spawn the checker if
+/// configured, see [`CredentialsConfig::check_interval`].
+pub(crate) fn init(config: &CredentialsConfig) -> Result<()> {
+    load(config)?;
+
+    if config.check_interval > 0 {
+        spawn();
+    }
+
+    Ok(())
+}
+
+/// Load the credentials of the current config again, e.g. once the file
+/// updated, keeping the loaded ones if failed.
+pub(crate) fn reload() {
+    let config = Config::current();
+
+    if let Some(credentials_config) = config
+        .playurl
+        .as_ref()
+        .and_then(|playurl_config| playurl_config.credentials.as_ref())
+    {
+        if let Err(e) = load(credentials_config) {
+            tracing::error!("Reload credentials error, keeping the loaded ones: {e:#}");
+        }
+    }
+}
+
+/// `Cookie` header of the credentials loaded, if any.
+pub(crate) fn cookie() -> Option<HeaderValue> {
+    let credentials = CREDENTIALS.load_full()?;
+
+    let cookie = [
+        ("SESSDATA", &credentials.sessdata),
+        ("bili_jct", &credentials.bili_jct),
+        ("DedeUserID", &credentials.dede_user_id),
+    ]
+    .iter()
+    .filter_map(|(name, value)| Some(format!("{name}={}", value.as_deref()?)))
+    .collect::<Vec<_>>()
+    .join("; ");
+
+    if cookie.is_empty() {
+        return None;
+    }
+
+    HeaderValue::from_str(&cookie)
+        .inspect_err(|e| tracing::error!("Invalid credentials cookie: {e}"))
+        .ok()
+}
+
+/// Access key of the app of the credentials loaded, if any.
+pub(crate) fn access_key() -> Option<String> {
+    CREDENTIALS.load().as_ref()?.access_key.clone()
+}
+
+/// Load the credentials of `config`, those given inline taking precedence
+/// over those of [`CredentialsConfig::file`].
+fn load(config: &CredentialsConfig) -> Result<()> {
+    let mut credentials = match &config.file {
+        Some(path) => read(path)?,
+        None => Credentials::default(),
+    };
+
+    for (inline, value) in [
+        (&config.sessdata, &mut credentials.sessdata),
+        (&config.bili_jct, &mut credentials.bili_jct),
+        (&config.dede_user_id, &mut credentials.dede_user_id),
+        (&config.access_key, &mut credentials.access_key),
+    ] {
+        if let Some(inline) = inline {
+            *value = Some(inline.to_string());
+        }
+    }
+
+    if let Some(expires) = credentials.expires() {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+
+        if expires <= now {
+            tracing::error!(
+                "Credentials stale: SESSDATA expired {} hours ago, higher qualities will not be \
+                 given until it is updated",
+                (now - expires) / 3600
+            );
+        } else if expires - now <= EXPIRY_WARNING {
+            tracing::warn!(
+                "SESSDATA expires in {} hours, update it in time",
+                (expires - now) / 3600
+            );
+        } else {
+            tracing::debug!("SESSDATA expires in {} hours", (expires - now) / 3600);
+        }
+    }
+
+    CREDENTIALS.store(Some(Arc::new(credentials)));
+
+    Ok(())
+}
+
+/// Read the credentials file at `path`, refused if accessible by group or
+/// others.
+fn read(path: &Path) -> Result<Credentials> {
+    utils::check_private(path, "Credentials file")?;
+
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Read credentials file {}", path.display()))?;
+
+    toml::from_str(&content).with_context(|| format!("Parse credentials file {}", path.display()))
+}
+
+/// Spawn the checker, reloading the credentials and checking the login every
+/// [`CredentialsConfig::check_interval`].
+fn spawn() {
+    tokio::spawn(async move {
+        // Loaded already
+        let mut reload = false;
+
+        loop {
+            let config = Config::current();
+
+            let Some((playurl_config, credentials_config)) =
+                config.playurl.as_ref().and_then(|playurl_config| {
+                    Some((playurl_config, playurl_config.credentials.as_ref()?))
+                })
+            else {
+                break;
+            };
+
+            if credentials_config.check_interval == 0 {
+                break;
+            }
+
+            if reload {
+                if let Err(e) = load(credentials_config) {
+                    tracing::error!("Reload credentials error, keeping the loaded ones: {e:#}");
+                }
+            }
+            reload = true;
+
+            match playurl::forward(playurl_config, playurl::NAV_PATH).await {
+                Ok(response) => check(&response),
+                Err(e) => tracing::warn!("Check credentials error: {e:#}"),
+            }
+
+            tokio::time::sleep(Duration::from_secs(credentials_config.check_interval)).await;
+        }
+    });
+}
+
+/// Check the login by the nav API `response`, logging once found stale, or
+/// valid again.
+fn check(response: &Value) {
+    match response.get("code").and_then(Value::as_i64) {
+        Some(0) => {
+            if STALE.swap(false, Ordering::Relaxed) {
+                tracing::info!("Credentials valid again");
+            }
+        }
+        Some(-101) => {
+            if !STALE.swap(true, Ordering::Relaxed) {
+                tracing::error!(
+                    "Credentials stale: not logged in by the API, higher qualities will not be \
+                     given until SESSDATA is updated"
+                );
+            }
+        }
+        code => tracing::warn!("Check credentials: nav API responded code {code:?}"),
+    }
+}
@@ -0,0 +1,241 @@
+//! Run modes, for init systems and process supervisors.
+//!
+//! The server runs in the foreground by default, as expected by systemd and
+//! supervisors, or detached in the background if asked, see [`daemonize`].
+//! The PID is written to a file if asked, see [`PidFile`], so that classic
+//! init scripts can signal the server.
+//!
+//! SIGINT and SIGTERM shut the server down gracefully, see [`shutdown`].
+//! SIGHUP reloads the config and certificates and reopens log files, and
+//! SIGUSR1 logs runtime stats, see [`handle_signals`]; ignored where not
+//! supported.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+
+#[cfg(all(unix, feature = "admin"))]
+use crate::api_key;
+#[cfg(all(unix, feature = "playurl"))]
+use crate::credentials;
+#[cfg(all(unix, feature = "admin", feature = "tls"))]
+use crate::service::admin;
+#[cfg(all(unix, feature = "tls"))]
+use crate::tls;
+use crate::upgrade;
+#[cfg(unix)]
+use crate::{access_log, cache::Cache, config::Config, connection, logging};
+
+#[derive(Debug)]
+/// The PID file written, removed once dropped, i.e. on exit.
+pub(crate) struct PidFile(PathBuf);
+
+impl PidFile {
+    /// Write the PID of the process to `path`, refused if the one of another
+    /// process running is written there, except the one upgraded from, see
+    /// [`upgrade`].
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                if let Ok(pid) = content.trim().parse::<i32>() {
+                    let upgrading_from = upgrade::upgrading_from()
+                        .and_then(|upgrading_from| i32::try_from(upgrading_from).ok())
+                        == Some(pid);
+
+                    if pid > 0 && !upgrading_from && is_running(pid) {
+                        bail!(
+                            "PID file {} tells process {pid} running, already started?",
+                            path.display()
+                        );
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Read PID file {}", path.display())),
+        }
+
+        fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("Write PID file {}", path.display()))?;
+
+        Ok(Self(path.to_owned()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Taken over by the process upgraded to
+        if fs::read_to_string(&self.0)
+            .is_ok_and(|content| content.trim() != std::process::id().to_string())
+        {
+            return;
+        }
+
+        if let Err(e) = fs::remove_file(&self.0) {
+            tracing::error!("Remove PID file {} error: {e}", self.0.display());
+        }
+    }
+}
+
+/// Detach from the terminal and run in the background, with stdio redirected
+/// to `/dev/null`. Logs are to be written to a file then, see
+/// [`LogConfig::file`](crate::config::LogConfig::file).
+///
+/// To be called before the runtime built, as threads are not forked.
+pub(crate) fn daemonize() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsRawFd;
+
+        let dev_null = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")
+            .context("Open /dev/null")?;
+
+        #[allow(unsafe_code, reason = "FFI")]
+        // SAFETY: single-threaded yet, the child continues with the process as
+        // is.
+        match unsafe { libc::fork() } {
+            -1 => return Err(io::Error::last_os_error()).context("Fork"),
+            0 => {}
+            // The parent, exiting with the child left running
+            _ => std::process::exit(0),
+        }
+
+        #[allow(unsafe_code, reason = "FFI")]
+        // SAFETY: the fds are valid for the duration of the calls.
+        unsafe {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error()).context("Create session");
+            }
+
+            for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+                if libc::dup2(dev_null.as_raw_fd(), fd) == -1 {
+                    return Err(io::Error::last_os_error()).context("Redirect stdio");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    bail!("Running in the background is not supported on this platform")
+}
+
+/// Wait for SIGINT or SIGTERM, to shut down gracefully.
+pub(crate) async fn shutdown() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+
+        tracing::info!("Shutting down");
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Spawn the handler of SIGHUP and SIGUSR1, see [`reload`] and
+/// [`log_stats`].
+pub(crate) fn handle_signals() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        let mut user_defined1 = signal(SignalKind::user_defined1())?;
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = hangup.recv() => reload(),
+                    _ = user_defined1.recv() => log_stats(),
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+/// Reload the config, the credentials, the API keys and the admin TLS
+/// certificates, and reopen log files, e.g. once rotated by logrotate.
+fn reload() {
+    tracing::info!("Reloading");
+
+    logging::reopen();
+    access_log::reopen();
+
+    match Config::reload() {
+        Ok(()) => tracing::info!("Config reloaded"),
+        Err(e) => tracing::error!("Reload config error, keeping the loaded one: {e:#}"),
+    }
+
+    #[cfg(feature = "playurl")]
+    credentials::reload();
+    #[cfg(feature = "admin")]
+    api_key::reload();
+    #[cfg(all(feature = "admin", feature = "tls"))]
+    admin::reload();
+    #[cfg(feature = "tls")]
+    tls::reload();
+}
+
+#[cfg(unix)]
+/// Log stats of the runtime, connections and the cache.
+fn log_stats() {
+    let metrics = tokio::runtime::Handle::current().metrics();
+
+    tracing::info!(
+        workers = metrics.num_workers(),
+        alive_tasks = metrics.num_alive_tasks(),
+        global_queue_depth = metrics.global_queue_depth(),
+        connections = connection::count(),
+        "Runtime stats"
+    );
+
+    if let Some(cache) = Cache::global() {
+        let occupancy = cache.occupancy();
+
+        tracing::info!(
+            keys = occupancy.keys,
+            size = occupancy.size,
+            max_size = occupancy.max_size,
+            "Cache stats"
+        );
+    }
+}
+
+/// Whether the process of `pid` is running.
+fn is_running(pid: i32) -> bool {
+    #[cfg(unix)]
+    {
+        #[allow(unsafe_code, reason = "FFI")]
+        // SAFETY: signal 0 only checks for the process.
+        let ret = unsafe { libc::kill(pid, 0) };
+
+        // Running, but owned by another user
+        ret == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+
+        false
+    }
+}
@@ -0,0 +1,117 @@
+//! Errors of handling requests, answered by their status, see [`Error`] and
+//! [`IntoResponse`].
+
+use std::io;
+
+use http::{StatusCode, header::CONTENT_RANGE};
+use macro_toolset::{str_concat_v2, string_v2::StringExtT};
+
+use crate::proto;
+
+#[derive(Debug)]
+#[derive(thiserror::Error)]
+/// Error of a handler failing without responding, answered by its
+/// [`status`](Error::status) unless the response was written already.
+pub enum Error {
+    #[error("Bad request: {0:#}")]
+    /// Request invalid, e.g. malformed
+    BadRequest(anyhow::Error),
+
+    #[error("Not found")]
+    /// Nothing of the request path
+    NotFound,
+
+    #[error("Range not satisfiable of {0} bytes")]
+    /// `Range` requested not within the content of the length
+    RangeNotSatisfiable(u64),
+
+    #[error("Upstream error: {0:#}")]
+    /// Upstream failed, or responded invalid content
+    Upstream(anyhow::Error),
+
+    #[error("IO error: {0}")]
+    /// IO error, e.g. of opening a file, by its kind
+    Io(#[from] io::Error),
+
+    #[error("{0:#}")]
+    /// Any other error
+    Internal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Error {
+    /// Of the error by which `error` was made if any, e.g. `Io` of
+    /// [`io::Error`], [`Internal`](Error::Internal) otherwise.
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<Self>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+
+        let error = match error.downcast::<io::Error>() {
+            Ok(error) => return Self::Io(error),
+            Err(error) => error,
+        };
+
+        if error.is::<proto::Error>() {
+            return Self::BadRequest(error);
+        }
+
+        Self::Internal(error)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Internal(error.into())
+    }
+}
+
+impl From<http::header::InvalidHeaderValue> for Error {
+    fn from(error: http::header::InvalidHeaderValue) -> Self {
+        Self::Internal(error.into())
+    }
+}
+
+impl Error {
+    /// Status answered.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            Self::Upstream(_) => StatusCode::BAD_GATEWAY,
+            Self::Io(e) => match e.kind() {
+                io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// A type answered as a response, e.g. [`Error`].
+pub(crate) trait IntoResponse {
+    /// The response of it.
+    fn into_response(self) -> proto::Response;
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> proto::Response {
+        proto::Response::status(self).with_body(Vec::new())
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> proto::Response {
+        let mut response = self.status().into_response();
+
+        if let Self::RangeNotSatisfiable(length) = self {
+            if let Ok(value) = str_concat_v2!("bytes */", length).to_http_header_value() {
+                response.headers_mut().insert(CONTENT_RANGE, value);
+            }
+        }
+
+        response
+    }
+}
@@ -0,0 +1,119 @@
+//! Errors logged lately, kept in memory, see [`list`], so that they are told
+//! by the dashboard without the log at hand, see
+//! [`dashboard`](crate::service::admin::dashboard).
+//!
+//! The latest [`MAX_ERRORS`] events of level `ERROR` are kept, of those not
+//! filtered out by [`LogConfig::level`](crate::config::LogConfig::level).
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use serde::Serialize;
+use tracing::{
+    Event, Level, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_subscriber::{Layer, layer::Context};
+
+/// Max number of errors kept, the oldest dropped beyond.
+const MAX_ERRORS: usize = 100;
+
+/// Errors kept, the latest last.
+static ERRORS: Mutex<VecDeque<LoggedError>> = Mutex::new(VecDeque::new());
+
+#[derive(Debug, Clone)]
+#[derive(Serialize)]
+/// An error logged.
+pub(crate) struct LoggedError {
+    /// Seconds since the Unix epoch
+    time: u64,
+
+    /// Module logging it
+    target: String,
+
+    /// The message, followed by the other fields like `name=value`
+    message: String,
+}
+
+/// The layer keeping errors logged.
+pub(crate) fn layer<S>() -> impl Layer<S>
+where
+    S: Subscriber,
+{
+    ErrorLog
+}
+
+/// Errors kept, the latest first.
+pub(crate) fn list() -> Vec<LoggedError> {
+    ERRORS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .rev()
+        .cloned()
+        .collect()
+}
+
+#[derive(Debug)]
+/// See [`layer`].
+struct ErrorLog;
+
+impl<S> Layer<S> for ErrorLog
+where
+    S: Subscriber,
+{
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let metadata = event.metadata();
+
+        if *metadata.level() != Level::ERROR {
+            return;
+        }
+
+        let mut message = Message(String::new());
+        event.record(&mut message);
+
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+
+        let mut errors = ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+
+        if errors.len() >= MAX_ERRORS {
+            errors.pop_front();
+        }
+
+        errors.push_back(LoggedError {
+            time,
+            target: metadata.target().to_owned(),
+            message: message.0,
+        });
+    }
+}
+
+/// Fields of an event written down, see [`LoggedError::message`].
+struct Message(String);
+
+impl Visit for Message {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.record_debug(field, &format_args!("{value}"));
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+
+        let _ = match field.name() {
+            "message" => write!(self.0, "{value:?}"),
+            name => write!(self.0, "{name}={value:?}"),
+        };
+    }
+}
@@ -0,0 +1,186 @@
+//! Typed params of requests, extracted before handled, see [`extract`].
+//!
+//! - [`Path`] of the params of the route matched, see
+//!   [`router`](crate::router).
+//! - [`Query`] of the query.
+//! - [`Header`] of a header, see [`TypedHeader`].
+//!
+//! Requests of which any failed are answered `400 Bad Request`, unless
+//! extracted as an [`Option`], `None` then.
+
+use std::marker::PhantomData;
+
+use http::{HeaderName, HeaderValue, header::HOST};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    error::Error,
+    proto::{AnyStream, Request},
+    router::{Handler, HandlerFuture, Params},
+};
+
+#[derive(Debug)]
+#[derive(thiserror::Error)]
+/// Error extracting a param of a request, see [`FromRequest`].
+pub enum Rejection {
+    #[error("Invalid path params: {0}")]
+    /// Path params not of the type
+    Path(String),
+
+    #[error("Invalid query: {0}")]
+    /// Query not of the type
+    Query(String),
+
+    #[error("Missing header {0}")]
+    /// Header not sent
+    MissingHeader(HeaderName),
+
+    #[error("Invalid header {0}")]
+    /// Header not of the type
+    InvalidHeader(HeaderName),
+}
+
+/// A type extracted of requests, see the [module docs](self).
+pub trait FromRequest: Sized {
+    /// Extract of `request` of the `params` matched.
+    ///
+    /// # Errors
+    ///
+    /// If missing or invalid.
+    fn from_request(request: &Request, params: &Params) -> Result<Self, Rejection>;
+}
+
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(request: &Request, params: &Params) -> Result<Self, Rejection> {
+        Ok(T::from_request(request, params).ok())
+    }
+}
+
+/// Implement [`FromRequest`] for tuples of those implementing it.
+macro_rules! impl_from_request_tuple {
+    ($($ty:ident),+) => {
+        impl<$($ty: FromRequest),+> FromRequest for ($($ty,)+) {
+            fn from_request(request: &Request, params: &Params) -> Result<Self, Rejection> {
+                Ok(($($ty::from_request(request, params)?,)+))
+            }
+        }
+    };
+}
+
+impl_from_request_tuple!(A);
+impl_from_request_tuple!(A, B);
+impl_from_request_tuple!(A, B, C);
+impl_from_request_tuple!(A, B, C, D);
+
+#[derive(Debug, Clone)]
+/// Params of the route matched: `T` of the only one for a route of a single
+/// param, or else a struct of them by name.
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(_request: &Request, params: &Params) -> Result<Self, Rejection> {
+        let encoded = serde_urlencoded::to_string(params.iter().collect::<Vec<_>>())
+            .map_err(|e| Rejection::Path(e.to_string()))?;
+
+        let params = if params.iter().count() == 1 {
+            serde_urlencoded::from_str::<Vec<(String, T)>>(&encoded)
+                .map(|params| params.into_iter().map(|(_, value)| value).next())
+                .map_err(|e| Rejection::Path(e.to_string()))?
+                .ok_or_else(|| Rejection::Path("no param".to_owned()))?
+        } else {
+            serde_urlencoded::from_str(&encoded).map_err(|e| Rejection::Path(e.to_string()))?
+        };
+
+        Ok(Self(params))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The query, percent-decoded, of params not of `T` ignored.
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(request: &Request, _params: &Params) -> Result<Self, Rejection> {
+        let query = request
+            .request_uri
+            .query()
+            .map_or("", |query| query.as_str());
+
+        serde_urlencoded::from_str(query)
+            .map(Self)
+            .map_err(|e| Rejection::Query(e.to_string()))
+    }
+}
+
+/// A header of a request, typed, see [`Header`].
+pub trait TypedHeader: Sized {
+    /// Name of the header
+    const NAME: HeaderName;
+
+    /// Decode `value` of the header, `None` if invalid.
+    fn decode(value: &HeaderValue) -> Option<Self>;
+}
+
+#[derive(Debug, Clone)]
+/// The first header of [`TypedHeader::NAME`].
+pub struct Header<T>(pub T);
+
+impl<T: TypedHeader> FromRequest for Header<T> {
+    fn from_request(request: &Request, _params: &Params) -> Result<Self, Rejection> {
+        let value = request
+            .headers
+            .get(T::NAME)
+            .ok_or(Rejection::MissingHeader(T::NAME))?;
+
+        T::decode(value)
+            .map(Self)
+            .ok_or(Rejection::InvalidHeader(T::NAME))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// `Host`
+pub struct Host(pub String);
+
+impl TypedHeader for Host {
+    const NAME: HeaderName = HOST;
+
+    fn decode(value: &HeaderValue) -> Option<Self> {
+        value
+            .to_str()
+            .ok()
+            .filter(|host| !host.is_empty())
+            .map(|host| Self(host.to_owned()))
+    }
+}
+
+/// A handler of the closure `f`, given `T` extracted of the request, e.g. a
+/// tuple of [`Path`] and [`Query`], answering `400 Bad Request` if failed.
+pub fn extract<T, F>(f: F) -> impl Handler
+where
+    T: FromRequest + 'static,
+    F: for<'a> Fn(&'a Request, T, &'a mut AnyStream) -> HandlerFuture<'a> + Send + Sync,
+{
+    Extract(f, PhantomData)
+}
+
+/// Handler of [`extract`].
+struct Extract<T, F>(F, PhantomData<fn() -> T>);
+
+impl<T, F> Handler for Extract<T, F>
+where
+    T: FromRequest,
+    F: for<'a> Fn(&'a Request, T, &'a mut AnyStream) -> HandlerFuture<'a> + Send + Sync,
+{
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        params: &'a Params,
+        stream: &'a mut AnyStream,
+    ) -> HandlerFuture<'a> {
+        match T::from_request(request, params) {
+            Ok(extracted) => (self.0)(request, extracted, stream),
+            Err(e) => Box::pin(async move { Err(Error::BadRequest(e.into())) }),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Request firewall, see [`FirewallRule`].
+//!
+//! Requests are checked against the rules configured in order once parsed,
+//! before routed, so that known scanner traffic, e.g. probing `/wp-admin` or
+//! `/.env`, is shed cheaply without any file I/O or upstream request. The
+//! first rule matched tells what is done, see [`FirewallAction`].
+
+#[cfg(test)]
+mod tests;
+
+use anyhow::Result;
+use http::{StatusCode, header::USER_AGENT};
+
+use crate::{
+    config::{Config, FirewallAction, FirewallRule},
+    error::Error,
+    layer::{Layer, layer_fn},
+    metrics, proto, service,
+};
+
+/// Layer refusing the requests matching the rules, see [`check`].
+pub(crate) fn layer() -> impl Layer {
+    layer_fn(|request, params, stream, next| match check(request) {
+        Some(action) => Box::pin(refuse(action, stream)),
+        None => next.run(request, params, stream),
+    })
+}
+
+/// The action of the first rule `request` matches, if any.
+pub(crate) fn check(request: &proto::Request) -> Option<FirewallAction> {
+    let config = Config::current();
+
+    let rule = config.firewall.iter().find(|rule| matches(rule, request))?;
+
+    tracing::debug!(
+        rule = rule.name.as_deref(),
+        "Request refused by firewall, {:?}",
+        rule.action
+    );
+
+    Some(rule.action)
+}
+
+/// Refuse the request as `action` tells.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn refuse(
+    action: FirewallAction,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    metrics::firewall_refused(action);
+
+    match action {
+        FirewallAction::Forbid => service::write_status(StatusCode::FORBIDDEN, tcp_stream).await,
+        FirewallAction::Drop => Ok(false),
+    }
+}
+
+/// Whether `request` meets all the conditions of `rule`.
+fn matches(rule: &FirewallRule, request: &proto::Request) -> bool {
+    let header = |name: &str| {
+        request
+            .headers
+            .get(name)
+            .map(|value| String::from_utf8_lossy(value.as_bytes()))
+    };
+
+    if let Some(path) = &rule.path {
+        if !path.is_match(&normalized_path(request)) {
+            return false;
+        }
+    }
+
+    if let Some(user_agent) = &rule.user_agent {
+        if !user_agent.is_match(&header(USER_AGENT.as_str()).unwrap_or_default()) {
+            return false;
+        }
+    }
+
+    rule.headers
+        .iter()
+        .all(|(name, pattern)| header(name).is_some_and(|value| pattern.is_match(&value)))
+}
+
+/// The path of `request` percent-decoded, and of no empty, `.` or `..`
+/// segment, so that rules are not got around by spelling it differently,
+/// e.g. `/%73ecret` or `/static/../secret`.
+fn normalized_path(request: &proto::Request) -> String {
+    let path = request.request_uri.path();
+
+    let mut segments = Vec::new();
+
+    for segment in path.split('/') {
+        let segment = segment.decode().into_string_lossy();
+
+        match &*segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+
+    for segment in &segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+
+    if segments.is_empty() || path.as_str().ends_with('/') {
+        normalized.push('/');
+    }
+
+    normalized
+}
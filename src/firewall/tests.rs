@@ -0,0 +1,144 @@
+//! Paths matched percent-decoded and normalized, and requests matched only if
+//! meeting all the conditions of a rule.
+
+use super::{matches, normalized_path};
+use crate::{config::FirewallRule, proto};
+
+/// A request of `path` and the header lines given.
+async fn request(path: &str, headers: &str) -> proto::Request {
+    let head = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n");
+
+    proto::Request::handle(&mut head.as_bytes())
+        .await
+        .expect("Parsed")
+        .expect("Request")
+}
+
+/// Parse a rule of the TOML given.
+fn rule(rule: &str) -> FirewallRule {
+    toml::from_str(rule).expect("Firewall rule")
+}
+
+#[tokio::test]
+/// Segments percent-decoded, empty and `.` ones dropped, and `..` ones
+/// popping the last, never above the root.
+async fn normalized() {
+    for (path, normalized) in [
+        ("/", "/"),
+        ("/secret", "/secret"),
+        ("/static/", "/static/"),
+        ("/%73ecret", "/secret"),
+        ("/%2Eenv", "/.env"),
+        ("/static///secret//", "/static/secret/"),
+        ("/./static/./secret", "/static/secret"),
+        ("/static/../secret", "/secret"),
+        ("/static/%2e%2e/secret", "/secret"),
+        ("/../../secret", "/secret"),
+        ("/static/..", "/"),
+        ("/static/secret?download=1", "/static/secret"),
+    ] {
+        assert_eq!(
+            normalized_path(&request(path, "").await),
+            normalized,
+            "{path}"
+        );
+    }
+}
+
+#[tokio::test]
+/// Paths matched however spelled.
+async fn path() {
+    let rule = rule(r#"path = '^/(wp-admin|\.env)'"#);
+
+    for path in [
+        "/wp-admin",
+        "/wp-admin/install.php",
+        "/.env",
+        "/%2eenv",
+        "/%77p-admin",
+        "/static//../wp-admin",
+        "/static/../wp-admin",
+        "/./.env",
+    ] {
+        assert!(matches(&rule, &request(path, "").await), "{path}");
+    }
+
+    for path in ["/", "/static/wp-admin", "/env", "/static/.env"] {
+        assert!(!matches(&rule, &request(path, "").await), "{path}");
+    }
+}
+
+#[tokio::test]
+/// `User-Agent` matched as empty if not sent, and headers not matched if not
+/// sent.
+async fn headers() {
+    let user_agent = rule(r#"user_agent = '(?i)(zgrab|masscan)'"#);
+
+    assert!(matches(
+        &user_agent,
+        &request("/", "User-Agent: Mozilla/5.0 zgrab/0.x\r\n").await
+    ));
+    assert!(matches(
+        &user_agent,
+        &request("/", "User-Agent: MASSCAN/1.3\r\n").await
+    ));
+    assert!(!matches(
+        &user_agent,
+        &request("/", "User-Agent: curl/8.0\r\n").await
+    ));
+    assert!(!matches(&user_agent, &request("/", "").await));
+
+    let empty = rule(r#"user_agent = '^$'"#);
+
+    assert!(matches(&empty, &request("/", "").await));
+    assert!(!matches(
+        &empty,
+        &request("/", "User-Agent: curl/8.0\r\n").await
+    ));
+
+    let header = rule("headers = { x-scanner = '.*' }");
+
+    assert!(matches(&header, &request("/", "X-Scanner: 1\r\n").await));
+    assert!(!matches(&header, &request("/", "").await));
+}
+
+#[tokio::test]
+/// All the conditions set met, and all requests matched if none set.
+async fn conditions() {
+    let rule = self::rule(
+        r#"
+            path = '^/admin'
+            user_agent = 'curl'
+            headers = { x-debug = '^1$' }
+        "#,
+    );
+
+    assert!(matches(
+        &rule,
+        &request("/admin", "User-Agent: curl/8.0\r\nX-Debug: 1\r\n").await
+    ));
+    assert!(!matches(
+        &rule,
+        &request("/", "User-Agent: curl/8.0\r\nX-Debug: 1\r\n").await
+    ));
+    assert!(!matches(
+        &rule,
+        &request("/admin", "User-Agent: wget\r\nX-Debug: 1\r\n").await
+    ));
+    assert!(!matches(
+        &rule,
+        &request("/admin", "User-Agent: curl/8.0\r\nX-Debug: 0\r\n").await
+    ));
+    assert!(!matches(
+        &rule,
+        &request("/admin", "User-Agent: curl/8.0\r\n").await
+    ));
+
+    let any = self::rule("name = 'all'");
+
+    assert!(matches(&any, &request("/", "").await));
+    assert!(matches(
+        &any,
+        &request("/static/video.mp4", "Range: bytes=0-\r\n").await
+    ));
+}
@@ -0,0 +1,75 @@
+//! Internals of the HTTP engine exposed to the fuzz targets in `fuzz`, of the
+//! `fuzz` feature only.
+//!
+//! Input is untrusted network bytes, so whatever given, these are to return
+//! rather than panic or hang.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use anyhow::{Context as _, Result};
+use futures_util::FutureExt;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::{Error, proto::Request, service};
+
+/// Parse a request head of `data`, see [`Request::handle`].
+///
+/// The first byte tells how many bytes are read at a time, i.e. how the head
+/// comes in pieces, the rest being what's read.
+///
+/// # Errors
+///
+/// If the head is invalid.
+pub fn parse_request(data: &[u8]) -> Result<Option<Request>> {
+    let Some((&piece, data)) = data.split_first() else {
+        return Ok(None);
+    };
+
+    let mut reader = Pieces {
+        data,
+        piece: usize::from(piece).max(1),
+    };
+
+    // Never pending, read from memory
+    Request::handle(&mut reader)
+        .now_or_never()
+        .context("Pending")?
+}
+
+/// Resolve a `Range` header value against the content length, returning the
+/// inclusive `(start, end)` byte positions if any, see
+/// [`requested_range`](crate::service::requested_range).
+///
+/// # Errors
+///
+/// [`Error::RangeNotSatisfiable`] if not within the content.
+pub fn resolve_range(range: &str, length: u64) -> Result<Option<(u64, u64)>, Error> {
+    service::parse_range(range, length)
+}
+
+#[derive(Debug)]
+/// Bytes in memory read at most `piece` bytes at a time.
+struct Pieces<'a> {
+    data: &'a [u8],
+    piece: usize,
+}
+
+impl AsyncRead for Pieces<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let len = self.piece.min(self.data.len()).min(buf.remaining());
+        let (piece, rest) = self.data.split_at(len);
+
+        buf.put_slice(piece);
+        self.data = rest;
+
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,180 @@
+//! gRPC interface of the app of bilibili, on a dedicated listener, see
+//! [`PlayurlConfig::grpc_listen`], so that patched clients fetch playurls
+//! from this server the same way they do from the official API. Only the
+//! `PlayView` method is served, see [`playurl`].
+//!
+//! Served over cleartext HTTP/2, see [`h2`]; TLS is left to a reverse proxy
+//! in front, e.g. `grpc_pass` of nginx. Compressed messages are refused,
+//! clients falling back to uncompressed ones as told by
+//! `grpc-accept-encoding`.
+//!
+//! [`PlayurlConfig::grpc_listen`]: crate::config::PlayurlConfig::grpc_listen
+
+mod h2;
+mod hpack;
+mod playurl;
+mod protobuf;
+
+use std::{fmt::Write, net::SocketAddr};
+
+use anyhow::Result;
+
+use self::h2::{Request, Response};
+use crate::listener;
+
+#[derive(Debug, Clone, Copy)]
+/// Status codes of gRPC.
+enum Code {
+    Ok = 0,
+    Unknown = 2,
+    InvalidArgument = 3,
+    NotFound = 5,
+    Unimplemented = 12,
+    Unavailable = 14,
+}
+
+#[derive(Debug)]
+/// Status of a call failed.
+struct Status {
+    code: Code,
+
+    message: String,
+}
+
+impl Status {
+    /// Create a new [`Status`].
+    fn new(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Listen on `listen` for gRPC calls, served in background.
+pub(crate) fn spawn(listen: SocketAddr) -> Result<()> {
+    // With `SO_REUSEPORT`, so that bound by the new process on upgrading too,
+    // see `upgrade`
+    let listener =
+        listener::Listener::new(listener::bind_socket(listen, true)?, listener::Kind::Grpc);
+
+    tracing::info!("gRPC listening on {listen}");
+
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, peer_addr) = listener.accept().await;
+
+            tracing::debug!("New gRPC connection from {peer_addr}");
+
+            tokio::spawn(async move {
+                if let Err(e) = h2::serve(tcp_stream, handle).await {
+                    tracing::debug!("gRPC connection from {peer_addr} error: {e}");
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+/// Handle a call, i.e. a request of a single message.
+async fn handle(request: Request) -> Response {
+    tracing::debug!("gRPC {:?}", request.header(":path"));
+
+    if request.header(":method") != Some("POST") {
+        return Response {
+            headers: vec![(":status".to_owned(), "405".to_owned())],
+            ..Response::default()
+        };
+    }
+
+    if !request
+        .header("content-type")
+        .is_some_and(|content_type| content_type.starts_with("application/grpc"))
+    {
+        return Response {
+            headers: vec![(":status".to_owned(), "415".to_owned())],
+            ..Response::default()
+        };
+    }
+
+    let result = match message(&request) {
+        Ok(message) => match request.header(":path") {
+            Some(playurl::PLAY_VIEW) => playurl::play_view(&request, message).await,
+            path => Err(Status::new(
+                Code::Unimplemented,
+                format!("Unknown method {}", path.unwrap_or_default()),
+            )),
+        },
+        Err(status) => Err(status),
+    };
+
+    let mut headers = vec![
+        (":status".to_owned(), "200".to_owned()),
+        ("content-type".to_owned(), "application/grpc".to_owned()),
+        ("grpc-accept-encoding".to_owned(), "identity".to_owned()),
+    ];
+
+    match result {
+        Ok(reply) => {
+            let mut body = Vec::with_capacity(5 + reply.len());
+            body.push(0);
+            body.extend_from_slice(&(reply.len() as u32).to_be_bytes());
+            body.extend_from_slice(&reply);
+
+            Response {
+                headers,
+                body,
+                trailers: vec![("grpc-status".to_owned(), (Code::Ok as u8).to_string())],
+            }
+        }
+        Err(status) => {
+            tracing::debug!("gRPC call failed: {status:?}");
+
+            // Trailers-only
+            headers.push(("grpc-status".to_owned(), (status.code as u8).to_string()));
+            headers.push(("grpc-message".to_owned(), percent_encode(&status.message)));
+
+            Response {
+                headers,
+                ..Response::default()
+            }
+        }
+    }
+}
+
+/// The single message of the request, uncompressed.
+fn message(request: &Request) -> Result<&[u8], Status> {
+    let invalid = || Status::new(Code::InvalidArgument, "Invalid message framing");
+
+    let (prefix, message) = request.body.split_at_checked(5).ok_or_else(invalid)?;
+
+    if prefix[0] != 0 {
+        return Err(Status::new(
+            Code::Unimplemented,
+            "Compressed messages not supported",
+        ));
+    }
+
+    let length = u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]);
+    if length as usize != message.len() {
+        return Err(invalid());
+    }
+
+    Ok(message)
+}
+
+/// Percent-encode `grpc-message`, i.e. all but printable ASCII.
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+
+    for &byte in message.as_bytes() {
+        if (0x20..0x7f).contains(&byte) && byte != b'%' {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+
+    encoded
+}
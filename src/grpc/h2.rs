@@ -0,0 +1,895 @@
+//! A minimal HTTP/2 server, see RFC 9113, over cleartext TCP with prior
+//! knowledge, i.e. h2c, enough for unary gRPC calls.
+//!
+//! A request is handled once received whole, concurrently with others of
+//! the connection, and its response sent whole, i.e. headers, body and
+//! trailers, as flow control allows. Server push and priorities are not
+//! supported.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io, mem,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::mpsc,
+};
+
+use super::hpack::{self, Header};
+
+/// Connection preface of clients.
+const PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Max size of frame payloads received, i.e. `SETTINGS_MAX_FRAME_SIZE` left
+/// as the default.
+const MAX_FRAME_SIZE: usize = 16 * 1024;
+
+/// Max number of streams open at the same time.
+const MAX_CONCURRENT_STREAMS: usize = 100;
+
+/// Initial flow control window of connections and streams.
+const DEFAULT_WINDOW_SIZE: i64 = 65_535;
+
+/// Max flow control window.
+const MAX_WINDOW_SIZE: i64 = (1 << 31) - 1;
+
+/// Max size of a request body.
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// Frame type `DATA`.
+const DATA: u8 = 0x0;
+
+/// Frame type `HEADERS`.
+const HEADERS: u8 = 0x1;
+
+/// Frame type `RST_STREAM`.
+const RST_STREAM: u8 = 0x3;
+
+/// Frame type `SETTINGS`.
+const SETTINGS: u8 = 0x4;
+
+/// Frame type `PUSH_PROMISE`.
+const PUSH_PROMISE: u8 = 0x5;
+
+/// Frame type `PING`.
+const PING: u8 = 0x6;
+
+/// Frame type `GOAWAY`.
+const GOAWAY: u8 = 0x7;
+
+/// Frame type `WINDOW_UPDATE`.
+const WINDOW_UPDATE: u8 = 0x8;
+
+/// Frame type `CONTINUATION`.
+const CONTINUATION: u8 = 0x9;
+
+/// Flag `END_STREAM` of `DATA` and `HEADERS` frames.
+const END_STREAM: u8 = 0x1;
+
+/// Flag `ACK` of `SETTINGS` and `PING` frames.
+const ACK: u8 = 0x1;
+
+/// Flag `END_HEADERS` of `HEADERS` and `CONTINUATION` frames.
+const END_HEADERS: u8 = 0x4;
+
+/// Flag `PADDED` of `DATA` and `HEADERS` frames.
+const PADDED: u8 = 0x8;
+
+/// Flag `PRIORITY` of `HEADERS` frames.
+const PRIORITY: u8 = 0x20;
+
+/// Setting `SETTINGS_INITIAL_WINDOW_SIZE`.
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+
+/// Setting `SETTINGS_MAX_FRAME_SIZE`.
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+/// Setting `SETTINGS_MAX_CONCURRENT_STREAMS`.
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+
+/// Setting `SETTINGS_MAX_HEADER_LIST_SIZE`.
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+#[derive(Debug, Clone, Copy)]
+/// Error codes of `RST_STREAM` and `GOAWAY` frames.
+enum ErrorCode {
+    ProtocolError = 0x1,
+    FlowControlError = 0x3,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    CompressionError = 0x9,
+}
+
+#[derive(Debug)]
+#[derive(thiserror::Error)]
+/// Error ending a connection.
+enum Error {
+    #[error(transparent)]
+    /// IO error, the connection gone
+    Io(#[from] io::Error),
+
+    #[error("HTTP/2 connection error {code:?}: {reason}")]
+    /// Connection error, told to the client by a `GOAWAY` frame
+    Connection {
+        code: ErrorCode,
+        reason: &'static str,
+    },
+}
+
+/// Connection error of `code`.
+const fn connection_error(code: ErrorCode, reason: &'static str) -> Error {
+    Error::Connection { code, reason }
+}
+
+#[derive(Debug)]
+/// A frame received.
+struct Frame {
+    kind: u8,
+
+    flags: u8,
+
+    stream_id: u32,
+
+    payload: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+/// A request received.
+pub(super) struct Request {
+    /// Header fields, pseudo-header fields like `:path` included
+    pub headers: Vec<Header>,
+
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Value of the header field `name`, the first if repeated.
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+/// A response to send.
+pub(super) struct Response {
+    /// Header fields, `:status` first
+    pub headers: Vec<Header>,
+
+    pub body: Vec<u8>,
+
+    /// Trailer fields, none sent if empty
+    pub trailers: Vec<Header>,
+}
+
+#[derive(Debug)]
+/// Body and trailers of a response waiting for flow control windows.
+struct Sending {
+    body: Vec<u8>,
+
+    /// Bytes of the body sent so far
+    sent: usize,
+
+    trailers: Vec<Header>,
+}
+
+#[derive(Debug)]
+/// State of a connection.
+struct Connection {
+    writer: OwnedWriteHalf,
+
+    decoder: hpack::Decoder,
+
+    /// ID of the latest stream opened by the client
+    last_stream_id: u32,
+
+    /// Send windows of streams open, by ID
+    windows: HashMap<u32, i64>,
+
+    /// Streams receiving the request, by ID
+    receiving: HashMap<u32, Request>,
+
+    /// Streams sending the response, by ID
+    sending: BTreeMap<u32, Sending>,
+
+    /// Header block being continued, with its stream ID and flags
+    continued: Option<(u32, u8, Vec<u8>)>,
+
+    /// Send window of the connection
+    window: i64,
+
+    /// Initial send window of streams, told by the client
+    initial_window: i64,
+
+    /// Max size of frame payloads sent, told by the client
+    max_frame_size: usize,
+}
+
+/// Serve the HTTP/2 connection `tcp_stream`, handling requests by `handler`.
+pub(super) async fn serve<H, F>(tcp_stream: TcpStream, handler: H) -> io::Result<()>
+where
+    H: Fn(Request) -> F + Clone + Send + 'static,
+    F: Future<Output = Response> + Send + 'static,
+{
+    let (reader, writer) = tcp_stream.into_split();
+
+    let (frame_tx, mut frame_rx) = mpsc::channel(16);
+    let reader = tokio::spawn(read_frames(reader, frame_tx));
+
+    let (response_tx, mut response_rx) = mpsc::channel(16);
+
+    let mut connection = Connection {
+        writer,
+        decoder: hpack::Decoder::new(),
+        last_stream_id: 0,
+        windows: HashMap::new(),
+        receiving: HashMap::new(),
+        sending: BTreeMap::new(),
+        continued: None,
+        window: DEFAULT_WINDOW_SIZE,
+        initial_window: DEFAULT_WINDOW_SIZE,
+        max_frame_size: MAX_FRAME_SIZE,
+    };
+
+    let result = async {
+        let mut settings = Vec::with_capacity(12);
+        for (id, value) in [
+            (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS),
+            (SETTINGS_MAX_HEADER_LIST_SIZE, hpack::MAX_HEADER_LIST_SIZE),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        connection.write_frame(SETTINGS, 0, 0, &settings).await?;
+
+        loop {
+            tokio::select! {
+                frame = frame_rx.recv() => {
+                    match frame {
+                        Some(frame) => {
+                            if !connection.on_frame(frame?, &handler, &response_tx).await? {
+                                break;
+                            }
+                        }
+                        None => break,
+                    }
+                }
+                Some((stream_id, response)) = response_rx.recv() => {
+                    connection.on_response(stream_id, response).await?;
+                }
+            }
+        }
+
+        Ok::<_, Error>(())
+    }
+    .await;
+
+    reader.abort();
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(Error::Io(e)) => Err(e),
+        Err(e @ Error::Connection { code, .. }) => {
+            let mut payload = connection.last_stream_id.to_be_bytes().to_vec();
+            payload.extend_from_slice(&(code as u32).to_be_bytes());
+
+            // The client may be gone already
+            let _ = connection.write_frame(GOAWAY, 0, 0, &payload).await;
+
+            Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+        }
+    }
+}
+
+/// Read the client preface and then frames, passing them on until the
+/// connection is gone or broken.
+async fn read_frames(reader: OwnedReadHalf, frame_tx: mpsc::Sender<Result<Frame, Error>>) {
+    let mut reader = BufReader::new(reader);
+
+    let result = async {
+        let mut preface = [0; PREFACE.len()];
+        reader.read_exact(&mut preface).await?;
+
+        if &preface != PREFACE {
+            return Err(connection_error(
+                ErrorCode::ProtocolError,
+                "invalid preface",
+            ));
+        }
+
+        loop {
+            let mut header = [0; 9];
+            reader.read_exact(&mut header).await?;
+
+            let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            if length > MAX_FRAME_SIZE {
+                return Err(connection_error(
+                    ErrorCode::FrameSizeError,
+                    "frame too large",
+                ));
+            }
+
+            let mut payload = vec![0; length];
+            reader.read_exact(&mut payload).await?;
+
+            let frame = Frame {
+                kind: header[3],
+                flags: header[4],
+                stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]])
+                    & 0x7fff_ffff,
+                payload,
+            };
+
+            if frame_tx.send(Ok(frame)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+    .await;
+
+    if let Err(e) = result {
+        let _ = frame_tx.send(Err(e)).await;
+    }
+}
+
+impl Connection {
+    /// Handle `frame`, dispatching requests received whole to `handler`
+    /// with their responses sent back by `response_tx`.
+    ///
+    /// Returns whether the connection should be kept.
+    async fn on_frame<H, F>(
+        &mut self,
+        frame: Frame,
+        handler: &H,
+        response_tx: &mpsc::Sender<(u32, Response)>,
+    ) -> Result<bool, Error>
+    where
+        H: Fn(Request) -> F + Clone + Send + 'static,
+        F: Future<Output = Response> + Send + 'static,
+    {
+        let Frame {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        } = frame;
+
+        if let Some((continued_id, continued_flags, mut block)) = self.continued.take() {
+            if kind != CONTINUATION || stream_id != continued_id {
+                return Err(connection_error(
+                    ErrorCode::ProtocolError,
+                    "header block interrupted",
+                ));
+            }
+
+            if block.len() + payload.len() > hpack::MAX_HEADER_LIST_SIZE {
+                return Err(connection_error(
+                    ErrorCode::ProtocolError,
+                    "header block too large",
+                ));
+            }
+
+            block.extend_from_slice(&payload);
+
+            if flags & END_HEADERS == 0 {
+                self.continued = Some((continued_id, continued_flags, block));
+            } else {
+                self.on_headers(stream_id, continued_flags, &block, handler, response_tx)
+                    .await?;
+            }
+
+            return Ok(true);
+        }
+
+        match kind {
+            DATA => {
+                if stream_id == 0 {
+                    return Err(connection_error(
+                        ErrorCode::ProtocolError,
+                        "DATA of stream 0",
+                    ));
+                }
+
+                let length = payload.len();
+                let data = unpad(&payload, flags)?;
+
+                // Received data is taken at once, so are windows restored
+                if length > 0 {
+                    self.write_window_update(0, length).await?;
+                }
+
+                let Some(request) = self.receiving.get_mut(&stream_id) else {
+                    if stream_id > self.last_stream_id {
+                        return Err(connection_error(
+                            ErrorCode::ProtocolError,
+                            "DATA of idle stream",
+                        ));
+                    }
+
+                    self.write_rst_stream(stream_id, ErrorCode::StreamClosed)
+                        .await?;
+                    return Ok(true);
+                };
+
+                if request.body.len() + data.len() > MAX_BODY_SIZE {
+                    tracing::debug!("Request body of stream {stream_id} too large");
+
+                    self.close(stream_id);
+                    self.write_rst_stream(stream_id, ErrorCode::RefusedStream)
+                        .await?;
+                    return Ok(true);
+                }
+
+                request.body.extend_from_slice(data);
+
+                if flags & END_STREAM != 0 {
+                    self.dispatch(stream_id, handler, response_tx);
+                } else if length > 0 {
+                    self.write_window_update(stream_id, length).await?;
+                } else {
+                    // Empty DATA frame, nothing to restore
+                }
+            }
+            HEADERS => {
+                if stream_id == 0 {
+                    return Err(connection_error(
+                        ErrorCode::ProtocolError,
+                        "HEADERS of stream 0",
+                    ));
+                }
+
+                let mut fragment = unpad(&payload, flags)?;
+                if flags & PRIORITY != 0 {
+                    fragment = fragment.get(5..).ok_or(connection_error(
+                        ErrorCode::FrameSizeError,
+                        "HEADERS too short",
+                    ))?;
+                }
+
+                if flags & END_HEADERS == 0 {
+                    self.continued = Some((stream_id, flags, fragment.to_vec()));
+                } else {
+                    let block = fragment.to_vec();
+                    self.on_headers(stream_id, flags, &block, handler, response_tx)
+                        .await?;
+                }
+            }
+            RST_STREAM => {
+                if stream_id == 0 || payload.len() != 4 {
+                    return Err(connection_error(
+                        ErrorCode::ProtocolError,
+                        "invalid RST_STREAM",
+                    ));
+                }
+
+                self.close(stream_id);
+            }
+            SETTINGS => {
+                if stream_id != 0 || payload.len() % 6 != 0 {
+                    return Err(connection_error(
+                        ErrorCode::FrameSizeError,
+                        "invalid SETTINGS",
+                    ));
+                }
+
+                if flags & ACK == 0 {
+                    for setting in payload.chunks_exact(6) {
+                        let id = u16::from_be_bytes([setting[0], setting[1]]);
+                        let value =
+                            u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+
+                        self.apply_setting(id, value)?;
+                    }
+
+                    self.write_frame(SETTINGS, ACK, 0, &[]).await?;
+                    self.flush().await?;
+                }
+            }
+            PING => {
+                if stream_id != 0 || payload.len() != 8 {
+                    return Err(connection_error(ErrorCode::FrameSizeError, "invalid PING"));
+                }
+
+                if flags & ACK == 0 {
+                    self.write_frame(PING, ACK, 0, &payload).await?;
+                }
+            }
+            GOAWAY => {
+                tracing::debug!("GOAWAY received");
+                return Ok(false);
+            }
+            WINDOW_UPDATE => {
+                let Ok(increment) = <[u8; 4]>::try_from(payload.as_slice()) else {
+                    return Err(connection_error(
+                        ErrorCode::FrameSizeError,
+                        "invalid WINDOW_UPDATE",
+                    ));
+                };
+
+                let increment = i64::from(u32::from_be_bytes(increment) & 0x7fff_ffff);
+                if increment == 0 {
+                    return Err(connection_error(
+                        ErrorCode::ProtocolError,
+                        "zero window increment",
+                    ));
+                }
+
+                let window = match stream_id {
+                    0 => Some(&mut self.window),
+                    stream_id => self.windows.get_mut(&stream_id),
+                };
+
+                if let Some(window) = window {
+                    *window += increment;
+
+                    if *window > MAX_WINDOW_SIZE {
+                        return Err(connection_error(
+                            ErrorCode::FlowControlError,
+                            "window overflow",
+                        ));
+                    }
+                }
+
+                self.flush().await?;
+            }
+            PUSH_PROMISE | CONTINUATION => {
+                return Err(connection_error(
+                    ErrorCode::ProtocolError,
+                    "unexpected frame",
+                ));
+            }
+            // PRIORITY and unknown frames
+            _ => {}
+        }
+
+        Ok(true)
+    }
+
+    /// Handle the header `block` of a `HEADERS` frame with `flags`, either
+    /// opening a stream or being trailers.
+    async fn on_headers<H, F>(
+        &mut self,
+        stream_id: u32,
+        flags: u8,
+        block: &[u8],
+        handler: &H,
+        response_tx: &mpsc::Sender<(u32, Response)>,
+    ) -> Result<(), Error>
+    where
+        H: Fn(Request) -> F + Clone + Send + 'static,
+        F: Future<Output = Response> + Send + 'static,
+    {
+        // Decoded anyway, keeping the dynamic table in sync
+        let headers = self.decoder.decode(block).ok_or(connection_error(
+            ErrorCode::CompressionError,
+            "invalid header block",
+        ))?;
+
+        if self.receiving.contains_key(&stream_id) {
+            // Trailers, ignored
+            if flags & END_STREAM == 0 {
+                return Err(connection_error(
+                    ErrorCode::ProtocolError,
+                    "trailers not ending stream",
+                ));
+            }
+
+            self.dispatch(stream_id, handler, response_tx);
+
+            return Ok(());
+        }
+
+        if stream_id % 2 == 0 || stream_id <= self.last_stream_id {
+            return Err(connection_error(
+                ErrorCode::ProtocolError,
+                "HEADERS of invalid stream",
+            ));
+        }
+
+        self.last_stream_id = stream_id;
+
+        if self.windows.len() >= MAX_CONCURRENT_STREAMS {
+            self.write_rst_stream(stream_id, ErrorCode::RefusedStream)
+                .await?;
+            return Ok(());
+        }
+
+        self.windows.insert(stream_id, self.initial_window);
+        self.receiving.insert(
+            stream_id,
+            Request {
+                headers,
+                body: Vec::new(),
+            },
+        );
+
+        if flags & END_STREAM != 0 {
+            self.dispatch(stream_id, handler, response_tx);
+        }
+
+        Ok(())
+    }
+
+    /// Handle the request of `stream_id`, received whole, in background.
+    fn dispatch<H, F>(
+        &mut self,
+        stream_id: u32,
+        handler: &H,
+        response_tx: &mpsc::Sender<(u32, Response)>,
+    ) where
+        H: Fn(Request) -> F + Clone + Send + 'static,
+        F: Future<Output = Response> + Send + 'static,
+    {
+        let Some(request) = self.receiving.remove(&stream_id) else {
+            return;
+        };
+
+        let handler = handler.clone();
+        let response_tx = response_tx.clone();
+
+        tokio::spawn(async move {
+            let response = handler(request).await;
+
+            // The connection may be gone
+            let _ = response_tx.send((stream_id, response)).await;
+        });
+    }
+
+    /// Send `response` of `stream_id`, unless reset meanwhile.
+    async fn on_response(&mut self, stream_id: u32, response: Response) -> Result<(), Error> {
+        if !self.windows.contains_key(&stream_id) {
+            return Ok(());
+        }
+
+        let Response {
+            headers,
+            body,
+            trailers,
+        } = response;
+
+        let end_stream = body.is_empty() && trailers.is_empty();
+
+        self.write_headers(stream_id, &headers, end_stream).await?;
+
+        if end_stream {
+            self.close(stream_id);
+        } else {
+            self.sending.insert(
+                stream_id,
+                Sending {
+                    body,
+                    sent: 0,
+                    trailers,
+                },
+            );
+
+            self.flush().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Send the bodies and trailers of responses as far as windows allow.
+    async fn flush(&mut self) -> Result<(), Error> {
+        let mut sending = mem::take(&mut self.sending);
+        let mut done = Vec::new();
+
+        for (&stream_id, response) in &mut sending {
+            loop {
+                let remaining = response.body.len() - response.sent;
+
+                if remaining == 0 {
+                    if !response.trailers.is_empty() {
+                        self.write_headers(stream_id, &response.trailers, true)
+                            .await?;
+                    }
+
+                    done.push(stream_id);
+                    break;
+                }
+
+                let window = self
+                    .window
+                    .min(self.windows.get(&stream_id).copied().unwrap_or(0));
+                if window <= 0 {
+                    break;
+                }
+
+                let length = remaining
+                    .min(self.max_frame_size)
+                    .min(usize::try_from(window).unwrap_or(usize::MAX));
+                let last = length == remaining && response.trailers.is_empty();
+
+                self.write_frame(
+                    DATA,
+                    if last { END_STREAM } else { 0 },
+                    stream_id,
+                    &response.body[response.sent..response.sent + length],
+                )
+                .await?;
+
+                response.sent += length;
+                self.window -= length as i64;
+                if let Some(window) = self.windows.get_mut(&stream_id) {
+                    *window -= length as i64;
+                }
+
+                if last {
+                    done.push(stream_id);
+                    break;
+                }
+            }
+        }
+
+        for stream_id in done {
+            sending.remove(&stream_id);
+            self.windows.remove(&stream_id);
+        }
+
+        self.sending = sending;
+
+        Ok(())
+    }
+
+    /// Apply the setting of `id` told by the client.
+    fn apply_setting(&mut self, id: u16, value: u32) -> Result<(), Error> {
+        match id {
+            SETTINGS_INITIAL_WINDOW_SIZE => {
+                let value = i64::from(value);
+                if value > MAX_WINDOW_SIZE {
+                    return Err(connection_error(
+                        ErrorCode::FlowControlError,
+                        "initial window too large",
+                    ));
+                }
+
+                // Of streams open too, none of which may overflow
+                let delta = value - self.initial_window;
+                if self
+                    .windows
+                    .values()
+                    .any(|window| window + delta > MAX_WINDOW_SIZE)
+                {
+                    return Err(connection_error(
+                        ErrorCode::FlowControlError,
+                        "window overflow",
+                    ));
+                }
+
+                self.initial_window = value;
+
+                for window in self.windows.values_mut() {
+                    *window += delta;
+                }
+            }
+            SETTINGS_MAX_FRAME_SIZE => {
+                if !(16_384..=16_777_215).contains(&value) {
+                    return Err(connection_error(
+                        ErrorCode::ProtocolError,
+                        "invalid max frame size",
+                    ));
+                }
+
+                self.max_frame_size = value as usize;
+            }
+            // The dynamic table is never used by the encoder, and the rest
+            // do not matter to a server
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Forget `stream_id`, closed or reset.
+    fn close(&mut self, stream_id: u32) {
+        self.windows.remove(&stream_id);
+        self.receiving.remove(&stream_id);
+        self.sending.remove(&stream_id);
+    }
+
+    /// Write the header block of `headers`, split into `CONTINUATION` frames
+    /// as needed.
+    async fn write_headers(
+        &mut self,
+        stream_id: u32,
+        headers: &[Header],
+        end_stream: bool,
+    ) -> Result<(), Error> {
+        let block = hpack::encode(
+            headers
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+
+        let mut fragments = block.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+
+        // An empty block is still sent
+        let mut fragment = fragments.next().unwrap_or_default();
+
+        loop {
+            if fragments.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+
+            self.write_frame(kind, flags, stream_id, fragment).await?;
+
+            let Some(next) = fragments.next() else {
+                break;
+            };
+
+            fragment = next;
+            kind = CONTINUATION;
+            flags = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Write a `WINDOW_UPDATE` frame of `increment`.
+    async fn write_window_update(&mut self, stream_id: u32, increment: usize) -> Result<(), Error> {
+        self.write_frame(
+            WINDOW_UPDATE,
+            0,
+            stream_id,
+            &(increment as u32).to_be_bytes(),
+        )
+        .await
+    }
+
+    /// Write a `RST_STREAM` frame of `code`.
+    async fn write_rst_stream(&mut self, stream_id: u32, code: ErrorCode) -> Result<(), Error> {
+        self.write_frame(RST_STREAM, 0, stream_id, &(code as u32).to_be_bytes())
+            .await
+    }
+
+    /// Write a frame.
+    async fn write_frame(
+        &mut self,
+        kind: u8,
+        flags: u8,
+        stream_id: u32,
+        payload: &[u8],
+    ) -> Result<(), Error> {
+        let mut frame = Vec::with_capacity(9 + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&stream_id.to_be_bytes());
+        frame.extend_from_slice(payload);
+
+        self.writer.write_all(&frame).await?;
+
+        Ok(())
+    }
+}
+
+/// Strip the padding of the payload of a `DATA` or `HEADERS` frame with
+/// `flags`.
+fn unpad(payload: &[u8], flags: u8) -> Result<&[u8], Error> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+
+    let invalid = || connection_error(ErrorCode::ProtocolError, "invalid padding");
+
+    let (&pad_length, rest) = payload.split_first().ok_or_else(invalid)?;
+    let end = rest
+        .len()
+        .checked_sub(usize::from(pad_length))
+        .ok_or_else(invalid)?;
+
+    Ok(&rest[..end])
+}
@@ -0,0 +1,223 @@
+//! Connections served over loopback, of frames written and read by hand.
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{
+    DATA, END_HEADERS, END_STREAM, ErrorCode, Frame, GOAWAY, HEADERS, MAX_FRAME_SIZE,
+    MAX_WINDOW_SIZE, PREFACE, Request, Response, SETTINGS, SETTINGS_INITIAL_WINDOW_SIZE,
+    WINDOW_UPDATE, serve,
+};
+use crate::grpc::hpack;
+
+/// Respond with the request body, then a trailer.
+async fn echo(request: Request) -> Response {
+    Response {
+        headers: vec![(":status".to_owned(), "200".to_owned())],
+        body: request.body,
+        trailers: vec![("grpc-status".to_owned(), "0".to_owned())],
+    }
+}
+
+/// Connect to a connection served by [`echo`], the preface sent.
+async fn connect() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Bound");
+
+    let mut client = TcpStream::connect(listener.local_addr().expect("Address"))
+        .await
+        .expect("Connected");
+    let (server, _) = listener.accept().await.expect("Accepted");
+
+    tokio::spawn(serve(server, echo));
+
+    client.write_all(PREFACE).await.expect("Preface sent");
+
+    client
+}
+
+/// Write a frame.
+async fn write_frame(client: &mut TcpStream, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+    let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+    frame.push(kind);
+    frame.push(flags);
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.extend_from_slice(payload);
+
+    client.write_all(&frame).await.expect("Frame sent");
+}
+
+/// Write a `SETTINGS` frame of the setting `id` of `value`.
+async fn write_setting(client: &mut TcpStream, id: u16, value: u32) {
+    let mut payload = id.to_be_bytes().to_vec();
+    payload.extend_from_slice(&value.to_be_bytes());
+
+    write_frame(client, SETTINGS, 0, 0, &payload).await;
+}
+
+/// Read the next frame of `kind`, skipping others, e.g. `SETTINGS` and
+/// `WINDOW_UPDATE` ones.
+async fn read_frame(client: &mut TcpStream, kind: u8) -> Frame {
+    loop {
+        let mut header = [0; 9];
+        client.read_exact(&mut header).await.expect("Frame read");
+
+        let mut payload =
+            vec![0; u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize];
+        client.read_exact(&mut payload).await.expect("Payload read");
+
+        if header[3] == kind {
+            return Frame {
+                kind,
+                flags: header[4],
+                stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]]),
+                payload,
+            };
+        }
+    }
+}
+
+/// Read the `GOAWAY` frame ending the connection, of `code`.
+async fn assert_goaway(client: &mut TcpStream, code: ErrorCode) {
+    let frame = read_frame(client, GOAWAY).await;
+
+    assert_eq!(
+        u32::from_be_bytes(frame.payload[4..8].try_into().expect("Error code")),
+        code as u32
+    );
+}
+
+/// Open stream 1 by a `POST` request, ended with its headers if `end_stream`.
+async fn open_stream(client: &mut TcpStream, end_stream: bool) {
+    let block = hpack::encode([(":method", "POST"), (":path", "/echo")]);
+    let flags = END_HEADERS | if end_stream { END_STREAM } else { 0 };
+
+    write_frame(client, HEADERS, flags, 1, &block).await;
+}
+
+#[tokio::test]
+/// A request answered by headers, body and trailers.
+async fn request() {
+    let mut client = connect().await;
+    write_frame(&mut client, SETTINGS, 0, 0, &[]).await;
+
+    open_stream(&mut client, false).await;
+    write_frame(&mut client, DATA, END_STREAM, 1, b"hello").await;
+
+    let mut decoder = hpack::Decoder::new();
+
+    let headers = read_frame(&mut client, HEADERS).await;
+    assert_eq!((headers.stream_id, headers.flags), (1, END_HEADERS));
+    assert_eq!(
+        decoder.decode(&headers.payload),
+        Some(vec![(":status".to_owned(), "200".to_owned())])
+    );
+
+    let data = read_frame(&mut client, DATA).await;
+    assert_eq!((data.flags, &data.payload[..]), (0, &b"hello"[..]));
+
+    let trailers = read_frame(&mut client, HEADERS).await;
+    assert_eq!(trailers.flags, END_HEADERS | END_STREAM);
+    assert_eq!(
+        decoder.decode(&trailers.payload),
+        Some(vec![("grpc-status".to_owned(), "0".to_owned())])
+    );
+}
+
+#[tokio::test]
+/// A response body held back by the stream window till updated.
+async fn flow_control() {
+    let mut client = connect().await;
+    write_setting(&mut client, SETTINGS_INITIAL_WINDOW_SIZE, 3).await;
+
+    open_stream(&mut client, false).await;
+    write_frame(&mut client, DATA, END_STREAM, 1, b"hello").await;
+
+    let data = read_frame(&mut client, DATA).await;
+    assert_eq!(&data.payload[..], b"hel");
+
+    write_frame(&mut client, WINDOW_UPDATE, 0, 1, &2_u32.to_be_bytes()).await;
+
+    let data = read_frame(&mut client, DATA).await;
+    assert_eq!(&data.payload[..], b"lo");
+
+    let trailers = read_frame(&mut client, HEADERS).await;
+    assert_eq!(trailers.flags, END_HEADERS | END_STREAM);
+}
+
+#[tokio::test]
+/// Frames longer than the max frame size refused before read.
+async fn frame_too_large() {
+    let mut client = connect().await;
+
+    let mut header = ((MAX_FRAME_SIZE + 1) as u32).to_be_bytes()[1..].to_vec();
+    header.extend_from_slice(&[DATA, 0, 0, 0, 0, 1]);
+    client.write_all(&header).await.expect("Header sent");
+
+    assert_goaway(&mut client, ErrorCode::FrameSizeError).await;
+}
+
+#[tokio::test]
+/// `SETTINGS` frames not of whole settings refused.
+async fn settings_malformed() {
+    let mut client = connect().await;
+    write_frame(&mut client, SETTINGS, 0, 0, &[0, 4, 0, 0, 0]).await;
+
+    assert_goaway(&mut client, ErrorCode::FrameSizeError).await;
+}
+
+#[tokio::test]
+/// An initial window beyond the max refused.
+async fn initial_window_too_large() {
+    let mut client = connect().await;
+    write_setting(&mut client, SETTINGS_INITIAL_WINDOW_SIZE, 1 << 31).await;
+
+    assert_goaway(&mut client, ErrorCode::FlowControlError).await;
+}
+
+#[tokio::test]
+/// An initial window raised past the max of a stream open refused.
+async fn initial_window_overflow() {
+    let mut client = connect().await;
+    open_stream(&mut client, false).await;
+
+    // To the max exactly
+    let increment = (MAX_WINDOW_SIZE - 65_535) as u32;
+    write_frame(&mut client, WINDOW_UPDATE, 0, 1, &increment.to_be_bytes()).await;
+
+    write_setting(&mut client, SETTINGS_INITIAL_WINDOW_SIZE, 65_536).await;
+
+    assert_goaway(&mut client, ErrorCode::FlowControlError).await;
+}
+
+#[tokio::test]
+/// The connection window updated past the max refused.
+async fn window_update_overflow() {
+    let mut client = connect().await;
+
+    write_frame(
+        &mut client,
+        WINDOW_UPDATE,
+        0,
+        0,
+        &(MAX_WINDOW_SIZE as u32).to_be_bytes(),
+    )
+    .await;
+
+    assert_goaway(&mut client, ErrorCode::FlowControlError).await;
+}
+
+#[tokio::test]
+/// Window increments of 0 or not of 4 bytes refused.
+async fn window_update_malformed() {
+    let mut client = connect().await;
+    write_frame(&mut client, WINDOW_UPDATE, 0, 0, &0_u32.to_be_bytes()).await;
+
+    assert_goaway(&mut client, ErrorCode::ProtocolError).await;
+
+    let mut client = connect().await;
+    write_frame(&mut client, WINDOW_UPDATE, 0, 0, &[0, 0, 1]).await;
+
+    assert_goaway(&mut client, ErrorCode::FrameSizeError).await;
+}
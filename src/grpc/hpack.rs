@@ -0,0 +1,595 @@
+//! HPACK, the header compression of HTTP/2, see RFC 7541.
+//!
+//! Header blocks are fully decoded, dynamic table included. Header blocks
+//! sent are of literals without indexing only, never Huffman encoded, which
+//! every decoder takes and leaves its dynamic table alone.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::LazyLock,
+};
+
+/// Max size of the dynamic table, i.e. the default
+/// `SETTINGS_HEADER_TABLE_SIZE` left as is.
+const MAX_TABLE_SIZE: usize = 4096;
+
+/// Max size of a decoded header list, counted like the dynamic table.
+pub(super) const MAX_HEADER_LIST_SIZE: usize = 64 * 1024;
+
+/// Overhead of an entry of the dynamic table, besides its name and value.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// A header field, by lowercase name and value.
+pub(super) type Header = (String, String);
+
+/// The static table, indexed from 1, see RFC 7541 Appendix A.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The Huffman code by symbol, i.e. the code and its length in bits, the
+/// last being EOS, see RFC 7541 Appendix B.
+const HUFFMAN_CODE: [(u32, u8); 257] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+/// Symbols of the Huffman code, by its length and the code.
+static HUFFMAN_SYMBOLS: LazyLock<HashMap<(u8, u32), u16>> = LazyLock::new(|| {
+    (0..)
+        .zip(HUFFMAN_CODE)
+        .map(|(symbol, (code, length))| ((length, code), symbol))
+        .collect()
+});
+
+#[derive(Debug)]
+/// Decoder of header blocks of a connection, keeping the dynamic table.
+pub(super) struct Decoder {
+    /// The dynamic table, the latest entry first
+    table: VecDeque<Header>,
+
+    /// Size of the dynamic table
+    size: usize,
+
+    /// Max size of the dynamic table, as updated by the encoder
+    max_size: usize,
+}
+
+impl Decoder {
+    /// Create a new [`Decoder`] with an empty dynamic table.
+    pub(super) const fn new() -> Self {
+        Self {
+            table: VecDeque::new(),
+            size: 0,
+            max_size: MAX_TABLE_SIZE,
+        }
+    }
+
+    /// Decode the header `block`.
+    ///
+    /// `None` if malformed, a connection error, or the header list too large.
+    pub(super) fn decode(&mut self, mut block: &[u8]) -> Option<Vec<Header>> {
+        let mut headers = Vec::new();
+        let mut list_size = 0;
+
+        while let Some(&first) = block.first() {
+            let header = if first & 0x80 != 0 {
+                // Indexed
+                let index = integer(&mut block, 7)?;
+                self.get(index)?
+            } else if first & 0x40 != 0 {
+                // Literal with incremental indexing
+                let index = integer(&mut block, 6)?;
+                let header = self.literal(&mut block, index)?;
+                self.insert(header.clone());
+                header
+            } else if first & 0x20 != 0 {
+                // Dynamic table size update
+                let max_size = integer(&mut block, 5)?;
+                if max_size > MAX_TABLE_SIZE {
+                    return None;
+                }
+
+                self.max_size = max_size;
+                self.evict(0);
+                continue;
+            } else {
+                // Literal without indexing, or never indexed
+                let index = integer(&mut block, 4)?;
+                self.literal(&mut block, index)?
+            };
+
+            list_size += header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+            if list_size > MAX_HEADER_LIST_SIZE {
+                return None;
+            }
+
+            headers.push(header);
+        }
+
+        Some(headers)
+    }
+
+    /// The entry of `index`, of the static table then the dynamic one.
+    fn get(&self, index: usize) -> Option<Header> {
+        match index {
+            0 => None,
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Some((name.to_owned(), value.to_owned()))
+            }
+            _ => self.table.get(index - 62).cloned(),
+        }
+    }
+
+    /// Read a literal header field, with the name of `index` or a literal
+    /// one if 0.
+    fn literal(&self, block: &mut &[u8], index: usize) -> Option<Header> {
+        let name = match index {
+            0 => string(block)?,
+            index => self.get(index)?.0,
+        };
+
+        Some((name, string(block)?))
+    }
+
+    /// Insert `header` into the dynamic table, evicting the oldest entries
+    /// as needed.
+    fn insert(&mut self, header: Header) {
+        let size = header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+
+        self.evict(size);
+
+        // An entry larger than the table empties it
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front(header);
+        }
+    }
+
+    /// Evict the oldest entries until `reserved` bytes fit.
+    fn evict(&mut self, reserved: usize) {
+        while self.size + reserved > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+/// Encode `headers` into a header block.
+pub(super) fn encode<'a>(headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<u8> {
+    let mut block = Vec::new();
+
+    for (name, value) in headers {
+        // Literal without indexing, of a literal name
+        block.push(0);
+
+        for string in [name, value] {
+            encode_integer(&mut block, 0, 7, string.len());
+            block.extend_from_slice(string.as_bytes());
+        }
+    }
+
+    block
+}
+
+/// Read an integer of a `prefix` bits prefix.
+fn integer(block: &mut &[u8], prefix: u8) -> Option<usize> {
+    let (&first, rest) = block.split_first()?;
+    *block = rest;
+
+    let max = (1 << prefix) - 1;
+    let mut value = usize::from(first) & max;
+
+    if value < max {
+        return Some(value);
+    }
+
+    // Way more than any length or index allowed
+    for shift in [0, 7, 14, 21] {
+        let (&byte, rest) = block.split_first()?;
+        *block = rest;
+
+        value += usize::from(byte & 0x7f) << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Write `value` as an integer of a `prefix` bits prefix, the rest of the
+/// first byte being `flags`.
+fn encode_integer(block: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+    let max = (1 << prefix) - 1;
+
+    if value < max {
+        block.push(flags | value as u8);
+        return;
+    }
+
+    block.push(flags | max as u8);
+    value -= max;
+
+    while value >= 0x80 {
+        block.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    block.push(value as u8);
+}
+
+/// Read a string literal, Huffman decoded if encoded.
+fn string(block: &mut &[u8]) -> Option<String> {
+    let huffman = block.first()? & 0x80 != 0;
+    let length = integer(block, 7)?;
+
+    let data = block.get(..length)?;
+    *block = &block[length..];
+
+    let data = if huffman {
+        huffman_decode(data)?
+    } else {
+        data.to_vec()
+    };
+
+    String::from_utf8(data).ok()
+}
+
+/// Decode the Huffman encoded `data`.
+fn huffman_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(data.len() * 8 / 5);
+
+    let mut code = 0;
+    let mut length = 0;
+
+    for byte in data {
+        for bit in (0..8).rev() {
+            code = code << 1 | u32::from(byte >> bit & 1);
+            length += 1;
+
+            match HUFFMAN_SYMBOLS.get(&(length, code)) {
+                // EOS
+                Some(256) => return None,
+                Some(&symbol) => {
+                    decoded.push(symbol as u8);
+                    code = 0;
+                    length = 0;
+                }
+                None if length >= 30 => return None,
+                None => {}
+            }
+        }
+    }
+
+    // Padded with the most significant bits of EOS, i.e. all ones, to the
+    // end of the last byte
+    if length > 7 || code != (1 << length) - 1 {
+        return None;
+    }
+
+    Some(decoded)
+}
@@ -0,0 +1,325 @@
+//! Decoding of the examples of RFC 7541 Appendix C, and of blocks malformed.
+
+use super::{Decoder, MAX_HEADER_LIST_SIZE, encode, encode_integer, huffman_decode, integer};
+
+/// A header block in hex, its headers, and the size of the dynamic table
+/// after decoded.
+type Block<'a> = (&'a str, &'static [(&'static str, &'static str)], usize);
+
+/// Bytes of the hex `dump`, spaces ignored.
+fn hex(dump: &str) -> Vec<u8> {
+    let digits: Vec<u8> = dump
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).expect("ASCII"), 16).expect("Hex"))
+        .collect()
+}
+
+/// Decode the header blocks `blocks` in order by the same decoder, checking
+/// the headers of each and the size of the dynamic table after.
+fn decode_all(decoder: &mut Decoder, blocks: &[Block<'_>]) {
+    for (i, &(block, expected, size)) in blocks.iter().enumerate() {
+        let headers = decoder.decode(&hex(block)).expect("Decoded");
+
+        assert_eq!(
+            headers,
+            expected
+                .iter()
+                .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+                .collect::<Vec<_>>(),
+            "Headers of block {i}"
+        );
+        assert_eq!(decoder.size, size, "Table size after block {i}");
+    }
+}
+
+/// Requests of C.3 and C.4, the same headers of either.
+fn requests(blocks: [&str; 3]) -> [Block<'_>; 3] {
+    [
+        (
+            blocks[0],
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+            ],
+            57,
+        ),
+        (
+            blocks[1],
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ],
+            110,
+        ),
+        (
+            blocks[2],
+            &[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ],
+            164,
+        ),
+    ]
+}
+
+/// Responses of C.5 and C.6, the same headers of either, of a dynamic table
+/// of 256 bytes, set by a size update ahead of the first block.
+fn responses(blocks: [&str; 3]) -> [Block<'_>; 3] {
+    [
+        (
+            blocks[0],
+            &[
+                (":status", "302"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                ("location", "https://www.example.com"),
+            ],
+            222,
+        ),
+        (
+            blocks[1],
+            &[
+                (":status", "307"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+                ("location", "https://www.example.com"),
+            ],
+            222,
+        ),
+        (
+            blocks[2],
+            &[
+                (":status", "200"),
+                ("cache-control", "private"),
+                ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+                ("location", "https://www.example.com"),
+                ("content-encoding", "gzip"),
+                (
+                    "set-cookie",
+                    "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1",
+                ),
+            ],
+            215,
+        ),
+    ]
+}
+
+#[test]
+/// C.1, integers of prefixes of 5 and 8 bits, both ways.
+fn integers() {
+    for (dump, prefix, value) in [("0a", 5, 10), ("1f9a0a", 5, 1337), ("2a", 8, 42)] {
+        let bytes = hex(dump);
+
+        let mut block = &bytes[..];
+        assert_eq!(integer(&mut block, prefix), Some(value), "{dump}");
+        assert!(block.is_empty(), "All of {dump} read");
+
+        let mut encoded = Vec::new();
+        encode_integer(&mut encoded, 0, prefix, value);
+        assert_eq!(encoded, bytes, "{value} encoded");
+    }
+
+    // Flags kept of the first byte
+    let mut encoded = Vec::new();
+    encode_integer(&mut encoded, 0xe0, 5, 10);
+    assert_eq!(encoded, [0xea]);
+
+    // Truncated, and of more continuation bytes than any length allowed
+    assert_eq!(integer(&mut &hex("1f9a")[..], 5), None);
+    assert_eq!(integer(&mut &hex("1fffffffff7f")[..], 5), None);
+}
+
+#[test]
+/// Strings of C.4, and the padding of EOS checked.
+fn huffman() {
+    assert_eq!(
+        huffman_decode(&hex("f1e3 c2e5 f23a 6ba0 ab90 f4ff")).as_deref(),
+        Some(&b"www.example.com"[..])
+    );
+    assert_eq!(
+        huffman_decode(&hex("a8eb 1064 9cbf")).as_deref(),
+        Some(&b"no-cache"[..])
+    );
+    assert_eq!(
+        huffman_decode(&hex("25a8 49e9 5ba9 7d7f")).as_deref(),
+        Some(&b"custom-key"[..])
+    );
+
+    // Padding not of ones, longer than 7 bits, or EOS itself
+    assert_eq!(huffman_decode(&hex("a8eb 1064 9cbe")), None);
+    assert_eq!(huffman_decode(&hex("a8eb 1064 9c")), None);
+    assert_eq!(
+        huffman_decode(&hex("f1e3 c2e5 f23a 6ba0 ab90 f4ff ff")),
+        None
+    );
+    assert_eq!(huffman_decode(&hex("ffff fffc")), None);
+}
+
+#[test]
+/// C.3, requests without Huffman coding.
+fn requests_plain() {
+    decode_all(
+        &mut Decoder::new(),
+        &requests([
+            "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "8286 84be 5808 6e6f 2d63 6163 6865",
+            "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+        ]),
+    );
+}
+
+#[test]
+/// C.4, requests with Huffman coding.
+fn requests_huffman() {
+    decode_all(
+        &mut Decoder::new(),
+        &requests([
+            "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+            "8286 84be 5886 a8eb 1064 9cbf",
+            "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+        ]),
+    );
+}
+
+#[test]
+/// C.5, responses without Huffman coding, the oldest entries evicted.
+fn responses_plain() {
+    let mut decoder = Decoder::new();
+
+    decode_all(
+        &mut decoder,
+        &responses([
+            "3fe1 01 4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 \
+             3133 2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70 \
+             6c65 2e63 6f6d",
+            "4803 3330 37c1 c0bf",
+            "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220 474d 54c0 \
+             5a04 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157 454f 5049 5541 5851 \
+             5745 4f49 553b 206d 6178 2d61 6765 3d33 3630 303b 2076 6572 7369 6f6e 3d31",
+        ]),
+    );
+
+    assert_eq!(
+        decoder
+            .table
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>(),
+        ["set-cookie", "content-encoding", "date"]
+    );
+}
+
+#[test]
+/// C.6, responses with Huffman coding, the oldest entries evicted.
+fn responses_huffman() {
+    let mut decoder = Decoder::new();
+
+    decode_all(
+        &mut decoder,
+        &responses([
+            "3fe1 01 4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 \
+             82a6 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+            "4883 640e ffc1 c0bf",
+            "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab 77ad \
+             94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f 9587 3160 \
+             65c0 03ed 4ee5 b106 3d50 07",
+        ]),
+    );
+
+    assert_eq!(decoder.table.len(), 3);
+}
+
+#[test]
+/// Size updates evicting entries, those beyond the max refused, and an
+/// entry larger than the table emptying it.
+fn table_size() {
+    let mut decoder = Decoder::new();
+
+    decode_all(
+        &mut decoder,
+        &requests([
+            "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "8286 84be 5808 6e6f 2d63 6163 6865",
+            "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+        ]),
+    );
+
+    // 110, the oldest entry evicted
+    decoder.decode(&hex("3f4f")).expect("Size updated");
+    assert_eq!((decoder.size, decoder.table.len()), (107, 2));
+
+    // 0
+    decoder.decode(&hex("20")).expect("Size updated");
+    assert_eq!((decoder.size, decoder.table.len()), (0, 0));
+
+    // 4097, larger than the max
+    assert_eq!(decoder.decode(&hex("3fe2 1f")), None);
+
+    // 64, an entry of 57 bytes fitting, then one of 67 not
+    decoder.decode(&hex("3f21")).expect("Size updated");
+    decoder
+        .decode(&hex("410f 7777 772e 6578 616d 706c 652e 636f 6d"))
+        .expect("Entry inserted");
+    assert_eq!((decoder.size, decoder.table.len()), (57, 1));
+
+    decoder
+        .decode(&hex("400a 6375 7374 6f6d 2d6b 6579 1963 7573 746f 6d2d \
+                      7661 6c75 652d 6375 7374 6f6d 2d76 616c 7565"))
+        .expect("Entry not inserted");
+    assert_eq!((decoder.size, decoder.table.len()), (0, 0));
+
+    // Of the dynamic table, now empty
+    assert_eq!(decoder.decode(&hex("be")), None);
+}
+
+#[test]
+/// Blocks malformed or too large refused.
+fn malformed() {
+    // Index 0, beyond both tables, and a literal truncated
+    assert_eq!(Decoder::new().decode(&hex("80")), None);
+    assert_eq!(Decoder::new().decode(&hex("c6")), None);
+    assert_eq!(Decoder::new().decode(&hex("400a 6375 7374")), None);
+
+    // A value of no UTF-8
+    assert_eq!(Decoder::new().decode(&hex("0001 6101 ff")), None);
+
+    let value = "x".repeat(MAX_HEADER_LIST_SIZE);
+    let block = encode([("large", value.as_str())]);
+    assert_eq!(Decoder::new().decode(&block), None);
+}
+
+#[test]
+/// Blocks encoded decoded as they are, leaving the dynamic table alone.
+fn round_trip() {
+    let value = "v".repeat(300);
+    let headers = [
+        (":status", "200"),
+        ("content-type", "application/grpc"),
+        ("long", value.as_str()),
+    ];
+
+    let mut decoder = Decoder::new();
+    let decoded = decoder.decode(&encode(headers)).expect("Decoded");
+
+    assert_eq!(
+        decoded,
+        headers
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect::<Vec<_>>()
+    );
+    assert!(decoder.table.is_empty());
+}
@@ -0,0 +1,259 @@
+//! `bilibili.app.playurl.v1.PlayURL` service.
+//!
+//! `PlayView` is served by the playurl resolved from the web API, see
+//! [`playurl::fetch`], the URLs pointed at the resource route like the
+//! `/playurl` endpoint does, see [`service::playurl`].
+
+#[cfg(test)]
+mod tests;
+
+use super::{
+    Code, Status,
+    h2::Request,
+    protobuf::{self, Message},
+};
+use crate::{
+    config::Config,
+    playurl::{self, ApiResponse, Durl, Format, Playurl, Query, Stream, Video},
+    service::{self, resource},
+};
+
+/// Path of `PlayView`.
+pub(super) const PLAY_VIEW: &str = "/bilibili.app.playurl.v1.PlayURL/PlayView";
+
+/// `PlayView`, replying `PlayViewReply` to `PlayViewReq`, of which only
+/// `aid`, `cid`, `qn`, `fnver`, `fnval`, `fourk` and `prefer_codec_type` are
+/// taken.
+pub(super) async fn play_view(request: &Request, message: &[u8]) -> Result<Vec<u8>, Status> {
+    let config = Config::current();
+
+    let Some(playurl_config) = &config.playurl else {
+        return Err(Status::new(Code::Unavailable, "Playurl not configured"));
+    };
+
+    let query = query(message)?;
+
+    let api_response = playurl::fetch(playurl_config, &query).await.map_err(|e| {
+        tracing::error!(
+            "Fetch playurl of {} {} error: {e:#}",
+            query.video,
+            query.cid
+        );
+        Status::new(Code::Unavailable, "Playurl API not reachable")
+    })?;
+
+    let api_response =
+        serde_json::from_value::<ApiResponse<Playurl>>(api_response).map_err(|e| {
+            tracing::error!("Parse playurl of {} {} error: {e}", query.video, query.cid);
+            Status::new(Code::Unknown, "Invalid playurl")
+        })?;
+
+    if api_response.code != 0 {
+        return Err(Status::new(
+            if api_response.code == -404 {
+                Code::NotFound
+            } else {
+                Code::Unknown
+            },
+            format!("{}: {}", api_response.code, api_response.message),
+        ));
+    }
+
+    let Some(mut playurl) = api_response.data else {
+        return Err(Status::new(Code::Unknown, "No playurl"));
+    };
+
+    playurl::expect_segments(&playurl);
+
+    let base = match &playurl_config.public_url {
+        Some(public_url) => public_url.trim_end_matches('/').to_owned(),
+        None => {
+            // The host the client reaches, the HTTP port
+            let host = request
+                .header(":authority")
+                .map(|authority| {
+                    authority
+                        .rsplit_once(':')
+                        .map_or(authority, |(host, _)| host)
+                })
+                .unwrap_or("127.0.0.1");
+
+            format!("http://{host}:{}", config.listen.port())
+        }
+    };
+
+    let token = resource::mint_session(query.video.to_string());
+
+    service::playurl::rewrite(&mut playurl, &base, token.as_deref());
+
+    Ok(reply(&playurl, query.codecid).into_bytes())
+}
+
+/// The query of the `PlayViewReq` `message`.
+fn query(message: &[u8]) -> Result<Query, Status> {
+    let fields = protobuf::decode(message)
+        .ok_or_else(|| Status::new(Code::InvalidArgument, "Invalid PlayViewReq"))?;
+
+    let (aid, cid) = (
+        protobuf::varint_field(&fields, 1),
+        protobuf::varint_field(&fields, 2),
+    );
+    if aid == 0 || cid == 0 {
+        return Err(Status::new(Code::InvalidArgument, "No aid or cid"));
+    }
+
+    Ok(Query {
+        video: Video::Aid(aid),
+        cid,
+        qn: match protobuf::varint_field(&fields, 3) as u32 {
+            0 => 80,
+            qn => qn,
+        },
+        // FLV / MP4 if 0, as of the web API
+        fnval: protobuf::varint_field(&fields, 5) as u32,
+        fnver: protobuf::varint_field(&fields, 4) as u32,
+        fourk: protobuf::varint_field(&fields, 8) != 0,
+        // `prefer_codec_type`, of `CodeType`
+        codecid: match protobuf::varint_field(&fields, 12) {
+            1 => Some(7),
+            2 => Some(12),
+            3 => Some(13),
+            _ => None,
+        },
+    })
+}
+
+/// `PlayViewReply` of `playurl`, i.e. `video_info` only, the video streams of
+/// codec `codecid` preferred if any, or of `video_codecid`.
+fn reply(playurl: &Playurl, codecid: Option<u32>) -> Message {
+    let codecid = codecid.unwrap_or(playurl.video_codecid);
+
+    let audio = playurl
+        .dash
+        .as_ref()
+        .and_then(|dash| dash.audio.as_deref())
+        .unwrap_or_default();
+
+    // Of the quality given, as the streams picked
+    let video_codecid = match &playurl.dash {
+        Some(dash)
+            if dash
+                .video
+                .iter()
+                .any(|video| video.id == playurl.quality && video.codecid == codecid) =>
+        {
+            codecid
+        }
+        _ => playurl.video_codecid,
+    };
+
+    let mut video_info = Message::default();
+    video_info
+        .uint(1, u64::from(playurl.quality))
+        .string(2, &playurl.format)
+        .uint(3, playurl.timelength)
+        .uint(4, u64::from(video_codecid));
+
+    for format in &playurl.support_formats {
+        let mut stream = Message::default();
+        stream.message(1, &stream_info(format));
+
+        match &playurl.dash {
+            Some(dash) => {
+                let video = dash
+                    .video
+                    .iter()
+                    .filter(|video| video.id == format.quality)
+                    .min_by_key(|video| video.codecid != codecid);
+
+                if let Some(video) = video {
+                    let audio_id =
+                        playurl::paired_audio(video.id, audio).map_or(0, |audio| audio.id);
+
+                    stream.message(2, &dash_video(video, audio_id));
+                }
+            }
+            None if format.quality == playurl.quality => {
+                stream.message(3, &segment_video(&playurl.durl));
+            }
+            None => {}
+        }
+
+        video_info.message(5, &stream);
+    }
+
+    for stream in audio {
+        video_info.message(6, &dash_item(stream));
+    }
+
+    let mut reply = Message::default();
+    reply.message(1, &video_info);
+
+    reply
+}
+
+/// `StreamInfo` of `format`.
+fn stream_info(format: &Format) -> Message {
+    let mut stream_info = Message::default();
+    stream_info
+        .uint(1, u64::from(format.quality))
+        .string(2, &format.format)
+        .string(3, &format.new_description)
+        .bool(8, true)
+        .string(11, &format.new_description)
+        .string(12, &format.display_desc)
+        .string(13, &format.superscript);
+
+    stream_info
+}
+
+/// `DashVideo` of the video `stream`, played along with the audio stream of
+/// `audio_id`.
+fn dash_video(stream: &Stream, audio_id: u32) -> Message {
+    let mut dash_video = Message::default();
+    dash_video
+        .string(1, &stream.base_url)
+        .strings(2, stream.backup_url.iter().flatten())
+        .uint(3, stream.bandwidth)
+        .uint(4, u64::from(stream.codecid))
+        .uint(7, u64::from(audio_id))
+        .string(9, &stream.frame_rate)
+        .uint(10, u64::from(stream.width))
+        .uint(11, u64::from(stream.height));
+
+    dash_video
+}
+
+/// `SegmentVideo` of `durl`.
+fn segment_video(durl: &[Durl]) -> Message {
+    let mut segment_video = Message::default();
+
+    for segment in durl {
+        let mut response_url = Message::default();
+        response_url
+            .uint(1, u64::from(segment.order))
+            .uint(2, segment.length)
+            .uint(3, segment.size)
+            .string(4, &segment.url)
+            .strings(5, segment.backup_url.iter().flatten())
+            .string(6, &segment.md5);
+
+        segment_video.message(1, &response_url);
+    }
+
+    segment_video
+}
+
+/// `DashItem` of the audio `stream`.
+fn dash_item(stream: &Stream) -> Message {
+    let mut dash_item = Message::default();
+    dash_item
+        .uint(1, u64::from(stream.id))
+        .string(2, &stream.base_url)
+        .strings(3, stream.backup_url.iter().flatten())
+        .uint(4, stream.bandwidth)
+        .uint(5, u64::from(stream.codecid))
+        .string(8, &stream.frame_rate);
+
+    dash_item
+}
@@ -0,0 +1,185 @@
+//! `PlayViewReq` decoded, and `PlayViewReply` encoded of a captured playurl.
+
+use super::{query, reply};
+use crate::{
+    grpc::{Code, Status, protobuf::Message},
+    playurl::{ApiResponse, Playurl, Video},
+};
+
+/// A field of a message being checked.
+enum Field<'a> {
+    Varint(u64),
+    Len(&'a [u8]),
+}
+
+/// Decode all fields of `data`, of no other wire types than varint and
+/// length-delimited, as the reply has.
+fn fields(mut data: &[u8]) -> Vec<(u32, Field<'_>)> {
+    let mut fields = Vec::new();
+
+    while !data.is_empty() {
+        let key = varint(&mut data);
+
+        let field = if key & 0x7 == 0 {
+            Field::Varint(varint(&mut data))
+        } else {
+            assert_eq!(key & 0x7, 2, "Wire type");
+
+            let length = varint(&mut data) as usize;
+            let (value, rest) = data.split_at(length);
+            data = rest;
+
+            Field::Len(value)
+        };
+
+        fields.push(((key >> 3) as u32, field));
+    }
+
+    fields
+}
+
+/// Read a varint.
+fn varint(data: &mut &[u8]) -> u64 {
+    let mut value = 0;
+
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().expect("Varint");
+        *data = rest;
+
+        value |= u64::from(byte & 0x7f) << shift;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    value
+}
+
+/// The varint field `number` of `fields`.
+fn uint(fields: &[(u32, Field<'_>)], number: u32) -> u64 {
+    fields
+        .iter()
+        .find_map(|(n, field)| match field {
+            Field::Varint(value) if *n == number => Some(*value),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+/// The length-delimited fields `number` of `fields`.
+fn lens<'a>(fields: &[(u32, Field<'a>)], number: u32) -> Vec<&'a [u8]> {
+    fields
+        .iter()
+        .filter_map(|(n, field)| match field {
+            Field::Len(value) if *n == number => Some(*value),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+/// The fields taken of a request, others skipped.
+fn request() {
+    let mut message = Message::default();
+    message
+        .uint(1, 170_001)
+        .uint(2, 279_786)
+        .uint(3, 116)
+        .uint(4, 0)
+        .uint(5, 4048)
+        .string(7, "skipped")
+        .bool(8, true)
+        .uint(12, 2);
+
+    let taken = query(&message.into_bytes()).expect("Query");
+
+    assert!(matches!(taken.video, Video::Aid(170_001)));
+    assert_eq!(
+        (taken.cid, taken.qn, taken.fnver, taken.fnval, taken.fourk),
+        (279_786, 116, 0, 4048, true)
+    );
+    assert_eq!(taken.codecid, Some(12));
+
+    // The defaults, of 1080P and no codec preferred
+    let mut message = Message::default();
+    message.uint(1, 170_001).uint(2, 279_786).uint(12, 4);
+
+    let taken = query(&message.into_bytes()).expect("Query");
+
+    assert_eq!((taken.qn, taken.fnval, taken.codecid), (80, 0, None));
+}
+
+#[test]
+/// Requests malformed, or of no video, refused.
+fn request_invalid() {
+    let mut message = Message::default();
+    message.uint(1, 170_001);
+
+    for message in [message.into_bytes(), vec![0x08, 0x80]] {
+        assert!(matches!(
+            query(&message),
+            Err(Status {
+                code: Code::InvalidArgument,
+                ..
+            })
+        ));
+    }
+}
+
+#[test]
+/// A reply of DASH streams, the video ones of the codec preferred if any.
+fn reply_dash() {
+    let response: ApiResponse<Playurl> =
+        serde_json::from_str(include_str!("../../../tests/fixtures/playurl/dash.json"))
+            .expect("Captured response");
+    let playurl = response.data.expect("Playurl");
+
+    let reply = reply(&playurl, Some(12)).into_bytes();
+    let reply = fields(&reply);
+
+    let video_info = lens(&reply, 1);
+    assert_eq!(video_info.len(), 1);
+
+    let video_info = fields(video_info[0]);
+    assert_eq!(uint(&video_info, 1), 80);
+    assert_eq!(lens(&video_info, 2), [b"flv"]);
+    assert_eq!(uint(&video_info, 3), 212_312);
+    // Of no stream of HEVC at 1080P
+    assert_eq!(uint(&video_info, 4), 7);
+
+    // Of each format, of no video at 112
+    let streams: Vec<_> = lens(&video_info, 5).into_iter().map(fields).collect();
+    assert_eq!(streams.len(), 3);
+
+    let videos: Vec<_> = streams
+        .iter()
+        .map(|stream| {
+            let stream_info = fields(lens(stream, 1)[0]);
+            let dash_video = lens(stream, 2).first().copied().map(fields);
+
+            (
+                uint(&stream_info, 1),
+                dash_video.map(|dash_video| {
+                    (
+                        uint(&dash_video, 4),
+                        uint(&dash_video, 7),
+                        lens(&dash_video, 1)[0].ends_with(b"&logo=80000000"),
+                    )
+                }),
+            )
+        })
+        .collect();
+    assert_eq!(
+        videos,
+        [
+            (112, None),
+            (80, Some((7, 30280, true))),
+            (64, Some((12, 30280, false)))
+        ]
+    );
+
+    let audio = lens(&video_info, 6);
+    assert_eq!(audio.len(), 1);
+    assert_eq!(uint(&fields(audio[0]), 1), 30280);
+}
@@ -0,0 +1,167 @@
+//! Protocol Buffers wire format, just enough for the messages of the
+//! services served, see the [encoding] spec.
+//!
+//! Messages are decoded into their varint fields by number, see [`decode`],
+//! and encoded field by field, see [`Message`], the proto3 default values
+//! omitted.
+//!
+//! [encoding]: https://protobuf.dev/programming-guides/encoding/
+
+#[cfg(test)]
+mod tests;
+
+/// Wire type of `int32`, `uint64`, `bool`, enums, etc.
+const VARINT: u8 = 0;
+
+/// Wire type of `fixed64`, `double`, etc.
+const I64: u8 = 1;
+
+/// Wire type of `string`, `bytes`, messages and packed repeated fields.
+const LEN: u8 = 2;
+
+/// Wire type of `fixed32`, `float`, etc.
+const I32: u8 = 5;
+
+/// Decode the varint fields of the message `data`, by number. Fields of
+/// other types are skipped, the requests served having none of use.
+///
+/// `None` if malformed.
+pub(super) fn decode(mut data: &[u8]) -> Option<Vec<(u32, u64)>> {
+    let mut fields = Vec::new();
+
+    while !data.is_empty() {
+        let key = varint(&mut data)?;
+        let number = u32::try_from(key >> 3).ok().filter(|&number| number > 0)?;
+
+        match (key & 0x7) as u8 {
+            VARINT => fields.push((number, varint(&mut data)?)),
+            I64 => {
+                take(&mut data, 8)?;
+            }
+            LEN => {
+                let length = usize::try_from(varint(&mut data)?).ok()?;
+                take(&mut data, length)?;
+            }
+            I32 => {
+                take(&mut data, 4)?;
+            }
+            _ => return None,
+        }
+    }
+
+    Some(fields)
+}
+
+/// The last value of the varint field `number` in `fields`, 0 if absent as
+/// proto3 takes it.
+pub(super) fn varint_field(fields: &[(u32, u64)], number: u32) -> u64 {
+    fields
+        .iter()
+        .rev()
+        .find_map(|&(n, value)| (n == number).then_some(value))
+        .unwrap_or(0)
+}
+
+/// Read a varint.
+fn varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+
+        value |= u64::from(byte & 0x7f) << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Take `length` bytes.
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Option<&'a [u8]> {
+    let taken = data.get(..length)?;
+    *data = &data[length..];
+
+    Some(taken)
+}
+
+#[derive(Debug, Default)]
+/// A message being encoded.
+pub(super) struct Message(Vec<u8>);
+
+impl Message {
+    /// Encode an unsigned integer field, like `uint32` or a non-negative
+    /// `int64`.
+    pub(super) fn uint(&mut self, number: u32, value: u64) -> &mut Self {
+        if value != 0 {
+            self.key(number, VARINT);
+            self.varint(value);
+        }
+
+        self
+    }
+
+    /// Encode a `bool` field.
+    pub(super) fn bool(&mut self, number: u32, value: bool) -> &mut Self {
+        self.uint(number, u64::from(value))
+    }
+
+    /// Encode a `string` field.
+    pub(super) fn string(&mut self, number: u32, value: &str) -> &mut Self {
+        if !value.is_empty() {
+            self.len(number, value.as_bytes());
+        }
+
+        self
+    }
+
+    /// Encode a `repeated string` field.
+    pub(super) fn strings<'a>(
+        &mut self,
+        number: u32,
+        values: impl IntoIterator<Item = &'a String>,
+    ) -> &mut Self {
+        for value in values {
+            self.len(number, value.as_bytes());
+        }
+
+        self
+    }
+
+    /// Encode a message field, present even if empty.
+    pub(super) fn message(&mut self, number: u32, value: &Self) -> &mut Self {
+        self.len(number, &value.0);
+
+        self
+    }
+
+    /// The message encoded.
+    pub(super) fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Encode a length-delimited field.
+    fn len(&mut self, number: u32, value: &[u8]) {
+        self.key(number, LEN);
+        self.varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+    }
+
+    /// Encode the key of field `number`.
+    fn key(&mut self, number: u32, wire_type: u8) {
+        self.varint(u64::from(number) << 3 | u64::from(wire_type));
+    }
+
+    /// Encode a varint.
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+
+        self.0.push(value as u8);
+    }
+}
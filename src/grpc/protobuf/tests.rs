@@ -0,0 +1,76 @@
+//! Messages encoded and decoded back, and those malformed refused.
+
+use super::{Message, decode, varint_field};
+
+#[test]
+/// Varint fields decoded as encoded, those of other wire types skipped and
+/// default values omitted.
+fn round_trip() {
+    let mut nested = Message::default();
+    nested.uint(1, 1);
+
+    let mut message = Message::default();
+    message
+        .uint(1, 170_001)
+        .uint(2, u64::MAX)
+        .uint(3, 0)
+        .bool(4, true)
+        .bool(5, false)
+        .string(6, "skipped")
+        .message(7, &nested)
+        .uint(536_870_911, 300);
+
+    let fields = decode(&message.into_bytes()).expect("Decoded");
+
+    assert_eq!(
+        fields,
+        [(1, 170_001), (2, u64::MAX), (4, 1), (536_870_911, 300)]
+    );
+    assert_eq!(varint_field(&fields, 2), u64::MAX);
+    assert_eq!(varint_field(&fields, 3), 0);
+}
+
+#[test]
+/// The last value of repeated fields taken, as proto3 does.
+fn last_value() {
+    let mut message = Message::default();
+    message.uint(1, 1).uint(1, 2);
+
+    assert_eq!(
+        varint_field(&decode(&message.into_bytes()).expect("Decoded"), 1),
+        2
+    );
+}
+
+#[test]
+/// Fixed size fields skipped.
+fn fixed() {
+    // `fixed64` 1 of 0x01..0x08, `fixed32` 2 of 0x01..0x04, then varint 3 of 1
+    let data = [0x09, 1, 2, 3, 4, 5, 6, 7, 8, 0x15, 1, 2, 3, 4, 0x18, 1];
+
+    assert_eq!(decode(&data), Some(vec![(3, 1)]));
+}
+
+#[test]
+/// Messages malformed refused.
+fn malformed() {
+    for data in [
+        // Varint truncated
+        &[0x08, 0x80][..],
+        // Varint longer than 10 bytes
+        &[
+            0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+        ][..],
+        // Field number 0
+        &[0x00, 0x01][..],
+        // Groups, i.e. wire types 3 and 4
+        &[0x0b][..],
+        &[0x0c][..],
+        // Length beyond the message
+        &[0x12, 0x05, b'a'][..],
+        // Fixed size field truncated
+        &[0x09, 1, 2, 3][..],
+    ] {
+        assert_eq!(decode(data), None, "{data:02x?}");
+    }
+}
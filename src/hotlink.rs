@@ -0,0 +1,106 @@
+//! Anti-hotlinking of resources, see [`HotlinkConfig`].
+//!
+//! Like upos, requests of pages not of the origins allowed are refused, so
+//! that the cache is not taken as a file host by other sites. The origin is
+//! told by `Origin` if sent, as of CORS requests, or else of `Referer`.
+//!
+//! Requests telling neither, e.g. of apps, or pages of
+//! `Referrer-Policy: no-referrer`, are served as configured, see
+//! [`HotlinkConfig::allow_empty`], and so are those of `Origin: null` sent by
+//! sandboxed pages.
+
+use http::header::{ORIGIN, REFERER};
+
+use crate::{config::HotlinkConfig, proto};
+
+#[derive(Debug, Clone)]
+#[derive(thiserror::Error)]
+/// Why a request is refused.
+pub(crate) enum Error {
+    #[error("Neither Referer nor Origin told")]
+    /// Neither `Referer` nor `Origin` told, and not allowed
+    Empty,
+
+    #[error("Origin {0:?} not allowed")]
+    /// Of a page not of the origins allowed
+    NotAllowed(String),
+}
+
+/// Check the origin of the page `request` is of, see the [module-level
+/// documentation](self).
+pub(crate) fn check(config: &HotlinkConfig, request: &proto::Request) -> Result<(), Error> {
+    let Some(origin) = origin(request) else {
+        return if config.allow_empty {
+            Ok(())
+        } else {
+            Err(Error::Empty)
+        };
+    };
+
+    if config
+        .allowed_origins
+        .iter()
+        .any(|allowed| matches(allowed, &origin))
+    {
+        Ok(())
+    } else {
+        Err(Error::NotAllowed(origin))
+    }
+}
+
+/// The origin of the page `request` is of, told by `Origin` if sent, or else
+/// of `Referer`. `None` if neither told, or `Origin: null`.
+pub(crate) fn origin(request: &proto::Request) -> Option<String> {
+    let header = |name| {
+        request
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+
+    match (header(ORIGIN), header(REFERER)) {
+        (Some("null"), _) | (None, None) => None,
+        (Some(origin), _) => Some(origin.to_owned()),
+        (None, Some(referer)) => Some(origin_of(referer)),
+    }
+}
+
+/// The origin of `url`, i.e. the scheme and host, the user info left out,
+/// or `url` as is if not absolute.
+fn origin_of(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_owned();
+    };
+
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+
+    format!("{scheme}://{host}")
+}
+
+/// Whether `origin` matches `allowed`, the host of which may start with `*.`
+/// for any subdomain. Case-insensitive.
+pub(crate) fn matches(allowed: &str, origin: &str) -> bool {
+    let (Some((allowed_scheme, allowed_host)), Some((scheme, host))) =
+        (allowed.split_once("://"), origin.split_once("://"))
+    else {
+        return allowed.eq_ignore_ascii_case(origin);
+    };
+
+    if !allowed_scheme.eq_ignore_ascii_case(scheme) {
+        return false;
+    }
+
+    match allowed_host.strip_prefix('*') {
+        Some(suffix) => {
+            host.len() > suffix.len()
+                && host.is_char_boundary(host.len() - suffix.len())
+                && host[host.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+        }
+        None => allowed_host.eq_ignore_ascii_case(host),
+    }
+}
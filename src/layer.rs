@@ -0,0 +1,95 @@
+//! Layers wrapping handlers, see [`Layer`], so that concerns shared by
+//! routes, e.g. methods allowed, rate limiting and the firewall, are written
+//! once rather than in each handler.
+//!
+//! A layer is added to a route by [`Handler::layer`], or to all the routes of
+//! a router by [`Router::layer`](crate::Router::layer), the one added last
+//! being the outermost, i.e. called first.
+
+use std::sync::Arc;
+
+use crate::{
+    proto::{AnyStream, Request},
+    router::{Handler, HandlerFuture, Params},
+};
+
+/// A wrapper of handlers, e.g. refusing some requests before handled.
+pub trait Layer: Send + Sync {
+    /// Wrap `handler`.
+    fn layer(&self, handler: Box<dyn Handler>) -> Box<dyn Handler>;
+}
+
+#[derive(Clone, Copy)]
+/// The handler wrapped by a layer of [`layer_fn`], to be called on.
+pub struct Next<'a>(&'a dyn Handler);
+
+impl std::fmt::Debug for Next<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Next")
+    }
+}
+
+impl<'a> Next<'a> {
+    /// Handle `request` by the handler wrapped.
+    pub fn run(
+        self,
+        request: &'a Request,
+        params: &'a Params,
+        stream: &'a mut AnyStream,
+    ) -> HandlerFuture<'a> {
+        self.0.call(request, params, stream)
+    }
+}
+
+/// A layer of the closure `f`, given the handler wrapped as [`Next`], e.g.
+/// `layer_fn(|request, params, stream, next| next.run(request, params,
+/// stream))`.
+pub fn layer_fn<F>(f: F) -> impl Layer
+where
+    F: for<'a> Fn(&'a Request, &'a Params, &'a mut AnyStream, Next<'a>) -> HandlerFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    FnLayer(Arc::new(f))
+}
+
+/// Layer of [`layer_fn`], shared by the handlers wrapped.
+struct FnLayer<F>(Arc<F>);
+
+impl<F> Layer for FnLayer<F>
+where
+    F: for<'a> Fn(&'a Request, &'a Params, &'a mut AnyStream, Next<'a>) -> HandlerFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    fn layer(&self, handler: Box<dyn Handler>) -> Box<dyn Handler> {
+        Box::new(Layered {
+            f: self.0.clone(),
+            handler,
+        })
+    }
+}
+
+/// A handler wrapped by a layer of [`layer_fn`].
+struct Layered<F> {
+    f: Arc<F>,
+    handler: Box<dyn Handler>,
+}
+
+impl<F> Handler for Layered<F>
+where
+    F: for<'a> Fn(&'a Request, &'a Params, &'a mut AnyStream, Next<'a>) -> HandlerFuture<'a>
+        + Send
+        + Sync,
+{
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        params: &'a Params,
+        stream: &'a mut AnyStream,
+    ) -> HandlerFuture<'a> {
+        (self.f)(request, params, stream, Next(&*self.handler))
+    }
+}
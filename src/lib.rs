@@ -0,0 +1,497 @@
+//! Mikufans-BVC-Server
+//!
+//! The server is run by [`run`], serving the [`routes`] of its services, or a
+//! [`Router`] of others, e.g. of a binary adding its own [`Handler`]s.
+//!
+//! Subsystems besides serving files are of features, all on by default, so
+//! that a minimal build stays small, e.g. for routers: `tls`, `upstream`,
+//! `playurl`, `metrics`, `admin`, `sessions` and `signing`. Those configured
+//! but not built are refused on loading the config.
+
+mod access_log;
+#[cfg(feature = "upstream")]
+mod alert;
+#[cfg(feature = "admin")]
+mod api_key;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+mod buf;
+mod cache;
+mod client;
+mod config;
+mod connection;
+#[cfg(feature = "playurl")]
+mod credentials;
+mod daemon;
+mod error;
+#[cfg(feature = "admin")]
+mod error_log;
+mod extract;
+mod firewall;
+#[cfg(feature = "fuzz")]
+#[doc(hidden)]
+pub mod fuzz;
+#[cfg(feature = "playurl")]
+mod grpc;
+mod hotlink;
+mod layer;
+mod listener;
+mod logging;
+mod metrics;
+mod mp4;
+mod otlp;
+#[cfg(feature = "playurl")]
+mod playurl;
+mod proto;
+mod proxy_protocol;
+mod rate_limit;
+mod request_id;
+mod router;
+mod routes;
+mod security_headers;
+mod service;
+#[cfg(feature = "sessions")]
+mod session;
+#[cfg(feature = "signing")]
+mod sign;
+mod slow_log;
+mod timing;
+#[cfg(feature = "tls")]
+mod tls;
+mod transfer;
+mod upgrade;
+#[cfg(feature = "upstream")]
+mod upstream;
+mod usage;
+mod utils;
+#[cfg(feature = "playurl")]
+mod wbi;
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+pub use config::Args;
+pub use error::Error;
+use error::IntoResponse;
+pub use extract::{FromRequest, Header, Host, Path, Query, Rejection, TypedHeader, extract};
+use http::StatusCode;
+pub use layer::{Layer, Next, layer_fn};
+pub use proto::{AnyStream, Request, Stream};
+pub use router::{Handler, HandlerFuture, Params, Router, from_fn};
+pub use routes::routes;
+use tokio::{
+    runtime::{Builder, Runtime},
+    task::yield_now,
+    time::sleep,
+};
+
+/// Run the server of `args`, serving `router`, until shut down.
+///
+/// # Errors
+///
+/// If failed to start, e.g. of an invalid config, or to shut down cleanly.
+pub fn run(args: &Args, router: Router) -> Result<()> {
+    config::Config::init(args)?;
+
+    // Before the runtime built, see `daemon::daemonize`
+    if args.daemon {
+        daemon::daemonize()?;
+    }
+
+    let _pid_file = args
+        .pidfile
+        .as_deref()
+        .map(daemon::PidFile::create)
+        .transpose()?;
+
+    runtime(&config::Config::current().runtime)?.block_on(serve(router))
+}
+
+/// Build the tokio runtime configured.
+fn runtime(config: &config::RuntimeConfig) -> io::Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+
+    builder.enable_all().thread_name(&config.thread_name);
+
+    if let Some(worker_threads) = config.worker_threads {
+        builder.worker_threads(worker_threads.max(1));
+    }
+
+    if let Some(max_blocking_threads) = config.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads.max(1));
+    }
+
+    if let Some(thread_stack_size) = config.thread_stack_size {
+        builder.thread_stack_size(thread_stack_size);
+    }
+
+    builder.build()
+}
+
+/// Serve `router` until interrupted.
+async fn serve(router: Router) -> Result<()> {
+    logging::init(&config::Config::current().log)?;
+
+    metrics::init();
+
+    if let Some(cache_config) = &config::Config::current().cache {
+        cache::Cache::init(cache_config)?;
+    }
+
+    // Checked once here rather than failing each response.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if config::Config::current().transfer.io_uring && !transfer::uring_available() {
+        tracing::warn!("io_uring is not available, files are read as if disabled");
+    }
+
+    if let Some(access_log_config) = &config::Config::current().access_log {
+        access_log::init(access_log_config)?;
+    }
+
+    if let Some(usage_config) = &config::Config::current().usage {
+        usage::init(usage_config)?;
+    }
+
+    #[cfg(feature = "upstream")]
+    if let Some(upstream_config) = &config::Config::current().upstream {
+        upstream::init(upstream_config);
+    }
+
+    #[cfg(feature = "playurl")]
+    if let Some(credentials_config) = config::Config::current()
+        .playurl
+        .as_ref()
+        .and_then(|playurl| playurl.credentials.as_ref())
+    {
+        credentials::init(credentials_config)?;
+    }
+
+    #[cfg(feature = "tls")]
+    if let Some(tls_config) = &config::Config::current().tls {
+        tls::init(tls_config)?;
+    }
+
+    let listeners = listener::bind(&config::Config::current())?;
+
+    #[cfg(feature = "playurl")]
+    if let Some(grpc_listen) = config::Config::current()
+        .playurl
+        .as_ref()
+        .and_then(|playurl| playurl.grpc_listen)
+    {
+        grpc::spawn(grpc_listen)?;
+    }
+
+    #[cfg(feature = "admin")]
+    if let Some(admin_config) = &config::Config::current().admin {
+        api_key::init(admin_config)?;
+        service::admin::spawn(admin_config)?;
+    }
+
+    daemon::handle_signals()?;
+
+    // Checked in order before routed: the firewall, the routes served over
+    // TLS, the rate limit, then the static dirs
+    let router = router
+        .layer(service::static_files::layer())
+        .layer(rate_limit::layer());
+
+    #[cfg(feature = "tls")]
+    let router = router.layer(tls::layer());
+
+    let router = Arc::new(router.layer(firewall::layer()));
+    let listeners: Vec<_> = listeners.into_iter().map(Arc::new).collect();
+    let acceptors: Vec<_> = listeners
+        .iter()
+        .map(|listener| tokio::spawn(accept(listener.clone(), router.clone())))
+        .collect();
+
+    upgrade::ready();
+
+    let upgraded = tokio::select! {
+        result = daemon::shutdown() => {
+            result?;
+            false
+        }
+        result = upgrade::upgraded(&listeners) => {
+            result?;
+            true
+        }
+    };
+
+    if upgraded {
+        // Accepted by the new process since
+        for acceptor in acceptors {
+            acceptor.abort();
+        }
+        drop(listeners);
+
+        tokio::select! {
+            () = connection::drained() => tracing::info!("Drained, exiting"),
+            result = daemon::shutdown() => result?,
+        }
+
+        otlp::shutdown();
+
+        // The cache and usage left to the new process, see `upgrade`
+        return Ok(());
+    }
+
+    otlp::shutdown();
+
+    if let Some(cache) = cache::Cache::global() {
+        cache.persist().await?;
+    }
+
+    usage::persist().await?;
+
+    Ok(())
+}
+
+/// Accept connections from `listener` and handle them by `router`, until
+/// aborted.
+async fn accept(listener: Arc<listener::Listener>, router: Arc<Router>) {
+    loop {
+        let slot = connection::acquire().await;
+        let (mut tcp_stream, peer_addr) = listener.accept().await;
+        let proxy_protocol = listener.proxy_protocol();
+        let tls = listener.tls();
+
+        tracing::debug!("New connection from {peer_addr}");
+
+        let router = router.clone();
+
+        tokio::spawn(async move {
+            let peer_addr = if proxy_protocol {
+                match proxy_protocol::read(&mut tcp_stream, peer_addr).await {
+                    Ok(client) => client,
+                    Err(e) => {
+                        tracing::debug!("Connection from {peer_addr} error: {e:?}");
+                        return;
+                    }
+                }
+            } else {
+                peer_addr
+            };
+
+            let Some(connection) = connection::register(peer_addr, slot) else {
+                tracing::debug!("Too many connections, rejecting {peer_addr}");
+
+                // Not responded over TLS, not to handshake for rejecting
+                if !tls {
+                    connection::reject(tcp_stream, &config::Config::current().connections).await;
+                }
+
+                return;
+            };
+
+            if tls {
+                // Never over TLS if not built, see `Config::check_features`
+                #[cfg(feature = "tls")]
+                if let Some(tls_stream) = tls::accept(tcp_stream, peer_addr).await {
+                    let stream = AnyStream::Tls(Box::new(tls_stream));
+                    serve_connection(stream, peer_addr, connection, router).await;
+                }
+            } else {
+                let stream = AnyStream::Plain(tcp_stream);
+                serve_connection(stream, peer_addr, connection, router).await;
+            }
+        });
+    }
+}
+
+/// Serve requests on the `connection` from `peer_addr` by `router` until
+/// closed, idle for long, or asked to close.
+async fn serve_connection(
+    mut stream: AnyStream,
+    peer_addr: SocketAddr,
+    connection: connection::Registered,
+    router: Arc<Router>,
+) {
+    let _connection = metrics::connection();
+    let idle_handler = utils::IdleHandler::new();
+    let should_shutdown: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+
+    let handler = {
+        let idle_handler = idle_handler.clone();
+        let should_shutdown = should_shutdown.clone();
+
+        tokio::spawn(connection.scope(async move {
+            loop {
+                {
+                    // HTTP/1.1 Keep-Alive, wait for new data
+                    tokio::select! {
+                        biased;
+                        readable = stream.readable() => {
+                            if readable {
+                                tracing::debug!("New incoming data from {peer_addr}");
+                            } else {
+                                tracing::debug!("Connection was shut down by peer");
+                                break
+                            }
+                        },
+                        _ = async {
+                            let sleep_dur = Duration::from_millis(500);
+                            loop {
+                                if should_shutdown.load(Ordering::Acquire) {
+                                    break;
+                                }
+
+                                sleep(sleep_dur).await;
+                                yield_now().await;
+                            }
+                        } => {
+                            break
+                        }
+                    }
+                }
+
+                {
+                    let _guard = idle_handler.idle_guard();
+
+                    let result = client::scope(
+                        peer_addr.ip(),
+                        // Boxed, not to overflow the stack when polled
+                        Box::pin(request_id::scope(
+                            peer_addr.ip(),
+                            timing::scope(slow_log::observe(access_log::observe(
+                                peer_addr.ip(),
+                                usage::observe(
+                                    peer_addr.ip(),
+                                    // Boxed, the handler future being large
+                                    metrics::observe(Box::pin(guarded_handler(
+                                        &mut stream,
+                                        &router,
+                                    ))),
+                                ),
+                            ))),
+                        )),
+                    )
+                    .await;
+
+                    connection::done();
+
+                    match result {
+                        Ok(can_continue) => {
+                            if !can_continue {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            if !respond_error(e, &mut stream).await {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }))
+    };
+    let abort_handle = handler.abort_handle();
+
+    tokio::select! {
+        _ = handler => {}
+        _ = idle_handler.wait_max_idle(None) => {
+            tracing::debug!("Keep-alive idle timeout, shutting down connection from {peer_addr}");
+
+            should_shutdown.store(true, Ordering::Release);
+        }
+        () = connection.closed() => {
+            tracing::info!("Closing connection from {peer_addr} as asked");
+
+            abort_handle.abort();
+        }
+    }
+}
+
+/// [`handler`], with panics caught rather than killing the connection task,
+/// responded `500 Internal Server Error` if possible, then the connection
+/// closed.
+async fn guarded_handler(tcp_stream: &mut AnyStream, router: &Router) -> Result<bool, Error> {
+    let panic = match utils::catch_unwind(handler(tcp_stream, router)).await {
+        Ok(result) => return result,
+        Err(panic) => panic,
+    };
+
+    metrics::panicked();
+
+    let message = utils::panic_message(&*panic);
+
+    match connection::current_request() {
+        Some((method, path)) => tracing::error!("Handler of {method} {path} panicked: {message}"),
+        None => tracing::error!("Handler panicked: {message}"),
+    }
+
+    // Possibly in the middle of the response already, of which the client
+    // tells by the framing broken
+    let _ = service::write_status(StatusCode::INTERNAL_SERVER_ERROR, tcp_stream).await;
+
+    Ok(false)
+}
+
+#[inline]
+/// Handle a request on `tcp_stream` by `router`.
+///
+/// Returns whether the connection can be kept alive.
+async fn handler(tcp_stream: &mut AnyStream, router: &Router) -> Result<bool, Error> {
+    let request = timing::timed(timing::Phase::Parse, proto::Request::handle(tcp_stream)).await?;
+
+    if request.is_none() {
+        tracing::debug!("No Request?");
+        return Ok(true);
+    }
+
+    let request = request.unwrap();
+    tracing::debug!("{request:?}");
+
+    request_id::accept(&request);
+    client::accept(&request);
+    otlp::accept(&request);
+    access_log::request(&request);
+    slow_log::request(&request);
+    usage::request();
+    connection::request(&request);
+
+    let result = router.handle(&request, tcp_stream).await;
+
+    if request.is_reusable() {
+        return result;
+    }
+
+    tracing::debug!("Bytes past the head of the request left, close the connection");
+
+    if let Err(e) = result {
+        respond_error(e, tcp_stream).await;
+    }
+
+    Ok(false)
+}
+
+/// Respond the error `e` a request failed with, returns whether written.
+async fn respond_error(e: Error, tcp_stream: &mut AnyStream) -> bool {
+    if e.status().is_server_error() {
+        tracing::error!("{e:?}");
+    } else {
+        tracing::debug!("{e:?}");
+    }
+
+    // Response of the error
+    if let Err(e) = e.into_response().write_to_stream(tcp_stream).await {
+        tracing::error!("Write response error: {e:?}");
+        return false;
+    }
+
+    true
+}
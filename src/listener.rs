@@ -0,0 +1,379 @@
+//! Listeners accepting connections, bound to [`Config::listen`].
+//!
+//! With [`ConnectionsConfig::reuse_port`], several sockets are bound to the
+//! address with `SO_REUSEPORT`, so that the kernel balances connections
+//! between them, accepted by a task each, rather than a single accept loop
+//! being the bottleneck on many-core machines.
+//!
+//! Sockets passed by systemd socket activation, see `sd_listen_fds(3)`, are
+//! taken instead if any, so that privileged ports are bound without running
+//! as root, and connections are queued while the service is restarting.
+//! So are those inherited on upgrading, see [`upgrade`](crate::upgrade).
+//!
+//! IPv6 addresses are bound dual-stack, i.e. `[::]:7080` accepts IPv4
+//! connections too, regardless of `net.ipv6.bindv6only`. Their peers are
+//! taken as the IPv4 addresses, see [`canonical`].
+//!
+//! Connections accepted by those bound to [`Config::proxy_listen`], or passed
+//! by systemd named `proxy`, are prefixed with the PROXY protocol header, see
+//! [`proxy_protocol`](crate::proxy_protocol). Those by the ones bound to
+//! [`TlsConfig::listen`](crate::config::TlsConfig::listen), or passed named
+//! `tls`, are over TLS, see [`tls`](crate::tls).
+//!
+//! Connections from addresses not allowed on the listener, see
+//! [`IpFilterConfig`], are closed once accepted.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    thread::available_parallelism,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+
+use crate::{
+    config::{Config, ConnectionsConfig, IpFilterConfig},
+    metrics, upgrade,
+};
+
+/// Backlog of sockets bound, the same as of [`TcpListener::bind`].
+const BACKLOG: u32 = 1024;
+
+/// Delay before accepting again once failed, doubled once failed again in a
+/// row, see [`Listener::accept`].
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(10);
+
+/// Max delay before accepting again once failed.
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(1);
+
+/// First fd passed by systemd, see `sd_listen_fds(3)`.
+#[cfg(unix)]
+pub(crate) const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Name of the fds passed by systemd expecting the PROXY protocol, as set by
+/// `FileDescriptorName=` of the socket unit.
+#[cfg(unix)]
+pub(crate) const SD_PROXY_FD_NAME: &str = "proxy";
+
+/// Name of the fds passed by systemd expecting TLS.
+#[cfg(unix)]
+pub(crate) const SD_TLS_FD_NAME: &str = "tls";
+
+#[derive(Debug)]
+/// A listener bound, see [`bind`].
+pub(crate) struct Listener {
+    tcp_listener: TcpListener,
+
+    kind: Kind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// What a listener is for, telling the clients served, see
+/// [`IpFilterConfig`].
+pub(crate) enum Kind {
+    /// Of [`Config::listen`]
+    Main,
+
+    /// Of [`Config::proxy_listen`], connections prefixed with the PROXY
+    /// protocol header
+    Proxy,
+
+    /// Of [`TlsConfig::listen`](crate::config::TlsConfig::listen),
+    /// connections over TLS
+    Tls,
+
+    #[cfg(feature = "admin")]
+    /// Of [`AdminConfig::listen`](crate::config::AdminConfig::listen)
+    Admin,
+
+    #[cfg(feature = "playurl")]
+    /// Of [`PlayurlConfig::grpc_listen`](crate::config::PlayurlConfig::grpc_listen)
+    Grpc,
+}
+
+impl Kind {
+    /// Whether `ip` is served, by the filter of the current config.
+    fn allows(self, ip: IpAddr) -> bool {
+        let config = Config::current();
+
+        let ip_filter = match self {
+            Self::Main | Self::Tls => Some(&config.ip_filter),
+            Self::Proxy => Some(&config.proxy_ip_filter),
+            #[cfg(feature = "admin")]
+            Self::Admin => config.admin.as_ref().map(|admin| &admin.ip_filter),
+            #[cfg(feature = "playurl")]
+            Self::Grpc => config
+                .playurl
+                .as_ref()
+                .map(|playurl| &playurl.grpc_ip_filter),
+        };
+
+        ip_filter.is_none_or(|ip_filter: &IpFilterConfig| ip_filter.allows(ip))
+    }
+}
+
+impl Listener {
+    #[inline]
+    /// A listener of `kind` accepting from `tcp_listener`.
+    pub(crate) const fn new(tcp_listener: TcpListener, kind: Kind) -> Self {
+        Self { tcp_listener, kind }
+    }
+
+    /// Accept a connection, see [`canonical`] for the peer address. Those
+    /// from addresses not allowed are closed, see [`IpFilterConfig`].
+    ///
+    /// Errors are logged and retried rather than stopping accepting: those
+    /// of the connection only at once, others, e.g. running out of fds, after
+    /// a delay growing while failing, so that connections closed meanwhile
+    /// free the resources.
+    pub(crate) async fn accept(&self) -> (TcpStream, SocketAddr) {
+        let mut backoff = ACCEPT_BACKOFF_MIN;
+
+        loop {
+            match self.tcp_listener.accept().await {
+                Ok((tcp_stream, peer_addr)) => {
+                    let peer_addr = canonical(peer_addr);
+
+                    if self.kind.allows(peer_addr.ip()) {
+                        return (tcp_stream, peer_addr);
+                    }
+
+                    tracing::debug!("Connection from {peer_addr} not allowed, closing");
+                }
+                Err(e) if is_connection_error(&e) => {
+                    metrics::accept_error();
+
+                    tracing::debug!("Accept error: {e}");
+                }
+                Err(e) => {
+                    metrics::accept_error();
+
+                    tracing::error!("Accept error, retrying in {backoff:?}: {e}");
+
+                    tokio::time::sleep(backoff).await;
+
+                    backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    /// Whether connections are prefixed with the PROXY protocol header.
+    pub(crate) fn proxy_protocol(&self) -> bool {
+        self.kind == Kind::Proxy
+    }
+
+    #[inline]
+    /// Whether connections are over TLS.
+    pub(crate) fn tls(&self) -> bool {
+        self.kind == Kind::Tls
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsFd for Listener {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        self.tcp_listener.as_fd()
+    }
+}
+
+/// Whether accepting failed of the connection only, e.g. reset by the peer
+/// before accepted, or of network errors pending on it as Linux tells, see
+/// `accept(2)`.
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::NetworkDown
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::HostUnreachable
+    )
+}
+
+/// Bind the listeners configured, or take those passed by systemd.
+pub(crate) fn bind(config: &Config) -> Result<Vec<Listener>> {
+    #[cfg(unix)]
+    if let Some(listeners) = activated()? {
+        return Ok(listeners);
+    }
+
+    let acceptors = if config.connections.reuse_port {
+        acceptors(&config.connections)
+    } else {
+        1
+    };
+
+    let addrs = std::iter::once((config.listen, Kind::Main))
+        .chain(config.proxy_listen.iter().map(|addr| (*addr, Kind::Proxy)))
+        .chain(
+            config
+                .tls
+                .iter()
+                .flat_map(|tls| &tls.listen)
+                .map(|addr| (*addr, Kind::Tls)),
+        );
+
+    let mut listeners = Vec::new();
+
+    for (addr, kind) in addrs {
+        if config.connections.reuse_port {
+            tracing::info!("Binding {acceptors} sockets to {addr} with SO_REUSEPORT");
+        }
+
+        for _ in 0..acceptors {
+            listeners.push(Listener::new(
+                bind_socket(addr, config.connections.reuse_port)?,
+                kind,
+            ));
+        }
+    }
+
+    Ok(listeners)
+}
+
+/// Number of sockets to bind with `SO_REUSEPORT`.
+fn acceptors(config: &ConnectionsConfig) -> usize {
+    config
+        .acceptors
+        .unwrap_or_else(|| available_parallelism().map_or(1, |cores| cores.get()))
+        .max(1)
+}
+
+/// `addr`, or the IPv4 one if IPv4-mapped IPv6, so that a client is known
+/// by the same address in logs and limits whichever stack connected by.
+pub(crate) fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => v6
+            .ip()
+            .to_ipv4_mapped()
+            .map_or(addr, |ip| SocketAddr::from((ip, v6.port()))),
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Bind a socket to `addr`, with `SO_REUSEPORT` if `reuse_port`.
+pub(crate) fn bind_socket(addr: SocketAddr, reuse_port: bool) -> Result<TcpListener> {
+    let tcp_socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()
+    } else {
+        TcpSocket::new_v6()
+    }?;
+
+    tcp_socket.set_reuseaddr(true)?;
+
+    if reuse_port {
+        tcp_socket.set_reuseport(true)?;
+    }
+
+    #[cfg(unix)]
+    if addr.is_ipv6() {
+        set_dual_stack(&tcp_socket)?;
+    }
+
+    tcp_socket
+        .bind(addr)
+        .with_context(|| format!("Bind {addr}"))?;
+
+    Ok(tcp_socket.listen(BACKLOG)?)
+}
+
+#[cfg(unix)]
+/// Clear `IPV6_V6ONLY` of `tcp_socket`, so that it accepts IPv4 connections
+/// too.
+fn set_dual_stack(tcp_socket: &TcpSocket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let v6_only: libc::c_int = 0;
+
+    #[allow(unsafe_code, reason = "FFI")]
+    // SAFETY: the fd is valid for the duration of the call, and the option
+    // value is a `c_int` as required.
+    let ret = unsafe {
+        libc::setsockopt(
+            tcp_socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_V6ONLY,
+            (&raw const v6_only).cast(),
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+/// Listeners passed by systemd socket activation, or inherited on
+/// upgrading, if any.
+fn activated() -> Result<Option<Vec<Listener>>> {
+    use std::os::fd::FromRawFd;
+
+    // Set for another process, e.g. inherited from the parent. Not set on
+    // upgrading, the PID unknown before started.
+    if std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        != Some(std::process::id())
+        && upgrade::upgrading_from().is_none()
+    {
+        return Ok(None);
+    }
+
+    let count: i32 = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .context("Invalid LISTEN_FDS")?;
+
+    // Colon-separated, set by systemd since v227
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count)
+        .map(|fd| {
+            let kind = match names.next() {
+                Some(SD_PROXY_FD_NAME) => Kind::Proxy,
+                Some(SD_TLS_FD_NAME) => Kind::Tls,
+                _ => Kind::Main,
+            };
+
+            #[allow(unsafe_code, reason = "FFI")]
+            // SAFETY: the fds passed by systemd are open, and owned by none else.
+            let tcp_listener = unsafe {
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+
+                std::net::TcpListener::from_raw_fd(fd)
+            };
+
+            let local_addr = tcp_listener
+                .local_addr()
+                .with_context(|| format!("Fd {fd} passed by systemd is not a TCP socket"))?;
+
+            tracing::info!(
+                "Listening on {local_addr}, {}{}",
+                if upgrade::upgrading_from().is_some() {
+                    "inherited"
+                } else {
+                    "passed by systemd"
+                },
+                match kind {
+                    Kind::Proxy => ", with PROXY protocol",
+                    Kind::Tls => ", over TLS",
+                    _ => "",
+                }
+            );
+
+            tcp_listener.set_nonblocking(true)?;
+
+            Ok(Listener::new(TcpListener::from_std(tcp_listener)?, kind))
+        })
+        .collect::<Result<_>>()
+        .map(Some)
+}
@@ -0,0 +1,226 @@
+//! Logs, to stdout or a file rotated by size and time, see [`LogConfig`].
+//!
+//! Rotated files are numbered like logrotate does, `{file}.1` being the
+//! latest, and those past [`LogConfig::max_files`] removed. Rotated by
+//! logrotate instead, the file is to be reopened, see [`reopen`].
+//!
+//! Request spans are exported by OTLP as well if configured, see [`otlp`],
+//! and errors kept in memory for the dashboard, see [`error_log`].
+//!
+//! With the `console` feature, logs are set up by `console-subscriber`
+//! instead, serving tokio-console, and the config is ignored.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use tracing_subscriber::{
+    EnvFilter, fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt,
+};
+
+#[cfg(feature = "admin")]
+use crate::error_log;
+use crate::{
+    config::{LogConfig, LogRotation},
+    otlp,
+};
+
+/// Whether to reopen the log file once written next, see [`reopen`].
+static REOPEN: AtomicBool = AtomicBool::new(false);
+
+/// Set up logs as configured.
+pub(crate) fn init(config: &LogConfig) -> Result<()> {
+    if cfg!(feature = "console") {
+        #[cfg(feature = "console")]
+        console_subscriber::init();
+
+        return Ok(());
+    }
+
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&config.level))
+        .with_context(|| format!("Invalid log level {:?}", config.level))?;
+
+    let writer = match &config.file {
+        Some(path) => BoxMakeWriter::new(Mutex::new(
+            RollingFile::open(config, path)
+                .with_context(|| format!("Open log file {}", path.display()))?,
+        )),
+        None => BoxMakeWriter::new(io::stdout),
+    };
+
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(config.file.is_none())
+                .with_writer(writer),
+        )
+        .with(otlp::layer(config.otlp.as_ref())?);
+
+    // Told by the dashboard
+    #[cfg(feature = "admin")]
+    let registry = registry.with(error_log::layer());
+
+    registry
+        .try_init()
+        .map_err(|e| anyhow::anyhow!("Set up logs error: {e}"))?;
+
+    if config.otlp.is_some() && !cfg!(feature = "otlp") {
+        tracing::warn!("Built without the `otlp` feature, `log.otlp` ignored");
+    }
+
+    Ok(())
+}
+
+/// Reopen the log file, e.g. once moved away by logrotate, when written
+/// next.
+pub(crate) fn reopen() {
+    REOPEN.store(true, Ordering::Relaxed);
+}
+
+#[derive(Debug)]
+/// Log file, rotated when written, see the [module-level
+/// documentation](self).
+struct RollingFile {
+    path: PathBuf,
+
+    /// See [`LogConfig::max_size`].
+    max_size: u64,
+
+    rotation: LogRotation,
+
+    /// See [`LogConfig::max_files`].
+    max_files: usize,
+
+    file: File,
+
+    /// Size of the file written so far
+    size: u64,
+
+    /// Period the file is of, see [`period`].
+    period: u64,
+}
+
+impl RollingFile {
+    /// Open the log file at `path` for appending.
+    fn open(config: &LogConfig, path: &Path) -> io::Result<Self> {
+        let file = append(path)?;
+        let metadata = file.metadata()?;
+
+        // Of the period last written in, so that a file left from a previous
+        // one is rotated once written.
+        let modified = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+
+        Ok(Self {
+            path: path.to_owned(),
+            max_size: config.max_size,
+            rotation: config.rotation,
+            max_files: config.max_files,
+            file,
+            size: metadata.len(),
+            period: period(config.rotation, modified),
+        })
+    }
+
+    /// Move the file to `{file}.1`, shifting rotated ones, and start a new
+    /// one.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            // Not found if not rotated as many times yet
+            let _ = fs::remove_file(rotated_path(&self.path, self.max_files));
+
+            for n in (1..self.max_files).rev() {
+                let _ = fs::rename(rotated_path(&self.path, n), rotated_path(&self.path, n + 1));
+            }
+
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+
+            self.file = append(&self.path)?;
+        }
+
+        self.size = 0;
+
+        Ok(())
+    }
+
+    /// Open the file at the path again, written to from the end.
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file = append(&self.path)?;
+        self.size = self.file.metadata()?.len();
+
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Keep writing to the current file, not to lose logs.
+        if REOPEN.swap(false, Ordering::Relaxed) {
+            if let Err(e) = self.reopen() {
+                eprintln!("Reopen log file {} error: {e}", self.path.display());
+            }
+        }
+
+        let period = period(self.rotation, SystemTime::now());
+
+        let oversize =
+            self.max_size != 0 && self.size != 0 && self.size + buf.len() as u64 > self.max_size;
+
+        if period != self.period || oversize {
+            self.period = period;
+
+            // Keep writing to the current file, not to lose logs.
+            if let Err(e) = self.rotate() {
+                eprintln!("Rotate log file {} error: {e}", self.path.display());
+            }
+        }
+
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Open `path` for appending, creating it if not exists.
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Path of the `n`-th rotated file of `path`, i.e. `{path}.{n}`.
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{n}"));
+
+    rotated.into()
+}
+
+/// Index of the period of `rotation` that `time` is in, always `0` if not
+/// rotated periodically.
+fn period(rotation: LogRotation, time: SystemTime) -> u64 {
+    let length = match rotation {
+        LogRotation::Never => return 0,
+        LogRotation::Hourly => 60 * 60,
+        LogRotation::Daily => 24 * 60 * 60,
+    };
+
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() / length)
+}
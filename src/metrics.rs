@@ -0,0 +1,196 @@
+//! Metrics, rendered in the Prometheus text format by `GET /metrics`, see
+//! [`service::metrics`](crate::service::metrics).
+//!
+//! Each request is observed by route, see [`observe`] and [`route`]: the
+//! status of the response, see [`status`], body bytes sent and duration.
+//! Connections, cache writes and evictions, and upstream fetches are
+//! recorded where they happen. Cache lookups are observed by route too, see
+//! [`cache_lookup`].
+//!
+//! Requires the `metrics` feature, all no-op otherwise.
+
+#[cfg(feature = "metrics")]
+mod record;
+
+#[cfg(feature = "upstream")]
+use std::time::Duration;
+
+use http::StatusCode;
+
+#[cfg(feature = "metrics")]
+pub(crate) use self::record::render;
+#[cfg(feature = "admin")]
+pub(crate) use self::record::{connections_active, throughput, uptime};
+use crate::{
+    config::FirewallAction,
+    error::Error,
+    layer::{Layer, layer_fn},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Result of a cache lookup.
+pub(crate) enum CacheLookup {
+    /// Fresh object cached
+    Hit,
+
+    /// Stale object cached, served while revalidated or upstream unreachable
+    Stale,
+
+    /// Object being cached, the range requested held
+    Partial,
+
+    /// Object being cached, followed
+    Filling,
+
+    /// Not cached
+    Miss,
+}
+
+#[cfg(feature = "metrics")]
+impl CacheLookup {
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Hit => "hit",
+            Self::Stale => "stale",
+            Self::Partial => "partial",
+            Self::Filling => "filling",
+            Self::Miss => "miss",
+        }
+    }
+}
+
+#[derive(Debug)]
+/// An open connection, counted until dropped.
+pub(crate) struct Connection(());
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        record::connection_closed();
+    }
+}
+
+/// Mark the start, and spawn the sampler of the throughput.
+pub(crate) fn init() {
+    #[cfg(feature = "metrics")]
+    record::init();
+}
+
+/// Count a connection accepted, open until the returned guard dropped.
+pub(crate) fn connection() -> Connection {
+    #[cfg(feature = "metrics")]
+    record::connection_opened();
+
+    Connection(())
+}
+
+/// Count a connection rejected for the limit reached.
+pub(crate) fn connection_rejected() {
+    #[cfg(feature = "metrics")]
+    record::connection_rejected();
+}
+
+/// Count an error accepting a connection.
+pub(crate) fn accept_error() {
+    #[cfg(feature = "metrics")]
+    record::accept_error();
+}
+
+/// Count a request refused by the firewall, see
+/// [`firewall`](crate::firewall).
+pub(crate) fn firewall_refused(action: FirewallAction) {
+    #[cfg(feature = "metrics")]
+    record::firewall_refused(action);
+
+    #[cfg(not(feature = "metrics"))]
+    let _ = action;
+}
+
+/// Count a request handler panicked.
+pub(crate) fn panicked() {
+    #[cfg(feature = "metrics")]
+    record::panicked();
+}
+
+/// Run the handler `future` of a request, observing it by the route set, see
+/// [`route`]. Requests the handler fails without responding are taken as of
+/// the status of the error, as responded, see [`Error::status`].
+pub(crate) async fn observe<F>(future: F) -> Result<bool, Error>
+where
+    F: Future<Output = Result<bool, Error>>,
+{
+    #[cfg(feature = "metrics")]
+    return record::observe(future).await;
+
+    #[cfg(not(feature = "metrics"))]
+    future.await
+}
+
+/// Set the route of the request being observed, see [`observe`].
+pub(crate) fn route(route: &'static str) {
+    #[cfg(feature = "metrics")]
+    record::route(route);
+
+    #[cfg(not(feature = "metrics"))]
+    let _ = route;
+}
+
+/// Layer setting the route of the requests handled, see [`route`].
+pub(crate) fn route_layer(name: &'static str) -> impl Layer {
+    layer_fn(move |request, params, stream, next| {
+        route(name);
+
+        next.run(request, params, stream)
+    })
+}
+
+/// Set the status responded to the request being observed, see [`observe`].
+pub(crate) fn status(status: StatusCode) {
+    #[cfg(feature = "metrics")]
+    record::status(status);
+
+    #[cfg(not(feature = "metrics"))]
+    let _ = status;
+}
+
+/// Set the result of the cache lookup of the request being observed, see
+/// [`observe`].
+pub(crate) fn cache_lookup(lookup: CacheLookup) {
+    #[cfg(feature = "metrics")]
+    record::cache_lookup(lookup);
+
+    #[cfg(not(feature = "metrics"))]
+    let _ = lookup;
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+/// Count `length` bytes written into the cache, by the route of the request
+/// being observed if any.
+pub(crate) fn cache_written(length: u64) {
+    #[cfg(feature = "metrics")]
+    record::cache_written(length);
+
+    #[cfg(not(feature = "metrics"))]
+    let _ = length;
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+/// Count `count` objects evicted from the cache.
+pub(crate) fn cache_evictions(count: u64) {
+    #[cfg(feature = "metrics")]
+    record::cache_evictions(count);
+
+    #[cfg(not(feature = "metrics"))]
+    let _ = count;
+}
+
+#[cfg(feature = "upstream")]
+/// Record a fetch from an upstream host taking `duration` until the response
+/// head received, `failed` if not or a server error responded.
+pub(crate) fn upstream_fetch(duration: Duration, failed: bool) {
+    #[cfg(feature = "metrics")]
+    record::upstream_fetch(duration, failed);
+
+    #[cfg(not(feature = "metrics"))]
+    let _ = (duration, failed);
+}
@@ -0,0 +1,747 @@
+//! Metrics recorded and rendered, of the `metrics` feature, see
+//! [`metrics`](super).
+
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    sync::{
+        LazyLock, Mutex, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use http::StatusCode;
+
+use super::CacheLookup;
+use crate::{
+    cache::Cache,
+    config::FirewallAction,
+    error::Error,
+    timing::{self, Phase},
+    transfer,
+};
+
+/// Window of [`throughput`], in seconds.
+const THROUGHPUT_WINDOW: usize = 10;
+
+/// Upper bounds of the buckets of histograms of durations, in seconds.
+const DURATION_BUCKETS: [f64; 14] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Upper bounds of the buckets of histograms of sizes, in bytes, from 1 KiB
+/// to 1 GiB.
+const SIZE_BUCKETS: [f64; 11] = [
+    1024.0,
+    16384.0,
+    65536.0,
+    262_144.0,
+    1_048_576.0,
+    4_194_304.0,
+    16_777_216.0,
+    67_108_864.0,
+    268_435_456.0,
+    536_870_912.0,
+    1_073_741_824.0,
+];
+
+/// Upper bounds of the buckets of histograms of throughput, in bytes per
+/// second, from 100 KB/s to 10 Gbps.
+const THROUGHPUT_BUCKETS: [f64; 10] = [1e5, 1e6, 2.5e6, 5e6, 1e7, 2.5e7, 5e7, 1e8, 5e8, 1.25e9];
+
+/// Responses smaller than this are not taken into the throughput, which
+/// would be dominated by latency.
+const MIN_THROUGHPUT_SIZE: u64 = 64 * 1024;
+
+/// Requests observed.
+static REQUESTS: LazyLock<Mutex<Requests>> = LazyLock::new(|| Mutex::new(Requests::default()));
+
+/// Number of connections open.
+static CONNECTIONS_ACTIVE: AtomicU64 = AtomicU64::new(0);
+
+/// Number of connections accepted.
+static CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of connections rejected for the limit reached.
+static CONNECTIONS_REJECTED: AtomicU64 = AtomicU64::new(0);
+
+/// Number of errors accepting connections.
+static ACCEPT_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of requests refused by the firewall, responded `403 Forbidden`.
+static FIREWALL_FORBIDDEN: AtomicU64 = AtomicU64::new(0);
+
+/// Number of requests refused by the firewall, the connection dropped.
+static FIREWALL_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Number of request handlers panicked.
+static PANICS: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes written into the cache, by route, or `background` for those not
+/// of a request, e.g. prefetches.
+static CACHE_WRITTEN: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+/// Number of objects evicted from the cache, complete or partial.
+static CACHE_EVICTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Upstream fetches, until the response head received.
+static UPSTREAM_FETCHES: Mutex<Histogram> = Mutex::new(Histogram::new(&DURATION_BUCKETS));
+
+/// Number of upstream fetches failed, of all hosts and retries.
+static UPSTREAM_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+/// When started, see [`init`].
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Body bytes sent per second, averaged over [`THROUGHPUT_WINDOW`].
+static THROUGHPUT: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    /// The request being observed, see [`observe`].
+    static CURRENT: Cell<Current>;
+}
+
+#[derive(Debug, Clone, Copy)]
+/// The request being observed, as known so far.
+struct Current {
+    /// See [`route`]
+    route: &'static str,
+
+    /// See [`status`]
+    status: Option<StatusCode>,
+
+    /// See [`cache_lookup`]
+    lookup: Option<CacheLookup>,
+}
+
+#[derive(Debug, Default)]
+/// Requests observed, by route.
+struct Requests {
+    /// Number of requests, by route and status
+    total: BTreeMap<(&'static str, u16), u64>,
+
+    /// Body bytes sent, by route
+    bytes: BTreeMap<&'static str, u64>,
+
+    /// Durations, by route
+    durations: BTreeMap<&'static str, Histogram>,
+
+    /// Durations of writing responses, see [`Phase::Transfer`], by route
+    transfer_durations: BTreeMap<&'static str, Histogram>,
+
+    /// Body sizes of responses, by route
+    sizes: BTreeMap<&'static str, Histogram>,
+
+    /// Effective throughput of responses, i.e. body bytes by
+    /// [`Phase::Transfer`] duration, by route. Only of those no smaller than
+    /// [`MIN_THROUGHPUT_SIZE`].
+    throughputs: BTreeMap<&'static str, Histogram>,
+
+    /// Cache lookups, by route and result
+    cache_lookups: BTreeMap<(&'static str, CacheLookup), u64>,
+
+    /// Body bytes sent from the cache, i.e. of requests looked up not
+    /// [`CacheLookup::Miss`], by route
+    cache_served: BTreeMap<&'static str, u64>,
+}
+
+impl Requests {
+    /// Record a request of `route`, responded `status` with `sent` body
+    /// bytes, taking `duration`, `transfer_duration` of which writing.
+    fn record(
+        &mut self,
+        route: &'static str,
+        status: u16,
+        sent: u64,
+        duration: Duration,
+        transfer_duration: Duration,
+    ) {
+        *self.total.entry((route, status)).or_default() += 1;
+        *self.bytes.entry(route).or_default() += sent;
+
+        self.durations
+            .entry(route)
+            .or_insert_with(|| Histogram::new(&DURATION_BUCKETS))
+            .observe(duration.as_secs_f64());
+
+        if sent == 0 {
+            return;
+        }
+
+        self.transfer_durations
+            .entry(route)
+            .or_insert_with(|| Histogram::new(&DURATION_BUCKETS))
+            .observe(transfer_duration.as_secs_f64());
+        self.sizes
+            .entry(route)
+            .or_insert_with(|| Histogram::new(&SIZE_BUCKETS))
+            .observe(sent as f64);
+
+        if sent >= MIN_THROUGHPUT_SIZE && !transfer_duration.is_zero() {
+            self.throughputs
+                .entry(route)
+                .or_insert_with(|| Histogram::new(&THROUGHPUT_BUCKETS))
+                .observe(sent as f64 / transfer_duration.as_secs_f64());
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A histogram, with buckets of upper bounds `bounds`.
+struct Histogram {
+    bounds: &'static [f64],
+
+    /// Number of observations of each bucket, not cumulative
+    buckets: Vec<u64>,
+
+    /// Sum of observations
+    sum: f64,
+
+    count: u64,
+}
+
+impl Histogram {
+    const fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: Vec::new(),
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(index) = self.bounds.iter().position(|&bound| value <= bound) {
+            self.buckets.resize(self.bounds.len(), 0);
+            self.buckets[index] += 1;
+        }
+
+        self.sum += value;
+        self.count += 1;
+    }
+
+    /// Render as `name` with `labels`, like `route="resource"`, to `out`.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let (separator, label_set) = if labels.is_empty() {
+            ("", String::new())
+        } else {
+            (",", format!("{{{labels}}}"))
+        };
+        let mut cumulative = 0;
+
+        for (index, bound) in self.bounds.iter().enumerate() {
+            cumulative += self.buckets.get(index).copied().unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{separator}le=\"{bound}\"}} {cumulative}"
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{separator}le=\"+Inf\"}} {}",
+            self.count
+        );
+        let _ = writeln!(out, "{name}_sum{label_set} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{label_set} {}", self.count);
+    }
+}
+
+/// Mark the start, see [`uptime`], and spawn the sampler of [`throughput`].
+pub(super) fn init() {
+    if STARTED.set(Instant::now()).is_err() {
+        return;
+    }
+
+    tokio::spawn(async {
+        let mut samples = VecDeque::with_capacity(THROUGHPUT_WINDOW + 1);
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+
+            samples.push_back(transfer::total_sent());
+            if samples.len() > THROUGHPUT_WINDOW + 1 {
+                samples.pop_front();
+            }
+
+            if let (Some(first), Some(last)) = (samples.front(), samples.back()) {
+                let seconds = (samples.len() as u64 - 1).max(1);
+                THROUGHPUT.store((last - first) / seconds, Ordering::Relaxed);
+            }
+        }
+    });
+}
+
+#[cfg(feature = "admin")]
+/// How long since started.
+pub(crate) fn uptime() -> Duration {
+    STARTED.get().map_or(Duration::ZERO, Instant::elapsed)
+}
+
+#[cfg(feature = "admin")]
+/// Body bytes sent per second lately, averaged over [`THROUGHPUT_WINDOW`]
+/// seconds.
+pub(crate) fn throughput() -> u64 {
+    THROUGHPUT.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "admin")]
+/// Number of connections open.
+pub(crate) fn connections_active() -> u64 {
+    CONNECTIONS_ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Count a connection accepted, open until [`connection_closed`].
+pub(super) fn connection_opened() {
+    CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+    CONNECTIONS_ACTIVE.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a connection closed.
+pub(super) fn connection_closed() {
+    CONNECTIONS_ACTIVE.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Count a connection rejected for the limit reached.
+pub(super) fn connection_rejected() {
+    CONNECTIONS_REJECTED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count an error accepting a connection.
+pub(super) fn accept_error() {
+    ACCEPT_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a request refused by the firewall, see
+/// [`firewall`](crate::firewall).
+pub(super) fn firewall_refused(action: FirewallAction) {
+    match action {
+        FirewallAction::Forbid => FIREWALL_FORBIDDEN.fetch_add(1, Ordering::Relaxed),
+        FirewallAction::Drop => FIREWALL_DROPPED.fetch_add(1, Ordering::Relaxed),
+    };
+}
+
+/// Count a request handler panicked.
+pub(super) fn panicked() {
+    PANICS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Run the handler `future` of a request, observing it by the route set, see
+/// [`route`]. Requests the handler fails without responding are taken as of
+/// the status of the error, as responded, see [`Error::status`].
+pub(super) async fn observe<F>(future: F) -> Result<bool, Error>
+where
+    F: Future<Output = Result<bool, Error>>,
+{
+    let current = Current {
+        route: "other",
+        status: None,
+        lookup: None,
+    };
+
+    CURRENT
+        .scope(Cell::new(current), async move {
+            let started = Instant::now();
+            let (result, sent) = transfer::counted(future).await;
+            let Current {
+                route,
+                status,
+                lookup,
+            } = CURRENT.with(Cell::get);
+
+            let status = match (&result, status) {
+                (_, Some(status)) => status.as_u16(),
+                (Err(e), None) => e.status().as_u16(),
+                // Nothing requested, e.g. the connection closed
+                (Ok(_), None) => return result,
+            };
+
+            let mut requests = REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+
+            requests.record(
+                route,
+                status,
+                sent,
+                started.elapsed(),
+                timing::elapsed(Phase::Transfer),
+            );
+
+            if let Some(lookup) = lookup {
+                *requests.cache_lookups.entry((route, lookup)).or_default() += 1;
+
+                if lookup != CacheLookup::Miss {
+                    *requests.cache_served.entry(route).or_default() += sent;
+                }
+            }
+
+            result
+        })
+        .await
+}
+
+/// Set the route of the request being observed, see [`observe`].
+pub(super) fn route(route: &'static str) {
+    update(|current| current.route = route);
+}
+
+/// Set the status responded to the request being observed, see [`observe`].
+pub(super) fn status(status: StatusCode) {
+    update(|current| current.status = Some(status));
+}
+
+/// Set the result of the cache lookup of the request being observed, see
+/// [`observe`].
+pub(super) fn cache_lookup(lookup: CacheLookup) {
+    update(|current| current.lookup = Some(lookup));
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+/// Count `length` bytes written into the cache, by the route of the request
+/// being observed if any.
+pub(super) fn cache_written(length: u64) {
+    let route = CURRENT
+        .try_with(|current| current.get().route)
+        .unwrap_or("background");
+
+    *CACHE_WRITTEN
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(route)
+        .or_default() += length;
+}
+
+#[cfg(any(feature = "upstream", feature = "admin"))]
+/// Count `count` objects evicted from the cache.
+pub(super) fn cache_evictions(count: u64) {
+    CACHE_EVICTIONS.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Update the request being observed, if any.
+fn update(f: impl FnOnce(&mut Current)) {
+    let _ = CURRENT.try_with(|cell| {
+        let mut current = cell.get();
+        f(&mut current);
+        cell.set(current);
+    });
+}
+
+#[cfg(feature = "upstream")]
+/// Record a fetch from an upstream host taking `duration` until the response
+/// head received, `failed` if not or a server error responded.
+pub(super) fn upstream_fetch(duration: Duration, failed: bool) {
+    if failed {
+        UPSTREAM_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    UPSTREAM_FETCHES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .observe(duration.as_secs_f64());
+}
+
+/// Render histograms of `name` by route, to `out`.
+fn render_by_route(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    histograms: &BTreeMap<&'static str, Histogram>,
+) {
+    let _ = writeln!(out, "# HELP {name} {help}, by route.");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+
+    for (route, histogram) in histograms {
+        histogram.render(out, name, &format!("route=\"{route}\""));
+    }
+}
+
+/// Render metrics of the cache to `out`.
+fn render_cache(out: &mut String) {
+    {
+        let requests = REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+
+        out.push_str("# HELP bvc_cache_lookups_total Cache lookups, by route and result.\n");
+        out.push_str("# TYPE bvc_cache_lookups_total counter\n");
+        for ((route, lookup), count) in &requests.cache_lookups {
+            let _ = writeln!(
+                out,
+                "bvc_cache_lookups_total{{route=\"{route}\",result=\"{}\"}} {count}",
+                lookup.as_str()
+            );
+        }
+
+        out.push_str(
+            "# HELP bvc_cache_served_bytes_total Response body bytes sent from the cache, by \
+             route.\n",
+        );
+        out.push_str("# TYPE bvc_cache_served_bytes_total counter\n");
+        for (route, bytes) in &requests.cache_served {
+            let _ = writeln!(
+                out,
+                "bvc_cache_served_bytes_total{{route=\"{route}\"}} {bytes}"
+            );
+        }
+    }
+
+    out.push_str("# HELP bvc_cache_written_bytes_total Bytes written into the cache, by route.\n");
+    out.push_str("# TYPE bvc_cache_written_bytes_total counter\n");
+    for (route, bytes) in CACHE_WRITTEN
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+    {
+        let _ = writeln!(
+            out,
+            "bvc_cache_written_bytes_total{{route=\"{route}\"}} {bytes}"
+        );
+    }
+
+    out.push_str("# HELP bvc_cache_evictions_total Objects evicted from the cache.\n");
+    out.push_str("# TYPE bvc_cache_evictions_total counter\n");
+    let _ = writeln!(
+        out,
+        "bvc_cache_evictions_total {}",
+        CACHE_EVICTIONS.load(Ordering::Relaxed)
+    );
+
+    let Some(occupancy) = Cache::global().map(Cache::occupancy) else {
+        return;
+    };
+
+    out.push_str("# HELP bvc_cache_keys Keys cached, complete and partial.\n");
+    out.push_str("# TYPE bvc_cache_keys gauge\n");
+    let _ = writeln!(out, "bvc_cache_keys {}", occupancy.keys);
+
+    out.push_str("# HELP bvc_cache_size_bytes Total size of objects cached.\n");
+    out.push_str("# TYPE bvc_cache_size_bytes gauge\n");
+    let _ = writeln!(out, "bvc_cache_size_bytes {}", occupancy.size);
+
+    out.push_str("# HELP bvc_cache_max_size_bytes Max total size of objects cached.\n");
+    out.push_str("# TYPE bvc_cache_max_size_bytes gauge\n");
+    let _ = writeln!(out, "bvc_cache_max_size_bytes {}", occupancy.max_size);
+}
+
+/// Render metrics of the tokio runtime to `out`.
+///
+/// Those of the blocking pool and of polls are only known when built with
+/// `--cfg tokio_unstable`.
+fn render_runtime(out: &mut String) {
+    let Ok(handle) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    let runtime = handle.metrics();
+
+    out.push_str("# HELP bvc_tokio_workers Worker threads of the runtime.\n");
+    out.push_str("# TYPE bvc_tokio_workers gauge\n");
+    let _ = writeln!(out, "bvc_tokio_workers {}", runtime.num_workers());
+
+    out.push_str("# HELP bvc_tokio_alive_tasks Tasks alive, i.e. spawned and not done.\n");
+    out.push_str("# TYPE bvc_tokio_alive_tasks gauge\n");
+    let _ = writeln!(out, "bvc_tokio_alive_tasks {}", runtime.num_alive_tasks());
+
+    out.push_str("# HELP bvc_tokio_global_queue_depth Tasks queued in the global queue.\n");
+    out.push_str("# TYPE bvc_tokio_global_queue_depth gauge\n");
+    let _ = writeln!(
+        out,
+        "bvc_tokio_global_queue_depth {}",
+        runtime.global_queue_depth()
+    );
+
+    #[cfg(tokio_unstable)]
+    render_runtime_unstable(out, &runtime);
+}
+
+#[cfg(tokio_unstable)]
+/// Render metrics of the tokio runtime only known with `--cfg
+/// tokio_unstable` to `out`.
+fn render_runtime_unstable(out: &mut String, runtime: &tokio::runtime::RuntimeMetrics) {
+    out.push_str("# HELP bvc_tokio_blocking_threads Threads of the blocking pool.\n");
+    out.push_str("# TYPE bvc_tokio_blocking_threads gauge\n");
+    let _ = writeln!(
+        out,
+        "bvc_tokio_blocking_threads {}",
+        runtime.num_blocking_threads()
+    );
+
+    out.push_str("# HELP bvc_tokio_blocking_idle_threads Idle threads of the blocking pool.\n");
+    out.push_str("# TYPE bvc_tokio_blocking_idle_threads gauge\n");
+    let _ = writeln!(
+        out,
+        "bvc_tokio_blocking_idle_threads {}",
+        runtime.num_idle_blocking_threads()
+    );
+
+    out.push_str(
+        "# HELP bvc_tokio_blocking_queue_depth Tasks queued for the blocking pool, e.g. file \
+         operations.\n",
+    );
+    out.push_str("# TYPE bvc_tokio_blocking_queue_depth gauge\n");
+    let _ = writeln!(
+        out,
+        "bvc_tokio_blocking_queue_depth {}",
+        runtime.blocking_queue_depth()
+    );
+
+    out.push_str("# HELP bvc_tokio_worker_polls_total Tasks polled, by worker.\n");
+    out.push_str("# TYPE bvc_tokio_worker_polls_total counter\n");
+    for worker in 0..runtime.num_workers() {
+        let _ = writeln!(
+            out,
+            "bvc_tokio_worker_polls_total{{worker=\"{worker}\"}} {}",
+            runtime.worker_poll_count(worker)
+        );
+    }
+
+    out.push_str(
+        "# HELP bvc_tokio_worker_busy_seconds_total Time spent polling tasks, by worker.\n",
+    );
+    out.push_str("# TYPE bvc_tokio_worker_busy_seconds_total counter\n");
+    for worker in 0..runtime.num_workers() {
+        let _ = writeln!(
+            out,
+            "bvc_tokio_worker_busy_seconds_total{{worker=\"{worker}\"}} {}",
+            runtime.worker_total_busy_duration(worker).as_secs_f64()
+        );
+    }
+
+    out.push_str(
+        "# HELP bvc_tokio_worker_mean_poll_time_seconds Moving average of the time of a poll, by \
+         worker.\n",
+    );
+    out.push_str("# TYPE bvc_tokio_worker_mean_poll_time_seconds gauge\n");
+    for worker in 0..runtime.num_workers() {
+        let _ = writeln!(
+            out,
+            "bvc_tokio_worker_mean_poll_time_seconds{{worker=\"{worker}\"}} {}",
+            runtime.worker_mean_poll_time(worker).as_secs_f64()
+        );
+    }
+}
+
+/// All metrics in the Prometheus text format.
+pub(crate) fn render() -> String {
+    let mut out = String::new();
+
+    {
+        let requests = REQUESTS.lock().unwrap_or_else(|e| e.into_inner());
+
+        out.push_str("# HELP bvc_requests_total Requests handled, by route and status.\n");
+        out.push_str("# TYPE bvc_requests_total counter\n");
+        for ((route, status), count) in &requests.total {
+            let _ = writeln!(
+                out,
+                "bvc_requests_total{{route=\"{route}\",status=\"{status}\"}} {count}"
+            );
+        }
+
+        out.push_str("# HELP bvc_response_bytes_total Response body bytes sent, by route.\n");
+        out.push_str("# TYPE bvc_response_bytes_total counter\n");
+        for (route, bytes) in &requests.bytes {
+            let _ = writeln!(out, "bvc_response_bytes_total{{route=\"{route}\"}} {bytes}");
+        }
+
+        render_by_route(
+            &mut out,
+            "bvc_request_duration_seconds",
+            "Request durations",
+            &requests.durations,
+        );
+        render_by_route(
+            &mut out,
+            "bvc_response_transfer_duration_seconds",
+            "Durations of writing responses",
+            &requests.transfer_durations,
+        );
+        render_by_route(
+            &mut out,
+            "bvc_response_size_bytes",
+            "Response body sizes",
+            &requests.sizes,
+        );
+        render_by_route(
+            &mut out,
+            "bvc_response_throughput_bytes_per_second",
+            "Effective throughput of responses of 64 KiB or larger",
+            &requests.throughputs,
+        );
+    }
+
+    out.push_str("# HELP bvc_connections_active Connections open.\n");
+    out.push_str("# TYPE bvc_connections_active gauge\n");
+    let _ = writeln!(
+        out,
+        "bvc_connections_active {}",
+        CONNECTIONS_ACTIVE.load(Ordering::Relaxed)
+    );
+
+    out.push_str("# HELP bvc_connections_total Connections accepted.\n");
+    out.push_str("# TYPE bvc_connections_total counter\n");
+    let _ = writeln!(
+        out,
+        "bvc_connections_total {}",
+        CONNECTIONS.load(Ordering::Relaxed)
+    );
+
+    out.push_str(
+        "# HELP bvc_connections_rejected_total Connections rejected for the limit reached.\n",
+    );
+    out.push_str("# TYPE bvc_connections_rejected_total counter\n");
+    let _ = writeln!(
+        out,
+        "bvc_connections_rejected_total {}",
+        CONNECTIONS_REJECTED.load(Ordering::Relaxed)
+    );
+
+    out.push_str("# HELP bvc_accept_errors_total Errors accepting connections.\n");
+    out.push_str("# TYPE bvc_accept_errors_total counter\n");
+    let _ = writeln!(
+        out,
+        "bvc_accept_errors_total {}",
+        ACCEPT_ERRORS.load(Ordering::Relaxed)
+    );
+
+    out.push_str(
+        "# HELP bvc_firewall_refused_total Requests refused by the firewall, by action.\n",
+    );
+    out.push_str("# TYPE bvc_firewall_refused_total counter\n");
+    let _ = writeln!(
+        out,
+        "bvc_firewall_refused_total{{action=\"forbid\"}} {}",
+        FIREWALL_FORBIDDEN.load(Ordering::Relaxed)
+    );
+    let _ = writeln!(
+        out,
+        "bvc_firewall_refused_total{{action=\"drop\"}} {}",
+        FIREWALL_DROPPED.load(Ordering::Relaxed)
+    );
+
+    out.push_str("# HELP bvc_panics_total Request handlers panicked.\n");
+    out.push_str("# TYPE bvc_panics_total counter\n");
+    let _ = writeln!(out, "bvc_panics_total {}", PANICS.load(Ordering::Relaxed));
+
+    render_cache(&mut out);
+    render_runtime(&mut out);
+
+    out.push_str(
+        "# HELP bvc_upstream_fetch_duration_seconds Upstream fetches, until the response head \
+         received.\n",
+    );
+    out.push_str("# TYPE bvc_upstream_fetch_duration_seconds histogram\n");
+    UPSTREAM_FETCHES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .render(&mut out, "bvc_upstream_fetch_duration_seconds", "");
+
+    out.push_str("# HELP bvc_upstream_fetch_errors_total Upstream fetches failed.\n");
+    out.push_str("# TYPE bvc_upstream_fetch_errors_total counter\n");
+    let _ = writeln!(
+        out,
+        "bvc_upstream_fetch_errors_total {}",
+        UPSTREAM_ERRORS.load(Ordering::Relaxed)
+    );
+
+    out
+}
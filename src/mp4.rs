@@ -0,0 +1,373 @@
+//! Probing fragmented MP4 files, like the `.m4s` DASH streams of bilibili,
+//! for what a DASH manifest tells of them, see [`probe`].
+//!
+//! Only the boxes before the first fragment are read, i.e. `ftyp`, `moov`
+//! and `sidx`. A file is expected to hold a single track, indexed by a
+//! `sidx` box following `moov`.
+
+use std::io::{self, SeekFrom};
+
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+/// Max size of a box read into memory.
+const MAX_BOX_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Kind of a track.
+pub(crate) enum Kind {
+    Video,
+    Audio,
+}
+
+#[derive(Debug, Clone)]
+/// What a DASH manifest tells of a single track file.
+pub(crate) struct Track {
+    pub kind: Kind,
+
+    /// RFC 6381 codecs parameter, like `avc1.640032` or `mp4a.40.2`
+    pub codecs: String,
+
+    /// Width and height of a video track
+    pub resolution: Option<(u32, u32)>,
+
+    /// Sample rate of an audio track
+    pub sample_rate: Option<u32>,
+
+    /// Duration in seconds
+    pub duration: f64,
+
+    /// Byte range of the initialization segment, i.e. `ftyp` and `moov`,
+    /// inclusive
+    pub init_range: (u64, u64),
+
+    /// Byte range of the `sidx` box, inclusive
+    pub index_range: (u64, u64),
+
+    /// Size of the whole file
+    pub size: u64,
+}
+
+impl Track {
+    /// Average bandwidth in bits per second.
+    pub(crate) fn bandwidth(&self) -> u64 {
+        if self.duration > 0.0 {
+            (self.size as f64 * 8.0 / self.duration).ceil() as u64
+        } else {
+            0
+        }
+    }
+
+    /// MIME type, like `video/mp4`.
+    pub(crate) const fn mime_type(&self) -> &'static str {
+        match self.kind {
+            Kind::Video => "video/mp4",
+            Kind::Audio => "audio/mp4",
+        }
+    }
+}
+
+/// Probe the single track fragmented MP4 `file`.
+pub(crate) async fn probe(file: &mut File) -> io::Result<Track> {
+    let size = file.metadata().await?.len();
+
+    let mut offset = 0;
+    let mut moov = None;
+    let mut sidx = None;
+
+    while offset < size {
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        let mut header = [0; 8];
+        file.read_exact(&mut header).await?;
+
+        let box_type: [u8; 4] = [header[4], header[5], header[6], header[7]];
+        let (box_size, header_size) =
+            match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+                0 => (size - offset, 8),
+                1 => (file.read_u64().await?, 16),
+                box_size => (u64::from(box_size), 8),
+            };
+
+        if box_size < header_size || offset + box_size > size {
+            return Err(invalid("truncated box"));
+        }
+
+        match &box_type {
+            b"moov" => {
+                moov = Some((
+                    offset + box_size,
+                    read_payload(file, box_size - header_size).await?,
+                ))
+            }
+            b"sidx" => {
+                sidx = Some((
+                    offset,
+                    offset + box_size - 1,
+                    read_payload(file, box_size - header_size).await?,
+                ));
+                break;
+            }
+            b"moof" | b"mdat" => break,
+            _ => {}
+        }
+
+        offset += box_size;
+    }
+
+    let (Some((moov_end, moov)), Some((sidx_start, sidx_end, sidx))) = (moov, sidx) else {
+        return Err(invalid("no moov or sidx before fragments"));
+    };
+
+    let trak = find(&moov, &[b"trak"]).ok_or_else(|| invalid("no trak"))?;
+
+    let kind = match find(trak, &[b"mdia", b"hdlr"]).and_then(|hdlr| hdlr.get(8..12)) {
+        Some(b"vide") => Kind::Video,
+        Some(b"soun") => Kind::Audio,
+        _ => return Err(invalid("neither video nor audio track")),
+    };
+
+    let (entry_type, entry) = find(trak, &[b"mdia", b"minf", b"stbl", b"stsd"])
+        .and_then(|stsd| children(stsd.get(8..)?).next())
+        .ok_or_else(|| invalid("no sample entry"))?;
+
+    let (codecs, resolution, sample_rate) = match kind {
+        Kind::Video => {
+            let resolution = u16_at(entry, 24).zip(u16_at(entry, 26));
+            let codecs = entry
+                .get(78..)
+                .and_then(|children| video_codecs(entry_type, children))
+                .unwrap_or_else(|| fourcc(entry_type));
+
+            (
+                codecs,
+                resolution.map(|(width, height)| (u32::from(width), u32::from(height))),
+                None,
+            )
+        }
+        Kind::Audio => {
+            let sample_rate = u16_at(entry, 24).map(u32::from);
+            let codecs = entry
+                .get(28..)
+                .and_then(|children| audio_codecs(entry_type, children))
+                .unwrap_or_else(|| fourcc(entry_type));
+
+            (codecs, None, sample_rate)
+        }
+    };
+
+    Ok(Track {
+        kind,
+        codecs,
+        resolution,
+        sample_rate,
+        duration: sidx_duration(&sidx).ok_or_else(|| invalid("invalid sidx"))?,
+        init_range: (0, moov_end - 1),
+        index_range: (sidx_start, sidx_end),
+        size,
+    })
+}
+
+/// Read the payload of `size` bytes of the box at the cursor.
+async fn read_payload(file: &mut File, size: u64) -> io::Result<Vec<u8>> {
+    if size > MAX_BOX_SIZE {
+        return Err(invalid("box too large"));
+    }
+
+    let mut payload = vec![0; size as usize];
+    file.read_exact(&mut payload).await?;
+
+    Ok(payload)
+}
+
+/// The child boxes in `data`, by type along with the payload.
+fn children(mut data: &[u8]) -> impl Iterator<Item = (&[u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        let size = usize::try_from(u32_at(data, 0)?).ok()?;
+        let box_type = data.get(4..8)?.try_into().ok()?;
+        let payload = data.get(8..size)?;
+
+        data = &data[size..];
+
+        Some((box_type, payload))
+    })
+}
+
+/// The payload of the first box by `path` of types in `data`.
+fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    let (first, rest) = path.split_first()?;
+    let (_, payload) = children(data).find(|(box_type, _)| box_type == first)?;
+
+    if rest.is_empty() {
+        Some(payload)
+    } else {
+        find(payload, rest)
+    }
+}
+
+/// Total duration of the references of the `sidx` payload, in seconds.
+fn sidx_duration(sidx: &[u8]) -> Option<f64> {
+    let version = *sidx.first()?;
+    let timescale = u32_at(sidx, 8)?;
+
+    // Earliest presentation time and first offset, 32 or 64 bits each,
+    // then reserved 16 bits and the reference count
+    let references = if version == 0 { 24 } else { 32 };
+    let reference_count = usize::from(u16_at(sidx, references - 2)?);
+
+    let duration: u64 = (0..reference_count)
+        .map(|i| u32_at(sidx, references + i * 12 + 4).map(u64::from))
+        .sum::<Option<u64>>()?;
+
+    (timescale > 0).then(|| duration as f64 / f64::from(timescale))
+}
+
+/// Codecs parameter of a visual sample entry, by its child boxes.
+fn video_codecs(entry_type: &[u8; 4], children: &[u8]) -> Option<String> {
+    match entry_type {
+        b"avc1" | b"avc3" => {
+            let avcc = find(children, &[b"avcC"])?;
+
+            Some(format!(
+                "{}.{:02x}{:02x}{:02x}",
+                fourcc(entry_type),
+                avcc.get(1)?,
+                avcc.get(2)?,
+                avcc.get(3)?
+            ))
+        }
+        b"hev1" | b"hvc1" => {
+            let hvcc = find(children, &[b"hvcC"])?;
+
+            let profile_space = ["", "A", "B", "C"][usize::from(hvcc.get(1)? >> 6)];
+            let tier = if hvcc.get(1)? & 0x20 == 0 { 'L' } else { 'H' };
+            let profile_idc = hvcc.get(1)? & 0x1f;
+            let compatibility = u32_at(hvcc, 2)?.reverse_bits();
+            let level_idc = hvcc.get(12)?;
+
+            let mut codecs = format!(
+                "{}.{profile_space}{profile_idc}.{compatibility:X}.{tier}{level_idc}",
+                fourcc(entry_type)
+            );
+
+            let constraints = hvcc.get(6..12)?;
+            let used = constraints
+                .iter()
+                .rposition(|&b| b != 0)
+                .map_or(0, |last| last + 1);
+
+            for constraint in &constraints[..used] {
+                codecs.push_str(&format!(".{constraint:X}"));
+            }
+
+            Some(codecs)
+        }
+        b"av01" => {
+            let av1c = find(children, &[b"av1C"])?;
+
+            let profile = av1c.get(1)? >> 5;
+            let level = av1c.get(1)? & 0x1f;
+            let tier = if av1c.get(2)? & 0x80 == 0 { 'M' } else { 'H' };
+            let bit_depth = match (av1c.get(2)? & 0x40 != 0, av1c.get(2)? & 0x20 != 0) {
+                (true, true) => 12,
+                (true, false) => 10,
+                _ => 8,
+            };
+
+            Some(format!("av01.{profile}.{level:02}{tier}.{bit_depth:02}"))
+        }
+        _ => None,
+    }
+}
+
+/// Codecs parameter of an audio sample entry, by its child boxes.
+fn audio_codecs(entry_type: &[u8; 4], children: &[u8]) -> Option<String> {
+    match entry_type {
+        b"mp4a" => {
+            let esds = find(children, &[b"esds"])?;
+
+            // ES_Descriptor, skipping the version and flags
+            let (tag, es) = descriptor(esds.get(4..)?)?;
+            if tag != 0x03 {
+                return None;
+            }
+
+            let flags = *es.get(2)?;
+            let mut rest = es.get(3..)?;
+            if flags & 0x80 != 0 {
+                rest = rest.get(2..)?;
+            }
+            if flags & 0x40 != 0 {
+                rest = rest.get(1 + usize::from(*rest.first()?)..)?;
+            }
+            if flags & 0x20 != 0 {
+                rest = rest.get(2..)?;
+            }
+
+            // DecoderConfigDescriptor
+            let (tag, decoder_config) = descriptor(rest)?;
+            if tag != 0x04 {
+                return None;
+            }
+
+            let object_type = *decoder_config.first()?;
+
+            // DecoderSpecificInfo, i.e. AudioSpecificConfig of AAC
+            match descriptor(decoder_config.get(13..)?) {
+                Some((0x05, info)) if object_type == 0x40 => {
+                    Some(format!("mp4a.40.{}", info.first()? >> 3))
+                }
+                _ => Some(format!("mp4a.{object_type:02x}")),
+            }
+        }
+        b"fLaC" => Some("flac".to_owned()),
+        _ => None,
+    }
+}
+
+/// Parse the MPEG-4 descriptor at the start of `data`, returning its tag and
+/// payload.
+fn descriptor(data: &[u8]) -> Option<(u8, &[u8])> {
+    let tag = *data.first()?;
+
+    let mut length = 0usize;
+    let mut position = 1;
+
+    loop {
+        let byte = *data.get(position)?;
+        position += 1;
+        length = length << 7 | usize::from(byte & 0x7f);
+
+        if byte & 0x80 == 0 || position > 4 {
+            break;
+        }
+    }
+
+    Some((tag, data.get(position..position + length)?))
+}
+
+/// The box type as a string.
+fn fourcc(box_type: &[u8; 4]) -> String {
+    String::from_utf8_lossy(box_type).into_owned()
+}
+
+/// Big-endian `u16` at `position` of `data`.
+fn u16_at(data: &[u8], position: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(position..position + 2)?.try_into().ok()?,
+    ))
+}
+
+/// Big-endian `u32` at `position` of `data`.
+fn u32_at(data: &[u8], position: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(position..position + 4)?.try_into().ok()?,
+    ))
+}
+
+/// An error of an invalid file.
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid MP4: {reason}"))
+}
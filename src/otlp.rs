@@ -0,0 +1,54 @@
+//! Export of request spans by OTLP, see [`OtlpConfig`], so that traces of
+//! the server are correlated with those of the player in Jaeger or Tempo.
+//!
+//! The span of each request, see
+//! [`request_id::scope`](crate::request_id::scope), is exported with those
+//! within it, as a child of the one told by the W3C `traceparent` header if
+//! any, see [`accept`].
+//!
+//! Requires the `otlp` feature, all no-op otherwise.
+
+#[cfg(feature = "otlp")]
+mod export;
+
+use anyhow::Result;
+use tracing::Subscriber;
+use tracing_subscriber::{Layer, registry::LookupSpan};
+
+use crate::{config::OtlpConfig, proto};
+
+/// The layer exporting spans to the collector configured, if any.
+pub(crate) fn layer<S>(config: Option<&OtlpConfig>) -> Result<Option<impl Layer<S>>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let Some(config) = config else {
+        return Ok(None);
+    };
+
+    #[cfg(feature = "otlp")]
+    return export::layer(config).map(Some);
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        let _ = config;
+
+        Ok(None::<tracing_subscriber::layer::Identity>)
+    }
+}
+
+/// Take the trace told by the `traceparent` header of `request` as the
+/// parent of the span of it.
+pub(crate) fn accept(request: &proto::Request) {
+    #[cfg(feature = "otlp")]
+    export::accept(request);
+
+    #[cfg(not(feature = "otlp"))]
+    let _ = request;
+}
+
+/// Export spans not yet exported, before exiting.
+pub(crate) fn shutdown() {
+    #[cfg(feature = "otlp")]
+    export::shutdown();
+}
@@ -0,0 +1,88 @@
+//! OTLP exporter, by `opentelemetry-otlp` over gRPC.
+
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use http::{HeaderMap, HeaderName};
+use opentelemetry::{
+    propagation::{Extractor, TextMapPropagator},
+    trace::TracerProvider,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    Resource,
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracerProvider},
+};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{Layer, registry::LookupSpan};
+
+use crate::{config::OtlpConfig, proto};
+
+/// Tracer provider set up, see [`layer`].
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// The layer exporting spans to the collector configured.
+pub(super) fn layer<S>(config: &OtlpConfig) -> Result<impl Layer<S>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(&config.endpoint)
+        .build()
+        .with_context(|| format!("Set up OTLP exporter to {}", config.endpoint))?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+
+    let _ = PROVIDER.set(provider);
+
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// See [`otlp::accept`](super::accept).
+pub(super) fn accept(request: &proto::Request) {
+    if PROVIDER.get().is_none() {
+        return;
+    }
+
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(&request.headers));
+
+    tracing::Span::current().set_parent(parent);
+}
+
+/// See [`otlp::shutdown`](super::shutdown).
+pub(super) fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            tracing::error!("Shut down OTLP exporter error: {e}");
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Request headers, as of [`TextMapPropagator::extract`].
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
@@ -1 +1,484 @@
 //! Playurl auth module
+//!
+//! Playurls are resolved from the API of bilibili, see [`resolve`], and
+//! served with the URLs rewritten by the `/playurl` endpoint, see
+//! [`service::playurl`](crate::service::playurl).
+//!
+//! Segments listed in playurl responses come with their sizes, sometimes
+//! checksums as well. They are recorded by cache key, see
+//! [`resource::expect`], so that segments fetched from upstream are validated
+//! against them.
+//!
+//! API responses are cached in memory by query, see [`fetch`], until shortly
+//! before the earliest deadline of the URLs listed, so that bursts of player
+//! reloads are answered without the API.
+//!
+//! Responses are modeled by [`model`], rewritten and generated alike.
+
+mod model;
+
+use std::{
+    fmt,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result, bail};
+use http::{HeaderMap, Method, StatusCode, Uri, header::COOKIE};
+use serde::Deserialize;
+use serde_json::Value;
+
+pub(crate) use self::model::{
+    ApiResponse, Dash, Dolby, Durl, Flac, Format, Playurl, SegmentBase, SegmentBaseCamel, Stream,
+};
+use crate::{
+    config::PlayurlConfig,
+    credentials,
+    service::resource::{self, Expected},
+    upstream::{self, Priority},
+    wbi,
+};
+#[cfg(feature = "admin")]
+use crate::{
+    extract::{self, FromRequest},
+    proto,
+    router::Params,
+};
+
+/// Bit of `fnval` asking for DASH streams rather than FLV / MP4 segments.
+pub(crate) const FNVAL_DASH: u32 = 16;
+
+/// Bit of `fnval` asking for HDR video streams as well.
+pub(crate) const FNVAL_HDR: u32 = 64;
+
+/// Bit of `fnval` asking for 4K video streams as well, along with `fourk=1`.
+pub(crate) const FNVAL_4K: u32 = 128;
+
+/// Bit of `fnval` asking for Dolby audio streams as well.
+pub(crate) const FNVAL_DOLBY_AUDIO: u32 = 256;
+
+/// Bit of `fnval` asking for Dolby Vision video streams as well.
+pub(crate) const FNVAL_DOLBY_VISION: u32 = 512;
+
+/// Bit of `fnval` asking for 8K video streams as well.
+pub(crate) const FNVAL_8K: u32 = 1024;
+
+/// Bit of `fnval` asking for AV1 video streams as well.
+pub(crate) const FNVAL_AV1: u32 = 2048;
+
+/// `fnval` asking for all DASH streams, HDR, 4K, 8K, AV1 and Dolby included.
+pub(crate) const FNVAL_DASH_ALL: u32 = 4048;
+
+/// Audio qualities of AAC streams, the worst first, i.e. 64K, 132K and 192K.
+const AUDIO_QUALITIES: [u32; 3] = [30216, 30232, 30280];
+
+/// Path of the playurl API.
+pub(crate) const API_PATH: &str = "/x/player/playurl";
+
+/// Path of the playurl API requiring WBI signing, see [`wbi`].
+pub(crate) const WBI_API_PATH: &str = "/x/player/wbi/playurl";
+
+/// Path of the nav API, telling whether logged in and the WBI keys.
+pub(crate) const NAV_PATH: &str = "/x/web-interface/nav";
+
+/// Max size of an API response body.
+const MAX_API_RESPONSE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Max number of API responses cached.
+const CACHED_RESPONSES: u64 = 4096;
+
+/// How long before the earliest deadline of its URLs an API response cached
+/// expires, so that clients get URLs with time left.
+const DEADLINE_MARGIN: u64 = 5 * 60;
+
+/// API responses cached, by query.
+static RESPONSES: LazyLock<moka::sync::Cache<ResponseKey, CachedResponse>> = LazyLock::new(|| {
+    moka::sync::Cache::builder()
+        .max_capacity(CACHED_RESPONSES)
+        .expire_after(ResponseExpiry)
+        .build()
+});
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Key of an API response cached, i.e. what of [`Query`] is forwarded.
+struct ResponseKey {
+    video: String,
+
+    cid: u64,
+
+    qn: u32,
+
+    fnval: u32,
+
+    fnver: u32,
+
+    fourk: bool,
+}
+
+#[derive(Debug, Clone)]
+/// An API response cached, see [`RESPONSES`].
+struct CachedResponse {
+    response: Arc<Value>,
+
+    ttl: Duration,
+}
+
+/// Expires API responses cached after their own TTL.
+struct ResponseExpiry;
+
+impl moka::Expiry<ResponseKey, CachedResponse> for ResponseExpiry {
+    fn expire_after_create(
+        &self,
+        _key: &ResponseKey,
+        value: &CachedResponse,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(value.ttl)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[derive(Deserialize)]
+#[serde(try_from = "RawQuery")]
+/// What playurl to resolve, i.e. the video `cid` of `video` in quality `qn`,
+/// with the streams asked by `fnval`.
+pub(crate) struct Query {
+    pub video: Video,
+
+    pub cid: u64,
+
+    pub qn: u32,
+
+    pub fnval: u32,
+
+    /// Version of `fnval`, 0 as of now
+    pub fnver: u32,
+
+    /// Whether 4K is asked for, along with [`FNVAL_4K`]
+    pub fourk: bool,
+
+    /// Codec of the video streams preferred if several given, see
+    /// [`Playurl::video_codecid`]
+    pub codecid: Option<u32>,
+}
+
+#[derive(Deserialize)]
+/// [`Query`] as given, like `bvid={bvid}&cid={cid}&qn={qn}&fnval={fnval}`, or
+/// `avid={aid}` in place of `bvid`. `qn` defaults to 80, i.e. 1080P, and
+/// `fnval` to [`FNVAL_DASH_ALL`], `fnver` to 0 and `fourk` to 1. The codec
+/// preferred may be given by `codecid`.
+struct RawQuery {
+    bvid: Option<String>,
+    avid: Option<u64>,
+    cid: u64,
+    qn: Option<u32>,
+    fnval: Option<u32>,
+    fnver: Option<u32>,
+    fourk: Option<String>,
+    codecid: Option<u32>,
+}
+
+impl TryFrom<RawQuery> for Query {
+    type Error = String;
+
+    fn try_from(raw: RawQuery) -> Result<Self, Self::Error> {
+        let video = match (raw.bvid, raw.avid) {
+            (Some(bvid), _) => {
+                if bvid.is_empty() || !bvid.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                    return Err(format!("Invalid bvid {bvid:?}"));
+                }
+
+                Video::Bvid(bvid)
+            }
+            (None, Some(aid)) => Video::Aid(aid),
+            (None, None) => return Err("Missing bvid or avid".to_owned()),
+        };
+
+        let fourk = match raw.fourk.as_deref() {
+            Some("1" | "true") | None => true,
+            Some("0" | "false" | "") => false,
+            Some(fourk) => return Err(format!("Invalid fourk {fourk:?}")),
+        };
+
+        Ok(Self {
+            video,
+            cid: raw.cid,
+            qn: raw.qn.unwrap_or(80),
+            fnval: raw.fnval.unwrap_or(FNVAL_DASH_ALL),
+            fnver: raw.fnver.unwrap_or(0),
+            fourk,
+            codecid: raw.codecid,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A video, by either ID.
+pub(crate) enum Video {
+    /// Like `BV1xx411c7mD`
+    Bvid(String),
+
+    /// Like `170001`
+    Aid(u64),
+}
+
+impl fmt::Display for Video {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bvid(bvid) => f.write_str(bvid),
+            Self::Aid(aid) => write!(f, "av{aid}"),
+        }
+    }
+}
+
+impl Query {
+    #[cfg(all(feature = "admin", feature = "playurl"))]
+    /// Parse the query of `request`, see [`RawQuery`], `None` if invalid.
+    pub(crate) fn of_request(request: &proto::Request) -> Option<Self> {
+        extract::Query::from_request(request, &Params::default())
+            .ok()
+            .map(|extract::Query(query)| query)
+    }
+
+    /// Whether DASH streams are asked for, or else FLV / MP4 segments.
+    pub(crate) const fn is_dash(&self) -> bool {
+        self.fnval & FNVAL_DASH != 0
+    }
+
+    /// Whether video streams of quality `qn` are asked for: 4K only if asked
+    /// by both `fnval` and `fourk`, HDR, Dolby Vision and 8K only if asked by
+    /// `fnval`, the others always.
+    pub(crate) const fn allows(&self, qn: u32) -> bool {
+        let bit = match qn {
+            120 => {
+                if !self.fourk {
+                    return false;
+                }
+
+                FNVAL_4K
+            }
+            125 => FNVAL_HDR,
+            126 => FNVAL_DOLBY_VISION,
+            127 => FNVAL_8K,
+            _ => return true,
+        };
+
+        self.fnval & bit != 0
+    }
+}
+
+#[cfg(all(feature = "admin", feature = "playurl"))]
+/// Resolve the playurl of `query`.
+pub(crate) async fn resolve(config: &PlayurlConfig, query: &Query) -> Result<Playurl> {
+    let response: ApiResponse<Playurl> = serde_json::from_value(fetch(config, query).await?)
+        .context("Parse playurl API response")?;
+
+    match response.data {
+        Some(playurl) if response.code == 0 => Ok(playurl),
+        _ => bail!("Playurl API error {}: {}", response.code, response.message),
+    }
+}
+
+/// Fetch the API response resolving the playurl, see [`resolve`], as is,
+/// e.g. to be rewritten and passed on.
+///
+/// Successful responses are cached for [`PlayurlConfig::cache_ttl`] at most,
+/// and expire [`DEADLINE_MARGIN`] before the earliest `deadline` of the URLs
+/// listed. Those without such a deadline are cached for the former.
+pub(crate) async fn fetch(config: &PlayurlConfig, query: &Query) -> Result<Value> {
+    let key = ResponseKey {
+        video: query.video.to_string(),
+        cid: query.cid,
+        qn: query.qn,
+        fnval: query.fnval,
+        fnver: query.fnver,
+        fourk: query.fourk,
+    };
+
+    if let Some(cached) = RESPONSES.get(&key) {
+        tracing::debug!("Playurl cache hit: {key:?}");
+
+        return Ok(Value::clone(&cached.response));
+    }
+
+    let response = fetch_uncached(config, query).await?;
+
+    if response.get("code").and_then(Value::as_i64) == Some(0) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let ttl = match earliest_deadline(&response) {
+            Some(deadline) => deadline
+                .saturating_sub(now + DEADLINE_MARGIN)
+                .min(config.cache_ttl),
+            None => config.cache_ttl,
+        };
+
+        if ttl > 0 {
+            RESPONSES.insert(
+                key,
+                CachedResponse {
+                    response: Arc::new(response.clone()),
+                    ttl: Duration::from_secs(ttl),
+                },
+            );
+        }
+    }
+
+    Ok(response)
+}
+
+/// Fetch the API response of `query` from the API, see [`fetch`].
+async fn fetch_uncached(config: &PlayurlConfig, query: &Query) -> Result<Value> {
+    let Query {
+        video,
+        cid,
+        qn,
+        fnval,
+        fnver,
+        fourk,
+        ..
+    } = query;
+
+    let video = match video {
+        Video::Bvid(bvid) => ("bvid", bvid.clone()),
+        Video::Aid(aid) => ("avid", aid.to_string()),
+    };
+    let mut params = vec![
+        video,
+        ("cid", cid.to_string()),
+        ("qn", qn.to_string()),
+        ("fnval", fnval.to_string()),
+        ("fnver", fnver.to_string()),
+        ("fourk", u8::from(*fourk).to_string()),
+    ];
+    if let Some(access_key) = credentials::access_key() {
+        params.push(("access_key", access_key));
+    }
+
+    if !config.wbi {
+        let query = params
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        return forward(config, &format!("{API_PATH}?{query}")).await;
+    }
+
+    let query = wbi::sign(config, &params)
+        .await
+        .context("Sign playurl API request")?;
+    let response = forward(config, &format!("{WBI_API_PATH}?{query}")).await?;
+
+    // Risk control, likely for the keys rotated
+    if response.get("code").and_then(Value::as_i64) == Some(-352) {
+        wbi::invalidate();
+    }
+
+    Ok(response)
+}
+
+/// Fetch the API response of `path_and_query` as is, e.g. that of a client
+/// passed through, with the cookies of the credentials if any, see
+/// [`credentials::cookie`].
+pub(crate) async fn forward(config: &PlayurlConfig, path_and_query: &str) -> Result<Value> {
+    let mut headers = HeaderMap::new();
+    if let Some(cookie) = credentials::cookie() {
+        headers.insert(COOKIE, cookie);
+    }
+
+    let mut response = upstream::fetch(
+        &config.api,
+        &Method::GET,
+        path_and_query,
+        &headers,
+        Priority::Interactive,
+    )
+    .await?;
+
+    if response.status != StatusCode::OK {
+        bail!("Playurl API responded {}", response.status);
+    }
+
+    let mut body = Vec::new();
+
+    while let Some(data) = response.next().await? {
+        if body.len() + data.len() > MAX_API_RESPONSE_SIZE {
+            bail!("Playurl API response too large");
+        }
+
+        body.extend_from_slice(data);
+    }
+
+    serde_json::from_slice(&body).context("Parse playurl API response")
+}
+
+/// The earliest `deadline` query param, in seconds since UNIX epoch, of the
+/// URLs in `value`.
+fn earliest_deadline(value: &Value) -> Option<u64> {
+    match value {
+        Value::String(url) => url
+            .split_once('?')?
+            .1
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("deadline="))?
+            .parse()
+            .ok(),
+        Value::Array(values) => values.iter().filter_map(earliest_deadline).min(),
+        Value::Object(object) => object.values().filter_map(earliest_deadline).min(),
+        _ => None,
+    }
+}
+
+/// The audio stream of `audio` to play along with the video stream of
+/// quality `qn`, like the app pairs them: 192K for 1080P and above, 132K for
+/// 720P and 64K below, or the closest one available. Streams of other
+/// qualities, e.g. Dolby, are taken only if none of AAC.
+pub(crate) fn paired_audio(qn: u32, audio: &[Stream]) -> Option<&Stream> {
+    let preferred = match qn {
+        80.. => 2,
+        64.. => 1,
+        _ => 0,
+    };
+
+    audio
+        .iter()
+        .filter_map(|stream| {
+            let rank = AUDIO_QUALITIES.iter().position(|&id| id == stream.id)?;
+            Some((rank.abs_diff(preferred), usize::MAX - rank, stream))
+        })
+        .min_by_key(|&(distance, rank, _)| (distance, rank))
+        .map(|(.., stream)| stream)
+        .or_else(|| audio.iter().max_by_key(|stream| stream.bandwidth))
+}
+
+/// Cache key and upstream path and query of the object of the absolute
+/// `url`, e.g. a `base_url` of a playurl.
+pub(crate) fn split_url(url: &str) -> Option<(String, String)> {
+    let uri: Uri = url.parse().ok()?;
+    uri.scheme()?;
+
+    let path_and_query = uri.path_and_query()?;
+
+    Some((
+        path_and_query.path().trim_start_matches('/').to_owned(),
+        path_and_query.as_str().to_owned(),
+    ))
+}
+
+/// Record the sizes and checksums of the FLV / MP4 segments of `playurl`,
+/// see [`resource::expect`].
+pub(crate) fn expect_segments(playurl: &Playurl) {
+    for durl in &playurl.durl {
+        if let Some((key, _)) = split_url(&durl.url) {
+            resource::expect(
+                &key,
+                Expected {
+                    size: Some(durl.size),
+                    md5: Some(durl.md5.clone()).filter(|md5| !md5.is_empty()),
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! Playurl API responses, shared by those of the API, parsed to be rewritten,
+//! and those generated, see [`service::playurl`](crate::service::playurl).
+//!
+//! Fields not modeled are kept in `extra` of each, so that responses of the
+//! API are passed on as they are but what is rewritten. Fields the API gives
+//! in both cases, like `base_url` and `baseUrl`, are modeled apart, the
+//! camel case ones optional.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+/// Envelope of API responses.
+pub(crate) struct ApiResponse<T> {
+    pub code: i64,
+
+    #[serde(default)]
+    pub message: String,
+
+    #[serde(default)]
+    pub ttl: u32,
+
+    /// Null on errors
+    pub data: Option<T>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+/// Playurl of a video, i.e. `data` of `/x/player/playurl`.
+pub(crate) struct Playurl {
+    #[serde(default)]
+    /// Like `local`
+    pub from: String,
+
+    #[serde(default)]
+    /// Like `suee`
+    pub result: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    /// Quality (`qn`) actually given
+    pub quality: u32,
+
+    #[serde(default)]
+    /// Format of the quality given, like `flv` or `mp4`
+    pub format: String,
+
+    #[serde(default)]
+    /// Duration in milliseconds
+    pub timelength: u64,
+
+    #[serde(default)]
+    /// Formats of [`Self::accept_quality`], joined by `,`
+    pub accept_format: String,
+
+    #[serde(default)]
+    /// Descriptions of [`Self::accept_quality`]
+    pub accept_description: Vec<String>,
+
+    #[serde(default)]
+    /// All qualities of the video, the best first
+    pub accept_quality: Vec<u32>,
+
+    #[serde(default)]
+    /// Codec ID of the video streams preferred, 7 for AVC, 12 for HEVC and
+    /// 13 for AV1
+    pub video_codecid: u32,
+
+    #[serde(default)]
+    /// Like `start`
+    pub seek_param: String,
+
+    #[serde(default)]
+    /// Like `offset`
+    pub seek_type: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// DASH streams, when asked by `fnval`
+    pub dash: Option<Dash>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// FLV / MP4 segments otherwise
+    pub durl: Vec<Durl>,
+
+    #[serde(default)]
+    /// All qualities of the video, the best first
+    pub support_formats: Vec<Format>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+/// A quality of [`Playurl::support_formats`].
+pub(crate) struct Format {
+    pub quality: u32,
+
+    #[serde(default)]
+    pub format: String,
+
+    #[serde(default)]
+    /// Like `1080P 高清`
+    pub new_description: String,
+
+    #[serde(default)]
+    /// Like `1080P`
+    pub display_desc: String,
+
+    #[serde(default)]
+    /// Like `高码率`
+    pub superscript: String,
+
+    #[serde(default)]
+    /// Codecs of the video streams of the quality, like `avc1.640032`, null
+    /// if none
+    pub codecs: Option<Vec<String>>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+/// DASH streams of [`Playurl`].
+pub(crate) struct Dash {
+    #[serde(default)]
+    /// Duration in seconds
+    pub duration: u64,
+
+    #[serde(
+        rename = "minBufferTime",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_buffer_time_camel: Option<f64>,
+
+    #[serde(default)]
+    pub min_buffer_time: f64,
+
+    #[serde(default)]
+    pub video: Vec<Stream>,
+
+    #[serde(default)]
+    /// Null when the video has no audio
+    pub audio: Option<Vec<Stream>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dolby: Option<Dolby>,
+
+    #[serde(default)]
+    /// Null when no Hi-Res audio
+    pub flac: Option<Flac>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+/// Dolby audio streams of [`Dash`].
+pub(crate) struct Dolby {
+    #[serde(default)]
+    /// 0 if none, 1 for Dolby Audio
+    pub r#type: u32,
+
+    #[serde(default)]
+    /// Null if none
+    pub audio: Option<Vec<Stream>>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+/// Hi-Res audio stream of [`Dash`].
+pub(crate) struct Flac {
+    #[serde(default)]
+    pub display: bool,
+
+    #[serde(default)]
+    /// Null if none
+    pub audio: Option<Stream>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+/// A DASH stream, video or audio, each a single object requested by ranges.
+pub(crate) struct Stream {
+    /// Quality (`qn`) of a video stream, or the audio quality
+    pub id: u32,
+
+    #[serde(rename = "baseUrl", default, skip_serializing_if = "Option::is_none")]
+    pub base_url_camel: Option<String>,
+
+    pub base_url: String,
+
+    #[serde(rename = "backupUrl", default, skip_serializing_if = "Option::is_none")]
+    pub backup_url_camel: Option<Vec<String>>,
+
+    #[serde(default)]
+    /// May be null
+    pub backup_url: Option<Vec<String>>,
+
+    #[serde(default)]
+    /// In bits per second
+    pub bandwidth: u64,
+
+    #[serde(rename = "mimeType", default, skip_serializing_if = "Option::is_none")]
+    pub mime_type_camel: Option<String>,
+
+    #[serde(default)]
+    /// Like `video/mp4`
+    pub mime_type: String,
+
+    #[serde(default)]
+    /// RFC 6381 codecs, like `avc1.640032`
+    pub codecs: String,
+
+    #[serde(default)]
+    pub width: u32,
+
+    #[serde(default)]
+    pub height: u32,
+
+    #[serde(rename = "frameRate", default, skip_serializing_if = "Option::is_none")]
+    pub frame_rate_camel: Option<String>,
+
+    #[serde(default)]
+    /// Like `29.970`
+    pub frame_rate: String,
+
+    #[serde(default)]
+    /// Like `1:1`
+    pub sar: String,
+
+    #[serde(
+        rename = "startWithSap",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub start_with_sap_camel: Option<u32>,
+
+    #[serde(default)]
+    pub start_with_sap: u32,
+
+    #[serde(
+        rename = "SegmentBase",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub segment_base_camel: Option<SegmentBaseCamel>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_base: Option<SegmentBase>,
+
+    #[serde(default)]
+    /// 7 for AVC, 12 for HEVC and 13 for AV1, 0 for audio
+    pub codecid: u32,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+/// Byte ranges of the initialization segment and `sidx` of a [`Stream`], like
+/// `0-1000`.
+pub(crate) struct SegmentBase {
+    pub initialization: String,
+
+    pub index_range: String,
+}
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// [`SegmentBase`] in the other case.
+pub(crate) struct SegmentBaseCamel {
+    #[serde(rename = "Initialization")]
+    pub initialization: String,
+
+    pub index_range: String,
+}
+
+#[derive(Debug, Clone, Default)]
+#[derive(Serialize, Deserialize)]
+/// A FLV / MP4 segment of [`Playurl`].
+pub(crate) struct Durl {
+    #[serde(default)]
+    /// Starting from 1
+    pub order: u32,
+
+    #[serde(default)]
+    /// Duration in milliseconds
+    pub length: u64,
+
+    /// Size in bytes
+    pub size: u64,
+
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    /// MD5 of the content in hex, may be empty
+    pub md5: String,
+
+    pub url: String,
+
+    #[serde(default)]
+    /// May be null
+    pub backup_url: Option<Vec<String>>,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Playurl {
+    /// All DASH streams, video, audio, Dolby and Hi-Res ones.
+    pub(crate) fn streams_mut(&mut self) -> impl Iterator<Item = &mut Stream> {
+        self.dash.iter_mut().flat_map(|dash| {
+            dash.video
+                .iter_mut()
+                .chain(dash.audio.iter_mut().flatten())
+                .chain(
+                    dash.dolby
+                        .iter_mut()
+                        .flat_map(|dolby| dolby.audio.iter_mut().flatten()),
+                )
+                .chain(dash.flac.iter_mut().flat_map(|flac| flac.audio.as_mut()))
+        })
+    }
+}
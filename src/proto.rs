@@ -1,21 +1,220 @@
 //! HTTP 1.1 protocol implementation.
 
+mod body;
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{self, Poll},
+};
+
 use anyhow::{Context, Result, bail};
+use bytes::Bytes;
 use fluent_uri::UriRef;
+#[cfg(any(feature = "admin", feature = "sessions", feature = "signing"))]
+use fluent_uri::encoding::EStr;
+#[cfg(feature = "admin")]
+use http::header::EXPECT;
 use http::{
     HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
-    header::{CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, SERVER},
+    header::{CONNECTION, CONTENT_LENGTH, CONTENT_TYPE, SERVER, TRANSFER_ENCODING},
 };
 use macro_toolset::string_v2::{NumStr, StringExtT};
+#[cfg(any(feature = "admin", feature = "upstream"))]
+use tokio::io::AsyncBufRead;
+#[cfg(feature = "admin")]
+use tokio::io::Chain;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter,
+        DuplexStream, ReadBuf,
+    },
     net::TcpStream,
 };
 
+pub(crate) use self::body::Body;
+#[cfg(feature = "upstream")]
+use crate::alert;
+#[cfg(feature = "tls")]
+use crate::tls;
+use crate::{
+    access_log,
+    buf::{self, Buf, Size},
+    config::ThrottleConfig,
+    metrics, request_id, security_headers, slow_log,
+    timing::{self, Phase},
+    transfer,
+};
+
+/// A connection requests are read from and responses written to, plain TCP
+/// or TLS, see [`tls`](crate::tls).
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {
+    /// The TCP stream if responses are written to it as is, i.e. not over
+    /// TLS, so that files can be sent by `sendfile(2)`.
+    fn plain(&self) -> Option<&TcpStream>;
+
+    /// The server name told by SNI, if over TLS.
+    fn server_name(&self) -> Option<&str>;
+
+    /// Address of the peer connected.
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Wait until data come, e.g. the next request on a kept-alive
+    /// connection, returning whether any rather than closed.
+    fn readable(&mut self) -> impl Future<Output = bool> + Send;
+}
+
+impl Stream for TcpStream {
+    fn plain(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+
+    fn server_name(&self) -> Option<&str> {
+        None
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+
+    async fn readable(&mut self) -> bool {
+        let mut buf = [0; 1];
+
+        self.peek(&mut buf).await.is_ok_and(|count| count > 0)
+    }
+}
+
+/// A connection in memory, of the server end of [`tokio::io::duplex`], e.g.
+/// of tests, buffered like [`tls::TlsStream`] to be waited for data.
+pub(crate) type MemoryStream = BufReader<DuplexStream>;
+
+impl Stream for MemoryStream {
+    fn plain(&self) -> Option<&TcpStream> {
+        None
+    }
+
+    fn server_name(&self) -> Option<&str> {
+        None
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+
+    async fn readable(&mut self) -> bool {
+        self.fill_buf().await.is_ok_and(|buf| !buf.is_empty())
+    }
+}
+
+#[derive(Debug)]
+/// A connection accepted, plain TCP or TLS, so that the routes of a
+/// [`Router`](crate::Router) are handled alike on either.
+pub enum AnyStream {
+    /// Plain TCP
+    Plain(TcpStream),
+
+    #[cfg(feature = "tls")]
+    /// TLS
+    Tls(Box<tls::TlsStream>),
+
+    /// In memory, see [`MemoryStream`]
+    Memory(MemoryStream),
+}
+
+impl AsyncRead for AnyStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Memory(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AnyStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Memory(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Memory(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Memory(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+impl Stream for AnyStream {
+    fn plain(&self) -> Option<&TcpStream> {
+        match self {
+            Self::Plain(stream) => stream.plain(),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.plain(),
+            Self::Memory(stream) => stream.plain(),
+        }
+    }
+
+    fn server_name(&self) -> Option<&str> {
+        match self {
+            Self::Plain(stream) => stream.server_name(),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.server_name(),
+            Self::Memory(stream) => stream.server_name(),
+        }
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Self::Plain(stream) => Stream::peer_addr(stream),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.peer_addr(),
+            Self::Memory(stream) => stream.peer_addr(),
+        }
+    }
+
+    async fn readable(&mut self) -> bool {
+        match self {
+            Self::Plain(stream) => stream.readable().await,
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.readable().await,
+            Self::Memory(stream) => stream.readable().await,
+        }
+    }
+}
+
 #[allow(dead_code, reason = "May be used in the future")]
 #[derive(Debug, Clone)]
 /// HTTP Request
-pub(crate) struct Request {
+pub struct Request {
     /// Request-Line - HTTP Method
     pub method: Method,
 
@@ -24,6 +223,10 @@ pub(crate) struct Request {
 
     /// Request Headers
     pub headers: HeaderMap,
+
+    /// Bytes buffered past the head when parsing, i.e. the beginning of the
+    /// body, see [`Request::read_body`].
+    pub body_prefix: Bytes,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -41,6 +244,11 @@ pub(crate) enum Error {
     /// Invalid HTTP Request-Line URI
     RequestLineUri,
 
+    #[cfg(feature = "upstream")]
+    #[error("Invalid HTTP Status-Line")]
+    /// Invalid HTTP Status-Line
+    StatusLine,
+
     #[error("Invalid HTTP Version")]
     /// Invalid HTTP Version
     HTTPVersion,
@@ -48,23 +256,84 @@ pub(crate) enum Error {
     #[error("Invalid HTTP Header")]
     /// Invalid HTTP Header
     Header,
+
+    #[cfg(any(feature = "admin", feature = "upstream"))]
+    #[error("Invalid HTTP Body")]
+    /// Invalid HTTP Body, e.g. malformed chunks or truncated
+    Body,
+}
+
+/// Max bytes of a message head, beyond which it's refused.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+#[cfg(any(feature = "admin", feature = "upstream"))]
+/// Max bytes of a line of a chunked body, i.e. a chunk size along with its
+/// extensions or a trailer field, beyond which it's refused.
+const MAX_LINE_SIZE: usize = 4 * 1024;
+
+#[cfg(any(feature = "admin", feature = "upstream"))]
+/// Max bytes of the trailer fields of a chunked body, all together, beyond
+/// which they're refused.
+const MAX_TRAILER_SIZE: usize = 16 * 1024;
+
+/// End of the head in `buf`, i.e. past the empty line ending it, if read.
+///
+/// Searched from `from` on, the bytes before known not to end it, so that a
+/// head read a few bytes at a time is not searched over and over.
+fn head_end(buf: &[u8], from: usize) -> Option<usize> {
+    buf.windows(2)
+        .enumerate()
+        .skip(from)
+        .find_map(|(i, window)| match window {
+            [b'\n', b'\n'] => Some(i + 2),
+            [b'\n', b'\r'] if buf.get(i + 2) == Some(&b'\n') => Some(i + 3),
+            _ => None,
+        })
 }
 
 impl Request {
-    /// Parse a HTTP Request from a [`TcpStream`].
-    pub(crate) async fn handle(tcp_stream: &mut TcpStream) -> Result<Option<Self>> {
-        let mut request_lines = BufReader::new(tcp_stream).lines();
+    /// Parse a HTTP Request from `tcp_stream`, any [`Stream`] or else, the
+    /// head read into a pooled buffer, see [`buf`](crate::buf).
+    pub(crate) async fn handle<S>(tcp_stream: &mut S) -> Result<Option<Self>>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut head = Buf::take(Size::Head);
+        let mut searched = 0;
 
-        let start_line = request_lines.next_line().await?;
+        let end = loop {
+            if let Some(end) = head_end(&head, searched) {
+                break end;
+            }
 
-        if start_line.is_none() {
-            return Ok(None);
-        }
+            // The last 2 bytes may start the end along with those to come
+            searched = head.len().saturating_sub(2);
 
-        // Start handle request
-        let start_line = start_line.unwrap();
+            if head.len() >= MAX_HEAD_SIZE {
+                bail!(Error::Header)
+            }
+
+            if head.len() == head.capacity() {
+                head.reserve(buf::HEAD_SIZE);
+            }
+
+            if tcp_stream.read_buf(&mut *head).await? == 0 {
+                if head.is_empty() {
+                    return Ok(None);
+                }
+
+                bail!(Error::Header)
+            }
+        };
+
+        let head = head.freeze();
 
-        let mut start_line = start_line.split(' ');
+        let mut lines = std::str::from_utf8(&head[..end])
+            .context(Error::Header)?
+            .lines();
+
+        // Start handle request
+        let mut start_line = lines.next().context(Error::RequestLine)?.split(' ');
 
         let mut request = Request {
             method: Method::from_bytes(
@@ -80,37 +349,155 @@ impl Request {
             .context(Error::RequestLineUri)?
             .to_owned(),
             headers: HeaderMap::with_capacity(8),
+            body_prefix: head.slice(end..),
         };
 
         if start_line.next().context(Error::RequestLine)? != "HTTP/1.1" {
             bail!(Error::HTTPVersion)
         }
 
-        loop {
-            let header_line = request_lines
-                .next_line()
-                .await
-                .context(Error::Header)?
-                .context(Error::Header)?;
-
-            if header_line.is_empty() {
-                break;
-            }
-
+        for header_line in lines.take_while(|line| !line.is_empty()) {
             let (header_name, header_value) = header_line.split_once(':').context(Error::Header)?;
-            request.headers.insert(
+            request.headers.append(
                 HeaderName::from_bytes(header_name.as_bytes()).context(Error::Header)?,
                 header_value.trim().parse().context(Error::Header)?,
             );
         }
 
+        request.check_framing()?;
+
         Ok(Some(request))
     }
+
+    /// Refuse a body framed ambiguously, which a proxy in between may tell
+    /// apart from the next request otherwise than we do: `Content-Length`
+    /// repeated or along with `Transfer-Encoding`, or the final transfer
+    /// coding not `chunked`.
+    fn check_framing(&self) -> Result<()> {
+        let mut content_lengths = self.headers.get_all(CONTENT_LENGTH).iter();
+
+        if let Some(length) = content_lengths.next() {
+            if content_lengths.next().is_some()
+                || self.headers.contains_key(TRANSFER_ENCODING)
+                || length
+                    .to_str()
+                    .ok()
+                    .and_then(|length| length.parse::<u64>().ok())
+                    .is_none()
+            {
+                bail!(Error::Header)
+            }
+        }
+
+        if self.headers.contains_key(TRANSFER_ENCODING) && !self.is_chunked() {
+            bail!(Error::Header)
+        }
+
+        Ok(())
+    }
+
+    /// Whether the connection may carry the next request once this one is
+    /// responded, i.e. nothing was read past the head and no body is
+    /// declared. Otherwise the body, or requests pipelined, are left unread,
+    /// and the next request could not be told apart from them.
+    pub(crate) fn is_reusable(&self) -> bool {
+        self.body_prefix.is_empty()
+            && !self.headers.contains_key(TRANSFER_ENCODING)
+            && self
+                .headers
+                .get(CONTENT_LENGTH)
+                .is_none_or(|length| length.as_bytes() == b"0")
+    }
+
+    #[cfg(feature = "admin")]
+    /// The body length if known beforehand, i.e. `Content-Length` of a body
+    /// not chunked.
+    pub(crate) fn content_length(&self) -> Option<u64> {
+        if self.is_chunked() {
+            return None;
+        }
+
+        self.headers
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok())
+    }
+
+    /// Whether the body is chunked, i.e. the final coding of the
+    /// `Transfer-Encoding` list is `chunked`.
+    fn is_chunked(&self) -> bool {
+        self.headers
+            .get_all(TRANSFER_ENCODING)
+            .iter()
+            .next_back()
+            .and_then(|encoding| encoding.to_str().ok())
+            .and_then(|encoding| encoding.rsplit(',').next())
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+    }
+
+    #[cfg(feature = "admin")]
+    /// Start reading the body, either of `Content-Length` or chunked, see
+    /// [`BodyReader`].
+    ///
+    /// `100 Continue` is sent first if the client expects it.
+    pub(crate) async fn body<'a, S>(
+        &'a self,
+        tcp_stream: &'a mut S,
+    ) -> Result<BodyReader<BufReader<Chain<&'a [u8], &'a mut S>>>>
+    where
+        S: Stream,
+    {
+        if self
+            .headers
+            .get(EXPECT)
+            .is_some_and(|expect| expect.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+        {
+            tcp_stream
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                .await?;
+        }
+
+        let chunked = self.is_chunked();
+
+        let remaining = match self.headers.get(CONTENT_LENGTH) {
+            Some(length) if !chunked => length
+                .to_str()
+                .ok()
+                .and_then(|length| length.parse().ok())
+                .context(Error::Header)?,
+            _ => 0,
+        };
+
+        Ok(BodyReader::new(
+            BufReader::new((&self.body_prefix[..]).chain(tcp_stream)),
+            chunked,
+            Some(remaining),
+        ))
+    }
+
+    #[cfg(any(feature = "admin", feature = "sessions", feature = "signing"))]
+    /// Get the percent-decoded value of the first query parameter of the
+    /// given name.
+    pub(crate) fn query_param(&self, name: &str) -> Option<String> {
+        self.request_uri.query()?.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, EStr::EMPTY));
+
+            if key.decode().into_string().ok()? != name {
+                return None;
+            }
+
+            value
+                .decode()
+                .into_string()
+                .ok()
+                .map(std::borrow::Cow::into_owned)
+        })
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 /// HTTP Response
-pub(crate) struct Response<B = Vec<u8>> {
+pub(crate) struct Response {
     /// Response Status code
     pub status: StatusCode,
 
@@ -118,7 +505,10 @@ pub(crate) struct Response<B = Vec<u8>> {
     pub headers: HeaderMap,
 
     /// Response Body
-    pub body: Option<B>,
+    pub body: Body,
+
+    /// Bandwidth limit of the body, if any
+    pub throttle: Option<ThrottleConfig>,
 }
 
 impl Default for Response {
@@ -126,7 +516,8 @@ impl Default for Response {
         let mut this = Self {
             status: StatusCode::OK,
             headers: HeaderMap::new(),
-            body: None,
+            body: Body::Empty,
+            throttle: None,
         };
 
         this.headers
@@ -142,6 +533,179 @@ impl Default for Response {
     }
 }
 
+#[cfg(any(feature = "admin", feature = "upstream"))]
+#[derive(Debug)]
+/// Message body reader, see [`Request::body`].
+///
+/// Bytes past the body are not preserved, so the connection should not be
+/// reused if the body has not been read to the end.
+pub(crate) struct BodyReader<R> {
+    reader: R,
+
+    /// Whether `Transfer-Encoding: chunked`
+    chunked: bool,
+
+    /// Whether delimited by the end of the stream, i.e. neither of
+    /// `Content-Length` nor chunked.
+    until_eof: bool,
+
+    /// Bytes remaining in the body, or the current chunk if chunked.
+    remaining: u64,
+
+    /// Whether a chunk has been started, i.e. a CRLF is expected before the
+    /// next one.
+    started: bool,
+
+    done: bool,
+
+    chunk: Buf,
+
+    line: Vec<u8>,
+}
+
+#[cfg(any(feature = "admin", feature = "upstream"))]
+impl<R> BodyReader<R>
+where
+    R: AsyncBufRead + Unpin,
+{
+    /// A body of `length` bytes, or chunked. Without either, the body lasts
+    /// until the end of the stream.
+    pub(crate) fn new(reader: R, chunked: bool, length: Option<u64>) -> Self {
+        let until_eof = !chunked && length.is_none();
+
+        Self {
+            reader,
+            chunked,
+            until_eof,
+            remaining: if until_eof {
+                u64::MAX
+            } else {
+                length.unwrap_or(0)
+            },
+            started: false,
+            done: !chunked && length == Some(0),
+            chunk: Buf::chunk(),
+            line: Vec::new(),
+        }
+    }
+
+    #[cfg(feature = "upstream")]
+    /// Whether read to the end and delimited without closing the connection,
+    /// i.e. the connection can carry the next message.
+    pub(crate) const fn is_finished(&self) -> bool {
+        self.done && !self.until_eof
+    }
+
+    #[cfg(feature = "upstream")]
+    /// The underlying reader.
+    pub(crate) fn into_reader(self) -> R {
+        self.reader
+    }
+
+    /// Read the next piece of the body, `None` at the end.
+    pub(crate) async fn next(&mut self) -> Result<Option<&[u8]>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        if self.remaining == 0 && (!self.chunked || !self.next_chunk().await?) {
+            self.done = true;
+            return Ok(None);
+        }
+
+        let want = self
+            .chunk
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+
+        let read = self.reader.read(&mut self.chunk[..want]).await?;
+
+        if read == 0 {
+            if self.until_eof {
+                self.done = true;
+                return Ok(None);
+            }
+
+            bail!(Error::Body)
+        }
+
+        self.remaining -= read as u64;
+
+        if !self.chunked && self.remaining == 0 {
+            self.done = true;
+        }
+
+        Ok(Some(&self.chunk[..read]))
+    }
+
+    /// Start the next chunk, returns `false` at the last one.
+    async fn next_chunk(&mut self) -> Result<bool> {
+        if self.started && self.read_line().await? != b"\r\n" {
+            bail!(Error::Body)
+        }
+        self.started = true;
+
+        // chunk-size [ chunk-ext ] CRLF
+        let size = std::str::from_utf8(self.read_line().await?)
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| u64::from_str_radix(size.trim(), 16).ok())
+            .context(Error::Body)?;
+
+        if size > 0 {
+            self.remaining = size;
+            return Ok(true);
+        }
+
+        // Trailer fields, ignored
+        let mut trailer_size = 0;
+
+        loop {
+            let line = self.read_line().await?;
+
+            if line == b"\r\n" {
+                return Ok(false);
+            }
+
+            trailer_size += line.len();
+
+            if trailer_size > MAX_TRAILER_SIZE {
+                bail!(Error::Body)
+            }
+        }
+    }
+
+    /// Read a line including the line break, failing at EOF or beyond
+    /// [`MAX_LINE_SIZE`].
+    async fn read_line(&mut self) -> Result<&[u8]> {
+        self.line.clear();
+
+        loop {
+            let available = self.reader.fill_buf().await?;
+
+            if available.is_empty() {
+                bail!(Error::Body)
+            }
+
+            let (end, found) = match available.iter().position(|&byte| byte == b'\n') {
+                Some(end) => (end + 1, true),
+                None => (available.len(), false),
+            };
+
+            if self.line.len() + end > MAX_LINE_SIZE {
+                bail!(Error::Body)
+            }
+
+            self.line.extend_from_slice(&available[..end]);
+            self.reader.consume(end);
+
+            if found {
+                return Ok(&self.line);
+            }
+        }
+    }
+}
+
 impl Response {
     #[inline]
     pub(crate) fn status(status: StatusCode) -> Self {
@@ -150,16 +714,82 @@ impl Response {
             ..Default::default()
         }
     }
-}
 
-#[allow(unused, reason = "pub(crate), may be used in the future")]
-impl<B> Response<B> {
+    #[cfg(feature = "upstream")]
+    /// Parse the head of a HTTP Response, i.e. Status-Line and headers,
+    /// leaving the body in `reader`. The head is read into a pooled buffer,
+    /// see [`buf`](crate::buf).
+    pub(crate) async fn read_head<R>(reader: &mut R) -> Result<Self>
+    where
+        R: AsyncBufRead + Unpin,
+    {
+        let mut head = Buf::take(Size::Head);
+        let mut searched = 0;
+
+        // Line by line, so that nothing past the head is consumed
+        while head_end(&head, searched).is_none() {
+            searched = head.len().saturating_sub(2);
+
+            if head.len() >= MAX_HEAD_SIZE {
+                bail!(Error::Header)
+            }
+
+            let available = reader.fill_buf().await.context(Error::Header)?;
+
+            if available.is_empty() {
+                bail!(Error::Header)
+            }
+
+            let line = available
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(available.len(), |end| end + 1);
+
+            head.extend_from_slice(&available[..line]);
+            reader.consume(line);
+        }
+
+        let mut lines = std::str::from_utf8(&head).context(Error::Header)?.lines();
+
+        // HTTP-Version SP Status-Code SP Reason-Phrase CRLF
+        let mut status_line = lines.next().context(Error::StatusLine)?.splitn(3, ' ');
+
+        if !status_line
+            .next()
+            .is_some_and(|version| version == "HTTP/1.1" || version == "HTTP/1.0")
+        {
+            bail!(Error::HTTPVersion)
+        }
+
+        let status =
+            StatusCode::from_bytes(status_line.next().context(Error::StatusLine)?.as_bytes())
+                .context(Error::StatusLine)?;
+
+        let mut headers = HeaderMap::with_capacity(16);
+
+        for header_line in lines.take_while(|line| !line.is_empty()) {
+            let (header_name, header_value) = header_line.split_once(':').context(Error::Header)?;
+            headers.append(
+                HeaderName::from_bytes(header_name.as_bytes()).context(Error::Header)?,
+                header_value.trim().parse().context(Error::Header)?,
+            );
+        }
+
+        Ok(Self {
+            status,
+            headers,
+            body: Body::Empty,
+            throttle: None,
+        })
+    }
+
     /// Set HTTP [`StatusCode`].
     pub(crate) const fn set_status(&mut self, status: StatusCode) -> &mut Self {
         self.status = status;
         self
     }
 
+    #[allow(unused, reason = "pub(crate), may be used in the future")]
     /// Set HTTP [`HeaderMap`].
     pub(crate) fn set_headers(&mut self, headers: HeaderMap) -> &mut Self {
         self.headers = headers;
@@ -167,24 +797,21 @@ impl<B> Response<B> {
     }
 
     /// Set Body
-    pub(crate) fn set_body(&mut self, body: B) -> &mut Self
-    where
-        B: AsRef<[u8]>,
-    {
-        self.body = Some(body);
+    pub(crate) fn set_body(&mut self, body: impl Into<Body>) -> &mut Self {
+        self.body = body.into();
         self
     }
 
     /// With Body
-    pub(crate) fn with_body<NB>(self, body: NB) -> Response<NB>
-    where
-        NB: AsRef<[u8]>,
-    {
-        Response {
-            status: self.status,
-            headers: self.headers,
-            body: Some(body),
-        }
+    pub(crate) fn with_body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// With the body throttled by `throttle` if any.
+    pub(crate) fn with_throttle(mut self, throttle: Option<ThrottleConfig>) -> Self {
+        self.throttle = throttle;
+        self
     }
 
     #[inline]
@@ -193,14 +820,48 @@ impl<B> Response<B> {
         &mut self.headers
     }
 
-    /// Write the response to a [`TcpStream`].
-    pub(crate) async fn write_to_stream(mut self, tcp_stream: &mut TcpStream) -> Result<()>
-    where
-        B: AsRef<[u8]>,
-    {
-        tracing::debug!("Writting response to {}", tcp_stream.peer_addr()?);
+    /// Write the response to a [`Stream`], the head then the [`Body`].
+    ///
+    /// `Content-Length` is set by the body if known in advance, or else the
+    /// body is sent chunked unless it is set, see [`Body::Stream`]. That of
+    /// [`Body::Empty`] is kept if set, e.g. of the content of a `HEAD`
+    /// request.
+    pub(crate) async fn write_to_stream(mut self, tcp_stream: &mut impl Stream) -> Result<()> {
+        tracing::debug!("Writting response of {}", self.status);
+
+        metrics::status(self.status);
+        access_log::status(self.status);
+        slow_log::responding(self.status);
+        #[cfg(feature = "upstream")]
+        alert::status(self.status);
+
+        if let Some(request_id) = request_id::current() {
+            self.headers.insert(request_id::HEADER, request_id);
+        }
 
-        let mut buf_writer = BufWriter::new(tcp_stream);
+        security_headers::apply(&mut self.headers, tcp_stream.plain().is_none());
+
+        let chunked = match (&self.body, self.body.len()) {
+            (Body::Empty, _) if self.headers.contains_key(CONTENT_LENGTH) => false,
+            (_, Some(len)) => {
+                self.headers.insert(
+                    CONTENT_LENGTH,
+                    NumStr::new_default(len).to_http_header_value()?,
+                );
+
+                false
+            }
+            (_, None) if self.headers.contains_key(CONTENT_LENGTH) => false,
+            (_, None) => {
+                self.headers
+                    .insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+
+                true
+            }
+        };
+
+        let timer = timing::start(Phase::Transfer);
+        let mut buf_writer = BufWriter::new(&mut *tcp_stream);
 
         // Response line
         buf_writer.write_all(b"HTTP/1.1 ").await?;
@@ -209,15 +870,7 @@ impl<B> Response<B> {
             .await?;
         buf_writer.write_all(b"\r\n").await?;
 
-        let body = self.body.as_ref().map(AsRef::as_ref);
-
         // Header lines
-        if let Some(len) = body.map(|body| body.as_ref().len()) {
-            self.headers.insert(
-                CONTENT_LENGTH,
-                NumStr::new_default(len).to_http_header_value()?,
-            );
-        }
         for (header_name, header_value) in self.headers.iter() {
             buf_writer
                 .write_all(header_name.as_str().as_bytes())
@@ -230,12 +883,22 @@ impl<B> Response<B> {
         // CRLF
         buf_writer.write_all(b"\r\n").await?;
 
-        // Body
-        if let Some(body) = body {
+        // Body in memory along with the head, unless throttled
+        if let (Body::Bytes(body), None) = (&self.body, &self.throttle) {
             buf_writer.write_all(body).await?;
+            buf_writer.flush().await?;
+
+            transfer::count(body.len() as u64);
+
+            return Ok(());
         }
 
         buf_writer.flush().await?;
+        drop(timer);
+
+        self.body
+            .write_to(self.throttle, chunked, tcp_stream)
+            .await?;
 
         Ok(())
     }
@@ -0,0 +1,200 @@
+//! Bodies of responses, see [`Body`].
+
+use std::io;
+
+use bytes::Bytes;
+use futures_util::{StreamExt, stream::BoxStream};
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+
+use super::Stream;
+#[cfg(target_os = "linux")]
+use crate::config;
+use crate::{
+    config::ThrottleConfig,
+    timing::{self, Phase},
+    transfer,
+};
+
+/// Body of a [`Response`](super::Response), written after the head by
+/// [`Response::write_to_stream`](super::Response::write_to_stream), which is
+/// thus where all the transfer is done.
+#[derive(Default)]
+pub(crate) enum Body {
+    #[default]
+    /// None, of `Content-Length: 0` unless set otherwise, e.g. of the
+    /// content of a `HEAD` request
+    Empty,
+
+    /// Bytes in memory, e.g. of a file mapped, see [`Bytes::from_owner`]
+    Bytes(Bytes),
+
+    /// `len` bytes of `file` from `offset`, e.g. of the range requested, sent
+    /// by `io_uring` or `sendfile(2)` when enabled
+    File {
+        /// File sent
+        file: File,
+
+        /// Where the bytes sent start
+        offset: u64,
+
+        /// Bytes sent
+        len: u64,
+    },
+
+    /// Pieces of unknown length in advance, e.g. relayed from upstream, sent
+    /// chunked unless `Content-Length` set
+    Stream(BoxStream<'static, io::Result<Bytes>>),
+}
+
+impl std::fmt::Debug for Body {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Empty"),
+            Self::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Self::File { offset, len, .. } => f
+                .debug_struct("File")
+                .field("offset", offset)
+                .field("len", len)
+                .finish_non_exhaustive(),
+            Self::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Self::Bytes(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::Bytes(bytes.into())
+    }
+}
+
+impl From<String> for Body {
+    fn from(string: String) -> Self {
+        Self::Bytes(string.into())
+    }
+}
+
+impl From<&'static [u8]> for Body {
+    fn from(bytes: &'static [u8]) -> Self {
+        Self::Bytes(Bytes::from_static(bytes))
+    }
+}
+
+impl<const N: usize> From<&'static [u8; N]> for Body {
+    fn from(bytes: &'static [u8; N]) -> Self {
+        Self::Bytes(Bytes::from_static(bytes))
+    }
+}
+
+impl Body {
+    /// Length of the body if known in advance, i.e. but of
+    /// [`Stream`](Self::Stream).
+    pub(crate) fn len(&self) -> Option<u64> {
+        match self {
+            Self::Empty => Some(0),
+            Self::Bytes(bytes) => Some(bytes.len() as u64),
+            Self::File { len, .. } => Some(*len),
+            Self::Stream(_) => None,
+        }
+    }
+
+    /// Write the body to `tcp_stream`, after the head written, throttled by
+    /// `throttle` if any, and chunked if `chunked`, of [`Stream`](Self::Stream)
+    /// only.
+    pub(super) async fn write_to(
+        self,
+        throttle: Option<ThrottleConfig>,
+        chunked: bool,
+        tcp_stream: &mut impl Stream,
+    ) -> io::Result<()> {
+        let throttle = transfer::Throttle::new(throttle.as_ref());
+
+        match self {
+            Self::Empty => Ok(()),
+            Self::Bytes(bytes) => transfer::write_buf(&bytes, throttle, tcp_stream).await,
+            Self::File { file, offset, len } => {
+                write_file(file, offset, len, throttle, tcp_stream).await
+            }
+            Self::Stream(stream) => write_stream(stream, chunked, throttle, tcp_stream).await,
+        }
+    }
+}
+
+/// Write `len` bytes of `file` from `offset`, by `io_uring` or `sendfile(2)`
+/// when enabled and available, falling back to [`transfer::copy_chunked`].
+/// Throttled responses, or those over TLS, never use `sendfile(2)`.
+async fn write_file(
+    mut file: File,
+    offset: u64,
+    len: u64,
+    throttle: Option<transfer::Throttle>,
+    tcp_stream: &mut impl Stream,
+) -> io::Result<()> {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if config::Config::current().transfer.io_uring && transfer::uring_available() {
+        return transfer::uring_copy(file, offset, len, throttle, tcp_stream).await;
+    }
+
+    #[cfg(target_os = "linux")]
+    if throttle.is_none() && config::Config::current().transfer.sendfile {
+        if let Some(plain) = tcp_stream.plain() {
+            return transfer::sendfile(&file, offset, len, plain).await;
+        }
+    }
+
+    if offset > 0 {
+        timing::timed(Phase::Open, file.seek(io::SeekFrom::Start(offset))).await?;
+    }
+
+    transfer::copy_chunked(&mut file, len, throttle, tcp_stream).await
+}
+
+/// Write the pieces of `stream` as they come, framed as chunks if `chunked`.
+async fn write_stream(
+    mut stream: BoxStream<'static, io::Result<Bytes>>,
+    chunked: bool,
+    mut throttle: Option<transfer::Throttle>,
+    tcp_stream: &mut impl Stream,
+) -> io::Result<()> {
+    while let Some(data) = stream.next().await {
+        let data = data?;
+
+        if data.is_empty() {
+            // Would end the chunked body
+            continue;
+        }
+
+        if let Some(throttle) = &mut throttle {
+            throttle.acquire(data.len()).await;
+        }
+
+        timing::timed(Phase::Transfer, async {
+            if chunked {
+                tcp_stream
+                    .write_all(format!("{:x}\r\n", data.len()).as_bytes())
+                    .await?;
+                tcp_stream.write_all(&data).await?;
+                tcp_stream.write_all(b"\r\n").await
+            } else {
+                tcp_stream.write_all(&data).await
+            }
+        })
+        .await?;
+
+        transfer::count(data.len() as u64);
+    }
+
+    if chunked {
+        timing::timed(Phase::Transfer, tcp_stream.write_all(b"0\r\n\r\n")).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! Bodies framed unambiguously only, and read by [`BodyReader`], chunked or
+//! of a length, those malformed or beyond the limits refused.
+
+use super::Request;
+#[cfg(any(feature = "admin", feature = "upstream"))]
+use super::{BodyReader, MAX_LINE_SIZE, MAX_TRAILER_SIZE};
+
+/// Parse the request of `head`, of the request line and the header lines
+/// given, followed by `rest`.
+async fn request(head: &str, rest: &str) -> anyhow::Result<Option<Request>> {
+    let message = format!("{}\r\n\r\n{rest}", head.replace('\n', "\r\n"));
+
+    Request::handle(&mut message.as_bytes()).await
+}
+
+#[cfg(any(feature = "admin", feature = "upstream"))]
+/// Read the whole body of `message`, chunked or of `length`.
+async fn read_body(message: &[u8], chunked: bool, length: Option<u64>) -> anyhow::Result<Vec<u8>> {
+    let mut reader = BodyReader::new(message, chunked, length);
+    let mut body = Vec::new();
+
+    while let Some(piece) = reader.next().await? {
+        body.extend_from_slice(piece);
+    }
+
+    Ok(body)
+}
+
+#[cfg(any(feature = "admin", feature = "upstream"))]
+#[tokio::test]
+/// Chunks joined, their extensions and the trailer fields skipped.
+async fn chunked() {
+    let body = read_body(
+        b"4;name=value\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\n",
+        true,
+        None,
+    )
+    .await
+    .expect("Body");
+
+    assert_eq!(body, b"Wikipedia");
+}
+
+#[cfg(any(feature = "admin", feature = "upstream"))]
+#[tokio::test]
+/// Bodies of a length read to it, or until the end of the stream without.
+async fn delimited() {
+    assert_eq!(
+        read_body(b"body and more", false, Some(4))
+            .await
+            .expect("Body"),
+        b"body"
+    );
+    assert_eq!(
+        read_body(b"body and more", false, None)
+            .await
+            .expect("Body"),
+        b"body and more"
+    );
+
+    read_body(b"bo", false, Some(4))
+        .await
+        .expect_err("Truncated body read");
+}
+
+#[cfg(any(feature = "admin", feature = "upstream"))]
+#[tokio::test]
+/// Chunks malformed, truncated, or of lines beyond the limits refused, not
+/// read on and on.
+async fn chunked_invalid() {
+    let long_size = format!("4;{}\r\nWiki\r\n0\r\n\r\n", "x".repeat(MAX_LINE_SIZE));
+    let no_line_break = "4".repeat(MAX_LINE_SIZE * 4);
+    let long_trailer = format!(
+        "4\r\nWiki\r\n0\r\n{}\r\n",
+        "Field: value\r\n".repeat(MAX_TRAILER_SIZE / 14 + 1)
+    );
+
+    for message in [
+        "4\r\nWikiX\r\n0\r\n\r\n",
+        "zz\r\nWiki\r\n0\r\n\r\n",
+        "4\r\nWiki\r\n",
+        "4\r\nWi",
+        &long_size,
+        &no_line_break,
+        &long_trailer,
+    ] {
+        assert!(
+            read_body(message.as_bytes(), true, None).await.is_err(),
+            "{:?} read",
+            &message[..message.len().min(32)]
+        );
+    }
+}
+
+#[tokio::test]
+/// Bodies of a length, or chunked as the final transfer coding, taken.
+async fn framing() {
+    for head in [
+        "PUT /upload/a HTTP/1.1\nContent-Length: 4",
+        "PUT /upload/a HTTP/1.1\nTransfer-Encoding: chunked",
+        "PUT /upload/a HTTP/1.1\nTransfer-Encoding: gzip, Chunked",
+        "PUT /upload/a HTTP/1.1\nTransfer-Encoding: gzip\nTransfer-Encoding: chunked",
+    ] {
+        request(head, "").await.expect("Parsed").expect("Request");
+    }
+}
+
+#[tokio::test]
+/// Bodies framed ambiguously refused, as they may be told apart from the
+/// next request otherwise by a proxy in between.
+async fn framing_ambiguous() {
+    for head in [
+        "PUT /upload/a HTTP/1.1\nContent-Length: 4\nContent-Length: 4",
+        "PUT /upload/a HTTP/1.1\nContent-Length: 4\nContent-Length: 5",
+        "PUT /upload/a HTTP/1.1\nContent-Length: 4\nTransfer-Encoding: chunked",
+        "PUT /upload/a HTTP/1.1\nContent-Length: -4",
+        "PUT /upload/a HTTP/1.1\nContent-Length: 4, 4",
+        "PUT /upload/a HTTP/1.1\nTransfer-Encoding: gzip",
+        "PUT /upload/a HTTP/1.1\nTransfer-Encoding: chunked, gzip",
+        "PUT /upload/a HTTP/1.1\nTransfer-Encoding: xchunked",
+        "PUT /upload/a HTTP/1.1\nTransfer-Encoding: chunked\nTransfer-Encoding: gzip",
+    ] {
+        assert!(request(head, "").await.is_err(), "{head:?} parsed");
+    }
+}
+
+#[tokio::test]
+/// Connections reused only if nothing is left past the head.
+async fn reusable() {
+    for (head, rest, reusable) in [
+        ("GET /healthz HTTP/1.1", "", true),
+        ("POST /healthz HTTP/1.1\nContent-Length: 0", "", true),
+        (
+            "GET /healthz HTTP/1.1",
+            "GET /healthz HTTP/1.1\r\n\r\n",
+            false,
+        ),
+        ("PUT /upload/a HTTP/1.1\nContent-Length: 4", "", false),
+        ("PUT /upload/a HTTP/1.1\nContent-Length: 4", "body", false),
+        (
+            "PUT /upload/a HTTP/1.1\nTransfer-Encoding: chunked",
+            "",
+            false,
+        ),
+    ] {
+        let request = request(head, rest).await.expect("Parsed").expect("Request");
+
+        assert_eq!(
+            request.is_reusable(),
+            reusable,
+            "{head:?} followed by {rest:?}"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! PROXY protocol, v1 and v2, so that the client address is known
+//! behind a TCP load balancer. See
+//! <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>.
+//!
+//! Expected of connections accepted by listeners designated, see
+//! [`Config::proxy_listen`](crate::config::Config::proxy_listen), and taken
+//! as the peer address in limits and logs.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::TcpStream,
+};
+
+use crate::listener;
+
+/// Signature of v2 headers.
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Max length of v1 headers, CRLF included.
+const V1_MAX_LENGTH: usize = 107;
+
+/// Time to read the header.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read the header of the connection from `peer_addr`, returning the client
+/// address told, or `peer_addr` if none, e.g. health checks of the load
+/// balancer.
+pub(crate) async fn read(tcp_stream: &mut TcpStream, peer_addr: SocketAddr) -> Result<SocketAddr> {
+    let client = tokio::time::timeout(TIMEOUT, read_header(tcp_stream))
+        .await
+        .context("Read PROXY protocol header timed out")??;
+
+    Ok(client.map_or(peer_addr, listener::canonical))
+}
+
+/// Read the header, of either version.
+async fn read_header(tcp_stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<SocketAddr>> {
+    // Either the v2 signature, or the v1 `PROXY ` and the protocol
+    let mut head = [0; 12];
+    tcp_stream.read_exact(&mut head).await?;
+
+    if &head == V2_SIGNATURE {
+        read_v2(tcp_stream).await
+    } else if head.starts_with(b"PROXY ") {
+        read_v1(tcp_stream, &head).await
+    } else {
+        bail!("Not a PROXY protocol header")
+    }
+}
+
+/// Read the rest of a v1 header, after `head`, like
+/// `PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n`.
+async fn read_v1(
+    tcp_stream: &mut (impl AsyncRead + Unpin),
+    head: &[u8],
+) -> Result<Option<SocketAddr>> {
+    let mut line = head.to_vec();
+
+    // Byte by byte, not to read past the header
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            bail!("PROXY protocol v1 header too long")
+        }
+
+        line.push(tcp_stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2]).context("Invalid v1 header")?;
+    let mut fields = line.split(' ').skip(1);
+
+    match fields.next() {
+        Some("TCP4" | "TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => bail!("Invalid v1 header protocol: {line:?}"),
+    }
+
+    let (Some(source), Some(_destination), Some(source_port)) =
+        (fields.next(), fields.next(), fields.next())
+    else {
+        bail!("Invalid v1 header: {line:?}")
+    };
+
+    let ip = source
+        .parse()
+        .with_context(|| format!("Invalid v1 header source address: {line:?}"))?;
+    let port = source_port
+        .parse()
+        .with_context(|| format!("Invalid v1 header source port: {line:?}"))?;
+
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+/// Read the rest of a v2 header, after the signature.
+async fn read_v2(tcp_stream: &mut (impl AsyncRead + Unpin)) -> Result<Option<SocketAddr>> {
+    let version_command = tcp_stream.read_u8().await?;
+    let family = tcp_stream.read_u8().await?;
+    let length = tcp_stream.read_u16().await?;
+
+    let mut addresses = vec![0; usize::from(length)];
+    tcp_stream.read_exact(&mut addresses).await?;
+
+    if version_command >> 4 != 2 {
+        bail!("Invalid v2 header version: {version_command:#x}")
+    }
+
+    // LOCAL, e.g. health checks of the load balancer
+    if version_command & 0x0f == 0 {
+        return Ok(None);
+    }
+
+    let client = match family {
+        // TCP over IPv4: source and destination addresses, then ports
+        0x11 if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[0..4].try_into()?;
+            let port = u16::from_be_bytes(addresses[8..10].try_into()?);
+
+            SocketAddr::from((Ipv4Addr::from(ip), port))
+        }
+        // TCP over IPv6
+        0x21 if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[0..16].try_into()?;
+            let port = u16::from_be_bytes(addresses[32..34].try_into()?);
+
+            SocketAddr::from((Ipv6Addr::from(ip), port))
+        }
+        // UNSPEC, UDP or UNIX, not of a client address
+        _ => return Ok(None),
+    };
+
+    Ok(Some(client))
+}
@@ -0,0 +1,130 @@
+//! Headers of both versions parsed into the client address, nothing past
+//! them read, and those malformed refused.
+
+use std::net::SocketAddr;
+
+use super::{V2_SIGNATURE, read_header};
+
+/// Read the header at the start of `stream`, returning the client address
+/// told and the bytes left unread.
+async fn read(stream: &[u8]) -> (anyhow::Result<Option<SocketAddr>>, &[u8]) {
+    let mut rest = stream;
+    let client = read_header(&mut rest).await;
+
+    (client, rest)
+}
+
+/// A v2 header of `version_command`, `family` and `addresses`.
+fn v2(version_command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    header.push(version_command);
+    header.push(family);
+    header.extend_from_slice(
+        &u16::try_from(addresses.len())
+            .expect("Length")
+            .to_be_bytes(),
+    );
+    header.extend_from_slice(addresses);
+
+    header
+}
+
+#[tokio::test]
+/// v1 of IPv4 and IPv6, and `UNKNOWN` of no address.
+async fn v1() {
+    let (client, rest) = read(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\nGET /").await;
+    assert_eq!(
+        client.expect("Parsed"),
+        Some("192.0.2.1:56324".parse().expect("Address"))
+    );
+    assert_eq!(rest, b"GET /");
+
+    let (client, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").await;
+    assert_eq!(
+        client.expect("Parsed"),
+        Some("[2001:db8::1]:56324".parse().expect("Address"))
+    );
+
+    let (client, rest) = read(b"PROXY UNKNOWN\r\nGET /").await;
+    assert_eq!(client.expect("Parsed"), None);
+    assert_eq!(rest, b"GET /");
+}
+
+#[tokio::test]
+/// v1 headers malformed, or too long, refused.
+async fn v1_invalid() {
+    for header in [
+        &b"PROXY UDP4 192.0.2.1 192.0.2.2 56324 443\r\n"[..],
+        b"PROXY TCP4 192.0.2.1 192.0.2.2\r\n",
+        b"PROXY TCP4 192.0.2.300 192.0.2.2 56324 443\r\n",
+        b"PROXY TCP4 192.0.2.1 192.0.2.2 65536 443\r\n",
+        b"GET / HTTP/1.1\r\n\r\n",
+    ] {
+        let (client, _) = read(header).await;
+        assert!(client.is_err(), "{:?}", String::from_utf8_lossy(header));
+    }
+
+    let mut long = b"PROXY TCP4 ".to_vec();
+    long.resize(200, b'1');
+    long.extend_from_slice(b"\r\n");
+
+    let (client, rest) = read(&long).await;
+    client.expect_err("Too long");
+    assert!(!rest.is_empty(), "Read past the limit");
+}
+
+#[tokio::test]
+/// v2 of IPv4 and IPv6, `LOCAL` and other families of no client address.
+async fn v2_parsed() {
+    let mut addresses = vec![192, 0, 2, 1, 192, 0, 2, 2];
+    addresses.extend_from_slice(&56324u16.to_be_bytes());
+    addresses.extend_from_slice(&443u16.to_be_bytes());
+
+    let mut stream = v2(0x21, 0x11, &addresses);
+    stream.extend_from_slice(b"GET /");
+
+    let (client, rest) = read(&stream).await;
+    assert_eq!(
+        client.expect("Parsed"),
+        Some("192.0.2.1:56324".parse().expect("Address"))
+    );
+    assert_eq!(rest, b"GET /");
+
+    let mut addresses = vec![0; 32];
+    addresses[0] = 0x20;
+    addresses[1] = 0x01;
+    addresses[15] = 1;
+    addresses.extend_from_slice(&56324u16.to_be_bytes());
+    addresses.extend_from_slice(&443u16.to_be_bytes());
+
+    let (client, _) = read(&v2(0x21, 0x21, &addresses)).await;
+    assert_eq!(
+        client.expect("Parsed"),
+        Some("[2001::1]:56324".parse().expect("Address"))
+    );
+
+    // LOCAL, its addresses skipped
+    let mut stream = v2(0x20, 0x11, &[0; 12]);
+    stream.extend_from_slice(b"GET /");
+
+    let (client, rest) = read(&stream).await;
+    assert_eq!(client.expect("Parsed"), None);
+    assert_eq!(rest, b"GET /");
+
+    // UNIX
+    let (client, _) = read(&v2(0x21, 0x31, &[0; 216])).await;
+    assert_eq!(client.expect("Parsed"), None);
+}
+
+#[tokio::test]
+/// v2 of another version, or cut short, refused.
+async fn v2_invalid() {
+    let (client, _) = read(&v2(0x11, 0x11, &[0; 12])).await;
+    client.expect_err("Version 1");
+
+    let mut stream = v2(0x21, 0x11, &[0; 12]);
+    stream.truncate(stream.len() - 1);
+
+    let (client, _) = read(&stream).await;
+    client.expect_err("Cut short");
+}
@@ -0,0 +1,163 @@
+//! Request rate limit per client address, see [`RateLimitConfig`].
+//!
+//! Each client has a token bucket of [`RateLimitConfig::burst`] tokens,
+//! refilled at [`RateLimitConfig::rate`]; a request takes a token when
+//! started, or is responded `429 Too Many Requests` with `Retry-After` if
+//! none left, see [`take`]. So are scraping and probing blunted, apart from
+//! bandwidth limits, see [`transfer`](crate::transfer).
+//!
+//! The client is the one told by a trusted proxy if any, see
+//! [`client`](crate::client).
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use http::{HeaderValue, StatusCode, header::RETRY_AFTER};
+
+use crate::{
+    client,
+    config::{Config, RateLimitConfig},
+    error::Error,
+    layer::{Layer, layer_fn},
+    proto,
+};
+
+/// How often buckets refilled full are removed.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Max seconds to retry after told.
+const MAX_RETRY_AFTER: f64 = 60.0 * 60.0;
+
+/// Buckets by client address.
+static BUCKETS: LazyLock<Mutex<Buckets>> = LazyLock::new(|| {
+    Mutex::new(Buckets {
+        buckets: HashMap::new(),
+        swept: Instant::now(),
+    })
+});
+
+#[derive(Debug)]
+/// Buckets by client address, see [`BUCKETS`].
+struct Buckets {
+    buckets: HashMap<IpAddr, Bucket>,
+
+    /// When swept last, see [`SWEEP_INTERVAL`]
+    swept: Instant,
+}
+
+impl Buckets {
+    /// Take a token of the client as of `now`, returning the seconds to retry
+    /// after if none left.
+    fn take(&mut self, config: &RateLimitConfig, client: IpAddr, now: Instant) -> Result<(), u64> {
+        if now.duration_since(self.swept) >= SWEEP_INTERVAL {
+            let burst = f64::from(config.burst);
+
+            self.buckets
+                .retain(|_, bucket| bucket.tokens(config, now) < burst);
+            self.swept = now;
+        }
+
+        let bucket = self.buckets.entry(client).or_insert(Bucket {
+            tokens: f64::from(config.burst),
+            updated: now,
+        });
+
+        bucket.tokens = bucket.tokens(config, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+
+            return Ok(());
+        }
+
+        // Until a token refilled, at least a second, and an hour at most if not
+        // refilled at all
+        let retry_after = ((1.0 - bucket.tokens) / config.rate)
+            .ceil()
+            .clamp(1.0, MAX_RETRY_AFTER);
+
+        Err(retry_after as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Token bucket of a client.
+struct Bucket {
+    /// Tokens left as of `updated`
+    tokens: f64,
+
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Tokens left by now, refilled since updated.
+    fn tokens(&self, config: &RateLimitConfig, now: Instant) -> f64 {
+        let refilled = now.duration_since(self.updated).as_secs_f64() * config.rate;
+
+        (self.tokens + refilled).min(f64::from(config.burst))
+    }
+}
+
+/// Layer answering requests of clients of no tokens left `429 Too Many
+/// Requests`, see [`take`].
+pub(crate) fn layer() -> impl Layer {
+    layer_fn(|request, params, stream, next| match take() {
+        Ok(()) => next.run(request, params, stream),
+        Err(retry_after) => Box::pin(reject(retry_after, stream)),
+    })
+}
+
+/// Take a token of the client of the request being handled if limited,
+/// returning the seconds to retry after if none left.
+pub(crate) fn take() -> Result<(), u64> {
+    let config = Config::current();
+
+    let (Some(rate_limit_config), Some(client)) = (&config.rate_limit, client::current()) else {
+        return Ok(());
+    };
+
+    if rate_limit_config
+        .unlimited_ips
+        .iter()
+        .any(|range| range.contains(client))
+    {
+        return Ok(());
+    }
+
+    BUCKETS.lock().unwrap_or_else(|e| e.into_inner()).take(
+        rate_limit_config,
+        client,
+        Instant::now(),
+    )
+}
+
+/// Respond `429 Too Many Requests`, to retry after `retry_after` seconds.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn reject(
+    retry_after: u64,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    tracing::debug!("Too many requests, retry after {retry_after}s");
+
+    let mut response = proto::Response::status(StatusCode::TOO_MANY_REQUESTS).with_body(b"");
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+
+    if let Err(e) = response.write_to_stream(tcp_stream).await {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
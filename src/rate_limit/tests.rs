@@ -0,0 +1,119 @@
+//! Tokens taken of buckets per client and refilled over time, buckets full
+//! swept.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use super::{Buckets, SWEEP_INTERVAL};
+use crate::config::RateLimitConfig;
+
+/// Parse `ip`.
+fn ip(ip: &str) -> IpAddr {
+    ip.parse().expect("IP address")
+}
+
+/// Config of `rate` requests per second, at most `burst` at once.
+fn config(rate: f64, burst: u32) -> RateLimitConfig {
+    RateLimitConfig {
+        rate,
+        burst,
+        unlimited_ips: Vec::new(),
+    }
+}
+
+/// Buckets none yet, swept at `now`.
+fn empty(now: Instant) -> Buckets {
+    Buckets {
+        buckets: HashMap::new(),
+        swept: now,
+    }
+}
+
+#[test]
+/// Burst served at once, then limited till refilled, per client.
+fn burst() {
+    let config = config(2.0, 3);
+    let now = Instant::now();
+    let mut buckets = empty(now);
+
+    for _ in 0..3 {
+        buckets
+            .take(&config, ip("192.0.2.1"), now)
+            .expect("Within burst");
+    }
+
+    assert_eq!(buckets.take(&config, ip("192.0.2.1"), now), Err(1));
+    buckets
+        .take(&config, ip("192.0.2.2"), now)
+        .expect("Client of its own bucket");
+
+    let later = now + Duration::from_millis(500);
+    buckets
+        .take(&config, ip("192.0.2.1"), later)
+        .expect("Refilled");
+    assert_eq!(buckets.take(&config, ip("192.0.2.1"), later), Err(1));
+}
+
+#[test]
+/// Refilled at most to burst.
+fn refill() {
+    let config = config(2.0, 3);
+    let now = Instant::now();
+    let mut buckets = empty(now);
+
+    buckets.take(&config, ip("192.0.2.1"), now).expect("Full");
+
+    let later = now + Duration::from_secs(30);
+    for _ in 0..3 {
+        buckets
+            .take(&config, ip("192.0.2.1"), later)
+            .expect("Refilled");
+    }
+
+    assert_eq!(buckets.take(&config, ip("192.0.2.1"), later), Err(1));
+}
+
+#[test]
+/// Retry after till a token refilled, at least a second, at most an hour.
+fn retry_after() {
+    let now = Instant::now();
+
+    for (rate, retry_after) in [(100.0, 1), (0.1, 10), (0.0, 3600)] {
+        let config = config(rate, 1);
+        let mut buckets = empty(now);
+
+        buckets.take(&config, ip("192.0.2.1"), now).expect("Full");
+        assert_eq!(
+            buckets.take(&config, ip("192.0.2.1"), now),
+            Err(retry_after),
+            "{rate}/s"
+        );
+    }
+}
+
+#[test]
+/// Buckets refilled full by then removed once swept, others kept.
+fn sweep() {
+    let config = config(2.0, 3);
+    let now = Instant::now();
+    let mut buckets = empty(now);
+
+    buckets.take(&config, ip("192.0.2.1"), now).expect("Full");
+
+    let later = now + SWEEP_INTERVAL - Duration::from_secs(1);
+    for _ in 0..3 {
+        buckets
+            .take(&config, ip("192.0.2.2"), later)
+            .expect("Within burst");
+    }
+    assert_eq!(buckets.buckets.len(), 2);
+
+    let swept = now + SWEEP_INTERVAL;
+    buckets.take(&config, ip("192.0.2.3"), swept).expect("Full");
+    assert_eq!(buckets.buckets.len(), 2);
+    assert!(!buckets.buckets.contains_key(&ip("192.0.2.1")));
+    assert_eq!(buckets.swept, swept);
+}
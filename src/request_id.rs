@@ -0,0 +1,112 @@
+//! Request IDs, i.e. `X-Mikufans-Request-ID`.
+//!
+//! Each request is given an ID, responded as the header and recorded by the
+//! span of the request, so that all logs of it are told apart, see
+//! [`scope`]. The ID told by a trusted proxy is taken instead, see
+//! [`Config::trusted_proxies`], so that a request is traced across them.
+//!
+//! IDs generated are like `{start}{pid}-{sequence}` in hex, unique across
+//! restarts and processes of a host.
+
+use std::{
+    cell::RefCell,
+    net::IpAddr,
+    sync::{
+        LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
+
+use http::{HeaderName, HeaderValue};
+use tracing::Instrument;
+
+use crate::{config::Config, proto};
+
+/// Header of request IDs.
+pub(crate) const HEADER: HeaderName = HeaderName::from_static("x-mikufans-request-id");
+
+/// Max length of IDs told by trusted proxies.
+const MAX_LENGTH: usize = 128;
+
+/// Prefix of IDs generated, of the process.
+static PREFIX: LazyLock<String> = LazyLock::new(|| {
+    let start = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis());
+
+    format!("{start:x}{:x}", std::process::id())
+});
+
+/// Sequence of IDs generated.
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    /// The request ID and client of the request being handled, see
+    /// [`scope`].
+    static CURRENT: RefCell<(HeaderValue, IpAddr)>;
+}
+
+/// Run the handler `future` of a request from `client`, within a span
+/// recording the ID of the request, and the phase timings of it, see
+/// [`timing`](crate::timing).
+pub(crate) async fn scope<F>(client: IpAddr, future: F) -> F::Output
+where
+    F: Future,
+{
+    let id = generate();
+
+    let span = tracing::info_span!(
+        "request",
+        id = id.to_str().unwrap_or_default(),
+        %client,
+        // See `timing`
+        parse = tracing::field::Empty,
+        open = tracing::field::Empty,
+        upstream = tracing::field::Empty,
+        transfer = tracing::field::Empty,
+    );
+
+    CURRENT
+        .scope(RefCell::new((id, client)), future.instrument(span))
+        .await
+}
+
+/// Take the ID told by `request` instead if from a trusted proxy, see
+/// [`Config::trusted_proxies`].
+pub(crate) fn accept(request: &proto::Request) {
+    let Some(told) = request.headers.get(HEADER).filter(|told| {
+        !told.is_empty()
+            && told.len() <= MAX_LENGTH
+            && told.as_bytes().iter().all(u8::is_ascii_graphic)
+    }) else {
+        return;
+    };
+
+    let _ = CURRENT.try_with(|current| {
+        let mut current = current.borrow_mut();
+
+        if Config::current()
+            .trusted_proxies
+            .iter()
+            .any(|range| range.contains(current.1))
+        {
+            current.0 = told.clone();
+
+            tracing::Span::current().record("id", told.to_str().unwrap_or_default());
+        }
+    });
+}
+
+/// ID of the request being handled, if any.
+pub(crate) fn current() -> Option<HeaderValue> {
+    CURRENT.try_with(|current| current.borrow().0.clone()).ok()
+}
+
+/// Generate an ID.
+fn generate() -> HeaderValue {
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+
+    HeaderValue::from_str(&format!("{}-{sequence:x}", *PREFIX))
+        .unwrap_or_else(|_| HeaderValue::from_static("-"))
+}
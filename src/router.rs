@@ -0,0 +1,283 @@
+//! Routing requests to their handlers by the request path, see [`Router`].
+//!
+//! Routes are matched in the order added, the first one matched handling the
+//! request, the fallback otherwise, see [`Router::fallback`]. A pattern is
+//! matched against the whole path, and is made of:
+//!
+//! - Literal text, matched as is, e.g. `/healthz`.
+//! - `{name}`, matching a non-empty segment, i.e. up to the next `/`.
+//! - `{*name}`, matching the rest of the path, possibly empty, e.g.
+//!   `/admin{*path}` matching `/admin/stats` of `path` `/stats`. Routes of it
+//!   not at the end are never matched.
+//!
+//! Those matched are given to the handler as [`Params`].
+
+use std::pin::Pin;
+
+use anyhow::Result;
+use http::StatusCode;
+
+use crate::{
+    error::Error,
+    layer::Layer,
+    proto::{AnyStream, Request},
+    service,
+};
+
+/// Future of [`Handler::call`], resolved to whether the connection can be
+/// kept alive.
+pub type HandlerFuture<'a> = Pin<Box<dyn Future<Output = Result<bool, Error>> + Send + 'a>>;
+
+/// Handler of the requests of a route, see [`Router::route`].
+///
+/// Responses are written to the stream as they are made, e.g. files sent by
+/// `sendfile(2)`, rather than returned.
+pub trait Handler: Send + Sync {
+    /// Handle `request` of the `params` matched, responding on `stream`.
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        params: &'a Params,
+        stream: &'a mut AnyStream,
+    ) -> HandlerFuture<'a>;
+
+    /// Wrap the handler by `layer`, see [`layer`](crate::layer).
+    fn layer(self, layer: impl Layer) -> Box<dyn Handler>
+    where
+        Self: Sized + 'static,
+    {
+        layer.layer(Box::new(self))
+    }
+}
+
+impl Handler for Box<dyn Handler> {
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        params: &'a Params,
+        stream: &'a mut AnyStream,
+    ) -> HandlerFuture<'a> {
+        (**self).call(request, params, stream)
+    }
+}
+
+impl<F> Handler for F
+where
+    F: for<'a> Fn(&'a Request, &'a Params, &'a mut AnyStream) -> HandlerFuture<'a> + Send + Sync,
+{
+    fn call<'a>(
+        &'a self,
+        request: &'a Request,
+        params: &'a Params,
+        stream: &'a mut AnyStream,
+    ) -> HandlerFuture<'a> {
+        self(request, params, stream)
+    }
+}
+
+/// A handler of the closure `f`, typed so that the lifetimes of its args are
+/// inferred, e.g. `from_fn(|request, params, stream| Box::pin(...))`.
+pub fn from_fn<F>(f: F) -> F
+where
+    F: for<'a> Fn(&'a Request, &'a Params, &'a mut AnyStream) -> HandlerFuture<'a> + Send + Sync,
+{
+    f
+}
+
+#[derive(Debug, Clone, Default)]
+/// Params of a route matched, by name.
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    /// The param of `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The params by name, in the order of the pattern.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A part of a pattern, see the [module docs](self).
+enum Part {
+    /// Matched as is
+    Literal(String),
+
+    /// `{name}`
+    Param(String),
+
+    /// `{*name}`
+    Rest(String),
+}
+
+/// A route added, see [`Router::route`].
+struct Route {
+    pattern: Vec<Part>,
+    handler: Box<dyn Handler>,
+}
+
+/// Routes of requests by the request path.
+pub struct Router {
+    routes: Vec<Route>,
+
+    /// Handling requests matching none, see [`Router::fallback`]
+    fallback: Box<dyn Handler>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            fallback: Box::new(from_fn(|_, _, stream| {
+                Box::pin(service::write_status(StatusCode::NOT_FOUND, stream))
+            })),
+        }
+    }
+}
+
+impl std::fmt::Debug for Router {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Router")
+            .field(
+                "routes",
+                &self
+                    .routes
+                    .iter()
+                    .map(|route| &route.pattern)
+                    .collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Router {
+    #[inline]
+    /// Create a new [`Router`] of no routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route requests of paths matching `pattern` to `handler`, after the
+    /// routes added before, see the [module docs](self) for the patterns.
+    pub fn route(mut self, pattern: &str, handler: impl Handler + 'static) -> Self {
+        self.routes.push(Route {
+            pattern: parse(pattern),
+            handler: Box::new(handler),
+        });
+
+        self
+    }
+
+    /// Handle requests matching none of the routes by `handler`, rather than
+    /// responding `404 Not Found`.
+    pub fn fallback(mut self, handler: impl Handler + 'static) -> Self {
+        self.fallback = Box::new(handler);
+
+        self
+    }
+
+    /// Wrap the handlers of the routes added before, and the fallback, by
+    /// `layer`, see [`layer`](crate::layer).
+    pub fn layer(self, layer: impl Layer) -> Self {
+        Self {
+            routes: self
+                .routes
+                .into_iter()
+                .map(|route| Route {
+                    pattern: route.pattern,
+                    handler: layer.layer(route.handler),
+                })
+                .collect(),
+            fallback: layer.layer(self.fallback),
+        }
+    }
+
+    /// Handle `request` by the handler of the first route matched, responding
+    /// on `stream`.
+    ///
+    /// Returns whether the connection can be kept alive.
+    pub async fn handle(&self, request: &Request, stream: &mut AnyStream) -> Result<bool, Error> {
+        let path = request.request_uri.path().as_str();
+
+        for route in &self.routes {
+            if let Some(params) = matches(&route.pattern, path) {
+                return route.handler.call(request, &params, stream).await;
+            }
+        }
+
+        self.fallback
+            .call(request, &Params::default(), stream)
+            .await
+    }
+}
+
+/// Parse `pattern` into its parts, a `{` not closed taken as is.
+fn parse(pattern: &str) -> Vec<Part> {
+    let mut parts = Vec::new();
+    let mut rest = pattern;
+
+    while !rest.is_empty() {
+        if let Some((name, after)) = rest
+            .strip_prefix('{')
+            .and_then(|param| param.split_once('}'))
+        {
+            parts.push(match name.strip_prefix('*') {
+                Some(name) => Part::Rest(name.to_owned()),
+                None => Part::Param(name.to_owned()),
+            });
+
+            rest = after;
+        } else {
+            // Up to the next `{`, or all of the rest if unclosed, no `}`
+            // following
+            let end = match rest.find('{') {
+                Some(end) if end > 0 => end,
+                _ => rest.len(),
+            };
+
+            parts.push(Part::Literal(rest[..end].to_owned()));
+
+            rest = &rest[end..];
+        }
+    }
+
+    parts
+}
+
+/// Params of `path` if matching `pattern`.
+fn matches(pattern: &[Part], path: &str) -> Option<Params> {
+    let mut params = Vec::new();
+    let mut rest = path;
+
+    for part in pattern {
+        match part {
+            Part::Literal(literal) => rest = rest.strip_prefix(literal.as_str())?,
+            Part::Param(name) => {
+                let end = rest.find('/').unwrap_or(rest.len());
+
+                if end == 0 {
+                    return None;
+                }
+
+                params.push((name.clone(), rest[..end].to_owned()));
+
+                rest = &rest[end..];
+            }
+            Part::Rest(name) => {
+                params.push((name.clone(), rest.to_owned()));
+
+                rest = "";
+            }
+        }
+    }
+
+    rest.is_empty().then_some(Params(params))
+}
@@ -0,0 +1,174 @@
+//! Routes of the server, see [`routes`].
+
+use anyhow::Result;
+use http::{
+    HeaderValue, Method,
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+};
+
+#[cfg(feature = "playurl")]
+use crate::extract::{Header, Host};
+use crate::{
+    error::Error,
+    extract::{Path, Query, extract},
+    metrics,
+    proto::{self, AnyStream},
+    router::{Handler, Router, from_fn},
+    service,
+};
+
+/// Methods of requests only read.
+const GET: &[Method] = &[Method::GET];
+
+/// Routes of the services of [`service`], in order, with the name of the
+/// route in metrics, see [`metrics::route`]. Those of the features not built
+/// are left out.
+///
+/// The static dirs configured are served before any route, see
+/// [`run`](crate::run).
+pub fn routes() -> Router {
+    #[cfg(feature = "admin")]
+    let router = admin().layer(service::admin::unless_apart()).route(
+        &format!("{}{{*key}}", service::upload::PREFIX),
+        extract(|request, Path(key): Path<String>, stream| {
+            Box::pin(async move { service::upload::handle(request, &key, stream).await })
+        })
+        .layer(metrics::route_layer("upload")),
+    );
+
+    #[cfg(not(feature = "admin"))]
+    let router = admin();
+
+    #[cfg(feature = "playurl")]
+    let router = router.route(
+        &format!("{}{{*path}}", service::danmaku::PREFIX),
+        from_fn(|request, _, stream| Box::pin(service::danmaku::handle(request, stream)))
+            .layer(service::methods(GET))
+            .layer(metrics::route_layer("danmaku")),
+    );
+
+    let router = router
+        .route(
+            service::mpd::PATH,
+            extract(|_, Query(query), stream| Box::pin(service::mpd::handle(query, stream)))
+                .layer(service::methods(GET))
+                .layer(metrics::route_layer("mpd")),
+        )
+        .route(
+            service::health::HEALTHZ_PATH,
+            from_fn(|_, _, stream| Box::pin(service::health::healthz(stream)))
+                .layer(service::methods(GET))
+                .layer(metrics::route_layer("health")),
+        )
+        .route(
+            service::health::READYZ_PATH,
+            from_fn(|_, _, stream| Box::pin(service::health::readyz(stream)))
+                .layer(service::methods(GET))
+                .layer(metrics::route_layer("health")),
+        );
+
+    #[cfg(feature = "playurl")]
+    let router = [service::playurl::PATH]
+        .into_iter()
+        .chain(service::playurl::API_PATHS)
+        .fold(router, |router, path| {
+            router.route(
+                path,
+                extract(
+                    |request, (query, host): (Option<Query<_>>, Option<Header<Host>>), stream| {
+                        Box::pin(async move {
+                            let host = host.map(|Header(Host(host))| host);
+
+                            service::playurl::handle(
+                                request,
+                                query.map(|Query(query)| query),
+                                host.as_deref(),
+                                stream,
+                            )
+                            .await
+                        })
+                    },
+                )
+                .layer(service::methods(GET))
+                .layer(metrics::route_layer("playurl")),
+            )
+        });
+
+    router
+        .route(
+            &format!("{}{{*key}}", service::resource::PREFIX),
+            extract(|request, Path(key): Path<String>, stream| {
+                Box::pin(async move { service::resource::handle(request, &key, stream).await })
+            })
+            .layer(metrics::route_layer("resource")),
+        )
+        .route(
+            "/favicon.ico",
+            from_fn(|_, _, stream| Box::pin(favicon(stream))),
+        )
+        .fallback(from_fn(|_, _, stream| Box::pin(index(stream))))
+}
+
+/// Routes of the admin API and metrics, also served apart if configured, see
+/// [`served_apart`](service::admin::served_apart), of the features built.
+pub(crate) fn admin() -> Router {
+    let router = Router::new();
+
+    #[cfg(feature = "admin")]
+    let router = router.route(
+        &format!("{}{{*path}}", service::admin::PREFIX),
+        extract(|request, Path(path): Path<String>, stream| {
+            Box::pin(async move { service::admin::handle(request, &path, stream).await })
+        })
+        .layer(metrics::route_layer("admin")),
+    );
+
+    #[cfg(feature = "metrics")]
+    let router = router.route(
+        service::metrics::PATH,
+        from_fn(|request, _, stream| Box::pin(service::metrics::handle(request, stream)))
+            .layer(service::methods(GET))
+            .layer(metrics::route_layer("metrics")),
+    );
+
+    router
+}
+
+/// `GET /favicon.ico`, of none.
+async fn favicon(stream: &mut AnyStream) -> Result<bool, Error> {
+    let mut response = proto::Response::default();
+
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("image/icon"));
+    response
+        .headers_mut()
+        .insert(CONTENT_LENGTH, HeaderValue::from_static("0"));
+
+    if let Err(e) = response.write_to_stream(stream).await {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Any other path, told the name of the server.
+async fn index(stream: &mut AnyStream) -> Result<bool, Error> {
+    let mut response = proto::Response::default();
+
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+
+    if let Err(e) = response
+        .with_body(env!("CARGO_PKG_NAME").as_bytes())
+        .write_to_stream(stream)
+        .await
+    {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,88 @@
+//! Security headers of responses, see [`SecurityHeadersConfig`].
+//!
+//! Added once a response is written, see
+//! [`Response::write_to_stream`](crate::proto::Response::write_to_stream), so
+//! that all the routes are covered alike, without any set by the handler
+//! overridden:
+//!
+//! - `Strict-Transport-Security`, over TLS only as browsers ignore it
+//!   otherwise, see [`tls`](crate::tls). Proxies terminating TLS in front are
+//!   to add it themselves.
+//! - `X-Content-Type-Options: nosniff`.
+//! - `Content-Security-Policy`, of responses other than media, i.e. not
+//!   `video/*`, `audio/*` or `image/*`, being played by pages of other origins.
+
+use http::{
+    HeaderMap, HeaderValue,
+    header::{
+        CONTENT_SECURITY_POLICY, CONTENT_TYPE, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS,
+    },
+};
+
+use crate::config::{Config, SecurityHeadersConfig};
+
+/// Types of media, not given a `Content-Security-Policy`.
+const MEDIA_TYPES: [&str; 3] = ["video/", "audio/", "image/"];
+
+/// Add the security headers configured to `headers` of a response, sent over
+/// TLS if `tls`.
+pub(crate) fn apply(headers: &mut HeaderMap, tls: bool) {
+    let config = Config::current();
+
+    let Some(security_headers) = &config.security_headers else {
+        return;
+    };
+
+    if tls && security_headers.hsts_max_age > 0 && !headers.contains_key(STRICT_TRANSPORT_SECURITY)
+    {
+        if let Some(value) = hsts(security_headers) {
+            headers.insert(STRICT_TRANSPORT_SECURITY, value);
+        }
+    }
+
+    if security_headers.nosniff {
+        headers
+            .entry(X_CONTENT_TYPE_OPTIONS)
+            .or_insert(HeaderValue::from_static("nosniff"));
+    }
+
+    if !security_headers.content_security_policy.is_empty()
+        && !headers.contains_key(CONTENT_SECURITY_POLICY)
+        && !is_media(headers)
+    {
+        match HeaderValue::from_str(&security_headers.content_security_policy) {
+            Ok(value) => {
+                headers.insert(CONTENT_SECURITY_POLICY, value);
+            }
+            Err(_) => tracing::warn!(
+                "Invalid Content-Security-Policy: {}",
+                security_headers.content_security_policy
+            ),
+        }
+    }
+}
+
+/// `Strict-Transport-Security` of `config`.
+fn hsts(config: &SecurityHeadersConfig) -> Option<HeaderValue> {
+    let value = if config.hsts_include_subdomains {
+        format!("max-age={}; includeSubDomains", config.hsts_max_age)
+    } else {
+        format!("max-age={}", config.hsts_max_age)
+    };
+
+    HeaderValue::try_from(value).ok()
+}
+
+/// Whether the response of `headers` is of media, by `Content-Type`.
+fn is_media(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| {
+            MEDIA_TYPES.iter().any(|media_type| {
+                content_type
+                    .get(..media_type.len())
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(media_type))
+            })
+        })
+}
@@ -0,0 +1,354 @@
+//! Request handlers.
+
+#[cfg(feature = "admin")]
+pub(crate) mod admin;
+#[cfg(feature = "playurl")]
+pub(crate) mod danmaku;
+pub(crate) mod health;
+#[cfg(feature = "metrics")]
+pub(crate) mod metrics;
+pub(crate) mod mpd;
+#[cfg(feature = "playurl")]
+pub(crate) mod playurl;
+pub(crate) mod resource;
+pub(crate) mod static_files;
+#[cfg(feature = "admin")]
+pub(crate) mod upload;
+
+use std::io;
+
+use anyhow::Result;
+use bytes::Bytes;
+use http::{
+    HeaderValue, Method, StatusCode,
+    header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE},
+};
+use http_range_header::{EndPosition, ParsedRanges, StartPosition, SyntacticallyCorrectRange};
+use macro_toolset::{str_concat_v2, string_v2::StringExtT};
+use memmap2::Mmap;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+use crate::{
+    config::ThrottleConfig,
+    error::{Error, IntoResponse},
+    layer::{Layer, layer_fn},
+    proto::{self, Body},
+    timing::{self, Phase},
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Per-route options of [`serve_file`].
+pub(crate) struct ServeOptions {
+    /// Files not larger than this are served from memory, mapped if
+    /// [`Self::stored`].
+    pub mmap_threshold: Option<u64>,
+
+    /// Whether the file is a stored object of the cache, which is never
+    /// truncated and so may be mapped, see [`serve_file`].
+    pub stored: bool,
+
+    /// Per-response bandwidth limit.
+    pub throttle: Option<ThrottleConfig>,
+}
+
+/// Write the given file as response body, honoring the `Range` request
+/// header.
+///
+/// Files not larger than [`ServeOptions::mmap_threshold`] are written from
+/// memory, saving the read syscalls. This suits hot, small objects like init
+/// segments. Stored objects of the cache are mapped, other files, which may
+/// be truncated meanwhile, read. Otherwise sent as [`Body::File`].
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn serve_file(
+    request: &proto::Request,
+    mut response: proto::Response,
+    mut file: File,
+    options: ServeOptions,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let file_length = timing::timed(Phase::Open, file.metadata()).await?.len();
+
+    let range = requested_range(request, file_length)?;
+    let body_length = set_content_headers(&mut response, range, file_length)?;
+    let start = range.map_or(0, |(start, _)| start);
+
+    let body = if request.method != Method::GET {
+        Body::Empty
+    } else if body_length > 0
+        && options
+            .mmap_threshold
+            .is_some_and(|threshold| file_length <= threshold)
+    {
+        let window = start as usize..(start + body_length) as usize;
+
+        if options.stored {
+            #[allow(unsafe_code, reason = "Mmap")]
+            // SAFETY: touching a page of the mapping past the end of the file
+            // raises `SIGBUS`, so the file must never be truncated while
+            // mapped. Stored objects of the cache never are: written under a
+            // temporary name, they are renamed into place once complete and
+            // then only renamed away or unlinked, leaving the mapping intact.
+            let mmap = unsafe { Mmap::map(&file)? };
+
+            if mmap.get(window.clone()).is_none() {
+                tracing::error!("File truncated while serving");
+                return Ok(false);
+            }
+
+            Body::Bytes(Bytes::from_owner(mmap).slice(window))
+        } else {
+            let mut buf = vec![0; window.len()];
+
+            if start > 0 {
+                file.seek(io::SeekFrom::Start(start)).await?;
+            }
+
+            match timing::timed(Phase::Open, file.read_exact(&mut buf)).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    tracing::error!("File truncated while serving");
+                    return Ok(false);
+                }
+                Err(e) => return Err(e.into()),
+            }
+
+            Body::Bytes(Bytes::from(buf))
+        }
+    } else {
+        Body::File {
+            file,
+            offset: start,
+            len: body_length,
+        }
+    };
+
+    if let Err(e) = response
+        .with_body(body)
+        .with_throttle(options.throttle)
+        .write_to_stream(tcp_stream)
+        .await
+    {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Write the response from memory, `head` holding the first bytes of content
+/// of `length` bytes, honoring the `Range` request header.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn serve_memory(
+    request: &proto::Request,
+    mut response: proto::Response,
+    head: Bytes,
+    length: u64,
+    throttle: Option<ThrottleConfig>,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let range = requested_range(request, length)?;
+    let body_length = set_content_headers(&mut response, range, length)?;
+    let start = range.map_or(0, |(start, _)| start);
+
+    let body = if request.method != Method::GET {
+        Body::Empty
+    } else {
+        let window = start as usize..(start + body_length) as usize;
+
+        if head.get(window.clone()).is_none() {
+            tracing::error!("Requested range not in memory");
+            return Ok(false);
+        }
+
+        Body::Bytes(head.slice(window))
+    };
+
+    if let Err(e) = response
+        .with_body(body)
+        .with_throttle(throttle)
+        .write_to_stream(tcp_stream)
+        .await
+    {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Set status and content headers for the resolved range of content of
+/// `length` bytes, returning the body length.
+fn set_content_headers(
+    response: &mut proto::Response,
+    range: Option<(u64, u64)>,
+    length: u64,
+) -> Result<u64> {
+    match range {
+        Some((start, end)) => {
+            // RANGE response
+            response.set_status(StatusCode::PARTIAL_CONTENT);
+            let headers = response.headers_mut();
+
+            headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+            headers.insert(CONTENT_LENGTH, (end - start + 1).to_http_header_value()?);
+            headers.insert(
+                CONTENT_RANGE,
+                str_concat_v2!("bytes ", start, "-", end, "/", length).to_http_header_value()?,
+            );
+
+            Ok(end - start + 1)
+        }
+        None => {
+            // No or invalid Range request, return all
+            response
+                .headers_mut()
+                .insert(CONTENT_LENGTH, length.to_http_header_value()?);
+
+            Ok(length)
+        }
+    }
+}
+
+#[cfg(feature = "admin")]
+/// Whether the body of `request` is declared longer than `max_body_size` if
+/// limited, see [`AdminConfig::max_body_size`].
+///
+/// [`AdminConfig::max_body_size`]: crate::config::AdminConfig::max_body_size
+pub(crate) fn body_too_large(request: &proto::Request, max_body_size: Option<u64>) -> bool {
+    max_body_size.is_some_and(|max_body_size| {
+        request
+            .content_length()
+            .is_some_and(|length| length > max_body_size)
+    })
+}
+
+/// Write a response of the given status with an empty body.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn write_status(
+    status: StatusCode,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    if let Err(e) = status.into_response().write_to_stream(tcp_stream).await {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Layer answering requests of methods other than `methods` `405 Method Not
+/// Allowed`.
+pub(crate) fn methods(methods: &'static [Method]) -> impl Layer {
+    layer_fn(move |request, params, stream, next| {
+        if !methods.contains(&request.method) {
+            return Box::pin(write_status(StatusCode::METHOD_NOT_ALLOWED, stream));
+        }
+
+        next.run(request, params, stream)
+    })
+}
+
+/// Write a response of the given status with a JSON body.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn write_json<T>(
+    status: StatusCode,
+    body: &T,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error>
+where
+    T: serde::Serialize,
+{
+    let mut response = proto::Response::status(status);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    if let Err(e) = response
+        .with_body(serde_json::to_vec(body)?)
+        .write_to_stream(tcp_stream)
+        .await
+    {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Parse the `Range` request header against the content length, returning
+/// the inclusive `(start, end)` byte positions if any.
+///
+/// Multiple ranges are not supported, nor are malformed ones, `None` is
+/// returned so that all is served instead.
+///
+/// # Errors
+///
+/// [`Error::RangeNotSatisfiable`] if the range is not within the content,
+/// answered `416 Range Not Satisfiable`.
+pub(crate) fn requested_range(
+    request: &proto::Request,
+    length: u64,
+) -> Result<Option<(u64, u64)>, Error> {
+    match request.headers.get(RANGE).map(HeaderValue::to_str) {
+        Some(Ok(range)) => parse_range(range, length),
+        _ => Ok(None),
+    }
+}
+
+/// Parse a `Range` header value against the content length, see
+/// [`requested_range`].
+pub(crate) fn parse_range(range: &str, length: u64) -> Result<Option<(u64, u64)>, Error> {
+    match http_range_header::parse_range_header(range) {
+        Ok(ParsedRanges { ranges }) => match ranges[..] {
+            [range] => resolve_range(range, length),
+            _ => Ok(None),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+/// Resolve a [`SyntacticallyCorrectRange`] against the file length, returning
+/// the inclusive `(start, end)` byte positions when satisfiable.
+fn resolve_range(
+    SyntacticallyCorrectRange { start, end }: SyntacticallyCorrectRange,
+    file_length: u64,
+) -> Result<Option<(u64, u64)>, Error> {
+    // Invalid rather than unsatisfiable, as of RFC 9110, so ignored
+    if matches!(
+        (start, end),
+        (StartPosition::Index(start), EndPosition::Index(end)) if end < start
+    ) {
+        return Ok(None);
+    }
+
+    let Some(last_byte) = file_length.checked_sub(1) else {
+        // Of no content, only suffix ranges satisfiable, i.e. all of nothing
+        return match start {
+            StartPosition::Index(_) => Err(Error::RangeNotSatisfiable(file_length)),
+            StartPosition::FromLast(_) => Ok(None),
+        };
+    };
+
+    let start = match start {
+        StartPosition::Index(idx) => idx,
+        StartPosition::FromLast(idx) => file_length.saturating_sub(idx),
+    };
+    let end = match end {
+        EndPosition::Index(idx) => idx.min(last_byte),
+        EndPosition::LastByte => last_byte,
+    };
+
+    // Starting past the end
+    if start > end {
+        return Err(Error::RangeNotSatisfiable(file_length));
+    }
+
+    Ok(Some((start, end)))
+}
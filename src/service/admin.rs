@@ -0,0 +1,460 @@
+//! Admin API, i.e. `/admin/*`.
+//!
+//! Disabled unless [`AdminConfig`](crate::config::AdminConfig) is set.
+//! Requests must carry an API key configured, see [`api_key`], and actions,
+//! i.e. requests other than `GET`, are audit logged.
+//!
+//! Those changing state of pages of other sites, e.g. purging the cache or
+//! closing connections by a form posted by a browser on the LAN, are refused
+//! against CSRF, see [`cross_site_origin`].
+//!
+//! Served apart along with metrics if configured, see [`listener`], and
+//! operated from a browser by the [`dashboard`] if enabled.
+
+mod dashboard;
+mod listener;
+#[cfg(feature = "playurl")]
+mod warmup;
+
+use std::time::Duration;
+
+use anyhow::Result;
+use http::{
+    Method, StatusCode,
+    header::{HOST, ORIGIN},
+};
+use serde::Serialize;
+
+#[cfg(feature = "sessions")]
+use crate::session;
+#[cfg(feature = "upstream")]
+use crate::upstream;
+use crate::{
+    api_key,
+    cache::{Cache, CacheUsage},
+    config::{AdminConfig, Config},
+    connection,
+    error::Error,
+    error_log, hotlink,
+    layer::{Layer, layer_fn},
+    metrics, proto, transfer,
+    usage::{self, RankBy},
+};
+
+/// Default number of clients listed by [`client_usage`].
+const DEFAULT_USAGE_LIMIT: usize = 20;
+
+/// Path prefix of the route
+pub(crate) const PREFIX: &str = "/admin";
+
+#[cfg(feature = "tls")]
+pub(crate) use self::listener::reload;
+pub(crate) use self::listener::spawn;
+
+/// Whether served apart, see [`listener`], not along with the others then.
+pub(crate) fn served_apart(config: &Config) -> bool {
+    config
+        .admin
+        .as_ref()
+        .is_some_and(|admin_config| admin_config.listen.is_some())
+}
+
+/// Layer of the routes served apart if configured, see [`served_apart`],
+/// answered `404 Not Found` along with the others then.
+pub(crate) fn unless_apart() -> impl Layer {
+    layer_fn(|request, params, stream, next| {
+        if served_apart(&Config::current()) {
+            return Box::pin(super::write_status(StatusCode::NOT_FOUND, stream));
+        }
+
+        next.run(request, params, stream)
+    })
+}
+
+/// Handle an admin request, `path` is the request path with [`PREFIX`]
+/// stripped.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn handle(
+    request: &proto::Request,
+    path: &str,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let config = Config::current();
+
+    let Some(admin_config) = &config.admin else {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    // Holding no data, the API key asked for by the page
+    if admin_config.dashboard && matches!(request.method, Method::GET | Method::HEAD) {
+        if let Some(asset) = dashboard::asset(path) {
+            return dashboard::serve(request, asset, tcp_stream).await;
+        }
+    }
+
+    // Closed, the body not read
+    if super::body_too_large(request, admin_config.max_body_size) {
+        tracing::warn!("Admin request body too large: {} {path}", request.method);
+
+        super::write_status(StatusCode::PAYLOAD_TOO_LARGE, tcp_stream).await?;
+
+        return Ok(false);
+    }
+
+    if let Some(origin) = cross_site_origin(request, admin_config) {
+        tracing::warn!(
+            "Refuse admin request of origin {origin:?}: {} {path}",
+            request.method
+        );
+
+        return super::write_status(StatusCode::FORBIDDEN, tcp_stream).await;
+    }
+
+    let Some(key_name) = api_key::authenticate(request, admin_config) else {
+        tracing::warn!("Unauthorized admin request: {} {path}", request.method);
+
+        return super::write_status(StatusCode::UNAUTHORIZED, tcp_stream).await;
+    };
+
+    if request.method != Method::GET && request.method != Method::HEAD {
+        api_key::audit(&key_name, request, &format!("{PREFIX}{path}"));
+    }
+
+    route(request, path, tcp_stream).await
+}
+
+/// The origin of the page `request` changing state is of, if of another
+/// site, i.e. neither of the host requested nor allowed, see
+/// [`AdminConfig::allowed_origins`].
+///
+/// Requests telling no origin, e.g. of tools rather than browsers, or
+/// carrying [`AdminConfig::csrf_header`], are taken as of no site, but not
+/// those of `Origin: null`.
+fn cross_site_origin(request: &proto::Request, config: &AdminConfig) -> Option<String> {
+    if matches!(request.method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return None;
+    }
+
+    if config
+        .csrf_header
+        .as_ref()
+        .is_some_and(|name| request.headers.contains_key(name.as_str()))
+    {
+        return None;
+    }
+
+    // Sent by sandboxed pages, of any site
+    if request
+        .headers
+        .get(ORIGIN)
+        .is_some_and(|origin| origin.as_bytes().trim_ascii() == b"null")
+    {
+        return Some("null".to_owned());
+    }
+
+    let origin = hotlink::origin(request)?;
+
+    let same_origin = request
+        .headers
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .zip(origin.split_once("://"))
+        .is_some_and(|(host, (_, authority))| authority.eq_ignore_ascii_case(host));
+
+    if same_origin
+        || config
+            .allowed_origins
+            .iter()
+            .any(|allowed| hotlink::matches(allowed, &origin))
+    {
+        None
+    } else {
+        Some(origin)
+    }
+}
+
+/// Route an authorized admin request by `path`.
+async fn route(
+    request: &proto::Request,
+    path: &str,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    match path {
+        "/cache" if request.method == Method::GET => cache_usage(tcp_stream).await,
+        "/cache" if request.method == Method::DELETE => purge_cache(request, tcp_stream).await,
+        "/cache" => super::write_status(StatusCode::METHOD_NOT_ALLOWED, tcp_stream).await,
+        "/stats" if request.method == Method::GET => stats(tcp_stream).await,
+        "/stats" => super::write_status(StatusCode::METHOD_NOT_ALLOWED, tcp_stream).await,
+        "/errors" if request.method == Method::GET => {
+            super::write_json(StatusCode::OK, &error_log::list(), tcp_stream).await
+        }
+        "/errors" => super::write_status(StatusCode::METHOD_NOT_ALLOWED, tcp_stream).await,
+        #[cfg(feature = "upstream")]
+        "/upstream" if request.method == Method::GET => upstream_health(tcp_stream).await,
+        #[cfg(feature = "upstream")]
+        "/upstream" => super::write_status(StatusCode::METHOD_NOT_ALLOWED, tcp_stream).await,
+        #[cfg(feature = "playurl")]
+        "/warmup" if request.method == Method::POST => warmup::start(request, tcp_stream).await,
+        #[cfg(feature = "playurl")]
+        "/warmup" if request.method == Method::GET => warmup::list(tcp_stream).await,
+        #[cfg(feature = "playurl")]
+        "/warmup" => super::write_status(StatusCode::METHOD_NOT_ALLOWED, tcp_stream).await,
+        _ => route_clients(request, path, tcp_stream).await,
+    }
+}
+
+/// Route an authorized admin request of clients, i.e. sessions, usage and
+/// connections, by `path`.
+async fn route_clients(
+    request: &proto::Request,
+    path: &str,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    match path {
+        #[cfg(feature = "sessions")]
+        "/sessions" if request.method == Method::GET => {
+            super::write_json(StatusCode::OK, &session::list(), tcp_stream).await
+        }
+        #[cfg(feature = "sessions")]
+        "/sessions" if request.method == Method::DELETE => {
+            revoke_session(request, tcp_stream).await
+        }
+        #[cfg(feature = "sessions")]
+        "/sessions" => super::write_status(StatusCode::METHOD_NOT_ALLOWED, tcp_stream).await,
+        "/usage" if request.method == Method::GET => client_usage(request, tcp_stream).await,
+        "/usage" if request.method == Method::DELETE => reset_usage(tcp_stream).await,
+        "/usage" => super::write_status(StatusCode::METHOD_NOT_ALLOWED, tcp_stream).await,
+        "/connections" if request.method == Method::GET => {
+            super::write_json(StatusCode::OK, &connection::list(), tcp_stream).await
+        }
+        "/connections" if request.method == Method::DELETE => {
+            close_connection(request, tcp_stream).await
+        }
+        "/connections" => super::write_status(StatusCode::METHOD_NOT_ALLOWED, tcp_stream).await,
+        _ => super::write_status(StatusCode::NOT_FOUND, tcp_stream).await,
+    }
+}
+
+/// `GET /admin/cache`
+///
+/// Respond with the cache usage, see [`CacheUsage`](crate::cache::CacheUsage).
+async fn cache_usage(tcp_stream: &mut impl proto::Stream) -> Result<bool, Error> {
+    let Some(cache) = Cache::global() else {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    super::write_json(StatusCode::OK, &cache.usage().await, tcp_stream).await
+}
+
+#[derive(Debug, Serialize)]
+/// Response of [`stats`].
+struct Stats {
+    /// Seconds since started
+    uptime: u64,
+
+    /// Number of connections open
+    connections: u64,
+
+    /// Number of file descriptors open, `None` when unknown
+    open_files: Option<usize>,
+
+    /// Body bytes sent in total
+    bytes_sent: u64,
+
+    /// Body bytes sent per second lately, see
+    /// [`metrics::throughput`](crate::metrics::throughput)
+    throughput: u64,
+
+    /// `None` when the cache is disabled
+    cache: Option<CacheUsage>,
+}
+
+/// `GET /admin/stats`
+///
+/// Respond with the runtime stats, see [`Stats`].
+async fn stats(tcp_stream: &mut impl proto::Stream) -> Result<bool, Error> {
+    let cache = match Cache::global() {
+        Some(cache) => Some(cache.usage().await),
+        None => None,
+    };
+
+    let stats = Stats {
+        uptime: metrics::uptime().as_secs(),
+        connections: metrics::connections_active(),
+        open_files: open_files().await,
+        bytes_sent: transfer::total_sent(),
+        throughput: metrics::throughput(),
+        cache,
+    };
+
+    super::write_json(StatusCode::OK, &stats, tcp_stream).await
+}
+
+/// Number of file descriptors open by the process.
+async fn open_files() -> Option<usize> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+
+    let mut entries = tokio::fs::read_dir("/proc/self/fd").await.ok()?;
+    let mut count: usize = 0;
+
+    while entries.next_entry().await.ok()?.is_some() {
+        count += 1;
+    }
+
+    // Of `read_dir` itself
+    Some(count.saturating_sub(1))
+}
+
+#[cfg(feature = "upstream")]
+/// `GET /admin/upstream`
+///
+/// Respond with the health of upstream hosts, see
+/// [`HostHealth`](crate::upstream::HostHealth).
+async fn upstream_health(tcp_stream: &mut impl proto::Stream) -> Result<bool, Error> {
+    let Some(upstream_config) = &Config::current().upstream else {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    super::write_json(
+        StatusCode::OK,
+        &upstream::health(upstream_config),
+        tcp_stream,
+    )
+    .await
+}
+
+#[derive(Debug, Serialize)]
+/// Response of [`purge_cache`].
+struct PurgeResponse {
+    /// Number of keys purged
+    purged: usize,
+}
+
+/// `DELETE /admin/cache?prefix={prefix}&older_than={seconds}` or
+/// `DELETE /admin/cache?all=true`
+///
+/// Evict cached objects whose key starts with `prefix` and / or which were
+/// stored more than `older_than` seconds ago, or all of them.
+async fn purge_cache(
+    request: &proto::Request,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let Some(cache) = Cache::global() else {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    let prefix = request.query_param("prefix");
+    let older_than = match request.query_param("older_than").map(|value| value.parse()) {
+        Some(Ok(secs)) => Some(Duration::from_secs(secs)),
+        Some(Err(_)) => return super::write_status(StatusCode::BAD_REQUEST, tcp_stream).await,
+        None => None,
+    };
+    let all = request.query_param("all").is_some_and(|all| all == "true");
+
+    if prefix.is_none() && older_than.is_none() && !all {
+        // Avoid purging everything by accident.
+        return super::write_status(StatusCode::BAD_REQUEST, tcp_stream).await;
+    }
+
+    let purged = cache.purge(|key, age| {
+        prefix
+            .as_deref()
+            .is_none_or(|prefix| key.starts_with(prefix))
+            && older_than.is_none_or(|older_than| age > older_than)
+    });
+
+    tracing::info!(
+        "Purged {purged} cached objects, prefix: {prefix:?}, older than: {older_than:?}, all: \
+         {all}"
+    );
+
+    super::write_json(StatusCode::OK, &PurgeResponse { purged }, tcp_stream).await
+}
+
+/// `GET /admin/usage?by={bytes|requests}&limit={limit}`
+///
+/// Respond with the usage of clients, ranked by bytes sent by default, the
+/// top [`DEFAULT_USAGE_LIMIT`] by default. See
+/// [`ClientUsage`](crate::usage::ClientUsage).
+async fn client_usage(
+    request: &proto::Request,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    if Config::current().usage.is_none() {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    }
+
+    let by = match request.query_param("by").as_deref() {
+        None | Some("bytes") => RankBy::Bytes,
+        Some("requests") => RankBy::Requests,
+        Some(_) => return super::write_status(StatusCode::BAD_REQUEST, tcp_stream).await,
+    };
+    let limit = match request.query_param("limit").map(|value| value.parse()) {
+        Some(Ok(limit)) => limit,
+        Some(Err(_)) => return super::write_status(StatusCode::BAD_REQUEST, tcp_stream).await,
+        None => DEFAULT_USAGE_LIMIT,
+    };
+
+    super::write_json(StatusCode::OK, &usage::ranked(by, limit), tcp_stream).await
+}
+
+/// `DELETE /admin/usage`
+///
+/// Reset the usage of all clients, e.g. monthly.
+async fn reset_usage(tcp_stream: &mut impl proto::Stream) -> Result<bool, Error> {
+    if Config::current().usage.is_none() {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    }
+
+    usage::reset();
+
+    tracing::info!("Reset usage of clients");
+
+    super::write_status(StatusCode::NO_CONTENT, tcp_stream).await
+}
+
+/// `DELETE /admin/connections?id={id}`
+///
+/// Close the connection of `id`, see [`connection::list`]. Responds
+/// `404 Not Found` if no such connection.
+async fn close_connection(
+    request: &proto::Request,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let Some(Ok(id)) = request.query_param("id").map(|id| id.parse()) else {
+        return super::write_status(StatusCode::BAD_REQUEST, tcp_stream).await;
+    };
+
+    if !connection::close(id) {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    }
+
+    tracing::info!("Closing connection {id}");
+
+    super::write_status(StatusCode::NO_CONTENT, tcp_stream).await
+}
+
+#[cfg(feature = "sessions")]
+/// `DELETE /admin/sessions?token={token}`
+///
+/// Revoke the playback session of `token`, see [`session::revoke`].
+/// Responds `404 Not Found` if no such session.
+async fn revoke_session(
+    request: &proto::Request,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let Some(token) = request.query_param("token") else {
+        return super::write_status(StatusCode::BAD_REQUEST, tcp_stream).await;
+    };
+
+    if !session::revoke(&token) {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    }
+
+    tracing::info!("Revoked session {token}");
+
+    super::write_status(StatusCode::NO_CONTENT, tcp_stream).await
+}
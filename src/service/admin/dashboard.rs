@@ -0,0 +1,91 @@
+//! Dashboard of the admin API, i.e. `GET /admin/`, a web UI so that the
+//! server is operated from a browser: connections open, throughput, cache
+//! occupancy and errors logged lately, see [`error_log`](crate::error_log),
+//! with the cache purged and warmed up by buttons.
+//!
+//! The assets are compiled into the binary, and served to anyone as holding
+//! no data: the page asks for an API key and calls the admin API with it,
+//! of the same origin so that its actions are not refused against CSRF, see
+//! [`cross_site_origin`](super::cross_site_origin).
+
+use anyhow::Result;
+use http::{
+    HeaderValue, Method,
+    header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_SECURITY_POLICY, CONTENT_TYPE},
+};
+use macro_toolset::string_v2::StringExtT;
+
+use crate::{
+    error::Error,
+    proto::{self, Body},
+};
+
+#[derive(Debug)]
+/// An asset of the dashboard.
+pub(super) struct Asset {
+    path: &'static str,
+
+    content_type: &'static str,
+
+    content: &'static [u8],
+}
+
+/// Assets, by path with [`PREFIX`](super::PREFIX) stripped.
+const ASSETS: &[Asset] = &[
+    Asset {
+        path: "/",
+        content_type: "text/html; charset=utf-8",
+        content: include_bytes!("dashboard/index.html"),
+    },
+    Asset {
+        path: "/dashboard.js",
+        content_type: "text/javascript; charset=utf-8",
+        content: include_bytes!("dashboard/dashboard.js"),
+    },
+    Asset {
+        path: "/dashboard.css",
+        content_type: "text/css; charset=utf-8",
+        content: include_bytes!("dashboard/dashboard.css"),
+    },
+];
+
+/// Assets of none but the dashboard itself, never framed.
+const POLICY: &str = "default-src 'self'; frame-ancestors 'none'";
+
+/// The asset of `path`, `/admin` being of the page as `/admin/`.
+pub(super) fn asset(path: &str) -> Option<&'static Asset> {
+    let path = if path.is_empty() { "/" } else { path };
+
+    ASSETS.iter().find(|asset| asset.path == path)
+}
+
+/// `GET /admin/` and the assets of it
+///
+/// Respond with `asset`, revalidated each time so that those of an upgraded
+/// binary are never stale.
+pub(super) async fn serve(
+    request: &proto::Request,
+    asset: &Asset,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let mut response = proto::Response::default();
+    let headers = response.headers_mut();
+
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(asset.content_type));
+    headers.insert(CONTENT_LENGTH, asset.content.len().to_http_header_value()?);
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    headers.insert(CONTENT_SECURITY_POLICY, HeaderValue::from_static(POLICY));
+
+    let body = if request.method == Method::GET {
+        Body::Bytes(asset.content.into())
+    } else {
+        Body::Empty
+    };
+
+    if let Err(e) = response.with_body(body).write_to_stream(tcp_stream).await {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,311 @@
+// Dashboard of the admin API, polling it with the API key given, kept for
+// the session of the tab only.
+
+"use strict";
+
+const POLL_INTERVAL = 2000;
+
+// Samples kept for the graphs, 5 minutes of them
+const SAMPLES = 150;
+
+const KEY = "admin-api-key";
+
+const history = { throughput: [], connections: [] };
+
+let timer = null;
+
+const $ = (id) => document.getElementById(id);
+
+function formatBytes(bytes) {
+  const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+  let unit = 0;
+
+  while (bytes >= 1024 && unit < units.length - 1) {
+    bytes /= 1024;
+    unit += 1;
+  }
+
+  return `${bytes.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+}
+
+function formatDuration(secs) {
+  secs = Math.floor(secs);
+
+  const days = Math.floor(secs / 86400);
+  const hours = Math.floor((secs % 86400) / 3600);
+  const minutes = Math.floor((secs % 3600) / 60);
+
+  if (days > 0) return `${days}d ${hours}h`;
+  if (hours > 0) return `${hours}h ${minutes}m`;
+  if (minutes > 0) return `${minutes}m ${secs % 60}s`;
+  return `${secs}s`;
+}
+
+class Unauthorized extends Error {}
+
+// Call the admin API, the JSON of the response if any.
+async function api(method, path, params) {
+  const query = params ? `?${new URLSearchParams(params)}` : "";
+  const response = await fetch(`/admin${path}${query}`, {
+    method,
+    headers: { Authorization: `Bearer ${sessionStorage.getItem(KEY)}` },
+    cache: "no-store",
+  });
+
+  if (response.status === 401) throw new Unauthorized();
+  if (!response.ok) {
+    const error = new Error(`${method} /admin${path}: ${response.status}`);
+    error.status = response.status;
+    throw error;
+  }
+
+  return response.status === 204 ? null : response.json();
+}
+
+// A row of `cells`, each a string or a node.
+function row(cells) {
+  const tr = document.createElement("tr");
+
+  for (const cell of cells) {
+    const td = document.createElement("td");
+
+    if (cell instanceof Node) {
+      td.append(cell);
+    } else {
+      td.textContent = cell;
+      if (typeof cell === "number") td.className = "number";
+    }
+
+    tr.append(td);
+  }
+
+  return tr;
+}
+
+function button(text, onclick) {
+  const node = document.createElement("button");
+  node.textContent = text;
+  node.addEventListener("click", onclick);
+  return node;
+}
+
+function push(samples, value) {
+  samples.push(value);
+  if (samples.length > SAMPLES) samples.shift();
+}
+
+function drawGraph(canvas, samples, format) {
+  const context = canvas.getContext("2d");
+  const { width, height } = canvas;
+  const max = Math.max(1, ...samples);
+  const step = width / (SAMPLES - 1);
+  const accent = getComputedStyle(document.documentElement).getPropertyValue("--accent");
+
+  context.clearRect(0, 0, width, height);
+
+  context.beginPath();
+  samples.forEach((value, i) => {
+    const x = width - (samples.length - 1 - i) * step;
+    const y = height - (value / max) * (height - 20);
+    if (i === 0) context.moveTo(x, y);
+    else context.lineTo(x, y);
+  });
+  context.strokeStyle = accent;
+  context.lineWidth = 2;
+  context.stroke();
+
+  context.fillStyle = getComputedStyle(canvas).color;
+  context.font = "12px system-ui, sans-serif";
+  context.fillText(`max ${format(max)}`, 4, 14);
+}
+
+function renderStats(stats) {
+  $("uptime").textContent = formatDuration(stats.uptime);
+  $("connections").textContent = stats.connections;
+  $("open-files").textContent = stats.open_files ?? "-";
+  $("bytes-sent").textContent = formatBytes(stats.bytes_sent);
+  $("throughput").textContent = `${formatBytes(stats.throughput)}/s`;
+
+  push(history.throughput, stats.throughput);
+  push(history.connections, stats.connections);
+
+  drawGraph($("throughput-graph"), history.throughput, (value) => `${formatBytes(value)}/s`);
+  drawGraph($("connections-graph"), history.connections, String);
+
+  const cache = stats.cache;
+
+  $("cache-disabled").hidden = cache !== null;
+  $("cache-usage").hidden = cache === null;
+
+  if (cache) {
+    $("cache-meter").value = cache.max_size ? cache.total_size / cache.max_size : 0;
+    $("cache-occupancy").textContent =
+      `${formatBytes(cache.total_size)} of ${formatBytes(cache.max_size)}, ` +
+      `${cache.keys} keys, ${cache.objects} objects, ${cache.partials} partial`;
+    $("cache-roots").replaceChildren(
+      ...cache.roots.map((root) =>
+        row([root.dir, root.free_space === null ? "-" : formatBytes(root.free_space)]),
+      ),
+    );
+  }
+}
+
+function renderConnections(connections) {
+  $("connection-list").replaceChildren(
+    ...connections.map((connection) => {
+      const request = connection.request
+        ? `${connection.request.method} ${connection.request.path}, ` +
+          `${formatBytes(connection.request.throughput)}/s`
+        : "idle";
+
+      return row([
+        connection.id,
+        connection.peer,
+        formatDuration(connection.age),
+        formatBytes(connection.bytes_sent),
+        request,
+        button("Close", () => act("DELETE", "/connections", { id: connection.id })),
+      ]);
+    }),
+  );
+}
+
+function renderWarmup(jobs) {
+  $("warmup-jobs").replaceChildren(
+    ...jobs.reverse().map((job) =>
+      row([
+        job.id,
+        `${job.video} ${job.cid}`,
+        job.quality ?? job.qn,
+        job.error ? `${job.state}: ${job.error}` : job.state,
+        job.objects,
+        `${job.cached} (${formatBytes(job.bytes)}), ${job.failed} failed`,
+      ]),
+    ),
+  );
+}
+
+function renderErrors(errors) {
+  $("errors").replaceChildren(
+    ...errors.map((error) => {
+      const li = document.createElement("li");
+      const time = document.createElement("time");
+
+      time.textContent = new Date(error.time * 1000).toLocaleString();
+      li.append(time, `${error.target}: ${error.message}`);
+
+      return li;
+    }),
+  );
+}
+
+async function refresh() {
+  try {
+    const [stats, connections, errors] = await Promise.all([
+      api("GET", "/stats"),
+      api("GET", "/connections"),
+      api("GET", "/errors"),
+    ]);
+
+    renderStats(stats);
+    renderConnections(connections);
+    renderErrors(errors);
+
+    // Not built, or no playurl, cache or upstream configured
+    try {
+      renderWarmup(await api("GET", "/warmup"));
+      $("warmup").hidden = false;
+    } catch (e) {
+      if (e.status !== 404) throw e;
+      $("warmup").hidden = true;
+    }
+
+    $("status").textContent = `Updated ${new Date().toLocaleTimeString()}`;
+  } catch (e) {
+    if (e instanceof Unauthorized) {
+      logout("Invalid API key");
+      return;
+    }
+
+    $("status").textContent = `Update failed: ${e.message}`;
+  }
+}
+
+// Take an action, refreshing once done.
+async function act(method, path, params) {
+  try {
+    const result = await api(method, path, params);
+    await refresh();
+    return result;
+  } catch (e) {
+    alert(e.message);
+  }
+}
+
+function login() {
+  $("login").hidden = true;
+  $("main").hidden = false;
+  $("logout").hidden = false;
+
+  refresh();
+  timer = setInterval(refresh, POLL_INTERVAL);
+}
+
+function logout(error) {
+  sessionStorage.removeItem(KEY);
+  clearInterval(timer);
+
+  $("main").hidden = true;
+  $("logout").hidden = true;
+  $("login").hidden = false;
+  $("login-error").textContent = error ?? "";
+}
+
+$("login").addEventListener("submit", (event) => {
+  event.preventDefault();
+  sessionStorage.setItem(KEY, $("key").value.trim());
+  $("key").value = "";
+  login();
+});
+
+$("logout").addEventListener("click", () => logout());
+
+$("purge").addEventListener("submit", async (event) => {
+  event.preventDefault();
+
+  const params = {};
+  for (const [name, value] of new FormData(event.target)) {
+    if (value) params[name] = value;
+  }
+
+  if (Object.keys(params).length === 0) {
+    alert("Give a key prefix or an age, or purge all");
+    return;
+  }
+
+  const result = await act("DELETE", "/cache", params);
+  if (result) alert(`Purged ${result.purged} objects`);
+});
+
+$("purge-all").addEventListener("click", async () => {
+  if (!confirm("Purge all cached objects?")) return;
+
+  const result = await act("DELETE", "/cache", { all: "true" });
+  if (result) alert(`Purged ${result.purged} objects`);
+});
+
+$("warmup-form").addEventListener("submit", (event) => {
+  event.preventDefault();
+
+  const form = new FormData(event.target);
+  const video = form.get("video").trim();
+  const params = { cid: form.get("cid"), qn: form.get("qn") || "80" };
+
+  if (/^av\d+$/i.test(video)) params.avid = video.slice(2);
+  else params.bvid = video;
+
+  act("POST", "/warmup", params);
+});
+
+if (sessionStorage.getItem(KEY)) login();
+else logout();
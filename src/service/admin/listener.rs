@@ -0,0 +1,217 @@
+//! Listener of the admin API and metrics apart, see [`AdminConfig::listen`],
+//! e.g. on a management network, over TLS if configured, see
+//! [`AdminTlsConfig`].
+//!
+//! With [`AdminTlsConfig::client_ca`], clients must present a certificate
+//! issued by the CA, i.e. mutual TLS, so that purging, warming up and closing
+//! connections are not open to all on the network even if the token leaked.
+
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+use std::{net::SocketAddr, sync::LazyLock, time::Duration};
+
+#[cfg(feature = "tls")]
+use anyhow::Context;
+use anyhow::Result;
+#[cfg(feature = "tls")]
+use arc_swap::ArcSwapOption;
+use http::StatusCode;
+#[cfg(feature = "tls")]
+use rustls::{
+    RootCertStore, ServerConfig,
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::WebPkiClientVerifier,
+};
+#[cfg(feature = "tls")]
+use tokio::io::BufReader;
+use tokio::net::TcpStream;
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+
+#[cfg(feature = "tls")]
+use crate::config::{AdminTlsConfig, Config};
+use crate::{
+    client,
+    config::AdminConfig,
+    listener,
+    proto::{self, AnyStream},
+    router::Router,
+    routes, service,
+};
+
+#[cfg(feature = "tls")]
+/// Time to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time to wait for the next request on a connection.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[cfg(feature = "tls")]
+/// Server config built of the current [`AdminTlsConfig`], see [`reload`].
+static SERVER_CONFIG: ArcSwapOption<ServerConfig> = ArcSwapOption::const_empty();
+
+/// Routes served, see [`routes::admin`].
+static ROUTER: LazyLock<Router> = LazyLock::new(routes::admin);
+
+/// Listen on [`AdminConfig::listen`] for admin requests and scrapes, served
+/// in background.
+pub(crate) fn spawn(config: &AdminConfig) -> Result<()> {
+    let Some(listen) = config.listen else {
+        return Ok(());
+    };
+
+    #[cfg(feature = "tls")]
+    if let Some(tls_config) = &config.tls {
+        SERVER_CONFIG.store(Some(server_config(tls_config)?));
+    }
+
+    // With `SO_REUSEPORT`, so that bound by the new process on upgrading too,
+    // see `upgrade`
+    let listener =
+        listener::Listener::new(listener::bind_socket(listen, true)?, listener::Kind::Admin);
+
+    if config.tls.is_some() {
+        tracing::info!("Admin listening on {listen} over TLS");
+    } else {
+        tracing::info!("Admin listening on {listen}");
+    }
+
+    tokio::spawn(async move {
+        loop {
+            let (tcp_stream, peer_addr) = listener.accept().await;
+
+            tracing::debug!("New admin connection from {peer_addr}");
+
+            tokio::spawn(accept(tcp_stream, peer_addr));
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+/// Build the server config of the current [`AdminTlsConfig`] again, e.g.
+/// once the certificates renewed, keeping the current one if failed.
+pub(crate) fn reload() {
+    // Not listening over TLS
+    if SERVER_CONFIG.load().is_none() {
+        return;
+    }
+
+    let config = Config::current();
+
+    let Some(tls_config) = config.admin.as_ref().and_then(|admin| admin.tls.as_ref()) else {
+        return;
+    };
+
+    match server_config(tls_config) {
+        Ok(server_config) => {
+            SERVER_CONFIG.store(Some(server_config));
+
+            tracing::info!("Admin TLS certificates reloaded");
+        }
+        Err(e) => {
+            tracing::error!("Reload admin TLS certificates error, keeping the loaded ones: {e:#}");
+        }
+    }
+}
+
+/// Handle a connection accepted, after the TLS handshake if over TLS.
+async fn accept(tcp_stream: TcpStream, peer_addr: SocketAddr) {
+    #[cfg(feature = "tls")]
+    if let Some(server_config) = SERVER_CONFIG.load_full() {
+        return serve_tls(server_config, tcp_stream, peer_addr).await;
+    }
+
+    serve(AnyStream::Plain(tcp_stream), peer_addr).await;
+}
+
+#[cfg(feature = "tls")]
+/// Complete the TLS handshake of a connection accepted, then serve it.
+async fn serve_tls(server_config: Arc<ServerConfig>, tcp_stream: TcpStream, peer_addr: SocketAddr) {
+    let handshake = TlsAcceptor::from(server_config).accept(tcp_stream);
+
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+        Ok(Ok(tls_stream)) => {
+            let stream = AnyStream::Tls(Box::new(BufReader::new(tls_stream)));
+            serve(stream, peer_addr).await;
+        }
+        Ok(Err(e)) => tracing::warn!("Admin TLS handshake with {peer_addr} error: {e}"),
+        Err(_) => tracing::debug!("Admin TLS handshake with {peer_addr} timed out"),
+    }
+}
+
+/// Serve requests on the connection from `peer_addr` until closed.
+async fn serve(mut stream: AnyStream, peer_addr: SocketAddr) {
+    loop {
+        let request =
+            match tokio::time::timeout(IDLE_TIMEOUT, proto::Request::handle(&mut stream)).await {
+                Ok(Ok(Some(request))) => request,
+                // Closed, or idle for long
+                Ok(Ok(None)) | Err(_) => return,
+                Ok(Err(e)) => {
+                    tracing::debug!("Admin request from {peer_addr} error: {e:?}");
+
+                    let _ = service::write_status(StatusCode::BAD_REQUEST, &mut stream).await;
+
+                    return;
+                }
+            };
+
+        let result = client::scope(peer_addr.ip(), ROUTER.handle(&request, &mut stream)).await;
+
+        match result {
+            // Not if bytes past the head are left, see `Request::is_reusable`
+            Ok(true) if request.is_reusable() => {}
+            Ok(_) => return,
+            Err(e) => {
+                tracing::error!("Admin request from {peer_addr} error: {e:?}");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+/// Build the server config of `config`, loading the certificates.
+fn server_config(config: &AdminTlsConfig) -> Result<Arc<ServerConfig>> {
+    let cert_chain = CertificateDer::pem_file_iter(&config.cert)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .with_context(|| format!("Read certificates {}", config.cert.display()))?;
+
+    let key = PrivateKeyDer::from_pem_file(&config.key)
+        .with_context(|| format!("Read private key {}", config.key.display()))?;
+
+    let provider = Arc::new(ring::default_provider());
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match &config.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+
+            for cert in CertificateDer::pem_file_iter(path)
+                .with_context(|| format!("Read client CA certificates {}", path.display()))?
+            {
+                roots.add(cert.with_context(|| {
+                    format!("Read client CA certificates {}", path.display())
+                })?)?;
+            }
+
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("Build client certificate verifier")?;
+
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let server_config = builder
+        .with_single_cert(cert_chain, key)
+        .context("Invalid certificate or private key")?;
+
+    Ok(Arc::new(server_config))
+}
@@ -0,0 +1,264 @@
+//! Warming up the cache with a whole video, i.e. `/admin/warmup`, e.g.
+//! pre-loading it for an offline viewing party.
+//!
+//! The playurl of the video is resolved, see [`playurl::resolve`], then the
+//! objects of the requested quality along with all audio streams are fetched
+//! into the cache one by one. Progress is kept per job, see [`Warmup`].
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use anyhow::Result;
+use http::StatusCode;
+use serde::Serialize;
+
+use crate::{
+    config::{Config, PlayurlConfig},
+    error::Error,
+    playurl::{self, Playurl, Query},
+    proto,
+    service::{self, resource},
+};
+
+/// Max number of jobs kept, the oldest finished ones are dropped beyond.
+const MAX_JOBS: usize = 32;
+
+/// Jobs, the latest last.
+static JOBS: LazyLock<Mutex<VecDeque<Arc<Mutex<Warmup>>>>> =
+    LazyLock::new(|| Mutex::new(VecDeque::new()));
+
+/// ID of the next job.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone)]
+#[derive(Serialize)]
+/// A warm-up job and its progress.
+pub(super) struct Warmup {
+    id: u64,
+
+    /// Like `BV1xx411c7mD` or `av170001`
+    video: String,
+
+    cid: u64,
+
+    /// Quality requested
+    qn: u32,
+
+    /// Quality given by the playurl, once resolved
+    quality: Option<u32>,
+
+    state: State,
+
+    /// Number of objects to fetch
+    objects: usize,
+
+    /// Number of objects cached so far
+    cached: usize,
+
+    /// Number of objects failed to fetch
+    failed: usize,
+
+    /// Bytes cached so far
+    bytes: u64,
+
+    /// Why the job failed
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+/// State of a [`Warmup`] job.
+enum State {
+    Resolving,
+    Fetching,
+    Finished,
+    Failed,
+}
+
+/// `POST /admin/warmup?bvid={bvid}&cid={cid}&qn={qn}&fnval={fnval}`
+///
+/// Start warming up the cache with the video of the query, see [`Query`],
+/// responding with the job.
+pub(super) async fn start(
+    request: &proto::Request,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let config = Config::current();
+
+    let (Some(playurl_config), Some(_), Some(_)) =
+        (&config.playurl, &config.cache, &config.upstream)
+    else {
+        return service::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    let Some(query) = Query::of_request(request) else {
+        return service::write_status(StatusCode::BAD_REQUEST, tcp_stream).await;
+    };
+
+    let job = Arc::new(Mutex::new(Warmup {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        video: query.video.to_string(),
+        cid: query.cid,
+        qn: query.qn,
+        quality: None,
+        state: State::Resolving,
+        objects: 0,
+        cached: 0,
+        failed: 0,
+        bytes: 0,
+        error: None,
+    }));
+
+    {
+        let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+
+        jobs.push_back(job.clone());
+
+        while jobs.len() > MAX_JOBS {
+            let Some(done) = jobs
+                .iter()
+                .position(|job| matches!(snapshot(job).state, State::Finished | State::Failed))
+            else {
+                break;
+            };
+
+            jobs.remove(done);
+        }
+    }
+
+    let warmup = snapshot(&job);
+
+    tokio::spawn(run(job, playurl_config.clone(), query));
+
+    service::write_json(StatusCode::ACCEPTED, &warmup, tcp_stream).await
+}
+
+/// `GET /admin/warmup`
+///
+/// Respond with all jobs kept, the latest last.
+pub(super) async fn list(tcp_stream: &mut impl proto::Stream) -> Result<bool, Error> {
+    let jobs: Vec<Warmup> = JOBS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|job| snapshot(job))
+        .collect();
+
+    service::write_json(StatusCode::OK, &jobs, tcp_stream).await
+}
+
+/// Resolve the playurl of `job` and fetch its objects.
+async fn run(job: Arc<Mutex<Warmup>>, config: PlayurlConfig, query: Query) {
+    let id = snapshot(&job).id;
+    let Query { video, cid, .. } = &query;
+
+    let playurl = match playurl::resolve(&config, &query).await {
+        Ok(playurl) => playurl,
+        Err(e) => {
+            tracing::warn!("Warm-up #{id}: resolve playurl of {video} {cid} error: {e:#}");
+
+            update(&job, |warmup| {
+                warmup.state = State::Failed;
+                warmup.error = Some(format!("{e:#}"));
+            });
+
+            return;
+        }
+    };
+
+    let objects = objects(&playurl);
+
+    tracing::info!(
+        "Warm-up #{id}: fetching {} objects of {video} {cid} in quality {}",
+        objects.len(),
+        playurl.quality
+    );
+
+    update(&job, |warmup| {
+        warmup.quality = Some(playurl.quality);
+        warmup.state = State::Fetching;
+        warmup.objects = objects.len();
+    });
+
+    for (key, path_and_query) in objects {
+        let size = resource::fetch_into_cache(&key, &path_and_query).await;
+
+        if size.is_none() {
+            tracing::warn!("Warm-up #{id}: {key:?} not cached");
+        }
+
+        update(&job, |warmup| match size {
+            Some(size) => {
+                warmup.cached += 1;
+                warmup.bytes += size;
+            }
+            None => warmup.failed += 1,
+        });
+    }
+
+    let warmup = update(&job, |warmup| warmup.state = State::Finished);
+
+    tracing::info!(
+        "Warm-up #{id}: finished, {} cached, {} failed, {} bytes",
+        warmup.cached,
+        warmup.failed,
+        warmup.bytes
+    );
+}
+
+/// Objects of `playurl` to fetch, i.e. the video streams of the quality
+/// given and all audio streams, or the FLV / MP4 segments, by key along with
+/// the upstream path and query.
+///
+/// The sizes and checksums of FLV / MP4 segments are recorded for validation,
+/// see [`playurl::expect_segments`].
+fn objects(playurl: &Playurl) -> Vec<(String, String)> {
+    let mut objects: Vec<(String, String)> = Vec::new();
+
+    if let Some(dash) = &playurl.dash {
+        let videos = dash
+            .video
+            .iter()
+            .filter(|stream| stream.id == playurl.quality);
+        let audios = dash.audio.iter().flatten();
+
+        objects.extend(
+            videos
+                .chain(audios)
+                .filter_map(|stream| playurl::split_url(&stream.base_url)),
+        );
+    }
+
+    playurl::expect_segments(playurl);
+
+    objects.extend(
+        playurl
+            .durl
+            .iter()
+            .filter_map(|durl| playurl::split_url(&durl.url)),
+    );
+
+    objects.dedup_by(|a, b| a.0 == b.0);
+
+    objects
+}
+
+/// Current progress of `job`.
+fn snapshot(job: &Mutex<Warmup>) -> Warmup {
+    job.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Update the progress of `job`, returning the updated one.
+fn update(job: &Mutex<Warmup>, f: impl FnOnce(&mut Warmup)) -> Warmup {
+    let mut warmup = job.lock().unwrap_or_else(|e| e.into_inner());
+
+    f(&mut warmup);
+
+    warmup.clone()
+}
@@ -0,0 +1,240 @@
+//! Danmaku route, i.e. `/x/v2/dm/*` of the API of bilibili.
+//!
+//! Requests are passed through to the API hosts, see [`PlayurlConfig::api`],
+//! like `/x/v2/dm/web/seg.so?type=1&oid={cid}&segment_index={n}` for the
+//! protobuf danmaku segments of a video, and the responses cached, so that a
+//! local playback page gets comments as well without talking to bilibili.
+//!
+//! Cached responses are served for [`DanmakuConfig::ttl`], and past it while
+//! the API is not reachable.
+//!
+//! [`PlayurlConfig::api`]: crate::config::PlayurlConfig::api
+
+use std::time::SystemTime;
+
+use anyhow::{Result, bail};
+use http::{
+    HeaderMap, HeaderValue, Method, StatusCode,
+    header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE},
+};
+use tokio::fs::File;
+
+use crate::{
+    cache::{Cache, CachedObject, Metadata},
+    config::{Config, DanmakuConfig, PlayurlConfig},
+    error::Error,
+    metrics::{self, CacheLookup},
+    proto,
+    timing::{self, Phase},
+    upstream::{self, Priority},
+};
+
+/// Path prefix of the route
+pub(crate) const PREFIX: &str = "/x/v2/dm/";
+
+/// Prefix of cache keys.
+const KEY_PREFIX: &str = "danmaku";
+
+/// Max size of a response body.
+const MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Query params of WBI signing, differing between requests of the same.
+const VOLATILE_PARAMS: [&str; 3] = ["w_rid", "wts", "web_location"];
+
+/// `GET /x/v2/dm/*`
+///
+/// Respond with the cached response of the request if fresh, or else with
+/// the response of the API, cached. Responds `502 Bad Gateway` when the API
+/// is not reachable and nothing cached.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn handle(
+    request: &proto::Request,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let config = Config::current();
+
+    let Some((playurl_config, danmaku_config)) = config
+        .playurl
+        .as_ref()
+        .and_then(|playurl_config| Some((playurl_config, playurl_config.danmaku.as_ref()?)))
+    else {
+        return Err(Error::NotFound);
+    };
+
+    let key = cache_key(request);
+    let cache = Cache::global();
+    let cached = cache.and_then(|cache| cache.get(&key));
+
+    if let (Some(cache), Some(cached)) = (cache, &cached) {
+        if !cached.is_expired() {
+            tracing::debug!("Cache hit: {key:?}");
+            metrics::cache_lookup(CacheLookup::Hit);
+
+            return serve_cached(request, cache, cached, tcp_stream).await;
+        }
+    }
+
+    if cache.is_some() {
+        metrics::cache_lookup(CacheLookup::Miss);
+    }
+
+    let path_and_query = request.request_uri.as_str();
+
+    let (content_type, body) = match fetch(playurl_config, path_and_query).await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            let e = e.context(format!("Fetch danmaku {path_and_query:?}"));
+
+            return match (cache, &cached) {
+                (Some(cache), Some(cached)) => {
+                    tracing::error!("{e:#}");
+                    tracing::debug!("Cache hit, stale: {key:?}");
+                    metrics::cache_lookup(CacheLookup::Stale);
+
+                    serve_cached(request, cache, cached, tcp_stream).await
+                }
+                _ => Err(Error::Upstream(e)),
+            };
+        }
+    };
+
+    if let Some(cache) = cache {
+        store(cache, &key, danmaku_config, content_type.clone(), &body).await;
+    }
+
+    let mut response = proto::Response::default();
+    headers(response.headers_mut());
+    if let Some(content_type) = content_type.and_then(|value| HeaderValue::from_str(&value).ok()) {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+
+    if let Err(e) = response.with_body(body).write_to_stream(tcp_stream).await {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Cache key of the request, i.e. the path and the query sorted, but the
+/// [`VOLATILE_PARAMS`].
+fn cache_key(request: &proto::Request) -> String {
+    let mut params: Vec<_> = request
+        .request_uri
+        .query()
+        .map(|query| query.as_str())
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+            !name.is_empty() && !VOLATILE_PARAMS.contains(&name)
+        })
+        .collect();
+    params.sort_unstable();
+
+    format!(
+        "{KEY_PREFIX}{}?{}",
+        request.request_uri.path(),
+        params.join("&")
+    )
+}
+
+/// Fetch `path_and_query` from the API, returning the `Content-Type` and the
+/// body of the response.
+async fn fetch(config: &PlayurlConfig, path_and_query: &str) -> Result<(Option<String>, Vec<u8>)> {
+    let mut response = upstream::fetch(
+        &config.api,
+        &Method::GET,
+        path_and_query,
+        &HeaderMap::new(),
+        Priority::Interactive,
+    )
+    .await?;
+
+    if response.status != StatusCode::OK {
+        bail!("API responded {}", response.status);
+    }
+
+    let content_type = response
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+
+    let mut body = Vec::new();
+
+    while let Some(data) = response.next().await? {
+        if body.len() + data.len() > MAX_RESPONSE_SIZE {
+            bail!("API response too large");
+        }
+
+        body.extend_from_slice(data);
+    }
+
+    Ok((content_type, body))
+}
+
+/// Cache `body` as the object of `key`, fresh for [`DanmakuConfig::ttl`].
+async fn store(
+    cache: &'static Cache,
+    key: &str,
+    config: &DanmakuConfig,
+    content_type: Option<String>,
+    body: &[u8],
+) {
+    let expires = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+        + config.ttl;
+
+    let result = async {
+        let mut writer = cache.writer(key).await?;
+
+        writer.set_metadata(Metadata {
+            content_type,
+            ..Metadata::default()
+        });
+        writer.set_expires(expires);
+        writer.write_all(body).await?;
+
+        writer.commit().await
+    };
+
+    if let Err(e) = result.await {
+        tracing::warn!("Cache {key:?} error: {e}");
+    }
+}
+
+/// Serve the `cached` object.
+async fn serve_cached(
+    request: &proto::Request,
+    cache: &Cache,
+    cached: &CachedObject,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let mut response = proto::Response::default();
+    headers(response.headers_mut());
+    if let Some(metadata) = cache.metadata(cached).await {
+        metadata.apply(response.headers_mut());
+    }
+
+    let file = timing::timed(Phase::Open, File::open(&cached.path)).await?;
+
+    super::serve_file(
+        request,
+        response,
+        file,
+        super::ServeOptions::default(),
+        tcp_stream,
+    )
+    .await
+}
+
+/// Set the headers common to responses.
+fn headers(headers: &mut HeaderMap) {
+    headers.insert(
+        ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("https://www.bilibili.com"),
+    );
+}
@@ -0,0 +1,141 @@
+//! Health routes, i.e. `/healthz` and `/readyz`, for systemd, Docker and
+//! load balancers.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use http::StatusCode;
+use serde::Serialize;
+
+#[cfg(feature = "upstream")]
+use crate::upstream;
+use crate::{cache::Cache, config::Config, error::Error, proto};
+
+/// Path of the liveness route
+pub(crate) const HEALTHZ_PATH: &str = "/healthz";
+
+/// Path of the readiness route
+pub(crate) const READYZ_PATH: &str = "/readyz";
+
+#[derive(Debug, Serialize)]
+/// Response of [`readyz`].
+struct Readiness {
+    /// Whether all checks passed
+    ready: bool,
+
+    /// Whether the config is applied, i.e. the cache opened if configured
+    config: bool,
+
+    /// Whether the cache directory, storage roots and static directories are
+    /// all accessible
+    storage: bool,
+
+    /// Whether any upstream host is reachable, if checked, see
+    /// [`HealthConfig::upstream`]
+    ///
+    /// [`HealthConfig::upstream`]: crate::config::HealthConfig::upstream
+    upstream: Option<bool>,
+}
+
+/// `GET /healthz`
+///
+/// Respond `200 OK` as long as the process is alive.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn healthz(tcp_stream: &mut impl proto::Stream) -> Result<bool, Error> {
+    super::write_status(StatusCode::OK, tcp_stream).await
+}
+
+/// `GET /readyz`
+///
+/// Respond with the checks of [`Readiness`], `200 OK` if all passed or else
+/// `503 Service Unavailable`.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn readyz(tcp_stream: &mut impl proto::Stream) -> Result<bool, Error> {
+    let config = Config::current();
+
+    let config_applied = config.cache.is_none() || Cache::global().is_some();
+
+    let storage = storage_accessible(&config).await;
+
+    let upstream = check_upstream(&config);
+
+    let ready = config_applied && storage && upstream.unwrap_or(true);
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    super::write_json(
+        status,
+        &Readiness {
+            ready,
+            config: config_applied,
+            storage,
+            upstream,
+        },
+        tcp_stream,
+    )
+    .await
+}
+
+/// Whether the cache directory, storage roots and static directories are all
+/// accessible directories.
+async fn storage_accessible(config: &Config) -> bool {
+    let dirs: Vec<PathBuf> = config
+        .cache
+        .iter()
+        .flat_map(|cache_config| {
+            std::iter::once(&cache_config.dir)
+                .chain(cache_config.roots.iter().map(|root| &root.dir))
+        })
+        .chain(config.static_dirs.iter().map(|static_dir| &static_dir.root))
+        .cloned()
+        .collect();
+
+    let mut accessible = true;
+
+    for dir in &dirs {
+        if !is_accessible_dir(dir).await {
+            tracing::warn!("Not ready: {} is not accessible", dir.display());
+            accessible = false;
+        }
+    }
+
+    accessible
+}
+
+/// Whether `dir` is an accessible directory.
+async fn is_accessible_dir(dir: &Path) -> bool {
+    tokio::fs::metadata(dir)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+}
+
+/// Whether any upstream host is reachable, i.e. healthy and not blacklisted,
+/// if checked.
+fn check_upstream(config: &Config) -> Option<bool> {
+    if !config.health.upstream {
+        return None;
+    }
+
+    #[cfg(feature = "upstream")]
+    let reachable = config.upstream.as_ref().is_some_and(|upstream_config| {
+        upstream::health(upstream_config)
+            .iter()
+            .any(|host| host.healthy && !host.blacklisted)
+    });
+
+    // None configured, see `Config::check_features`
+    #[cfg(not(feature = "upstream"))]
+    let reachable = false;
+
+    if !reachable {
+        tracing::warn!("Not ready: no upstream host reachable");
+    }
+
+    Some(reachable)
+}
@@ -0,0 +1,72 @@
+//! Metrics route, i.e. `/metrics`.
+//!
+//! Disabled unless [`MetricsConfig`] is set. Scrapes must carry the
+//! configured token as `Authorization: Bearer {token}` if any.
+
+use anyhow::Result;
+use http::{
+    HeaderValue, StatusCode,
+    header::{AUTHORIZATION, CONTENT_TYPE},
+};
+
+use crate::{
+    config::{Config, MetricsConfig},
+    error::Error,
+    metrics, proto,
+};
+
+/// Path of the route
+pub(crate) const PATH: &str = "/metrics";
+
+/// `GET /metrics`
+///
+/// Respond with all metrics in the Prometheus text format, see
+/// [`metrics`](crate::metrics).
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn handle(
+    request: &proto::Request,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let config = Config::current();
+
+    let Some(metrics_config) = &config.metrics else {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    if !authorized(request, metrics_config) {
+        tracing::warn!("Unauthorized metrics request");
+        return super::write_status(StatusCode::UNAUTHORIZED, tcp_stream).await;
+    }
+
+    let mut response = proto::Response::default();
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+    );
+
+    if let Err(e) = response
+        .with_body(metrics::render().into_bytes())
+        .write_to_stream(tcp_stream)
+        .await
+    {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Check the bearer token, if configured.
+fn authorized(request: &proto::Request, metrics_config: &MetricsConfig) -> bool {
+    let Some(expected) = &metrics_config.token else {
+        return true;
+    };
+
+    request
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token.trim() == &**expected)
+}
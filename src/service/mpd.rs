@@ -0,0 +1,183 @@
+//! DASH manifest route, i.e. `/mpd`.
+//!
+//! Generates an MPEG-DASH MPD of the on-demand profile for a cached video /
+//! audio pair, probed from the objects themselves, see [`mp4`], so that
+//! generic DASH players like dash.js or `ExoPlayer` play cached content
+//! without the player of bilibili. Segments are requested by ranges of the
+//! resource route.
+
+use std::fmt::Write;
+
+use anyhow::Result;
+use http::{
+    HeaderValue, StatusCode,
+    header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE},
+};
+use serde::Deserialize;
+use tokio::fs::File;
+
+use crate::{
+    cache::Cache,
+    error::Error,
+    mp4::{self, Track},
+    proto,
+    service::resource,
+};
+
+/// Path of the route
+pub(crate) const PATH: &str = "/mpd";
+
+#[derive(Debug, Clone)]
+#[derive(Deserialize)]
+/// Query of the route, see [`handle`].
+pub(crate) struct MpdQuery {
+    /// Key of the video object
+    pub video: String,
+
+    /// Key of the audio object, if any
+    pub audio: Option<String>,
+}
+
+/// `GET /mpd?video={key}&audio={key}`
+///
+/// Respond with the MPD of the cached video object of `key` and the audio
+/// one if given. Responds `404 Not Found` if either is not cached, or
+/// `422 Unprocessable Entity` if not a single track fragmented MP4 file
+/// indexed by `sidx`.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn handle(
+    query: MpdQuery,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let Some(cache) = Cache::global() else {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    let mut tracks = Vec::with_capacity(2);
+
+    for key in std::iter::once(query.video).chain(query.audio) {
+        let key = key.trim_start_matches('/').to_owned();
+
+        let Some(cached) = cache.get(&key).filter(|cached| !cached.is_expired()) else {
+            return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+        };
+
+        let mut file = File::open(&cached.path).await?;
+
+        match mp4::probe(&mut file).await {
+            Ok(track) => tracks.push((key, track)),
+            Err(e) => {
+                tracing::warn!("Probe {key:?} error: {e}");
+                return super::write_status(StatusCode::UNPROCESSABLE_ENTITY, tcp_stream).await;
+            }
+        }
+    }
+
+    // Of the video, the first track
+    let token = resource::mint_session(tracks[0].0.as_str());
+
+    let mut response = proto::Response::default();
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/dash+xml"),
+    );
+    response
+        .headers_mut()
+        .insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+
+    if let Err(e) = response
+        .with_body(manifest(&tracks, token.as_deref()).into_bytes())
+        .write_to_stream(tcp_stream)
+        .await
+    {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// The MPD of `tracks` by key, an adaptation set each, the URLs carrying the
+/// session `token` if any.
+fn manifest(tracks: &[(String, Track)], token: Option<&str>) -> String {
+    let duration = tracks
+        .iter()
+        .map(|(_, track)| track.duration)
+        .fold(0.0, f64::max);
+
+    let mut mpd = String::with_capacity(1024);
+
+    mpd.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        mpd,
+        "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" \
+         profiles=\"urn:mpeg:dash:profile:isoff-on-demand:2011\" type=\"static\" \
+         mediaPresentationDuration=\"PT{duration:.3}S\" minBufferTime=\"PT1.5S\">"
+    );
+    mpd.push_str("  <Period>\n");
+
+    for (id, (key, track)) in tracks.iter().enumerate() {
+        let _ = writeln!(
+            mpd,
+            "    <AdaptationSet mimeType=\"{}\" segmentAlignment=\"true\" startWithSAP=\"1\">",
+            track.mime_type()
+        );
+
+        let _ = write!(
+            mpd,
+            "      <Representation id=\"{id}\" codecs=\"{}\" bandwidth=\"{}\"",
+            escape(&track.codecs),
+            track.bandwidth()
+        );
+        if let Some((width, height)) = track.resolution {
+            let _ = write!(mpd, " width=\"{width}\" height=\"{height}\"");
+        }
+        if let Some(sample_rate) = track.sample_rate {
+            let _ = write!(mpd, " audioSamplingRate=\"{sample_rate}\"");
+        }
+        mpd.push_str(">\n");
+
+        let _ = writeln!(
+            mpd,
+            "        <BaseURL>{}</BaseURL>",
+            escape(&resource::url(&format!("/{key}"), token))
+        );
+        let _ = writeln!(
+            mpd,
+            "        <SegmentBase indexRange=\"{}-{}\">",
+            track.index_range.0, track.index_range.1
+        );
+        let _ = writeln!(
+            mpd,
+            "          <Initialization range=\"{}-{}\"/>",
+            track.init_range.0, track.init_range.1
+        );
+        mpd.push_str("        </SegmentBase>\n");
+        mpd.push_str("      </Representation>\n");
+        mpd.push_str("    </AdaptationSet>\n");
+    }
+
+    mpd.push_str("  </Period>\n");
+    mpd.push_str("</MPD>\n");
+
+    mpd
+}
+
+/// Escape `value` for XML text and attribute values.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
@@ -0,0 +1,265 @@
+//! Playurl route, i.e. `/playurl`.
+//!
+//! Responds like the playurl API of bilibili, with all stream URLs pointing
+//! at the resource route of this server, so that an unmodified web player
+//! may be pointed here.
+//!
+//! Legacy clients asking for `fnval=0` get the `durl` form, a progressive FLV
+//! or MP4 URL per quality, served by the resource route likewise. What else
+//! is given follows `fnval`, `fnver` and `fourk` as the API does, e.g. 4K
+//! streams only if asked by both `fnval` and `fourk`, see [`Query`].
+//!
+//! Videos stored locally are served without the API, see [`local`].
+
+mod local;
+
+use anyhow::{Context, Result};
+use http::{
+    HeaderValue, StatusCode,
+    header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE},
+};
+
+use crate::{
+    config::Config,
+    error::Error,
+    playurl::{self, ApiResponse, Playurl, Query},
+    proto,
+    service::resource,
+};
+
+/// Path of the route
+pub(crate) const PATH: &str = "/playurl";
+
+/// Paths of the playurl API of bilibili, also served by the route, so that
+/// clients with the API host pointed here work as is.
+pub(crate) const API_PATHS: [&str; 2] = [playurl::API_PATH, playurl::WBI_API_PATH];
+
+/// `GET /playurl?bvid={bvid}&cid={cid}&qn={qn}&fnval={fnval}`, or any of
+/// [`API_PATHS`] alike
+///
+/// Respond with the playurl of the local objects of the `cid` if any, see
+/// [`local`]. Otherwise, resolve the playurl of the query, see [`Query`], and
+/// respond with the API response as is, but the stream URLs rewritten, see
+/// [`rewrite`]. Responds `502 Bad Gateway` when the API is not reachable.
+///
+/// The query is forwarded to the API as is instead if configured, see
+/// [`PlayurlConfig::passthrough`], to the path requested if one of
+/// [`API_PATHS`].
+///
+/// The sizes and checksums of segments are recorded for validation, see
+/// [`playurl::expect_segments`].
+///
+/// `query` is that of the request if valid, and `host` its `Host`, of the
+/// stream URLs unless [`PlayurlConfig::public_url`] configured.
+///
+/// Returns whether the connection can be kept alive.
+///
+/// [`PlayurlConfig::passthrough`]: crate::config::PlayurlConfig::passthrough
+/// [`PlayurlConfig::public_url`]: crate::config::PlayurlConfig::public_url
+pub(crate) async fn handle(
+    request: &proto::Request,
+    query: Option<Query>,
+    host: Option<&str>,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let config = Config::current();
+
+    let base = match config
+        .playurl
+        .as_ref()
+        .and_then(|playurl_config| playurl_config.public_url.as_ref())
+    {
+        Some(public_url) => public_url.trim_end_matches('/').to_owned(),
+        None => host
+            .map(|host| {
+                if tcp_stream.plain().is_some() {
+                    format!("http://{host}")
+                } else {
+                    format!("https://{host}")
+                }
+            })
+            .unwrap_or_default(),
+    };
+
+    if let Some(query) = &query {
+        if let Some(api_response) = local::playurl(query, &base).await {
+            return respond(&api_response, tcp_stream).await;
+        }
+    }
+
+    let Some(playurl_config) = &config.playurl else {
+        return super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    let fetched = if playurl_config.passthrough {
+        let path = request.request_uri.path().as_str();
+        let path = if API_PATHS.contains(&path) {
+            path
+        } else {
+            playurl::API_PATH
+        };
+
+        match request.request_uri.query() {
+            Some(raw_query) => {
+                playurl::forward(playurl_config, &format!("{path}?{raw_query}")).await
+            }
+            None => playurl::forward(playurl_config, path).await,
+        }
+    } else if let Some(query) = &query {
+        playurl::fetch(playurl_config, query).await
+    } else {
+        return super::write_status(StatusCode::BAD_REQUEST, tcp_stream).await;
+    };
+
+    let api_response = fetched.and_then(|api_response| {
+        serde_json::from_value::<ApiResponse<Playurl>>(api_response)
+            .context("Parse playurl API response")
+    });
+
+    let mut api_response = match api_response {
+        Ok(api_response) => api_response,
+        Err(e) => {
+            tracing::error!(
+                "Fetch playurl of {:?} error: {e:#}",
+                request.request_uri.query().map(|query| query.as_str())
+            );
+            return super::write_status(StatusCode::BAD_GATEWAY, tcp_stream).await;
+        }
+    };
+
+    if api_response.code == 0 {
+        if let Some(playurl) = &mut api_response.data {
+            playurl::expect_segments(playurl);
+
+            let token = resource::mint_session(
+                query
+                    .as_ref()
+                    .map(|query| query.video.to_string())
+                    .unwrap_or_default(),
+            );
+
+            rewrite(playurl, &base, token.as_deref());
+        }
+    }
+
+    respond(&api_response, tcp_stream).await
+}
+
+/// Respond with the playurl API response `body`.
+async fn respond<T>(body: &T, tcp_stream: &mut impl proto::Stream) -> Result<bool, Error>
+where
+    T: serde::Serialize,
+{
+    let mut response = proto::Response::default();
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response.headers_mut().insert(
+        ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("https://www.bilibili.com"),
+    );
+
+    if let Err(e) = response
+        .with_body(serde_json::to_vec(body)?)
+        .write_to_stream(tcp_stream)
+        .await
+    {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Point the URLs of the streams and segments of `playurl` at the resource
+/// route under `base`, like
+/// `{base}/resource/mikufans/upgcxcode/...m4s?{query}`. The upstream host is
+/// dropped, objects are fetched from the configured ones. URLs carry the
+/// session `token` if any, and are signed if configured, see [`resource::url`].
+///
+/// The URLs under the other bases of this server, if configured, are listed
+/// first of the backup URLs, see [`PlayurlConfig::backup_urls`].
+///
+/// [`PlayurlConfig::backup_urls`]: crate::config::PlayurlConfig::backup_urls
+pub(crate) fn rewrite(playurl: &mut Playurl, base: &str, token: Option<&str>) {
+    let config = Config::current();
+    let backup_bases = config
+        .playurl
+        .as_ref()
+        .map_or(&[][..], |playurl_config| &playurl_config.backup_urls);
+
+    for stream in playurl.streams_mut() {
+        rewrite_urls(
+            &mut stream.base_url,
+            &mut stream.backup_url,
+            base,
+            token,
+            backup_bases,
+        );
+
+        if let Some(url) = &mut stream.base_url_camel {
+            rewrite_urls(url, &mut stream.backup_url_camel, base, token, backup_bases);
+        }
+    }
+
+    for durl in &mut playurl.durl {
+        rewrite_urls(
+            &mut durl.url,
+            &mut durl.backup_url,
+            base,
+            token,
+            backup_bases,
+        );
+    }
+}
+
+/// Rewrite `url` and its `backups`, see [`rewrite`], the URLs under
+/// `backup_bases` of the former prepended to the latter.
+fn rewrite_urls(
+    url: &mut String,
+    backups: &mut Option<Vec<String>>,
+    base: &str,
+    token: Option<&str>,
+    backup_bases: &[String],
+) {
+    rewrite_url(url, base, token);
+
+    let mut urls = backup_urls(url, base, backup_bases);
+
+    if let Some(backups) = backups {
+        for backup in backups.iter_mut() {
+            rewrite_url(backup, base, token);
+        }
+
+        urls.append(backups);
+    }
+
+    if !urls.is_empty() || backups.is_some() {
+        *backups = Some(urls);
+    }
+}
+
+/// Point `url` at the resource route under `base`, see [`rewrite`].
+fn rewrite_url(url: &mut String, base: &str, token: Option<&str>) {
+    if let Some((_, path_and_query)) = playurl::split_url(url) {
+        *url = resource_url(base, &path_and_query, token);
+    }
+}
+
+/// The URL `url` under `base` under each of `backup_bases` instead.
+fn backup_urls(url: &str, base: &str, backup_bases: &[String]) -> Vec<String> {
+    let Some(path_and_query) = url.strip_prefix(base) else {
+        return Vec::new();
+    };
+
+    backup_bases
+        .iter()
+        .map(|backup_base| format!("{}{path_and_query}", backup_base.trim_end_matches('/')))
+        .collect()
+}
+
+/// URL of the object of `path_and_query` (of upstream) on the resource route
+/// under `base`, see [`resource::url`].
+fn resource_url(base: &str, path_and_query: &str, token: Option<&str>) -> String {
+    format!("{base}{}", resource::url(path_and_query, token))
+}
@@ -0,0 +1,332 @@
+//! Playurls of local objects, i.e. those stored in the cache under
+//! `local/{cid}/`, e.g. uploaded, see [`upload`](crate::service::upload).
+//!
+//! Each object is a single track fragmented MP4 file named after its quality,
+//! like `local/{cid}/80.m4s` for the 1080P video stream or
+//! `local/{cid}/30280.m4s` for the 192K audio one, anything after the first
+//! `.` of the name ignored, so that a quality may have several objects, e.g.
+//! `80.avc.m4s` and `80.hevc.m4s`. Whether video or audio, the codecs and so
+//! on are probed from the objects themselves, see [`mp4::probe`].
+//!
+//! The quality asked by `qn` is given if stored, or else the best one below
+//! it, or the lowest one if none, see [`select`]. Of a quality stored in
+//! several codecs, the one preferred by `codecid` is listed first and given
+//! as `video_codecid`. AV1, HDR, Dolby Vision, 4K and 8K streams are listed
+//! only if asked by `fnval` (and `fourk`), see [`Query::allows`]. Only DASH
+//! streams are served, clients asking for FLV / MP4 segments get the playurl
+//! of the API.
+//!
+//! Dolby Audio and Hi-Res lossless audio streams, i.e. those of quality
+//! 30250 and 30251, are listed in `dolby` and `flac` as the API does, the
+//! former only if asked by `fnval`.
+
+use tokio::fs::File;
+
+use crate::{
+    cache::Cache,
+    config::Config,
+    mp4::{self, Kind, Track},
+    playurl::{
+        self, ApiResponse, Dash, Dolby, Flac, Format, Playurl, Query, SegmentBase,
+        SegmentBaseCamel, Stream,
+    },
+    service::resource,
+};
+
+/// Key prefix of local objects.
+const PREFIX: &str = "local";
+
+/// Audio quality of Dolby Audio streams.
+const AUDIO_DOLBY: u32 = 30250;
+
+/// Audio quality of Hi-Res lossless streams.
+const AUDIO_HI_RES: u32 = 30251;
+
+/// Codec ID of AV1.
+const CODECID_AV1: u32 = 13;
+
+/// Qualities known, the best first, as `(qn, format, new_description,
+/// display_desc, superscript)` in [`Format`]s.
+const QUALITIES: [(u32, &str, &str, &str, &str); 12] = [
+    (127, "hdflv2", "8K 超高清", "8K", ""),
+    (126, "hdflv2", "杜比视界", "杜比视界", ""),
+    (125, "hdflv2", "HDR 真彩色", "HDR", ""),
+    (120, "hdflv2", "4K 超清", "4K", ""),
+    (116, "hdflv2", "1080P 60帧", "1080P", "高帧率"),
+    (112, "hdflv2", "1080P 高码率", "1080P", "高码率"),
+    (80, "flv", "1080P 高清", "1080P", ""),
+    (74, "flv720", "720P 60帧", "720P", "高帧率"),
+    (64, "flv720", "720P 准高清", "720P", ""),
+    (32, "flv480", "480P 标清", "480P", ""),
+    (16, "mp4", "360P 流畅", "360P", ""),
+    (6, "mp4", "240P 极速", "240P", ""),
+];
+
+#[derive(Debug)]
+/// A local object probed.
+struct Object {
+    /// Quality, as the name of the object tells
+    id: u32,
+
+    key: String,
+
+    track: Track,
+}
+
+/// The playurl of `query` of the local objects of its `cid`, the URLs
+/// pointing at the resource route under `base`, see [`super::resource_url`].
+///
+/// `None` if no video stream of the `cid` stored, or not asked for DASH by
+/// `fnval`.
+pub(super) async fn playurl(query: &Query, base: &str) -> Option<ApiResponse<Playurl>> {
+    if !query.is_dash() {
+        return None;
+    }
+
+    let cache = Cache::global()?;
+
+    let (mut videos, mut audios): (Vec<_>, Vec<_>) = probe(cache, query.cid)
+        .await
+        .into_iter()
+        .partition(|object| object.track.kind == Kind::Video);
+
+    videos.retain(|object| query.allows(object.id));
+    if query.fnval & playurl::FNVAL_AV1 == 0 {
+        videos.retain(|object| codecid(&object.track.codecs) != CODECID_AV1);
+    }
+
+    // The best first, the codec preferred first of a quality, or AVC, HEVC
+    // then AV1 as the API lists them
+    videos.sort_by_key(|object| {
+        let codecid = codecid(&object.track.codecs);
+        (
+            u32::MAX - object.id,
+            query.codecid != Some(codecid),
+            codecid,
+        )
+    });
+
+    let mut accept_quality: Vec<u32> = videos.iter().map(|object| object.id).collect();
+    accept_quality.sort_unstable_by(|a, b| b.cmp(a));
+    accept_quality.dedup();
+
+    let quality = select(&accept_quality, query.qn)?;
+
+    let support_formats: Vec<_> = accept_quality
+        .iter()
+        .map(|&quality| {
+            let (_, format, new_description, display_desc, superscript) = describe(quality);
+
+            Format {
+                quality,
+                format: format.to_owned(),
+                new_description: new_description.to_owned(),
+                display_desc: display_desc.to_owned(),
+                superscript: superscript.to_owned(),
+                codecs: Some(
+                    videos
+                        .iter()
+                        .filter(|object| object.id == quality)
+                        .map(|object| object.track.codecs.clone())
+                        .collect(),
+                ),
+                ..Format::default()
+            }
+        })
+        .collect();
+
+    // Like the API, the qualities up to the one given
+    videos.retain(|object| object.id <= quality);
+
+    audios.sort_by_key(|object| u64::MAX - object.track.bandwidth());
+
+    // Listed apart from the AAC ones
+    let (mut dolby, audios): (Vec<_>, Vec<_>) = audios
+        .into_iter()
+        .partition(|object| object.id == AUDIO_DOLBY);
+    if query.fnval & playurl::FNVAL_DOLBY_AUDIO == 0 {
+        dolby.clear();
+    }
+
+    let (flac, audios): (Vec<_>, Vec<_>) = audios
+        .into_iter()
+        .partition(|object| object.id == AUDIO_HI_RES);
+    let flac = flac.into_iter().next();
+
+    let duration = videos
+        .iter()
+        .chain(&audios)
+        .chain(&dolby)
+        .chain(&flac)
+        .map(|object| object.track.duration)
+        .fold(0.0, f64::max);
+
+    let config = Config::current();
+    let backup_bases = config
+        .playurl
+        .as_ref()
+        .map_or(&[][..], |playurl_config| &playurl_config.backup_urls);
+
+    let token = resource::mint_session(query.video.to_string());
+    let stream = |object: &Object| self::stream(object, base, backup_bases, token.as_deref());
+
+    let data = Playurl {
+        from: "local".to_owned(),
+        result: "suee".to_owned(),
+        quality,
+        format: describe(quality).1.to_owned(),
+        timelength: (duration * 1000.0) as u64,
+        accept_format: support_formats
+            .iter()
+            .map(|format| format.format.as_str())
+            .collect::<Vec<_>>()
+            .join(","),
+        accept_description: support_formats
+            .iter()
+            .map(|format| format.new_description.clone())
+            .collect(),
+        accept_quality,
+        video_codecid: videos
+            .iter()
+            .find(|object| object.id == quality)
+            .map_or(0, |object| codecid(&object.track.codecs)),
+        seek_param: "start".to_owned(),
+        seek_type: "offset".to_owned(),
+        dash: Some(Dash {
+            duration: duration.ceil() as u64,
+            min_buffer_time_camel: Some(1.5),
+            min_buffer_time: 1.5,
+            video: videos.iter().map(stream).collect(),
+            audio: Some(audios.iter().map(stream).collect())
+                .filter(|audio: &Vec<_>| !audio.is_empty()),
+            dolby: Some(Dolby {
+                r#type: u32::from(!dolby.is_empty()),
+                audio: Some(dolby.iter().map(stream).collect())
+                    .filter(|audio: &Vec<_>| !audio.is_empty()),
+                ..Dolby::default()
+            }),
+            flac: flac.as_ref().map(|object| Flac {
+                display: true,
+                audio: Some(stream(object)),
+                ..Flac::default()
+            }),
+            ..Dash::default()
+        }),
+        support_formats,
+        ..Playurl::default()
+    };
+
+    Some(ApiResponse {
+        code: 0,
+        message: "0".to_owned(),
+        ttl: 1,
+        data: Some(data),
+        ..ApiResponse::default()
+    })
+}
+
+/// The quality of `accept_quality`, the best first, to give for `qn`: `qn`
+/// itself if stored, or else the best one below it, or the lowest one if
+/// none. `None` if none stored.
+fn select(accept_quality: &[u32], qn: u32) -> Option<u32> {
+    accept_quality
+        .iter()
+        .copied()
+        .find(|&quality| quality <= qn)
+        .or_else(|| accept_quality.last().copied())
+}
+
+/// Probe the local objects of `cid`, those failing skipped.
+async fn probe(cache: &Cache, cid: u64) -> Vec<Object> {
+    let mut objects = Vec::new();
+
+    for key in cache.keys(&format!("{PREFIX}/{cid}/")) {
+        let Some(id) = key
+            .rsplit('/')
+            .next()
+            .and_then(|name| name.split('.').next())
+            .and_then(|id| id.parse().ok())
+        else {
+            continue;
+        };
+
+        let Some(cached) = cache.get(&key).filter(|cached| !cached.is_expired()) else {
+            continue;
+        };
+
+        let track = match File::open(&cached.path).await {
+            Ok(mut file) => mp4::probe(&mut file).await,
+            Err(e) => Err(e),
+        };
+
+        match track {
+            Ok(track) => objects.push(Object { id, key, track }),
+            Err(e) => tracing::warn!("Probe {key:?} error: {e}"),
+        }
+    }
+
+    objects
+}
+
+/// [`Stream`] of `object`, backed up by the URLs under `backup_bases`.
+fn stream(object: &Object, base: &str, backup_bases: &[String], token: Option<&str>) -> Stream {
+    let url = super::resource_url(base, &format!("/{}", object.key), token);
+    let backup_urls = super::backup_urls(&url, base, backup_bases);
+    let (width, height) = object.track.resolution.unwrap_or_default();
+    let (initialization, index_range) = (
+        format!(
+            "{}-{}",
+            object.track.init_range.0, object.track.init_range.1
+        ),
+        format!(
+            "{}-{}",
+            object.track.index_range.0, object.track.index_range.1
+        ),
+    );
+
+    Stream {
+        id: object.id,
+        base_url_camel: Some(url.clone()),
+        base_url: url,
+        backup_url_camel: Some(backup_urls.clone()),
+        backup_url: Some(backup_urls),
+        bandwidth: object.track.bandwidth(),
+        mime_type_camel: Some(object.track.mime_type().to_owned()),
+        mime_type: object.track.mime_type().to_owned(),
+        codecs: object.track.codecs.clone(),
+        width,
+        height,
+        start_with_sap_camel: Some(1),
+        start_with_sap: 1,
+        segment_base_camel: Some(SegmentBaseCamel {
+            initialization: initialization.clone(),
+            index_range: index_range.clone(),
+        }),
+        segment_base: Some(SegmentBase {
+            initialization,
+            index_range,
+        }),
+        codecid: codecid(&object.track.codecs),
+        ..Stream::default()
+    }
+}
+
+/// Entry of [`QUALITIES`] of `quality`, empty descriptions if unknown.
+fn describe(quality: u32) -> (u32, &'static str, &'static str, &'static str, &'static str) {
+    QUALITIES
+        .iter()
+        .copied()
+        .find(|&(qn, ..)| qn == quality)
+        .unwrap_or((quality, "", "", "", ""))
+}
+
+/// Codec ID of the API of the RFC 6381 `codecs`, 7 for AVC, 12 for HEVC and
+/// 13 for AV1, or 0 if none of them.
+fn codecid(codecs: &str) -> u32 {
+    match codecs.split('.').next() {
+        Some("avc1" | "avc3") => 7,
+        Some("hev1" | "hvc1") => 12,
+        Some("av01") => CODECID_AV1,
+        _ => 0,
+    }
+}
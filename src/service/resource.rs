@@ -0,0 +1,408 @@
+//! Resource route, i.e. `/resource/mikufans/{key}`.
+
+mod follow;
+#[cfg(feature = "upstream")]
+mod prefetch;
+#[cfg(feature = "upstream")]
+mod proxy;
+#[cfg(feature = "upstream")]
+mod stale;
+#[cfg(feature = "upstream")]
+mod validate;
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use bytes::Bytes;
+#[cfg(all(feature = "admin", feature = "playurl"))]
+use http::HeaderMap;
+#[cfg(feature = "upstream")]
+use http::Method;
+use http::{
+    HeaderValue, StatusCode,
+    header::{
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+        ACCESS_CONTROL_MAX_AGE, CONTENT_TYPE,
+    },
+};
+use tokio::fs::File;
+
+#[cfg(feature = "playurl")]
+pub(crate) use self::validate::{Expected, expect};
+#[cfg(feature = "sessions")]
+use crate::session;
+#[cfg(feature = "signing")]
+use crate::sign;
+use crate::{
+    cache::{Cache, CachedObject, FillingObject, Metadata, PartialObject},
+    config,
+    error::Error,
+    hotlink,
+    metrics::{self, CacheLookup},
+    proto,
+    timing::{self, Phase},
+    transfer,
+};
+
+/// Path prefix of the route
+pub(crate) const PREFIX: &str = "/resource/mikufans";
+
+/// Serve the resource of `key` (the request path with [`PREFIX`] stripped)
+/// from the cache, falling back to the upstream CDN if configured, or the
+/// configured file.
+///
+/// A range request is also served from the partial object of `key` when the
+/// requested range is all present. An object being fetched into the cache is
+/// served as bytes arrive, see [`follow`].
+///
+/// A cached object past its freshness is served while refreshed in
+/// background if configured, see [`stale`].
+///
+/// URLs not signed are rejected with `403 Forbidden` if configured, see
+/// [`sign`], and so are requests of pages of other sites, see [`hotlink`],
+/// and those without a live session, see [`session`],
+/// or `429 Too Many Requests` beyond the concurrency limit of the session.
+///
+/// The following segments are prefetched from upstream if configured, see
+/// [`prefetch`].
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn handle(
+    request: &proto::Request,
+    key: &str,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let mut response = proto::Response::default();
+
+    // Resource HEADERS
+    {
+        let headers = response.headers_mut();
+
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("https://www.bilibili.com"),
+        );
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("GET, POST, PUT, DELETE, HEAD"),
+        );
+        headers.insert(
+            ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static("Content-Length,Content-Range"),
+        );
+        headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from_static("0"));
+
+        // By the extension unless told by the metadata or upstream, e.g. for
+        // the FLV segments of legacy clients asking for `fnval=0`
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(super::static_files::content_type(Path::new(key))),
+        );
+    }
+
+    let config = config::Config::current();
+
+    if let Err(status) = admit(request, key, &config.resource) {
+        return super::write_status(status, tcp_stream).await;
+    }
+
+    // Held until the response is sent
+    #[cfg(feature = "sessions")]
+    let session = match acquire(request, key, &config.resource, tcp_stream) {
+        Ok(session) => session,
+        Err(status) => return super::write_status(status, tcp_stream).await,
+    };
+
+    let (result, sent) =
+        transfer::counted(serve(request, key, response, &config, tcp_stream)).await;
+
+    #[cfg(feature = "sessions")]
+    if let Some(session) = &session {
+        session.record(sent);
+    }
+
+    #[cfg(not(feature = "sessions"))]
+    let _ = sent;
+
+    result
+}
+
+/// URL of the resource of `path_and_query`, like `/{key}?{query}`, on the
+/// route, carrying the session `token` if any and signed if configured, see
+/// [`session`] and [`sign`].
+pub(crate) fn url(path_and_query: &str, token: Option<&str>) -> String {
+    let url = format!("{PREFIX}{path_and_query}");
+
+    #[cfg(feature = "sessions")]
+    let url = session::attach(url, token);
+
+    #[cfg(not(feature = "sessions"))]
+    let _ = token;
+
+    #[cfg(feature = "signing")]
+    let url = sign::sign(url);
+
+    url
+}
+
+/// Mint a token of a new session playing `video` if configured, see
+/// [`session::mint`]. None if built without the `sessions` feature.
+pub(crate) fn mint_session(video: impl Into<String>) -> Option<String> {
+    #[cfg(feature = "sessions")]
+    return session::mint(video);
+
+    #[cfg(not(feature = "sessions"))]
+    {
+        let _ = video.into();
+        None
+    }
+}
+
+/// Serve the resource of `key` with `response`, see [`handle`].
+async fn serve(
+    request: &proto::Request,
+    key: &str,
+    mut response: proto::Response,
+    config: &config::Config,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let cache_key = key.trim_start_matches('/');
+
+    #[cfg(feature = "upstream")]
+    if let (Some(cache), Some(prefetch_config)) = (
+        Cache::global(),
+        config
+            .upstream
+            .as_ref()
+            .and_then(|upstream| upstream.prefetch.as_ref()),
+    ) {
+        if request.method == Method::GET {
+            prefetch::spawn(request, cache_key, cache, prefetch_config);
+        }
+    }
+
+    let stale_window = config
+        .upstream
+        .as_ref()
+        .map_or(0, |upstream| upstream.stale_while_revalidate);
+
+    // Whether a stored object of the cache, see [`super::ServeOptions::stored`]
+    let (file, stored) = match lookup(cache_key, Duration::from_secs(stale_window)).await {
+        Some((cache, cached, metadata)) => {
+            if cached.is_expired() {
+                tracing::debug!("Cache hit, stale: {key:?}");
+                metrics::cache_lookup(CacheLookup::Stale);
+
+                #[cfg(feature = "upstream")]
+                stale::spawn(request, cache_key, cache);
+            } else {
+                tracing::debug!("Cache hit: {key:?}");
+                metrics::cache_lookup(CacheLookup::Hit);
+            }
+
+            if let Some(metadata) = metadata {
+                metadata.apply(response.headers_mut());
+            }
+
+            if let Some(hot) = cache.get_hot(&cached).await.filter(|hot| {
+                hot.is_complete()
+                    || matches!(
+                        super::requested_range(request, hot.size),
+                        Ok(Some(range)) if hot.holds(range)
+                    )
+            }) {
+                return super::serve_memory(
+                    request,
+                    response,
+                    Bytes::from_owner(hot.head.clone()),
+                    hot.size,
+                    config.resource.throttle,
+                    tcp_stream,
+                )
+                .await;
+            }
+
+            (
+                timing::timed(Phase::Open, File::open(&cached.path)).await?,
+                true,
+            )
+        }
+        None => match partial_hit(request, cache_key) {
+            Some(partial) => {
+                tracing::debug!("Partial cache hit: {key:?}");
+                metrics::cache_lookup(CacheLookup::Partial);
+
+                (
+                    timing::timed(Phase::Open, File::open(&partial.path)).await?,
+                    false,
+                )
+            }
+            None => {
+                if let Some((filling, file)) = filling(cache_key).await {
+                    tracing::debug!("Follow object being cached: {key:?}");
+                    metrics::cache_lookup(CacheLookup::Filling);
+
+                    return follow::handle(
+                        request,
+                        response,
+                        filling,
+                        file,
+                        config.resource.throttle,
+                        tcp_stream,
+                    )
+                    .await;
+                }
+
+                metrics::cache_lookup(CacheLookup::Miss);
+
+                #[cfg(feature = "upstream")]
+                if let Some(upstream) = &config.upstream {
+                    return proxy::handle(request, response, cache_key, upstream, tcp_stream).await;
+                }
+
+                (
+                    timing::timed(Phase::Open, File::open(&config.resource.file)).await?,
+                    false,
+                )
+            }
+        },
+    };
+
+    let options = super::ServeOptions {
+        mmap_threshold: config.resource.mmap_threshold,
+        stored,
+        throttle: config.resource.throttle,
+    };
+
+    super::serve_file(request, response, file, options, tcp_stream).await
+}
+
+/// Check the origin and the signature of `request` if configured, see
+/// [`hotlink`] and [`sign`], or the status to reject it with.
+fn admit(
+    request: &proto::Request,
+    key: &str,
+    config: &config::ResourceConfig,
+) -> Result<(), StatusCode> {
+    if let Some(hotlink) = &config.hotlink {
+        if let Err(e) = hotlink::check(hotlink, request) {
+            tracing::debug!("Reject {key:?}: {e}");
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+
+    #[cfg(feature = "signing")]
+    if let Some(signing) = &config.signing {
+        if let Err(e) = sign::verify(signing, request) {
+            tracing::debug!("Reject {key:?}: {e}");
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "sessions")]
+/// Take a slot of the session of `request` if configured, see [`session`], or
+/// the status to reject it with.
+fn acquire(
+    request: &proto::Request,
+    key: &str,
+    config: &config::ResourceConfig,
+    tcp_stream: &impl proto::Stream,
+) -> Result<Option<session::Guard>, StatusCode> {
+    let Some(sessions) = &config.sessions else {
+        return Ok(None);
+    };
+
+    let client = tcp_stream.peer_addr().ok().map(|peer_addr| peer_addr.ip());
+
+    session::acquire(sessions, request, client)
+        .map(Some)
+        .map_err(|e| {
+            tracing::debug!("Reject {key:?}: {e}");
+
+            match e {
+                session::Error::TooMany => StatusCode::TOO_MANY_REQUESTS,
+                session::Error::Missing | session::Error::Unknown => StatusCode::FORBIDDEN,
+            }
+        })
+}
+
+#[cfg(all(feature = "admin", feature = "playurl"))]
+/// Fetch the whole object of `key` from upstream into the cache as a
+/// background fetch, unless cached already, e.g. to warm up the cache.
+///
+/// Returns the size of the cached object, `None` if not cached.
+pub(crate) async fn fetch_into_cache(key: &str, path_and_query: &str) -> Option<u64> {
+    let cache = Cache::global()?;
+    let cached = || {
+        cache
+            .get(key)
+            .filter(|cached| !cached.is_expired())
+            .map(|cached| cached.size)
+    };
+
+    if let Some(size) = cached() {
+        return Some(size);
+    }
+
+    let config = config::Config::current();
+
+    proxy::fetch_into_cache(
+        cache,
+        key.to_owned(),
+        path_and_query,
+        &HeaderMap::new(),
+        config.upstream.as_ref()?,
+    )
+    .await;
+
+    cached()
+}
+
+/// Look up the cached object of `key` along with its metadata, skipping
+/// expired ones, either by the index or the sidecar. One expired by the index
+/// within `stale_window` is still returned, to be served stale.
+async fn lookup(
+    key: &str,
+    stale_window: Duration,
+) -> Option<(&'static Cache, CachedObject, Option<Arc<Metadata>>)> {
+    let cache = Cache::global()?;
+    let cached = cache.get(key)?;
+
+    let metadata = cache.metadata(&cached).await;
+
+    if cached.is_expired_for(stale_window)
+        || metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.is_expired())
+    {
+        tracing::debug!("Cached object of {key:?} has expired");
+        return None;
+    }
+
+    Some((cache, cached, metadata))
+}
+
+/// Find the partial object of `key` having all of the requested range.
+fn partial_hit(request: &proto::Request, key: &str) -> Option<PartialObject> {
+    Cache::global()
+        .and_then(|cache| cache.get_partial(key))
+        .filter(|partial| {
+            matches!(
+                super::requested_range(request, partial.size),
+                Ok(Some((start, end))) if partial.ranges.covers(start..end + 1)
+            )
+        })
+}
+
+/// Find the object of `key` being written into the cache, opened.
+async fn filling(key: &str) -> Option<(FillingObject, File)> {
+    let filling = Cache::global()?.filling(key)?;
+
+    // Gone if just committed or given up
+    let file = File::open(&filling.path).await.ok()?;
+
+    Some((filling, file))
+}
@@ -0,0 +1,134 @@
+//! Serving objects being written into the cache as bytes arrive, see
+//! [`FillingObject`].
+
+use std::io;
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures_util::stream;
+use http::Method;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+use crate::{
+    buf::Buf,
+    cache::FillingObject,
+    config::ThrottleConfig,
+    error::Error,
+    proto::{self, Body},
+    service,
+    timing::{self, Phase},
+};
+
+/// Serve the object being written into `file`, following it till the
+/// requested range has been written, honoring the `Range` request header, see
+/// [`Body::Stream`].
+///
+/// Returns whether the connection can be kept alive.
+pub(super) async fn handle(
+    request: &proto::Request,
+    mut response: proto::Response,
+    filling: FillingObject,
+    mut file: File,
+    throttle: Option<ThrottleConfig>,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    if let Some(metadata) = &filling.metadata {
+        metadata.apply(response.headers_mut());
+    }
+
+    let range = service::requested_range(request, filling.size)?;
+    let body_length = service::set_content_headers(&mut response, range, filling.size)?;
+
+    if request.method == Method::GET {
+        let start = range.map_or(0, |(start, _)| start);
+
+        if start > 0 {
+            timing::timed(Phase::Open, file.seek(io::SeekFrom::Start(start))).await?;
+        }
+
+        let follow = Follow {
+            filling,
+            file,
+            offset: start,
+            end: start + body_length,
+        };
+
+        response.set_body(Body::Stream(Box::pin(stream::unfold(
+            follow,
+            |mut follow| async move { follow.next().await.map(|data| (data, follow)) },
+        ))));
+    }
+
+    if let Err(e) = response
+        .with_throttle(throttle)
+        .write_to_stream(tcp_stream)
+        .await
+    {
+        tracing::error!("Write followed response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// The requested range of an object being cached, read as written.
+struct Follow {
+    filling: FillingObject,
+    file: File,
+
+    /// Where the next piece starts
+    offset: u64,
+
+    /// Where the range ends, exclusive
+    end: u64,
+}
+
+impl Follow {
+    /// Read the next piece once written, `None` at the end of the range or
+    /// after failed.
+    async fn next(&mut self) -> Option<io::Result<Bytes>> {
+        if self.offset >= self.end {
+            return None;
+        }
+
+        let result = self.read().await;
+
+        match &result {
+            Ok(data) => self.offset += data.len() as u64,
+            Err(_) => self.offset = self.end,
+        }
+
+        Some(result)
+    }
+
+    /// Read the piece from [`offset`](Self::offset), waiting for it to be
+    /// written.
+    async fn read(&mut self) -> io::Result<Bytes> {
+        // Waiting for the object to be fetched
+        let available = timing::timed(Phase::Upstream, self.filling.available(self.offset))
+            .await?
+            .min(self.end);
+
+        let mut data = Buf::chunk();
+
+        let want = data
+            .len()
+            .min(usize::try_from(available - self.offset).unwrap_or(usize::MAX));
+        data.truncate(want);
+
+        match self.file.read(&mut data).await? {
+            0 => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Object being cached truncated",
+            )),
+            read => {
+                data.truncate(read);
+
+                Ok(data.freeze())
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Prefetching the segments following the requested one from upstream into
+//! the cache, see [`PrefetchConfig`].
+//!
+//! Two kinds of segments are recognized:
+//!
+//! - Byte ranges of the same object, as DASH players request `.m4s` files of
+//!   bilibili. The following ranges of the same length as the requested one are
+//!   prefetched into the partial object.
+//! - Objects numbered in the key, like `video/seg-0005.m4s`. The objects of the
+//!   last number in the file name incremented are prefetched.
+
+use std::{
+    collections::HashSet,
+    sync::{LazyLock, Mutex},
+};
+
+use http::{HeaderMap, HeaderValue, header::RANGE};
+use http_range_header::{EndPosition, ParsedRanges, StartPosition, SyntacticallyCorrectRange};
+
+use crate::{
+    cache::Cache,
+    config::{Config, PrefetchConfig},
+    proto,
+};
+
+/// Keys being prefetched, one prefetch per key at a time.
+static IN_FLIGHT: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Prefetch the segments following the one requested of `key` in
+/// background, unless present or being prefetched already.
+pub(super) fn spawn(
+    request: &proto::Request,
+    key: &str,
+    cache: &'static Cache,
+    config: &PrefetchConfig,
+) {
+    match requested_range(request) {
+        Some((start, end)) => {
+            let length = end - start + 1;
+            let start = end + 1;
+            let mut end = end + length * u64::from(config.segments);
+
+            if cache.get(key).is_some_and(|cached| !cached.is_expired()) {
+                return;
+            }
+
+            if let Some(partial) = cache.get_partial(key) {
+                if start >= partial.size {
+                    return;
+                }
+
+                end = end.min(partial.size - 1);
+
+                if partial.ranges.covers(start..end + 1) {
+                    return;
+                }
+            }
+
+            let Ok(range) = HeaderValue::try_from(format!("bytes={start}-{end}")) else {
+                return;
+            };
+
+            let mut headers = HeaderMap::new();
+            headers.insert(RANGE, range);
+
+            start_prefetch(
+                key.to_owned(),
+                super::proxy::path_and_query(request, key),
+                headers,
+                cache,
+                config,
+            );
+        }
+        None => {
+            for next in 1..=config.segments {
+                let Some(next_key) = numbered_key(key, next) else {
+                    return;
+                };
+
+                if cache
+                    .get(&next_key)
+                    .is_some_and(|cached| !cached.is_expired())
+                {
+                    continue;
+                }
+
+                start_prefetch(
+                    next_key.clone(),
+                    super::proxy::path_and_query(request, &next_key),
+                    HeaderMap::new(),
+                    cache,
+                    config,
+                );
+            }
+        }
+    }
+}
+
+/// Spawn a prefetch of `key`, if not in progress and within
+/// [`PrefetchConfig::concurrency`].
+fn start_prefetch(
+    key: String,
+    path_and_query: String,
+    headers: HeaderMap,
+    cache: &'static Cache,
+    config: &PrefetchConfig,
+) {
+    {
+        let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+
+        if in_flight.len() >= config.concurrency || !in_flight.insert(key.clone()) {
+            return;
+        }
+    }
+
+    tokio::spawn(async move {
+        tracing::debug!("Prefetch {key:?} {:?}", headers.get(RANGE));
+
+        if let Some(upstream_config) = &Config::current().upstream {
+            super::proxy::fetch_into_cache(
+                cache,
+                key.clone(),
+                &path_and_query,
+                &headers,
+                upstream_config,
+            )
+            .await;
+        }
+
+        IN_FLIGHT
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&key);
+    });
+}
+
+/// The single requested range with both ends given, i.e. `bytes={start}-{end}`.
+fn requested_range(request: &proto::Request) -> Option<(u64, u64)> {
+    let ParsedRanges { ranges } = request
+        .headers
+        .get(RANGE)
+        .and_then(|range| range.to_str().ok())
+        .and_then(|range| http_range_header::parse_range_header(range).ok())?;
+
+    match ranges[..] {
+        [
+            SyntacticallyCorrectRange {
+                start: StartPosition::Index(start),
+                end: EndPosition::Index(end),
+            },
+        ] if start <= end => Some((start, end)),
+        _ => None,
+    }
+}
+
+/// The key with the last number in the file name, extension excluded,
+/// increased by `next`, keeping the zero padding, like `video/seg-0006.m4s`
+/// of `video/seg-0005.m4s`.
+fn numbered_key(key: &str, next: u32) -> Option<String> {
+    let file_name_start = key.rfind('/').map_or(0, |slash| slash + 1);
+    let stem_end = key[file_name_start..]
+        .rfind('.')
+        .map_or(key.len(), |dot| file_name_start + dot);
+
+    let digits_end =
+        file_name_start + key[file_name_start..stem_end].rfind(|c: char| c.is_ascii_digit())? + 1;
+    let digits_start = key[..digits_end]
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |non_digit| non_digit + 1)
+        .max(file_name_start);
+
+    let digits = &key[digits_start..digits_end];
+    let number = digits.parse::<u64>().ok()?.checked_add(u64::from(next))?;
+
+    Some(format!(
+        "{}{number:0width$}{}",
+        &key[..digits_start],
+        &key[digits_end..],
+        width = digits.len()
+    ))
+}
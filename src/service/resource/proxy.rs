@@ -0,0 +1,574 @@
+//! Proxying resources not available locally from the upstream CDN, see
+//! [`upstream`](crate::upstream).
+
+use std::{io, time::SystemTime};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures_util::stream;
+use http::{
+    HeaderMap, HeaderName, Method, StatusCode,
+    header::{
+        ACCEPT_RANGES, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, EXPIRES,
+        LAST_MODIFIED,
+    },
+};
+
+use super::validate::Validator;
+#[cfg(feature = "sessions")]
+use crate::session;
+#[cfg(feature = "signing")]
+use crate::sign;
+use crate::{
+    buf::{Buf, Size},
+    cache::{Cache, CacheWriter, Metadata},
+    config::{Config, UpstreamConfig},
+    error::Error,
+    proto::{self, Body},
+    timing::{self, Phase},
+    upstream,
+};
+
+/// Response headers passed to the client as is.
+const FORWARDED_RESPONSE_HEADERS: [HeaderName; 8] = [
+    CONTENT_TYPE,
+    CONTENT_LENGTH,
+    CONTENT_RANGE,
+    ACCEPT_RANGES,
+    ETAG,
+    LAST_MODIFIED,
+    CACHE_CONTROL,
+    EXPIRES,
+];
+
+/// Query params of this server, not forwarded upstream: those of signing,
+/// see [`sign`], and the session token, see [`session`], of the features built.
+const LOCAL_PARAMS: &[&str] = &[
+    #[cfg(feature = "signing")]
+    sign::KEY_ID,
+    #[cfg(feature = "signing")]
+    sign::DEADLINE,
+    #[cfg(feature = "signing")]
+    sign::SIGN,
+    #[cfg(feature = "sessions")]
+    session::PARAM,
+];
+
+/// Fetch the resource of `key` from upstream, with the query and
+/// [`UpstreamConfig::forward_headers`] of the request kept, and stream the
+/// response back.
+///
+/// Requests other than `GET` are forwarded as `HEAD`. Responds
+/// `502 Bad Gateway` when upstream is not reachable.
+///
+/// The response body is also written into the cache while streamed, see
+/// [`tee`], and [`UpstreamConfig::complete_in_background`] for when the
+/// client disconnects midway.
+///
+/// An object declared in a playurl response is validated against it, see
+/// [`Validator`]. Responds `502 Bad Gateway` if upstream tells a different
+/// size, or aborts the response before the final bytes if the body turns out
+/// invalid, never caching it.
+///
+/// Returns whether the connection can be kept alive.
+pub(super) async fn handle(
+    request: &proto::Request,
+    mut response: proto::Response,
+    key: &str,
+    config: &UpstreamConfig,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let method = if request.method == Method::GET {
+        Method::GET
+    } else {
+        Method::HEAD
+    };
+
+    let path_and_query = path_and_query(request, key);
+
+    let mut headers = HeaderMap::new();
+    for name in &config.forward_headers {
+        let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+            continue;
+        };
+
+        for value in request.headers.get_all(&name) {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+
+    let upstream_response = match upstream::fetch(
+        config,
+        &method,
+        &path_and_query,
+        &headers,
+        upstream::Priority::Interactive,
+    )
+    .await
+    {
+        Ok(upstream_response) => upstream_response,
+        Err(e) => {
+            return Err(Error::Upstream(
+                e.context(format!("Fetch {key:?} from upstream")),
+            ));
+        }
+    };
+
+    tracing::debug!("Upstream responded {key:?}: {}", upstream_response.status);
+
+    let validator = match validator(key, &upstream_response) {
+        Ok(validator) => validator,
+        Err(e) => {
+            return Err(Error::Upstream(
+                e.context(format!("Reject {key:?} from upstream")),
+            ));
+        }
+    };
+
+    response.set_status(upstream_response.status);
+    for name in FORWARDED_RESPONSE_HEADERS {
+        if let Some(value) = upstream_response.headers.get(&name) {
+            response.headers_mut().insert(name, value.clone());
+        }
+    }
+
+    if method == Method::GET {
+        let tee = match Cache::global() {
+            Some(cache) => tee(cache, key, &upstream_response, &path_and_query).await,
+            None => None,
+        };
+
+        let relay = Relay {
+            key: key.to_owned(),
+            upstream: Some((upstream_response, tee, validator)),
+            complete_in_background: config.complete_in_background,
+        };
+
+        // Chunked unless of `Content-Length` forwarded
+        response.set_body(Body::Stream(Box::pin(stream::unfold(
+            relay,
+            |mut relay| async move { relay.next().await.map(|data| (data, relay)) },
+        ))));
+    }
+
+    if let Err(e) = response
+        .with_throttle(Config::current().resource.throttle)
+        .write_to_stream(tcp_stream)
+        .await
+    {
+        tracing::error!("Write proxied response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// The body of `key` relayed from upstream to the client, also into the cache
+/// via the [`Tee`] if any.
+///
+/// Dropped before the end, i.e. the client gone midway, the rest is cached in
+/// background if [`UpstreamConfig::complete_in_background`], see
+/// [`complete`].
+struct Relay {
+    key: String,
+
+    /// The upstream response, where it goes in the cache, and its validator,
+    /// taken once ended
+    upstream: Option<(upstream::Response, Option<Tee>, Option<Validator>)>,
+
+    complete_in_background: bool,
+}
+
+impl Relay {
+    /// Relay the next piece, `None` at the end or after failed.
+    async fn next(&mut self) -> Option<io::Result<Bytes>> {
+        let key = &self.key;
+        let (upstream_response, tee, validator) = self.upstream.as_mut()?;
+
+        let data = match timing::timed(Phase::Upstream, upstream_response.next()).await {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                let (_, tee, mut validator) = self.upstream.take()?;
+
+                if let Some(Err(e)) = validator.as_mut().map(Validator::finish) {
+                    return Some(Err(io::Error::other(
+                        e.context(format!("Reject {key:?} from upstream")),
+                    )));
+                }
+
+                if let Some(tee) = tee {
+                    tee.finish(key).await;
+                }
+
+                return None;
+            }
+            Err(e) => {
+                let e = e.context(format!("Read {key:?} from upstream"));
+                self.upstream = None;
+
+                return Some(Err(io::Error::other(e)));
+            }
+        };
+
+        if let Some(Err(e)) = validator.as_mut().map(|validator| validator.update(data)) {
+            self.upstream = None;
+
+            return Some(Err(io::Error::other(
+                e.context(format!("Reject {key:?} from upstream")),
+            )));
+        }
+
+        if let Some(cache_tee) = tee {
+            if let Err(e) = cache_tee.write(data).await {
+                tracing::warn!("Stop caching {key:?}: {e}");
+                *tee = None;
+            }
+        }
+
+        let mut piece = Buf::take(Size::Chunk);
+        piece.extend_from_slice(data);
+
+        Some(Ok(piece.freeze()))
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        let Some((mut upstream_response, Some(tee), validator)) = self.upstream.take() else {
+            return;
+        };
+
+        if self.complete_in_background {
+            tracing::debug!("Complete caching {:?} in background", self.key);
+
+            upstream_response.set_priority(upstream::Priority::Background);
+
+            tokio::spawn(complete(
+                std::mem::take(&mut self.key),
+                upstream_response,
+                tee,
+                validator,
+            ));
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Where the proxied body goes in the cache.
+enum Tee {
+    /// A whole object, of a `200 OK` response
+    Object(Box<CacheWriter>),
+
+    /// A span of the partial object of `key`, of a `206 Partial Content`
+    /// response
+    Partial {
+        cache: &'static Cache,
+        key: String,
+
+        /// Size of the whole object
+        size: u64,
+
+        /// Where the rest of the span goes
+        offset: u64,
+    },
+}
+
+impl Tee {
+    /// Write the next piece of the body.
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Object(writer) => writer.write_all(data).await,
+            Self::Partial {
+                cache,
+                key,
+                size,
+                offset,
+            } => {
+                cache.fill_partial(key, *size, *offset, data).await?;
+                *offset += data.len() as u64;
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Finish once the whole body has been written.
+    async fn finish(self, key: &str) {
+        let Self::Object(writer) = self else {
+            // Already in place
+            return;
+        };
+
+        let length = writer.written();
+
+        match writer.commit().await {
+            Ok(()) => tracing::info!("Cached {key:?} from upstream, {length} bytes"),
+            Err(e) => tracing::error!("Commit {key:?} into cache error: {e:?}"),
+        }
+    }
+}
+
+/// Fetch the object of `key` from upstream into the cache without a client,
+/// e.g. when prefetching.
+pub(super) async fn fetch_into_cache(
+    cache: &'static Cache,
+    key: String,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    config: &UpstreamConfig,
+) {
+    let upstream_response = match upstream::fetch(
+        config,
+        &Method::GET,
+        path_and_query,
+        headers,
+        upstream::Priority::Background,
+    )
+    .await
+    {
+        Ok(upstream_response) => upstream_response,
+        Err(e) => {
+            tracing::warn!("Fetch {key:?} from upstream error: {e:#}");
+            return;
+        }
+    };
+
+    let validator = match validator(&key, &upstream_response) {
+        Ok(validator) => validator,
+        Err(e) => {
+            tracing::warn!("Reject {key:?} from upstream: {e:#}");
+            return;
+        }
+    };
+
+    match tee(cache, &key, &upstream_response, path_and_query).await {
+        Some(tee) => complete(key, upstream_response, tee, validator).await,
+        None => tracing::debug!(
+            "Upstream responded {key:?}: {}, not cached",
+            upstream_response.status
+        ),
+    }
+}
+
+/// Upstream path and query of the object of `key`, with the query of the
+/// request kept but the params of this server, see [`LOCAL_PARAMS`].
+pub(super) fn path_and_query(request: &proto::Request, key: &str) -> String {
+    let query = request.request_uri.query().map(|query| {
+        query
+            .as_str()
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+                !LOCAL_PARAMS.contains(&name)
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    });
+
+    match query {
+        Some(query) if !query.is_empty() => format!("/{key}?{query}"),
+        _ => format!("/{key}"),
+    }
+}
+
+/// Decide where the proxied body of `key` goes in the cache, by the response
+/// status:
+///
+/// - `200 OK` of known length: a new object, keeping `Content-Type` and where
+///   it's fetched from, readable while written, see [`CacheWriter::share`]. It
+///   expires according to the response and the URL, see [`expires`].
+/// - `206 Partial Content`: the partial object, stitched into the spans fetched
+///   before, see [`Cache::fill_partial`].
+///
+/// Returns `None` when it cannot be cached, e.g. out of space or
+/// `Cache-Control: no-store`.
+async fn tee(
+    cache: &'static Cache,
+    key: &str,
+    upstream_response: &upstream::Response,
+    path_and_query: &str,
+) -> Option<Tee> {
+    if cache_directives(&upstream_response.headers).any(|directive| directive == "no-store") {
+        return None;
+    }
+
+    match upstream_response.status {
+        StatusCode::OK => {
+            let length = upstream_response.content_length()?;
+
+            let mut writer = cache
+                .writer(key)
+                .await
+                .and_then(|mut writer| writer.preallocate(length).map(|()| writer))
+                .inspect_err(|e| tracing::warn!("Not caching {key:?}: {e}"))
+                .ok()?;
+
+            writer.set_metadata(Metadata {
+                content_type: upstream_response
+                    .headers
+                    .get(CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_owned),
+                url: Some(upstream_response.url(path_and_query)),
+                ..Metadata::default()
+            });
+
+            if let Some(expires) = expires(&upstream_response.headers, path_and_query) {
+                writer.set_expires(expires);
+            }
+
+            writer.share(length);
+
+            Some(Tee::Object(Box::new(writer)))
+        }
+        StatusCode::PARTIAL_CONTENT => {
+            let (offset, size) = upstream_response
+                .headers
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_content_range)?;
+
+            Some(Tee::Partial {
+                cache,
+                key: key.to_owned(),
+                size,
+                offset,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// When an object fetched of `path_and_query` is no longer fresh, in seconds
+/// since UNIX epoch: the earlier of the `deadline` query parameter, till
+/// when a signed upos URL is valid, and `s-maxage` or `max-age` of
+/// `Cache-Control`.
+fn expires(headers: &HeaderMap, path_and_query: &str) -> Option<u64> {
+    let deadline = path_and_query
+        .split_once('?')
+        .and_then(|(_, query)| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("deadline="))
+        })
+        .and_then(|deadline| deadline.parse::<u64>().ok());
+
+    let max_age = {
+        let mut max_age = None;
+
+        for directive in cache_directives(headers) {
+            if let Some(seconds) = directive.strip_prefix("s-maxage=") {
+                // Takes precedence for shared caches
+                max_age = seconds.parse::<u64>().ok();
+                break;
+            }
+
+            if let Some(seconds) = directive.strip_prefix("max-age=") {
+                max_age = seconds.parse::<u64>().ok();
+            }
+        }
+
+        max_age.map(|max_age| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs())
+                .saturating_add(max_age)
+        })
+    };
+
+    match (deadline, max_age) {
+        (Some(deadline), Some(max_age)) => Some(deadline.min(max_age)),
+        (deadline, max_age) => deadline.or(max_age),
+    }
+}
+
+/// Directives of `Cache-Control`, lowercase.
+fn cache_directives(headers: &HeaderMap) -> impl Iterator<Item = String> {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+}
+
+/// Validator of the proxied object of `key` if declared in a playurl
+/// response, see [`Validator`]. Fails if the size of the whole object upstream
+/// tells differs already.
+///
+/// Only a whole object is validated as streamed, the body of a
+/// `206 Partial Content` response is not.
+fn validator(key: &str, upstream_response: &upstream::Response) -> Result<Option<Validator>> {
+    let Some(validator) = Validator::new(key) else {
+        return Ok(None);
+    };
+
+    match upstream_response.status {
+        StatusCode::OK => {
+            if let Some(length) = upstream_response.content_length() {
+                validator.check_size(length)?;
+            }
+
+            Ok(Some(validator))
+        }
+        StatusCode::PARTIAL_CONTENT => {
+            if let Some((_, size)) = upstream_response
+                .headers
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_content_range)
+            {
+                validator.check_size(size)?;
+            }
+
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Parse `Content-Range: bytes {start}-{end}/{size}`, returning the start
+/// and the size. `None` when the size is unknown.
+fn parse_content_range(content_range: &str) -> Option<(u64, u64)> {
+    let (range, size) = content_range.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+
+    Some((start.trim().parse().ok()?, size.trim().parse().ok()?))
+}
+
+/// Read the rest of the body into `tee` and finish it if valid, without a
+/// client, e.g. after the client has gone.
+async fn complete(
+    key: String,
+    mut upstream_response: upstream::Response,
+    mut tee: Tee,
+    mut validator: Option<Validator>,
+) {
+    loop {
+        let data = match upstream_response.next().await {
+            Ok(Some(data)) => data,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("Read {key:?} from upstream error: {e:?}");
+                return;
+            }
+        };
+
+        if let Some(Err(e)) = validator.as_mut().map(|validator| validator.update(data)) {
+            tracing::warn!("Reject {key:?} from upstream: {e:#}");
+            return;
+        }
+
+        if let Err(e) = tee.write(data).await {
+            tracing::warn!("Stop caching {key:?}: {e}");
+            return;
+        }
+    }
+
+    if let Some(Err(e)) = validator.as_mut().map(Validator::finish) {
+        tracing::warn!("Reject {key:?} from upstream: {e:#}");
+        return;
+    }
+
+    tee.finish(&key).await;
+}
@@ -0,0 +1,51 @@
+//! Serving cached objects past their freshness while refreshing them from
+//! upstream in background, see [`UpstreamConfig::stale_while_revalidate`].
+//!
+//! [`UpstreamConfig::stale_while_revalidate`]: crate::config::UpstreamConfig::stale_while_revalidate
+
+use std::{
+    collections::HashSet,
+    sync::{LazyLock, Mutex},
+};
+
+use http::HeaderMap;
+
+use crate::{cache::Cache, config::Config, proto};
+
+/// Keys being refreshed, one refresh per key at a time.
+static REFRESHING: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(|| Mutex::new(HashSet::new()));
+
+/// Refresh the stale object of `key` from upstream in background, with the
+/// query of the request kept, unless being refreshed already.
+pub(super) fn spawn(request: &proto::Request, key: &str, cache: &'static Cache) {
+    if !REFRESHING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key.to_owned())
+    {
+        return;
+    }
+
+    let key = key.to_owned();
+    let path_and_query = super::proxy::path_and_query(request, &key);
+
+    tokio::spawn(async move {
+        tracing::debug!("Refresh stale {key:?}");
+
+        if let Some(upstream_config) = &Config::current().upstream {
+            super::proxy::fetch_into_cache(
+                cache,
+                key.clone(),
+                &path_and_query,
+                &HeaderMap::new(),
+                upstream_config,
+            )
+            .await;
+        }
+
+        REFRESHING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&key);
+    });
+}
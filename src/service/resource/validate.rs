@@ -0,0 +1,168 @@
+//! Validating proxied objects against what playurl responses declare,
+//! recorded by cache key, see [`expect`].
+//!
+//! Requires the `playurl` feature, nothing declared to validate against
+//! otherwise.
+
+#[cfg(feature = "playurl")]
+use std::{sync::LazyLock, time::Duration};
+
+use anyhow::Result;
+#[cfg(feature = "playurl")]
+use anyhow::bail;
+#[cfg(feature = "playurl")]
+use md5::{Digest, Md5};
+
+#[cfg(feature = "playurl")]
+/// Max number of segments recorded.
+const EXPECTED_SEGMENTS: u64 = 64 * 1024;
+
+#[cfg(feature = "playurl")]
+/// How long a recorded segment is kept, about the lifetime of playurls.
+const EXPECTED_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+#[cfg(feature = "playurl")]
+/// Recorded segments, by cache key.
+static EXPECTED: LazyLock<moka::sync::Cache<String, Expected>> = LazyLock::new(|| {
+    moka::sync::Cache::builder()
+        .max_capacity(EXPECTED_SEGMENTS)
+        .time_to_live(EXPECTED_TTL)
+        .build()
+});
+
+#[cfg(feature = "playurl")]
+#[derive(Debug, Clone, Default)]
+/// What a segment is declared to be in a playurl response.
+pub(crate) struct Expected {
+    /// Size in bytes
+    pub size: Option<u64>,
+
+    /// MD5 of the content in hex
+    pub md5: Option<String>,
+}
+
+#[cfg(feature = "playurl")]
+/// Record what the segment of `key` is declared to be, see
+/// [`playurl::expect_segments`](crate::playurl::expect_segments).
+pub(crate) fn expect(key: &str, expected: Expected) {
+    EXPECTED.insert(key.trim_start_matches('/').to_owned(), expected);
+}
+
+#[cfg(feature = "playurl")]
+/// What the segment of `key` is declared to be, if recorded.
+fn expected(key: &str) -> Option<Expected> {
+    EXPECTED.get(key.trim_start_matches('/'))
+}
+
+#[cfg(feature = "playurl")]
+#[derive(Debug)]
+/// Validates a proxied object as its body is streamed.
+pub(super) struct Validator {
+    expected: Expected,
+
+    /// Bytes received so far
+    received: u64,
+
+    /// Present when an MD5 is expected
+    md5: Option<Md5>,
+
+    /// Whether validated already, see [`Validator::finish`].
+    finished: bool,
+}
+
+#[cfg(feature = "playurl")]
+impl Validator {
+    /// A validator of the object of `key`, `None` if nothing is declared of
+    /// it.
+    pub(super) fn new(key: &str) -> Option<Self> {
+        let expected = expected(key)?;
+
+        Some(Self {
+            md5: expected.md5.is_some().then(Md5::new),
+            expected,
+            received: 0,
+            finished: false,
+        })
+    }
+
+    /// Check the size of the whole object, as upstream tells up front.
+    pub(super) fn check_size(&self, size: u64) -> Result<()> {
+        match self.expected.size {
+            Some(expected) if expected != size => {
+                bail!("Size {size} differs from {expected} declared")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Take the next piece of the body, validating the whole object once
+    /// the declared size has been received, i.e. before the final piece
+    /// goes anywhere.
+    pub(super) fn update(&mut self, data: &[u8]) -> Result<()> {
+        self.received += data.len() as u64;
+
+        if let Some(md5) = &mut self.md5 {
+            md5.update(data);
+        }
+
+        match self.expected.size {
+            Some(size) if self.received > size => bail!("Larger than {size} declared"),
+            Some(size) if self.received == size => self.finish(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validate the whole object once the body ends, unless validated
+    /// already.
+    pub(super) fn finish(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+
+        self.finished = true;
+
+        if let Some(size) = self.expected.size {
+            if self.received != size {
+                bail!("Truncated, {} of {size} bytes declared", self.received);
+            }
+        }
+
+        if let (Some(md5), Some(expected)) = (self.md5.take(), &self.expected.md5) {
+            let md5 = format!("{:x}", md5.finalize());
+
+            if !md5.eq_ignore_ascii_case(expected) {
+                bail!("MD5 {md5} differs from {expected} declared");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "playurl"))]
+#[derive(Debug)]
+/// Validates nothing, nothing declared without playurl resolved.
+pub(super) enum Validator {}
+
+#[cfg(not(feature = "playurl"))]
+impl Validator {
+    /// Never any, nothing declared.
+    pub(super) fn new(_key: &str) -> Option<Self> {
+        None
+    }
+
+    /// Unreachable, of none.
+    pub(super) fn check_size(&self, _size: u64) -> Result<()> {
+        match *self {}
+    }
+
+    /// Unreachable, of none.
+    pub(super) fn update(&mut self, _data: &[u8]) -> Result<()> {
+        match *self {}
+    }
+
+    /// Unreachable, of none.
+    pub(super) fn finish(&mut self) -> Result<()> {
+        match *self {}
+    }
+}
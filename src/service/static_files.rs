@@ -0,0 +1,173 @@
+//! Static directory serving.
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use fluent_uri::encoding::{EStr, encoder};
+use http::{HeaderValue, StatusCode, header::CONTENT_TYPE};
+use tokio::fs::File;
+
+use crate::{
+    config::{Config, StaticDirConfig},
+    error::Error,
+    layer::{Layer, layer_fn},
+    metrics, proto,
+    timing::{self, Phase},
+};
+
+/// Layer serving requests of the static dirs configured, see
+/// [`Config::static_dirs`], before any route.
+pub(crate) fn layer() -> impl Layer {
+    layer_fn(|request, params, stream, next| {
+        Box::pin(async move {
+            let config = Config::current();
+            let request_path = request.request_uri.path().as_str();
+
+            match config
+                .static_dirs
+                .iter()
+                .find_map(|static_dir| Some((static_dir, static_dir.strip_prefix(request_path)?)))
+            {
+                Some((static_dir, sub_path)) => {
+                    metrics::route("static");
+                    handle(request, static_dir, sub_path, stream).await
+                }
+                None => next.run(request, params, stream).await,
+            }
+        })
+    })
+}
+
+/// Serve `sub_path` (the request path with [`StaticDirConfig::prefix`]
+/// stripped) from the configured directory.
+///
+/// Returns whether the connection can be kept alive.
+pub(crate) async fn handle(
+    request: &proto::Request,
+    config: &StaticDirConfig,
+    sub_path: &str,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let status = match resolve(config, sub_path).await {
+        Ok(Some(path)) => {
+            let file = timing::timed(Phase::Open, File::open(&path)).await?;
+
+            let mut response = proto::Response::default();
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(content_type(&path)));
+
+            let options = super::ServeOptions {
+                mmap_threshold: config.mmap_threshold,
+                stored: false,
+                throttle: config.throttle,
+            };
+
+            return super::serve_file(request, response, file, options, tcp_stream).await;
+        }
+        Ok(None) => StatusCode::FORBIDDEN,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        Err(e) => return Err(e.into()),
+    };
+
+    tracing::debug!("Static file {sub_path:?} under {:?}: {status}", config.root);
+
+    super::write_status(status, tcp_stream).await
+}
+
+/// Map the request sub path to a file under [`StaticDirConfig::root`].
+///
+/// Returns `None` when the path is not allowed, i.e. is malformed or resolves
+/// outside the root.
+async fn resolve(config: &StaticDirConfig, sub_path: &str) -> io::Result<Option<PathBuf>> {
+    let Some(relative) = relative_path(sub_path) else {
+        return Ok(None);
+    };
+
+    let root = tokio::fs::canonicalize(&config.root).await?;
+    let expected = root.join(&relative);
+
+    let mut path = tokio::fs::canonicalize(&expected).await?;
+
+    // Since `relative` contains no `.` or `..`, the canonical path only differs
+    // from the expected one when a symlink has been followed.
+    if !path.starts_with(&root) || (!config.follow_symlinks && path != expected) {
+        return Ok(None);
+    }
+
+    if tokio::fs::metadata(&path).await?.is_dir() {
+        let index = path.join(&config.index);
+
+        path = tokio::fs::canonicalize(&index).await?;
+
+        if !path.starts_with(&root) || (!config.follow_symlinks && path != index) {
+            return Ok(None);
+        }
+
+        if tokio::fs::metadata(&path).await?.is_dir() {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(path))
+}
+
+/// Percent-decode the request sub path into a relative path, rejecting any
+/// segment that may escape the root.
+fn relative_path(sub_path: &str) -> Option<PathBuf> {
+    let sub_path = EStr::<encoder::Path>::new(sub_path)?;
+
+    let mut relative = PathBuf::new();
+
+    for segment in sub_path.split('/') {
+        let segment = segment.decode().into_string().ok()?;
+
+        match &*segment {
+            "" | "." => {}
+            ".." => return None,
+            _ if segment.contains(['/', '\\', '\0']) => return None,
+            _ => relative.push(&*segment),
+        }
+    }
+
+    Some(relative)
+}
+
+/// Guess `Content-Type` from the file extension.
+pub(crate) fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("mpd") => "application/dash+xml",
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("mp4" | "m4v" | "m4s") => "video/mp4",
+        Some("m4a") => "audio/mp4",
+        Some("flv") => "video/x-flv",
+        Some("webm") => "video/webm",
+        Some("woff2") => "font/woff2",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,114 @@
+//! Request paths mapped under the root only, those escaping it by any
+//! spelling, or by a symlink, refused.
+
+use std::path::{Path, PathBuf};
+
+use super::{relative_path, resolve};
+use crate::config::StaticDirConfig;
+
+#[test]
+/// Segments decoded, empty and `.` ones skipped.
+fn relative() {
+    assert_eq!(
+        relative_path("/a/./b//%63.txt"),
+        Some(PathBuf::from("a/b/c.txt"))
+    );
+    assert_eq!(relative_path("/"), Some(PathBuf::new()));
+}
+
+#[test]
+/// `..` refused, as is or encoded, and segments decoded into a separator or
+/// NUL.
+fn relative_escaping() {
+    for sub_path in [
+        "/../secret",
+        "/a/../../secret",
+        "/%2e%2e/secret",
+        "/%2E%2E/secret",
+        "/a%2f..%2f..%2fsecret",
+        "/..%5csecret",
+        "/a%5c..%5csecret",
+        "/secret%00.txt",
+    ] {
+        assert_eq!(relative_path(sub_path), None, "{sub_path}");
+    }
+}
+
+#[test]
+/// Absolute paths kept under the root.
+fn relative_absolute() {
+    let relative = relative_path("//etc/passwd").expect("Relative path");
+
+    assert!(relative.is_relative(), "{relative:?}");
+    assert_eq!(relative, PathBuf::from("etc/passwd"));
+}
+
+#[cfg(unix)]
+/// Create a root of `name` under the temporary directory, of `a.txt`, `sub/`
+/// with an index, `inner` linked to `a.txt` and `outer` to a file outside.
+fn tree(name: &str) -> (PathBuf, StaticDirConfig) {
+    let dir = std::env::temp_dir().join(format!("static-files-{name}-{}", std::process::id()));
+    let root = dir.join("root");
+
+    // Left by a run failed
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(root.join("sub")).expect("Root created");
+    std::fs::write(root.join("a.txt"), "a").expect("File written");
+    std::fs::write(root.join("sub/index.html"), "index").expect("File written");
+    std::fs::write(dir.join("secret.txt"), "secret").expect("File written");
+    std::os::unix::fs::symlink("a.txt", root.join("inner")).expect("Symlink created");
+    std::os::unix::fs::symlink("../secret.txt", root.join("outer")).expect("Symlink created");
+
+    let config = StaticDirConfig {
+        prefix: "/static".to_owned(),
+        root: root.clone(),
+        index: "index.html".to_owned(),
+        follow_symlinks: false,
+        mmap_threshold: None,
+        throttle: None,
+    };
+
+    (dir, config)
+}
+
+#[cfg(unix)]
+/// Resolve `sub_path`, as the path relative to the root if allowed.
+async fn resolved(config: &StaticDirConfig, sub_path: &str) -> Option<PathBuf> {
+    let root = std::fs::canonicalize(&config.root).expect("Root");
+
+    resolve(config, sub_path)
+        .await
+        .expect("Resolved")
+        .map(|path| {
+            path.strip_prefix(&root)
+                .map(Path::to_owned)
+                .expect("Under the root")
+        })
+}
+
+#[cfg(unix)]
+#[tokio::test]
+/// Files and directory indexes found, symlinks refused unless followed, and
+/// refused anyway when leading outside.
+async fn symlinks() {
+    let (dir, mut config) = tree("symlinks");
+
+    assert_eq!(resolved(&config, "/a.txt").await, Some("a.txt".into()));
+    assert_eq!(
+        resolved(&config, "/sub").await,
+        Some("sub/index.html".into())
+    );
+    assert_eq!(resolved(&config, "/inner").await, None);
+    assert_eq!(resolved(&config, "/outer").await, None);
+    assert_eq!(resolved(&config, "/%2e%2e/secret.txt").await, None);
+
+    let missing = resolve(&config, "/missing").await.expect_err("Not found");
+    assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+
+    config.follow_symlinks = true;
+
+    assert_eq!(resolved(&config, "/inner").await, Some("a.txt".into()));
+    assert_eq!(resolved(&config, "/outer").await, None);
+
+    std::fs::remove_dir_all(dir).expect("Removed");
+}
@@ -0,0 +1,213 @@
+//! Resource ingestion route, i.e. `PUT /resource/upload/{key}`.
+//!
+//! Lets external tools push pre-downloaded segments into the cache, which are
+//! then served under [`resource::PREFIX`](super::resource::PREFIX) by the same
+//! key. Authenticated like the admin API by API keys, see
+//! [`api_key`](crate::api_key), and audit logged.
+
+mod resumable;
+
+use std::io;
+
+use anyhow::Result;
+use http::{HeaderName, Method, StatusCode, header::CONTENT_TYPE};
+
+use crate::{
+    api_key,
+    cache::{Cache, CacheWriter, Metadata},
+    config::Config,
+    error::Error,
+    proto,
+};
+
+/// Path prefix of the route
+pub(crate) const PREFIX: &str = "/resource/upload";
+
+/// Header carrying where the uploaded object was fetched from.
+const X_ORIGINAL_URL: HeaderName = HeaderName::from_static("x-original-url");
+
+/// Store the request body, of `Content-Length` or chunked, as the object of
+/// `key` (the request path with [`PREFIX`] stripped), replacing any existing
+/// one. `POST` is accepted as well as `PUT`.
+///
+/// The `Content-Type` of the request, if any, is kept in the metadata sidecar
+/// of the object and responded with.
+///
+/// Responds `503 Service Unavailable` when the cache is out of space, and
+/// `413 Content Too Large` past [`AdminConfig::max_body_size`].
+///
+/// `HEAD` and `PATCH` are for resumable uploads, see [`resumable`].
+///
+/// Returns whether the connection can be kept alive.
+///
+/// [`AdminConfig::max_body_size`]: crate::config::AdminConfig::max_body_size
+pub(crate) async fn handle(
+    request: &proto::Request,
+    key: &str,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let config = Config::current();
+
+    let (Some(admin_config), Some(cache)) = (&config.admin, Cache::global()) else {
+        return reject(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    if super::body_too_large(request, admin_config.max_body_size) {
+        tracing::warn!("Upload body too large: {key:?}");
+
+        return reject(StatusCode::PAYLOAD_TOO_LARGE, tcp_stream).await;
+    }
+
+    let Some(key_name) = api_key::authenticate(request, admin_config) else {
+        tracing::warn!("Unauthorized upload request: {key:?}");
+
+        return reject(StatusCode::UNAUTHORIZED, tcp_stream).await;
+    };
+
+    if request.method != Method::HEAD {
+        api_key::audit(&key_name, request, &format!("{PREFIX}{key}"));
+    }
+
+    let key = key.trim_start_matches('/');
+
+    if key.is_empty() {
+        return reject(StatusCode::BAD_REQUEST, tcp_stream).await;
+    }
+
+    match request.method {
+        Method::PUT | Method::POST => {}
+        Method::HEAD | Method::PATCH => {
+            return resumable::handle(request, key, cache, tcp_stream).await;
+        }
+        _ => return reject(StatusCode::METHOD_NOT_ALLOWED, tcp_stream).await,
+    }
+
+    let mut writer = match cache.writer(key).await {
+        Ok(writer) => writer,
+        Err(e) => return reject_writer_error(key, e, tcp_stream).await,
+    };
+
+    // Before `100 Continue`, so that the client does not send it at all.
+    if let Some(Err(e)) = request
+        .content_length()
+        .map(|length| writer.preallocate(length))
+    {
+        return reject_writer_error(key, e, tcp_stream).await;
+    }
+
+    if let Some(metadata) = metadata(request) {
+        writer.set_metadata(metadata);
+    }
+
+    let length = match copy_body(request, key, &mut writer, u64::MAX, tcp_stream).await? {
+        Ok(length) => length,
+        Err(status) => return reject(status, tcp_stream).await,
+    };
+
+    writer.commit().await?;
+
+    tracing::info!("Uploaded {key:?}, {length} bytes");
+
+    super::write_status(StatusCode::CREATED, tcp_stream).await
+}
+
+/// Append the request body to `writer`, at most `limit` bytes, and
+/// [`AdminConfig::max_body_size`] if limited.
+///
+/// Returns the bytes appended, or the status to reject the request with.
+///
+/// [`AdminConfig::max_body_size`]: crate::config::AdminConfig::max_body_size
+async fn copy_body(
+    request: &proto::Request,
+    key: &str,
+    writer: &mut CacheWriter,
+    limit: u64,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<Result<u64, StatusCode>> {
+    let max_body_size = Config::current()
+        .admin
+        .as_ref()
+        .and_then(|admin| admin.max_body_size);
+
+    let mut body = request.body(tcp_stream).await?;
+    let mut length = 0;
+
+    loop {
+        let data = match body.next().await {
+            Ok(Some(data)) => data,
+            Ok(None) => return Ok(Ok(length)),
+            Err(e) => {
+                tracing::error!("Read upload of {key:?} error: {e:?}");
+                return Ok(Err(StatusCode::BAD_REQUEST));
+            }
+        };
+
+        if max_body_size.is_some_and(|max_body_size| length + data.len() as u64 > max_body_size) {
+            tracing::warn!("Abort upload of {key:?}, exceeding the max body size");
+            return Ok(Err(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+
+        if length + data.len() as u64 > limit {
+            tracing::warn!("Upload of {key:?} exceeds the declared length");
+            return Ok(Err(StatusCode::BAD_REQUEST));
+        }
+
+        match writer.write_all(data).await {
+            Ok(()) => length += data.len() as u64,
+            Err(e) if e.kind() == io::ErrorKind::StorageFull => {
+                tracing::warn!("Abort upload of {key:?}: {e}");
+                return Ok(Err(StatusCode::SERVICE_UNAVAILABLE));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Metadata from the request headers: `Content-Type`, and
+/// `X-Original-Url` for where the object was fetched from.
+fn metadata(request: &proto::Request) -> Option<Metadata> {
+    let header = |name| {
+        request
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+    };
+
+    let metadata = Metadata {
+        content_type: header(CONTENT_TYPE)
+            // Set by `curl --data-binary` or tus clients, not meaningful
+            .filter(|content_type| {
+                content_type != "application/x-www-form-urlencoded"
+                    && content_type != "application/offset+octet-stream"
+            }),
+        url: header(X_ORIGINAL_URL),
+        ..Metadata::default()
+    };
+
+    (metadata.content_type.is_some() || metadata.url.is_some()).then_some(metadata)
+}
+
+/// Reject the request on failing to create or reserve space for a
+/// [`CacheWriter`], with `503 Service Unavailable` if out of space.
+async fn reject_writer_error(
+    key: &str,
+    e: io::Error,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    if e.kind() != io::ErrorKind::StorageFull {
+        return Err(e.into());
+    }
+
+    tracing::warn!("Reject upload of {key:?}: {e}");
+
+    reject(StatusCode::SERVICE_UNAVAILABLE, tcp_stream).await
+}
+
+/// Write an error response and close the connection, since the body has not
+/// been read (fully).
+async fn reject(status: StatusCode, tcp_stream: &mut impl proto::Stream) -> Result<bool, Error> {
+    super::write_status(status, tcp_stream).await?;
+
+    Ok(false)
+}
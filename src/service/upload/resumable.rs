@@ -0,0 +1,268 @@
+//! Resumable uploads, in the style of [tus](https://tus.io/protocols/resumable-upload).
+//!
+//! - `PATCH /resource/upload/{key}` with `Upload-Offset` appends the body at
+//!   the offset. The first one, at offset 0, must carry `Upload-Length` and
+//!   starts the upload, of [`AdminConfig::max_body_size`] at most if limited.
+//! - `HEAD /resource/upload/{key}` responds with the current `Upload-Offset`,
+//!   so that an interrupted client knows where to resume from.
+//!
+//! Once all `Upload-Length` bytes have been received, the object is committed
+//! into the cache like a plain upload. Uploads in progress are kept in memory
+//! only, and dropped after being idle for [`UPLOAD_EXPIRY`].
+//!
+//! [`AdminConfig::max_body_size`]: crate::config::AdminConfig::max_body_size
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use http::{HeaderName, HeaderValue, Method, StatusCode, header::CACHE_CONTROL};
+use macro_toolset::string_v2::StringExtT;
+
+use crate::{
+    cache::{Cache, CacheWriter},
+    config::Config,
+    error::Error,
+    proto,
+};
+
+/// Uploads in progress, by key, expired by a task spawned along, see
+/// [`expire`].
+static UPLOADS: LazyLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<Upload>>>>> =
+    LazyLock::new(|| {
+        tokio::spawn(async {
+            loop {
+                tokio::time::sleep(EXPIRE_INTERVAL).await;
+                expire(&mut uploads());
+            }
+        });
+
+        Mutex::new(HashMap::new())
+    });
+
+/// Idle uploads are dropped after this.
+const UPLOAD_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// Interval of dropping idle uploads
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Bytes received so far, or where to append.
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+
+/// Total bytes of the upload.
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+
+/// tus protocol version
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+
+#[derive(Debug)]
+/// An upload in progress
+struct Upload {
+    /// `None` once finished or aborted.
+    writer: Option<CacheWriter>,
+
+    /// Total bytes, see [`UPLOAD_LENGTH`].
+    length: u64,
+
+    last_active: Instant,
+}
+
+/// Handle a `HEAD` or `PATCH` request of a resumable upload.
+///
+/// Returns whether the connection can be kept alive.
+pub(super) async fn handle(
+    request: &proto::Request,
+    key: &str,
+    cache: &'static Cache,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    if request.method == Method::HEAD {
+        return head(key, tcp_stream).await;
+    }
+
+    patch(request, key, cache, tcp_stream).await
+}
+
+/// `HEAD /resource/upload/{key}`
+async fn head(key: &str, tcp_stream: &mut impl proto::Stream) -> Result<bool, Error> {
+    let Some(upload) = uploads().get(key).cloned() else {
+        return super::super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    // A `PATCH` in progress, maybe from a dead connection yet to time out.
+    let Ok(upload) = upload.try_lock() else {
+        return super::super::write_status(StatusCode::CONFLICT, tcp_stream).await;
+    };
+
+    let Some(writer) = &upload.writer else {
+        return super::super::write_status(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    respond(StatusCode::OK, writer.written(), upload.length, tcp_stream).await
+}
+
+/// `PATCH /resource/upload/{key}`
+async fn patch(
+    request: &proto::Request,
+    key: &str,
+    cache: &'static Cache,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let Some(offset) = header_u64(request, &UPLOAD_OFFSET) else {
+        return super::reject(StatusCode::BAD_REQUEST, tcp_stream).await;
+    };
+
+    let existing = uploads().get(key).cloned();
+
+    let upload = match existing {
+        Some(upload) => upload,
+        None if offset == 0 => {
+            let Some(length) = header_u64(request, &UPLOAD_LENGTH) else {
+                return super::reject(StatusCode::BAD_REQUEST, tcp_stream).await;
+            };
+
+            // Not to be got around by uploading in parts
+            if Config::current()
+                .admin
+                .as_ref()
+                .and_then(|admin| admin.max_body_size)
+                .is_some_and(|max_body_size| length > max_body_size)
+            {
+                tracing::warn!("Resumable upload too large: {key:?}, {length} bytes");
+
+                return super::reject(StatusCode::PAYLOAD_TOO_LARGE, tcp_stream).await;
+            }
+
+            let mut writer = match cache.writer(key).await {
+                Ok(writer) => writer,
+                Err(e) => return super::reject_writer_error(key, e, tcp_stream).await,
+            };
+
+            if let Err(e) = writer.preallocate(length) {
+                return super::reject_writer_error(key, e, tcp_stream).await;
+            }
+
+            if let Some(metadata) = super::metadata(request) {
+                writer.set_metadata(metadata);
+            }
+
+            tracing::info!("Start resumable upload of {key:?}, {length} bytes");
+
+            let upload = Arc::new(tokio::sync::Mutex::new(Upload {
+                writer: Some(writer),
+                length,
+                last_active: Instant::now(),
+            }));
+
+            uploads().entry(key.to_owned()).or_insert(upload).clone()
+        }
+        None => return super::reject(StatusCode::NOT_FOUND, tcp_stream).await,
+    };
+
+    let Ok(mut upload) = upload.try_lock_owned() else {
+        return super::reject(StatusCode::CONFLICT, tcp_stream).await;
+    };
+
+    upload.last_active = Instant::now();
+    let length = upload.length;
+
+    let Some(writer) = &mut upload.writer else {
+        return super::reject(StatusCode::NOT_FOUND, tcp_stream).await;
+    };
+
+    if writer.written() != offset {
+        tracing::debug!(
+            "Resumable upload of {key:?} is at {}, not {offset}",
+            writer.written()
+        );
+
+        return super::reject(StatusCode::CONFLICT, tcp_stream).await;
+    }
+
+    let copied = super::copy_body(request, key, writer, length - offset, tcp_stream).await?;
+    let offset = writer.written();
+
+    match copied {
+        Ok(_) => {}
+        Err(StatusCode::SERVICE_UNAVAILABLE) => {
+            upload.writer = None;
+            uploads().remove(key);
+
+            return super::reject(StatusCode::SERVICE_UNAVAILABLE, tcp_stream).await;
+        }
+        // Keep what has been received, to be resumed.
+        Err(status) => return super::reject(status, tcp_stream).await,
+    }
+
+    if offset == length {
+        if let Some(writer) = upload.writer.take() {
+            uploads().remove(key);
+            writer.commit().await?;
+
+            tracing::info!("Uploaded {key:?}, {length} bytes");
+        }
+    }
+
+    respond(StatusCode::NO_CONTENT, offset, length, tcp_stream).await
+}
+
+/// Write a response carrying the upload progress.
+async fn respond(
+    status: StatusCode,
+    offset: u64,
+    length: u64,
+    tcp_stream: &mut impl proto::Stream,
+) -> Result<bool, Error> {
+    let mut response = proto::Response::status(status);
+
+    {
+        let headers = response.headers_mut();
+
+        headers.insert(TUS_RESUMABLE, HeaderValue::from_static("1.0.0"));
+        headers.insert(UPLOAD_OFFSET, offset.to_http_header_value()?);
+        headers.insert(UPLOAD_LENGTH, length.to_http_header_value()?);
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    }
+
+    if let Err(e) = response.with_body(b"").write_to_stream(tcp_stream).await {
+        tracing::error!("Write response error: {e:?}");
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+/// Drop uploads idle for longer than [`UPLOAD_EXPIRY`].
+fn expire(uploads: &mut HashMap<String, Arc<tokio::sync::Mutex<Upload>>>) {
+    uploads.retain(|key, upload| {
+        let Ok(upload) = upload.try_lock() else {
+            // In progress
+            return true;
+        };
+
+        let expired = upload.last_active.elapsed() > UPLOAD_EXPIRY;
+
+        if expired {
+            tracing::info!("Resumable upload of {key:?} expired");
+        }
+
+        !expired
+    });
+}
+
+/// Parse a numeric request header.
+fn header_u64(request: &proto::Request, name: &HeaderName) -> Option<u64> {
+    request
+        .headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+#[inline]
+fn uploads() -> MutexGuard<'static, HashMap<String, Arc<tokio::sync::Mutex<Upload>>>> {
+    UPLOADS.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,330 @@
+//! Playback sessions, see [`SessionConfig`].
+//!
+//! Alternatively to signed URLs, see [`sign`](crate::sign), the playurl
+//! endpoints mint an opaque token per response, see [`mint`], appended to
+//! the resource URLs handed out like
+//!
+//! ```text
+//! /resource/mikufans/...m4s?{query}&bvc_token={token}
+//! ```
+//!
+//! or carried by the `X-Bvc-Token` header instead. Sessions are kept in
+//! memory, dropped once idle for the configured TTL, and may be revoked by
+//! the admin API. Each resource request of a session holds a slot of it for
+//! as long as the response is sent, see [`acquire`].
+//!
+//! What is played is tracked per session: requests and bytes served, the
+//! client, and stalls, inferred from gaps between requests, see
+//! [`SessionConfig::stall_gap`].
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    net::IpAddr,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use http::header::USER_AGENT;
+use ring::rand::{SecureRandom, SystemRandom};
+#[cfg(feature = "admin")]
+use serde::Serialize;
+
+use crate::{
+    config::{Config, SessionConfig},
+    proto,
+};
+
+/// Param of the token.
+pub(crate) const PARAM: &str = "bvc_token";
+
+/// Header of the token, for clients not keeping the query.
+const HEADER: &str = "x-bvc-token";
+
+/// Length of tokens, in bytes before hex encoded.
+const TOKEN_LENGTH: usize = 16;
+
+/// Sessions by token.
+static SESSIONS: LazyLock<Mutex<HashMap<String, Session>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug)]
+/// A playback session.
+struct Session {
+    #[cfg_attr(
+        not(feature = "admin"),
+        allow(dead_code, reason = "Listed by the admin API only")
+    )]
+    /// Like `BV1xx411c7mD` or `av170001`, or the key of the object
+    video: String,
+
+    #[cfg_attr(
+        not(feature = "admin"),
+        allow(dead_code, reason = "Listed by the admin API only")
+    )]
+    /// Minted at, in seconds since UNIX epoch
+    created: u64,
+
+    /// Last used at
+    last_active: Instant,
+
+    /// Number of resource requests being served
+    active: usize,
+
+    /// Number of resource requests served
+    requests: u64,
+
+    /// Body bytes served
+    bytes: u64,
+
+    /// Number of gaps between requests longer than
+    /// [`SessionConfig::stall_gap`]
+    stalls: u64,
+
+    /// Longest gap between requests
+    longest_gap: Duration,
+
+    /// Address of the client, the latest
+    client: Option<IpAddr>,
+
+    /// `User-Agent` of the client, the latest
+    user_agent: Option<String>,
+}
+
+#[cfg(feature = "admin")]
+#[derive(Debug, Clone)]
+#[derive(Serialize)]
+/// A playback session as listed by the admin API.
+pub(crate) struct SessionInfo {
+    token: String,
+
+    video: String,
+
+    /// Minted at, in seconds since UNIX epoch
+    created: u64,
+
+    /// Seconds since last used
+    idle: u64,
+
+    /// Number of resource requests being served
+    active: usize,
+
+    /// Number of resource requests served
+    requests: u64,
+
+    /// Body bytes served
+    bytes: u64,
+
+    /// Number of gaps between requests likely stalls
+    stalls: u64,
+
+    /// Longest gap between requests, in seconds
+    longest_gap: u64,
+
+    client: Option<IpAddr>,
+
+    user_agent: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[derive(thiserror::Error)]
+/// Why a request is refused a session.
+pub(crate) enum Error {
+    #[error("No session token")]
+    /// Token not carried
+    Missing,
+
+    #[error("Unknown or expired session")]
+    /// Token never minted, revoked or expired
+    Unknown,
+
+    #[error("Too many concurrent requests of the session")]
+    /// Limit of concurrent requests reached, see
+    /// [`SessionConfig::max_concurrent`]
+    TooMany,
+}
+
+#[derive(Debug)]
+/// A slot of a session held by a resource request, released on drop.
+pub(crate) struct Guard {
+    token: String,
+}
+
+impl Guard {
+    /// Record `bytes` of body served.
+    pub(crate) fn record(&self, bytes: u64) {
+        let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(session) = sessions.get_mut(&self.token) {
+            session.bytes += bytes;
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(session) = sessions.get_mut(&self.token) {
+            session.active = session.active.saturating_sub(1);
+            session.last_active = Instant::now();
+        }
+    }
+}
+
+/// Mint a token of a new session playing `video` if configured, see
+/// [`ResourceConfig::sessions`].
+///
+/// [`ResourceConfig::sessions`]: crate::config::ResourceConfig::sessions
+pub(crate) fn mint(video: impl Into<String>) -> Option<String> {
+    let config = Config::current();
+    let ttl = Duration::from_secs(config.resource.sessions.as_ref()?.ttl);
+
+    let mut bytes = [0; TOKEN_LENGTH];
+    if let Err(e) = SystemRandom::new().fill(&mut bytes) {
+        tracing::error!("Generate session token error: {e:?}");
+        return None;
+    }
+
+    let token = bytes.iter().fold(
+        String::with_capacity(TOKEN_LENGTH * 2),
+        |mut token, byte| {
+            let _ = write!(token, "{byte:02x}");
+            token
+        },
+    );
+
+    let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+
+    sessions.retain(|_, session| session.active > 0 || session.last_active.elapsed() < ttl);
+    sessions.insert(
+        token.clone(),
+        Session {
+            video: video.into(),
+            created: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            last_active: Instant::now(),
+            active: 0,
+            requests: 0,
+            bytes: 0,
+            stalls: 0,
+            longest_gap: Duration::ZERO,
+            client: None,
+            user_agent: None,
+        },
+    );
+
+    Some(token)
+}
+
+/// Append `token` if any to `path_and_query`.
+pub(crate) fn attach(path_and_query: String, token: Option<&str>) -> String {
+    let Some(token) = token else {
+        return path_and_query;
+    };
+
+    let separator = if path_and_query.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+
+    format!("{path_and_query}{separator}{PARAM}={token}")
+}
+
+/// Take a slot of the session of `request` from `client`, held until the
+/// guard dropped.
+pub(crate) fn acquire(
+    config: &SessionConfig,
+    request: &proto::Request,
+    client: Option<IpAddr>,
+) -> Result<Guard, Error> {
+    let token = request
+        .query_param(PARAM)
+        .or_else(|| {
+            request
+                .headers
+                .get(HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        })
+        .ok_or(Error::Missing)?;
+
+    let mut sessions = SESSIONS.lock().unwrap_or_else(|e| e.into_inner());
+
+    let Some(session) = sessions.get_mut(&token) else {
+        return Err(Error::Unknown);
+    };
+
+    if session.active == 0 && session.last_active.elapsed() >= Duration::from_secs(config.ttl) {
+        sessions.remove(&token);
+        return Err(Error::Unknown);
+    }
+
+    if config
+        .max_concurrent
+        .is_some_and(|max_concurrent| session.active >= max_concurrent)
+    {
+        return Err(Error::TooMany);
+    }
+
+    // Since the previous response sent, none being sent meanwhile
+    if session.active == 0 && session.requests > 0 {
+        let gap = session.last_active.elapsed();
+
+        if gap >= Duration::from_secs(config.stall_gap) {
+            session.stalls += 1;
+        }
+        session.longest_gap = session.longest_gap.max(gap);
+    }
+
+    session.active += 1;
+    session.requests += 1;
+    session.last_active = Instant::now();
+    session.client = client.or(session.client);
+    if let Some(user_agent) = request
+        .headers
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+    {
+        session.user_agent = Some(user_agent.to_owned());
+    }
+
+    Ok(Guard { token })
+}
+
+#[cfg(feature = "admin")]
+/// Revoke the session of `token`, requests being served are not cut off.
+///
+/// Returns whether the session existed.
+pub(crate) fn revoke(token: &str) -> bool {
+    SESSIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(token)
+        .is_some()
+}
+
+#[cfg(feature = "admin")]
+/// All sessions, expired ones not purged yet included.
+pub(crate) fn list() -> Vec<SessionInfo> {
+    SESSIONS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(token, session)| SessionInfo {
+            token: token.clone(),
+            video: session.video.clone(),
+            created: session.created,
+            idle: session.last_active.elapsed().as_secs(),
+            active: session.active,
+            requests: session.requests,
+            bytes: session.bytes,
+            stalls: session.stalls,
+            longest_gap: session.longest_gap.as_secs(),
+            client: session.client,
+            user_agent: session.user_agent.clone(),
+        })
+        .collect()
+}
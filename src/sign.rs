@@ -0,0 +1,172 @@
+//! Signed resource URLs, see [`SigningConfig`].
+//!
+//! Like the `deadline` and `upsig` of upos, a URL is signed by appending the
+//! key ID, the deadline and then the HMAC-SHA256 of the path and query before
+//! it, like
+//!
+//! ```text
+//! /resource/mikufans/...m4s?{query}&bvc_kid=k1&bvc_deadline=1700000000&bvc_sign={hex}
+//! ```
+//!
+//! Anything changed of the URL, or the deadline passed, fails the
+//! verification, see [`verify`]. The params are stripped from the query
+//! forwarded upstream.
+//!
+//! URLs are signed by the current key, and verified by the key of the ID
+//! embedded, so that keys are rotated without invalidating URLs handed out:
+//! the key replaced is kept retired, accepted until it expires, see
+//! [`SigningConfig::retired_keys`].
+
+#[cfg(test)]
+mod tests;
+
+use std::{fmt::Write, time::SystemTime};
+
+use ring::hmac;
+
+use crate::{
+    config::{Config, SigningConfig},
+    proto,
+};
+
+/// Param of the key ID.
+pub(crate) const KEY_ID: &str = "bvc_kid";
+
+/// Param of the deadline, in seconds since UNIX epoch.
+pub(crate) const DEADLINE: &str = "bvc_deadline";
+
+/// Param of the signature, the last one.
+pub(crate) const SIGN: &str = "bvc_sign";
+
+#[derive(Debug, Clone, Copy)]
+#[derive(thiserror::Error)]
+/// Why a URL fails the verification.
+pub(crate) enum Error {
+    #[error("URL not signed")]
+    /// URL not signed, or the params malformed
+    Unsigned,
+
+    #[error("Unknown signing key")]
+    /// URL signed by a key not configured, or retired and expired
+    UnknownKey,
+
+    #[error("Signed URL expired")]
+    /// Deadline passed
+    Expired,
+
+    #[error("Signature mismatch")]
+    /// URL tampered with
+    Mismatch,
+}
+
+/// Sign `path_and_query` of the resource route if configured, see
+/// [`ResourceConfig::signing`], or return it as is.
+///
+/// [`ResourceConfig::signing`]: crate::config::ResourceConfig::signing
+pub(crate) fn sign(path_and_query: String) -> String {
+    let Some(config) = &Config::current().resource.signing else {
+        return path_and_query;
+    };
+
+    sign_until(config, path_and_query, unix_timestamp() + config.ttl)
+}
+
+/// Sign `path_and_query` by the current key of `config`, valid until
+/// `deadline`, in seconds since UNIX epoch.
+fn sign_until(config: &SigningConfig, path_and_query: String, deadline: u64) -> String {
+    let separator = if path_and_query.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    let signed = format!(
+        "{path_and_query}{separator}{KEY_ID}={}&{DEADLINE}={deadline}",
+        config.key_id
+    );
+    let tag = hmac::sign(&hmac_key(&config.key), signed.as_bytes());
+
+    let sign = tag.as_ref().iter().fold(
+        String::with_capacity(tag.as_ref().len() * 2),
+        |mut sign, byte| {
+            let _ = write!(sign, "{byte:02x}");
+            sign
+        },
+    );
+
+    format!("{signed}&{SIGN}={sign}")
+}
+
+/// Verify the URL of `request` signed by the key of `config` of the ID
+/// embedded, the current one or one retired yet to expire.
+pub(crate) fn verify(config: &SigningConfig, request: &proto::Request) -> Result<(), Error> {
+    let path = request.request_uri.path().as_str();
+    let query = request.request_uri.query().ok_or(Error::Unsigned)?.as_str();
+
+    let (signed_query, sign) = query
+        .rsplit_once(&format!("{SIGN}="))
+        .ok_or(Error::Unsigned)?;
+    let signed_query = match signed_query.strip_suffix('&') {
+        Some(signed_query) => signed_query,
+        None if signed_query.is_empty() => signed_query,
+        None => return Err(Error::Unsigned),
+    };
+
+    let now = unix_timestamp();
+
+    let key_id = request.query_param(KEY_ID).ok_or(Error::Unsigned)?;
+    let key = if key_id == config.key_id {
+        &config.key
+    } else {
+        &config
+            .retired_keys
+            .iter()
+            .find(|retired| retired.key_id == key_id && retired.expires >= now)
+            .ok_or(Error::UnknownKey)?
+            .key
+    };
+
+    let deadline: u64 = request
+        .query_param(DEADLINE)
+        .and_then(|deadline| deadline.parse().ok())
+        .ok_or(Error::Unsigned)?;
+    if deadline < now {
+        return Err(Error::Expired);
+    }
+
+    let sign = decode_hex(sign).ok_or(Error::Mismatch)?;
+
+    hmac::verify(
+        &hmac_key(key),
+        format!("{path}?{signed_query}").as_bytes(),
+        &sign,
+    )
+    .map_err(|_| Error::Mismatch)
+}
+
+/// The HMAC-SHA256 key of `secret`.
+fn hmac_key(secret: &str) -> hmac::Key {
+    hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())
+}
+
+/// Bytes of the hex string `hex`, `None` if not of hex digit pairs.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    hex.as_bytes()
+        .chunks_exact(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect()
+}
+
+/// Current time in seconds since UNIX epoch.
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
@@ -0,0 +1,167 @@
+//! URLs signed verified back, and those tampered with or expired refused.
+//! Keys rotated: URLs of those retired verified until they expire.
+
+use super::{DEADLINE, Error, KEY_ID, SIGN, sign_until, unix_timestamp, verify};
+use crate::{config::SigningConfig, proto};
+
+/// Path and query of a resource signed.
+const PATH_AND_QUERY: &str = "/resource/mikufans/upgcxcode/80/30280.m4s?e=ig8euxZM&os=bcache";
+
+/// The config of the current key `k2`.
+fn config() -> SigningConfig {
+    toml::from_str(
+        r#"
+            key_id = "k2"
+            key = "secret of k2"
+        "#,
+    )
+    .expect("Signing config")
+}
+
+/// The config of the current key `k2`, `k1` retired to expire at `expires`.
+fn rotated(expires: u64) -> SigningConfig {
+    toml::from_str(&format!(
+        r#"
+            key_id = "k2"
+            key = "secret of k2"
+
+            [[retired_keys]]
+            key_id = "k1"
+            key = "secret of k1"
+            expires = {expires}
+        "#
+    ))
+    .expect("Signing config")
+}
+
+/// The config of `k1` as the current key, before rotated.
+fn before_rotated() -> SigningConfig {
+    toml::from_str(
+        r#"
+            key_id = "k1"
+            key = "secret of k1"
+        "#,
+    )
+    .expect("Signing config")
+}
+
+/// A request of `path_and_query`.
+async fn request(path_and_query: &str) -> proto::Request {
+    let head = format!("GET {path_and_query} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+
+    proto::Request::handle(&mut head.as_bytes())
+        .await
+        .expect("Parsed")
+        .expect("Request")
+}
+
+/// Verify `path_and_query` by `config`.
+async fn verified(config: &SigningConfig, path_and_query: &str) -> Result<(), Error> {
+    verify(config, &request(path_and_query).await)
+}
+
+#[tokio::test]
+/// URLs signed verified, with or without a query before.
+async fn round_trip() {
+    let config = config();
+    let deadline = unix_timestamp() + 60;
+
+    for path_and_query in [PATH_AND_QUERY, "/resource/mikufans/a.m4s"] {
+        let signed = sign_until(&config, path_and_query.to_owned(), deadline);
+
+        assert!(signed.starts_with(path_and_query));
+        assert!(
+            verified(&config, &signed).await.is_ok(),
+            "{signed} not verified"
+        );
+    }
+}
+
+#[tokio::test]
+/// URLs of the path, the query or the signature changed refused.
+async fn tampered() {
+    let config = config();
+    let deadline = unix_timestamp() + 60;
+    let signed = sign_until(&config, PATH_AND_QUERY.to_owned(), deadline);
+
+    let (_, sign) = signed.rsplit_once(&format!("{SIGN}=")).expect("Signed");
+    let forged = format!("{:0>64}", "");
+
+    for tampered in [
+        signed.replace("30280.m4s", "30232.m4s"),
+        signed.replace("os=bcache", "os=upos"),
+        signed.replace(
+            &format!("{DEADLINE}={deadline}"),
+            &format!("{DEADLINE}={}", deadline + 1),
+        ),
+        signed.replace(sign, &forged),
+        signed.replace(sign, "zz"),
+    ] {
+        assert!(
+            matches!(verified(&config, &tampered).await, Err(Error::Mismatch)),
+            "{tampered} verified"
+        );
+    }
+
+    assert!(matches!(
+        verified(&config, PATH_AND_QUERY).await,
+        Err(Error::Unsigned)
+    ));
+}
+
+#[tokio::test]
+/// URLs past the deadline refused, though signed.
+async fn expired() {
+    let config = config();
+    let signed = sign_until(&config, PATH_AND_QUERY.to_owned(), unix_timestamp() - 1);
+
+    assert!(matches!(
+        verified(&config, &signed).await,
+        Err(Error::Expired)
+    ));
+}
+
+#[tokio::test]
+/// URLs signed by a retired key verified until it expires, not after.
+async fn retired_key() {
+    let now = unix_timestamp();
+    let signed = sign_until(&before_rotated(), PATH_AND_QUERY.to_owned(), now + 60);
+
+    verified(&rotated(now + 60), &signed)
+        .await
+        .expect("Verified before expired");
+    assert!(matches!(
+        verified(&rotated(now - 1), &signed).await,
+        Err(Error::UnknownKey)
+    ));
+}
+
+#[tokio::test]
+/// URLs of a key ID neither current nor retired refused.
+async fn unknown_key() {
+    let signed = sign_until(&config(), PATH_AND_QUERY.to_owned(), unix_timestamp() + 60)
+        .replace(&format!("{KEY_ID}=k2"), &format!("{KEY_ID}=k3"));
+
+    assert!(matches!(
+        verified(&rotated(unix_timestamp() + 60), &signed).await,
+        Err(Error::UnknownKey)
+    ));
+}
+
+#[tokio::test]
+/// New URLs signed by the current key once rotated, not the retired one.
+async fn current_key() {
+    let now = unix_timestamp();
+    let config = rotated(now + 60);
+    let signed = sign_until(&config, PATH_AND_QUERY.to_owned(), now + 60);
+
+    assert!(signed.contains(&format!("{KEY_ID}=k2&")));
+    verified(&config, &signed).await.expect("Verified");
+
+    // Not verified by the retired key, of its ID
+    let as_retired = signed.replace(&format!("{KEY_ID}=k2"), &format!("{KEY_ID}=k1"));
+    assert!(matches!(
+        verified(&config, &as_retired).await,
+        Err(Error::Mismatch)
+    ));
+}
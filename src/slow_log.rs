@@ -0,0 +1,148 @@
+//! Logging slow requests, see [`SlowLogConfig`].
+//!
+//! A request handled longer than [`SlowLogConfig::duration`], or responded
+//! later than [`SlowLogConfig::ttfb`], is logged as a warning with all known
+//! of it, the phase timings included, see [`timing`], so that disk stalls and
+//! upstream hiccups are caught without debug logs. The span of the request
+//! tells the request ID and the client, see
+//! [`request_id::scope`](crate::request_id::scope).
+//!
+//! As of the access log, the query is left out of the path.
+
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use http::{Method, StatusCode, header::RANGE};
+
+use crate::{
+    config::{Config, SlowLogConfig},
+    error::Error,
+    proto,
+    timing::{self, Phase},
+    transfer,
+};
+
+tokio::task_local! {
+    /// The request being observed, see [`observe`].
+    static CURRENT: RefCell<Current>;
+}
+
+#[derive(Debug)]
+/// The request being observed, as known so far.
+struct Current {
+    started: Instant,
+
+    method: Option<Method>,
+
+    path: String,
+
+    /// `Range` request header
+    range: Option<String>,
+
+    status: Option<StatusCode>,
+
+    /// Time to the response head written
+    ttfb: Option<Duration>,
+}
+
+/// Run the handler `future` of a request, logging it once done if slow.
+///
+/// Must be run within [`timing::scope`] for the phase timings.
+pub(crate) async fn observe<F>(future: F) -> Result<bool, Error>
+where
+    F: Future<Output = Result<bool, Error>>,
+{
+    let Some(slow_log_config) = Config::current().slow_log.clone() else {
+        return future.await;
+    };
+
+    let current = Current {
+        started: Instant::now(),
+        method: None,
+        path: String::new(),
+        range: None,
+        status: None,
+        ttfb: None,
+    };
+
+    CURRENT
+        .scope(RefCell::new(current), async move {
+            let (result, bytes) = transfer::counted(future).await;
+
+            CURRENT.with(|current| log(&slow_log_config, &current.borrow(), bytes));
+
+            result
+        })
+        .await
+}
+
+/// Set the request being observed, see [`observe`].
+pub(crate) fn request(request: &proto::Request) {
+    let _ = CURRENT.try_with(|current| {
+        let mut current = current.borrow_mut();
+
+        current.method = Some(request.method.clone());
+        request
+            .request_uri
+            .path()
+            .as_str()
+            .clone_into(&mut current.path);
+        current.range = request
+            .headers
+            .get(RANGE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+    });
+}
+
+/// Mark the response head of `status` being written to the request being
+/// observed, see [`observe`].
+pub(crate) fn responding(status: StatusCode) {
+    let _ = CURRENT.try_with(|current| {
+        let mut current = current.borrow_mut();
+
+        current.status = Some(status);
+        if current.ttfb.is_none() {
+            current.ttfb = Some(current.started.elapsed());
+        }
+    });
+}
+
+/// Log the request done if slow, having sent `bytes` of body.
+fn log(slow_log_config: &SlowLogConfig, current: &Current, bytes: u64) {
+    let duration = current.started.elapsed();
+
+    let exceeds = |elapsed: Duration, threshold: u64| {
+        threshold != 0 && elapsed >= Duration::from_millis(threshold)
+    };
+
+    let slow = exceeds(duration, slow_log_config.duration)
+        || current
+            .ttfb
+            .is_some_and(|ttfb| exceeds(ttfb, slow_log_config.ttfb));
+
+    if !slow {
+        return;
+    }
+
+    tracing::warn!(
+        "Slow request: {} {}, range: {}, status: {}, bytes: {bytes}, duration: {duration:?}, \
+         ttfb: {:?}, parse: {:?}, open: {:?}, upstream: {:?}, transfer: {:?}",
+        current.method.as_ref().map_or("-", Method::as_str),
+        if current.path.is_empty() {
+            "-"
+        } else {
+            &current.path
+        },
+        current.range.as_deref().unwrap_or("-"),
+        current.status.as_ref().map_or("-", StatusCode::as_str),
+        current.ttfb,
+        timing::elapsed(Phase::Parse),
+        timing::elapsed(Phase::Open),
+        timing::elapsed(Phase::Upstream),
+        timing::elapsed(Phase::Transfer),
+    );
+}
@@ -0,0 +1,233 @@
+//! Connections served in memory, over [`tokio::io::duplex`], answered as the
+//! golden responses of the fixtures in `tests/fixtures`.
+//!
+//! A `<name>.request` holds the requests sent on a connection in order, each
+//! ended by a blank line, and `<name>.response` the responses to them, of LF
+//! line endings, and of the headers varying each run masked, see [`mask`].
+//! Run with `UPDATE_FIXTURES=1` to write the responses got instead.
+//!
+//! Those of the modules parsing other input are apart, by module.
+
+#[cfg(feature = "playurl")]
+mod playurl;
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use http::{Method, StatusCode};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream},
+};
+
+use crate::{
+    connection,
+    proto::{self, AnyStream},
+    request_id,
+    router::{Handler, Router, from_fn},
+    service,
+};
+
+/// Address the connections are served as from.
+const PEER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// Bytes buffered each way of a connection.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Methods of `/healthz`.
+const GET: &[Method] = &[Method::GET];
+
+/// Methods of `/file`.
+const GET_HEAD: &[Method] = &[Method::GET, Method::HEAD];
+
+/// Path of the fixture of `name`.
+fn fixture(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name]
+        .iter()
+        .collect()
+}
+
+/// Routes served: `/healthz` of an empty `200 OK`, and `/file` of the fixture
+/// `file.txt`.
+fn router() -> Router {
+    Router::new()
+        .route(
+            "/healthz",
+            from_fn(|_, _, stream| Box::pin(service::write_status(StatusCode::OK, stream)))
+                .layer(service::methods(GET)),
+        )
+        .route(
+            "/file",
+            from_fn(|request, _, stream| {
+                Box::pin(async move {
+                    let file = File::open(fixture("file.txt")).await?;
+
+                    service::serve_file(
+                        request,
+                        proto::Response::status(StatusCode::OK),
+                        file,
+                        service::ServeOptions::default(),
+                        stream,
+                    )
+                    .await
+                })
+            })
+            .layer(service::methods(GET_HEAD)),
+        )
+}
+
+/// Send `requests` on a connection served in memory one by one, each once the
+/// response to the previous one is read, returning the responses.
+async fn exchange(requests: &str) -> String {
+    let (client, server) = tokio::io::duplex(BUFFER_SIZE);
+
+    let connection =
+        connection::register(PEER_ADDR, connection::Slot::default()).expect("Registered");
+
+    let served = tokio::spawn(crate::serve_connection(
+        AnyStream::Memory(BufReader::new(server)),
+        PEER_ADDR,
+        connection,
+        Arc::new(router()),
+    ));
+
+    let mut client = BufReader::new(client);
+    let mut responses = String::new();
+
+    for request in requests.split("\n\n").filter(|request| !request.is_empty()) {
+        let request = format!("{}\r\n\r\n", request.trim_start().replace('\n', "\r\n"));
+
+        client
+            .write_all(request.as_bytes())
+            .await
+            .expect("Request sent");
+
+        responses.push_str(&read_response(&mut client, request.starts_with("HEAD ")).await);
+    }
+
+    client.shutdown().await.expect("Connection shut down");
+    served.await.expect("Connection served");
+
+    let mut rest = Vec::new();
+    client
+        .read_to_end(&mut rest)
+        .await
+        .expect("Connection closed");
+    assert!(rest.is_empty(), "Unexpected bytes after the responses");
+
+    responses
+}
+
+/// Read a response, of the body of its `Content-Length` unless `head_only`,
+/// i.e. of a `HEAD` request.
+async fn read_response(client: &mut BufReader<DuplexStream>, head_only: bool) -> String {
+    let mut response = String::new();
+    let mut length = 0;
+
+    loop {
+        let mut line = String::new();
+        client.read_line(&mut line).await.expect("Head read");
+
+        assert!(!line.is_empty(), "Connection closed amid the head");
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().expect("Valid Content-Length");
+            }
+        }
+
+        response.push_str(&mask(line.trim_end()));
+        response.push('\n');
+
+        if line == "\r\n" {
+            break;
+        }
+    }
+
+    if !head_only {
+        let mut body = vec![0; length];
+        client.read_exact(&mut body).await.expect("Body read");
+
+        response.push_str(&String::from_utf8(body).expect("Body of text"));
+    }
+
+    response
+}
+
+/// Mask the value of the header `line` if varying each run, e.g. the request
+/// ID, see [`request_id`].
+fn mask(line: &str) -> String {
+    match line.split_once(':') {
+        Some((name, _)) if name.eq_ignore_ascii_case(request_id::HEADER.as_str()) => {
+            format!("{name}: <masked>")
+        }
+        _ => line.to_owned(),
+    }
+}
+
+/// Check the responses to the requests of the fixture `name`.
+async fn golden(name: &str) {
+    let requests = std::fs::read_to_string(fixture(&format!("{name}.request"))).expect("Requests");
+    let responses = exchange(&requests).await;
+
+    let path = fixture(&format!("{name}.response"));
+
+    if std::env::var_os("UPDATE_FIXTURES").is_some() {
+        std::fs::write(&path, responses).expect("Responses written");
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).expect("Responses");
+
+    assert_eq!(responses, expected, "Responses of {name}");
+}
+
+#[tokio::test]
+/// Requests served one after another on the same connection.
+async fn keep_alive() {
+    golden("keep_alive").await;
+}
+
+#[tokio::test]
+/// `Range` requests of a file, satisfiable or not.
+async fn ranges() {
+    golden("ranges").await;
+}
+
+#[tokio::test]
+/// Requests failed, invalid or of no route, the connection kept alive after.
+async fn errors() {
+    golden("errors").await;
+}
+
+#[tokio::test]
+/// Requests parsed of any reader, the rest of the head kept as the body.
+async fn parse_request() {
+    let mut reader =
+        &b"PUT /upload/a?b=c HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody"[..];
+
+    let request = proto::Request::handle(&mut reader)
+        .await
+        .expect("Parsed")
+        .expect("Request");
+
+    assert_eq!(request.method, Method::PUT);
+    assert_eq!(request.request_uri.path().as_str(), "/upload/a");
+    #[cfg(feature = "admin")]
+    assert_eq!(request.query_param("b").as_deref(), Some("c"));
+    #[cfg(feature = "admin")]
+    assert_eq!(request.content_length(), Some(4));
+    assert_eq!(&request.body_prefix[..], b"body");
+
+    let mut reader = &b""[..];
+
+    assert!(
+        proto::Request::handle(&mut reader)
+            .await
+            .expect("Parsed")
+            .is_none()
+    );
+}
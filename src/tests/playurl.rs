@@ -0,0 +1,78 @@
+//! Playurl responses of the API captured, in `tests/fixtures/playurl`,
+//! passed through the model as they are, see [`model`](crate::playurl).
+
+use serde_json::Value;
+
+use super::fixture;
+use crate::playurl::{ApiResponse, Playurl};
+
+/// Parse the captured response of `name` into the model and back, checking
+/// that nothing is renamed or dropped.
+fn round_trip(name: &str) -> Playurl {
+    let captured = std::fs::read_to_string(fixture(&format!("playurl/{name}.json")))
+        .expect("Captured response");
+    let captured: Value = serde_json::from_str(&captured).expect("JSON");
+
+    let response: ApiResponse<Playurl> = serde_json::from_value(captured.clone()).expect("Modeled");
+
+    assert_eq!(
+        serde_json::to_value(&response).expect("Serialized"),
+        captured,
+        "Round trip of {name}"
+    );
+
+    response.data.expect("Playurl")
+}
+
+#[test]
+/// DASH streams, of both the snake and camel case fields.
+fn dash() {
+    let playurl = round_trip("dash");
+    let dash = playurl.dash.as_ref().expect("DASH");
+
+    assert_eq!(dash.video.len(), 2);
+    assert_eq!(dash.audio.as_ref().map(Vec::len), Some(1));
+    assert_eq!(
+        dash.video[0]
+            .segment_base
+            .as_ref()
+            .map(|segment_base| &*segment_base.index_range),
+        Some("1004-1531")
+    );
+    assert!(playurl.durl.is_empty());
+}
+
+#[test]
+/// FLV segments, of formats of no codecs.
+fn flv() {
+    let playurl = round_trip("flv");
+
+    assert!(playurl.dash.is_none());
+    assert_eq!(playurl.durl.len(), 1);
+    assert_eq!(playurl.durl[0].size, 20_415_773);
+    assert!(playurl.durl[0].md5.is_empty());
+    assert!(
+        playurl
+            .support_formats
+            .iter()
+            .all(|format| format.codecs.is_none())
+    );
+}
+
+#[test]
+/// MP4 segments of checksums, and formats of fields not modeled.
+fn formats() {
+    let playurl = round_trip("formats");
+
+    assert_eq!(playurl.durl[0].md5, "9f2c6a1e4b7d3f0a8c5e2b1d6f9a4c7e");
+    assert_eq!(
+        playurl
+            .support_formats
+            .iter()
+            .map(|format| format.quality)
+            .collect::<Vec<_>>(),
+        playurl.accept_quality
+    );
+    assert!(playurl.support_formats[0].extra.contains_key("need_login"));
+    assert!(playurl.extra.contains_key("volume"));
+}
@@ -0,0 +1,122 @@
+//! Phase timings of requests, recorded by the span of the request, see
+//! [`request_id::scope`](crate::request_id::scope), so that slow requests
+//! are attributed to disk, network or upstream.
+//!
+//! Time spent in each [`Phase`] is summed up as timed, see [`timed`] and
+//! [`start`], and recorded once the request done, see [`scope`].
+
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+tokio::task_local! {
+    /// Time spent in each [`Phase`] by the request being handled.
+    static TIMINGS: Cell<[Duration; Phase::ALL.len()]>;
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A phase of handling a request.
+pub(crate) enum Phase {
+    /// Reading and parsing the request head
+    Parse,
+
+    /// Opening and seeking files
+    Open,
+
+    /// Waiting for upstream, i.e. the CDN or the API
+    Upstream,
+
+    /// Writing the response to the client
+    Transfer,
+}
+
+impl Phase {
+    const ALL: [Self; 4] = [Self::Parse, Self::Open, Self::Upstream, Self::Transfer];
+
+    /// Name of the field of the span.
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Parse => "parse",
+            Self::Open => "open",
+            Self::Upstream => "upstream",
+            Self::Transfer => "transfer",
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Times a [`Phase`] until dropped, see [`start`].
+pub(crate) struct Timer {
+    phase: Phase,
+
+    started: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        add(self.phase, self.started.elapsed());
+    }
+}
+
+/// Run the handler `future` of a request, recording the phase timings by the
+/// current span once done.
+pub(crate) async fn scope<F>(future: F) -> F::Output
+where
+    F: Future,
+{
+    TIMINGS
+        .scope(Cell::new([Duration::ZERO; Phase::ALL.len()]), async move {
+            let started = Instant::now();
+            let output = future.await;
+
+            let timings = TIMINGS.with(Cell::get);
+            let span = tracing::Span::current();
+
+            for phase in Phase::ALL {
+                span.record(
+                    phase.as_str(),
+                    tracing::field::debug(timings[phase as usize]),
+                );
+            }
+
+            tracing::debug!("Request done in {:?}", started.elapsed());
+
+            output
+        })
+        .await
+}
+
+/// Time `future` as of `phase`.
+pub(crate) async fn timed<F>(phase: Phase, future: F) -> F::Output
+where
+    F: Future,
+{
+    let _timer = start(phase);
+
+    future.await
+}
+
+/// Time `phase` until the returned [`Timer`] dropped.
+pub(crate) fn start(phase: Phase) -> Timer {
+    Timer {
+        phase,
+        started: Instant::now(),
+    }
+}
+
+/// Time spent in `phase` by the request being handled so far.
+pub(crate) fn elapsed(phase: Phase) -> Duration {
+    TIMINGS
+        .try_with(|timings| timings.get()[phase as usize])
+        .unwrap_or_default()
+}
+
+/// Add `duration` spent in `phase`, if timed.
+fn add(phase: Phase, duration: Duration) {
+    let _ = TIMINGS.try_with(|timings| {
+        let mut sums = timings.get();
+        sums[phase as usize] += duration;
+        timings.set(sums);
+    });
+}
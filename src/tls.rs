@@ -0,0 +1,232 @@
+//! TLS of the listeners of [`TlsConfig::listen`].
+//!
+//! The certificate presented is chosen by the server name told by SNI, see
+//! [`TlsCertConfig::server_names`], so that a single instance serves as
+//! several hosts with valid certificates, e.g. the CDN hosts the URLs of
+//! rewritten playurls point at, and the API host. Each may be served a part
+//! of the routes only, see [`TlsCertConfig::routes`] and [`serves`].
+//!
+//! Files are never sent over TLS by `sendfile(2)`, but copied through
+//! userspace, see [`Body::File`](crate::proto::Body::File).
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result, bail};
+use arc_swap::ArcSwapOption;
+use http::StatusCode;
+use rustls::{
+    ServerConfig,
+    crypto::ring,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::{TlsAcceptor, server};
+
+use crate::{
+    config::{Config, TlsCertConfig, TlsConfig},
+    layer::{Layer, layer_fn},
+    proto, service,
+};
+
+/// Time to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Server config built of the current [`TlsConfig`], see [`reload`].
+static SERVER_CONFIG: ArcSwapOption<ServerConfig> = ArcSwapOption::const_empty();
+
+/// A connection over TLS, buffered so that the next request is waited for
+/// without the plaintext buffered by the TLS stream missed, see
+/// [`proto::Stream::readable`].
+pub(crate) type TlsStream = BufReader<server::TlsStream<TcpStream>>;
+
+impl proto::Stream for TlsStream {
+    fn plain(&self) -> Option<&TcpStream> {
+        None
+    }
+
+    fn server_name(&self) -> Option<&str> {
+        self.get_ref().get_ref().1.server_name()
+    }
+
+    fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.get_ref().get_ref().0.peer_addr()
+    }
+
+    async fn readable(&mut self) -> bool {
+        self.fill_buf().await.is_ok_and(|buf| !buf.is_empty())
+    }
+}
+
+#[derive(Debug)]
+/// Certificates chosen by SNI, see [`choose`].
+struct Resolver {
+    certs: Vec<TlsCertConfig>,
+
+    /// Loaded of `certs`, in order
+    keys: Vec<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.keys
+            .get(choose(&self.certs, client_hello.server_name()))
+            .cloned()
+    }
+}
+
+/// Load the certificates of `config`.
+pub(crate) fn init(config: &TlsConfig) -> Result<()> {
+    SERVER_CONFIG.store(Some(server_config(config)?));
+
+    Ok(())
+}
+
+/// Load the certificates of the current [`TlsConfig`] again, e.g. once
+/// renewed, keeping the loaded ones if failed.
+pub(crate) fn reload() {
+    // Not listening over TLS
+    if SERVER_CONFIG.load().is_none() {
+        return;
+    }
+
+    let config = Config::current();
+
+    let Some(tls_config) = &config.tls else {
+        return;
+    };
+
+    match server_config(tls_config) {
+        Ok(server_config) => {
+            SERVER_CONFIG.store(Some(server_config));
+
+            tracing::info!("TLS certificates reloaded");
+        }
+        Err(e) => tracing::error!("Reload TLS certificates error, keeping the loaded ones: {e:#}"),
+    }
+}
+
+/// Complete the TLS handshake of a connection accepted from `peer_addr`.
+pub(crate) async fn accept(tcp_stream: TcpStream, peer_addr: SocketAddr) -> Option<TlsStream> {
+    let server_config = SERVER_CONFIG.load_full()?;
+
+    let handshake = TlsAcceptor::from(server_config).accept(tcp_stream);
+
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+        Ok(Ok(tls_stream)) => Some(BufReader::new(tls_stream)),
+        Ok(Err(e)) => {
+            tracing::debug!("TLS handshake with {peer_addr} error: {e}");
+            None
+        }
+        Err(_) => {
+            tracing::debug!("TLS handshake with {peer_addr} timed out");
+            None
+        }
+    }
+}
+
+/// Layer answering requests of routes not served on the stream `404 Not
+/// Found`, see [`serves`].
+pub(crate) fn layer() -> impl Layer {
+    layer_fn(|request, params, stream, next| {
+        if !serves(stream, request.request_uri.path().as_str()) {
+            return Box::pin(service::write_status(StatusCode::NOT_FOUND, stream));
+        }
+
+        next.run(request, params, stream)
+    })
+}
+
+/// Whether the route of `path` is served on `stream`, i.e. not over TLS, or
+/// one of the routes of the certificate chosen, see
+/// [`TlsCertConfig::routes`].
+pub(crate) fn serves(stream: &impl proto::Stream, path: &str) -> bool {
+    if stream.plain().is_some() {
+        return true;
+    }
+
+    let config = Config::current();
+
+    let Some(cert) = config
+        .tls
+        .as_ref()
+        .and_then(|tls| tls.certs.get(choose(&tls.certs, stream.server_name())))
+    else {
+        return true;
+    };
+
+    cert.routes.is_empty() || cert.routes.iter().any(|route| path.starts_with(route))
+}
+
+/// Index of the certificate of `certs` to present for `server_name`, the
+/// first one if matched by none or not told.
+fn choose(certs: &[TlsCertConfig], server_name: Option<&str>) -> usize {
+    server_name
+        .and_then(|server_name| {
+            certs.iter().position(|cert| {
+                cert.server_names
+                    .iter()
+                    .any(|pattern| matches(pattern, server_name))
+            })
+        })
+        .unwrap_or(0)
+}
+
+/// Whether `server_name` matches `pattern`, the leftmost label of which may
+/// be `*` for any. Case-insensitive.
+fn matches(pattern: &str, server_name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(parent) => server_name
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest.eq_ignore_ascii_case(parent)),
+        None => pattern.eq_ignore_ascii_case(server_name),
+    }
+}
+
+/// Build the server config of `config`, loading the certificates.
+fn server_config(config: &TlsConfig) -> Result<Arc<ServerConfig>> {
+    if config.certs.is_empty() {
+        bail!("No TLS certificates configured");
+    }
+
+    let provider = Arc::new(ring::default_provider());
+
+    let keys = config
+        .certs
+        .iter()
+        .map(|cert| {
+            let cert_chain = CertificateDer::pem_file_iter(&cert.cert)
+                .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+                .with_context(|| format!("Read certificates {}", cert.cert.display()))?;
+
+            let key = PrivateKeyDer::from_pem_file(&cert.key)
+                .with_context(|| format!("Read private key {}", cert.key.display()))?;
+
+            CertifiedKey::from_der(cert_chain, key, &provider)
+                .map(Arc::new)
+                .with_context(|| {
+                    format!(
+                        "Invalid certificate {} or private key {}",
+                        cert.cert.display(),
+                        cert.key.display()
+                    )
+                })
+        })
+        .collect::<Result<_>>()?;
+
+    let mut server_config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(Resolver {
+            certs: config.certs.clone(),
+            keys,
+        }));
+
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config))
+}
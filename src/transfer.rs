@@ -0,0 +1,335 @@
+//! Response body transfer.
+
+mod throttle;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests;
+
+use std::{
+    cell::Cell,
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+};
+#[cfg(target_os = "linux")]
+use std::{
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd},
+    sync::Arc,
+};
+
+#[cfg(target_os = "linux")]
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+#[cfg(target_os = "linux")]
+use tokio::net::TcpStream;
+
+pub(crate) use self::throttle::Throttle;
+#[cfg(feature = "upstream")]
+pub(crate) use self::throttle::TokenBucket;
+use crate::{
+    buf::Buf,
+    config, connection,
+    timing::{self, Phase},
+};
+
+/// Body bytes sent in total, see [`total_sent`].
+static TOTAL_SENT: AtomicU64 = AtomicU64::new(0);
+
+tokio::task_local! {
+    /// Body bytes sent by the current task, if counted, see [`counted`].
+    static SENT: Cell<u64>;
+}
+
+/// Run `future`, counting the response body bytes sent by it. Counted ones
+/// are counted by the enclosing [`counted`] as well, if any.
+pub(crate) async fn counted<F>(future: F) -> (F::Output, u64)
+where
+    F: Future,
+{
+    let (output, sent) = SENT
+        .scope(Cell::new(0), async move {
+            let output = future.await;
+
+            (output, SENT.with(Cell::get))
+        })
+        .await;
+
+    let _ = SENT.try_with(|enclosing| enclosing.set(enclosing.get() + sent));
+
+    (output, sent)
+}
+
+#[inline]
+/// Count `length` body bytes sent, if counted, see [`counted`], in total,
+/// see [`total_sent`], and of the connection, see [`connection::sent`].
+pub(crate) fn count(length: u64) {
+    TOTAL_SENT.fetch_add(length, Ordering::Relaxed);
+    connection::sent(length);
+
+    let _ = SENT.try_with(|sent| sent.set(sent.get() + length));
+}
+
+#[cfg(feature = "metrics")]
+/// Body bytes sent in total, since started.
+pub(crate) fn total_sent() -> u64 {
+    TOTAL_SENT.load(Ordering::Relaxed)
+}
+
+/// Send `length` bytes read from `reader` to `writer`, one pooled [`Buf`]
+/// at a time.
+///
+/// Reading waits for the previous chunk to be written, so a slow client only
+/// ever holds a single chunk.
+pub(crate) async fn copy_chunked<R, W>(
+    reader: &mut R,
+    length: u64,
+    mut throttle: Option<Throttle>,
+    writer: &mut W,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let _timer = timing::start(Phase::Transfer);
+
+    let mut chunk = Buf::chunk();
+    let mut remaining = length;
+
+    while remaining > 0 {
+        let want = chunk
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+
+        match reader.read(&mut chunk[..want]).await? {
+            0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "File truncated while sending",
+                ));
+            }
+            read => {
+                if let Some(throttle) = &mut throttle {
+                    throttle.acquire(read).await;
+                }
+
+                writer.write_all(&chunk[..read]).await?;
+                count(read as u64);
+                remaining -= read as u64;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+/// Max bytes sent by a single `sendfile(2)` call, so that a blocking thread
+/// is not occupied for too long by one connection.
+const SENDFILE_CHUNK: usize = 1024 * 1024;
+
+#[cfg(target_os = "linux")]
+/// Send `length` bytes of `file` from `offset` to the [`TcpStream`] with
+/// `sendfile(2)`, without copying them through userspace. Not usable with a
+/// [`Throttle`].
+///
+/// Called on tokio's blocking pool once the socket is writable, as reading
+/// pages of the file not cached blocks.
+///
+/// The file position is not changed.
+pub(crate) async fn sendfile(
+    file: &File,
+    mut offset: u64,
+    length: u64,
+    tcp_stream: &TcpStream,
+) -> io::Result<()> {
+    let _timer = timing::start(Phase::Transfer);
+
+    // Duplicated, not to be closed under a call outliving the response
+    let fds: Arc<(OwnedFd, OwnedFd)> = Arc::new((
+        tcp_stream.as_fd().try_clone_to_owned()?,
+        file.as_fd().try_clone_to_owned()?,
+    ));
+
+    let end = offset + length;
+
+    while offset < end {
+        tcp_stream.writable().await?;
+
+        let count = (end - offset).min(SENDFILE_CHUNK as u64) as usize;
+
+        let sent = tokio::task::spawn_blocking({
+            let fds = fds.clone();
+
+            move || sendfile_at(fds.0.as_fd(), fds.1.as_fd(), offset, count)
+        })
+        .await
+        .map_err(io::Error::other)?;
+
+        match sent {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "File truncated while sending",
+                ));
+            }
+            Ok(sent) => {
+                self::count(sent);
+                offset += sent;
+            }
+            // Wait for it writable again, unless it is by now
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                match tcp_stream.try_io(tokio::io::Interest::WRITABLE, || {
+                    is_writable(tcp_stream.as_fd())
+                }) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+/// `sendfile(2)` up to `count` bytes of `file` from `offset` to `socket`,
+/// returning the bytes sent.
+fn sendfile_at(
+    socket: BorrowedFd<'_>,
+    file: BorrowedFd<'_>,
+    offset: u64,
+    count: usize,
+) -> io::Result<u64> {
+    let mut file_offset = offset as libc::off_t;
+
+    #[allow(unsafe_code, reason = "FFI")]
+    // SAFETY: both fds are valid for the duration of the call.
+    let sent = unsafe {
+        libc::sendfile(
+            socket.as_raw_fd(),
+            file.as_raw_fd(),
+            &mut file_offset,
+            count,
+        )
+    };
+
+    if sent < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(sent.unsigned_abs() as u64)
+    }
+}
+
+#[cfg(target_os = "linux")]
+/// Check whether `socket` is writable now, [`io::ErrorKind::WouldBlock`] if
+/// not.
+fn is_writable(socket: BorrowedFd<'_>) -> io::Result<()> {
+    let mut pollfd = libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events: libc::POLLOUT,
+        revents: 0,
+    };
+
+    #[allow(unsafe_code, reason = "FFI")]
+    // SAFETY: `pollfd` is valid for the duration of the call.
+    let ready = unsafe { libc::poll(&mut pollfd, 1, 0) };
+
+    match ready {
+        ..0 => Err(io::Error::last_os_error()),
+        0 => Err(io::ErrorKind::WouldBlock.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+/// Whether [`uring_copy`] is usable, i.e. `io_uring` is available.
+pub(crate) fn uring_available() -> bool {
+    uring::available()
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+/// Send `length` bytes of `file` from `offset` to `writer`, with file reads
+/// done by `io_uring` instead of tokio's blocking pool.
+pub(crate) async fn uring_copy<W>(
+    file: File,
+    mut offset: u64,
+    length: u64,
+    mut throttle: Option<Throttle>,
+    writer: &mut W,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let _timer = timing::start(Phase::Transfer);
+
+    let file = Arc::new(file.into_std().await);
+    let end = offset + length;
+
+    let mut chunk = Buf::chunk();
+    let chunk_size = chunk.len();
+
+    while offset < end {
+        let want = chunk_size.min(usize::try_from(end - offset).unwrap_or(usize::MAX));
+
+        let mut buf = std::mem::take(&mut *chunk);
+        buf.resize(want, 0);
+
+        let (read, buf) = uring::read_at(&file, offset, buf).await;
+        *chunk = buf;
+
+        match read? {
+            0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "File truncated while sending",
+                ));
+            }
+            read => {
+                if let Some(throttle) = &mut throttle {
+                    throttle.acquire(read).await;
+                }
+
+                writer.write_all(&chunk[..read]).await?;
+                count(read as u64);
+                offset += read as u64;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Send the whole `buf` to `writer`, in chunks when throttled.
+pub(crate) async fn write_buf<W>(
+    buf: &[u8],
+    throttle: Option<Throttle>,
+    writer: &mut W,
+) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let _timer = timing::start(Phase::Transfer);
+
+    match throttle {
+        Some(mut throttle) => {
+            let chunk_size = config::Config::current().transfer.chunk_size.max(1);
+
+            for chunk in buf.chunks(chunk_size) {
+                throttle.acquire(chunk.len()).await;
+                writer.write_all(chunk).await?;
+                count(chunk.len() as u64);
+            }
+
+            Ok(())
+        }
+        None => {
+            writer.write_all(buf).await?;
+            count(buf.len() as u64);
+
+            Ok(())
+        }
+    }
+}
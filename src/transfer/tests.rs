@@ -0,0 +1,54 @@
+//! Files sent by `sendfile(2)` whole, beyond what the socket buffers hold.
+
+use tokio::{
+    fs::File,
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+};
+
+use super::{SENDFILE_CHUNK, sendfile};
+
+#[tokio::test]
+/// A range of a file of several chunks sent, the socket waited for writable
+/// while the peer reads slowly.
+async fn sendfile_range() {
+    let content: Vec<u8> = (0..3 * SENDFILE_CHUNK + 123)
+        .map(|i| (i % 251) as u8)
+        .collect();
+
+    let path = std::env::temp_dir().join(format!("sendfile-{}", std::process::id()));
+    std::fs::write(&path, &content).expect("File written");
+    let file = File::open(&path).await.expect("File opened");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("Bound");
+    let addr = listener.local_addr().expect("Address");
+
+    let client = tokio::spawn(async move {
+        let mut stream = TcpStream::connect(addr).await.expect("Connected");
+        let mut received = Vec::new();
+        let mut buf = [0; 4096];
+
+        loop {
+            match stream.read(&mut buf).await.expect("Read") {
+                0 => break received,
+                read => received.extend_from_slice(&buf[..read]),
+            }
+
+            tokio::task::yield_now().await;
+        }
+    });
+
+    let (stream, _) = listener.accept().await.expect("Accepted");
+
+    let sent = sendfile(&file, 100, content.len() as u64 - 200, &stream).await;
+    drop(stream);
+
+    let received = client.await.expect("Client");
+    std::fs::remove_file(&path).expect("File removed");
+
+    sent.expect("Sent");
+    assert!(
+        received == content[100..content.len() - 100],
+        "Content differs"
+    );
+}
@@ -0,0 +1,153 @@
+//! Bandwidth throttling.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::{Config, ThrottleConfig};
+
+/// Global egress bucket, shared by all responses.
+///
+/// Only locked to reserve bytes, the wait is done with it unlocked, see
+/// [`Throttle::acquire`].
+static GLOBAL_BUCKET: Mutex<Option<TokenBucket>> = Mutex::new(None);
+
+#[derive(Debug)]
+/// Limits the bytes sent by a single response, both by the per-route
+/// [`ThrottleConfig`] and by the global
+/// [`TransferConfig::global_rate`](crate::config::TransferConfig::global_rate).
+pub(crate) struct Throttle {
+    /// Per-response bucket
+    local: Option<TokenBucket>,
+
+    /// See [`ThrottleConfig::weight`].
+    weight: u32,
+}
+
+impl Throttle {
+    /// Create a new [`Throttle`], returns `None` when there's no limit at all.
+    ///
+    /// The first [`ThrottleConfig::burst`] bytes are sent at full speed, then
+    /// paced at [`ThrottleConfig::rate`], like how CDNs deliver.
+    pub(crate) fn new(config: Option<&ThrottleConfig>) -> Option<Self> {
+        let local = config.and_then(|config| {
+            config
+                .rate
+                .map(|rate| TokenBucket::new(rate, rate).with_tokens(config.burst.unwrap_or(rate)))
+        });
+
+        if local.is_none() && Config::current().transfer.global_rate.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            local,
+            weight: config.map_or(1, |config| {
+                u32::try_from(config.weight.max(1)).unwrap_or(u32::MAX)
+            }),
+        })
+    }
+
+    /// Wait until `bytes` can be sent.
+    pub(crate) async fn acquire(&mut self, bytes: usize) {
+        let bytes = bytes as u64;
+
+        if let Some(local) = &mut self.local {
+            tokio::time::sleep(local.take(bytes)).await;
+        }
+
+        let Some(global_rate) = Config::current().transfer.global_rate else {
+            return;
+        };
+
+        let wait = {
+            let mut global = GLOBAL_BUCKET.lock().unwrap_or_else(|e| e.into_inner());
+
+            let bucket = match &mut *global {
+                Some(bucket) if bucket.rate == global_rate => bucket,
+                // Uninitialized or config changed, allow bursting up to 100ms.
+                bucket => bucket.insert(TokenBucket::new(global_rate, global_rate / 10)),
+            };
+
+            bucket.take(bytes)
+        };
+
+        // Wait `1 / weight` of the time the bytes reserved so far take, so
+        // that responses with a larger weight get a larger share. All bytes
+        // are reserved though, the backlog growing until the total is back
+        // within the global rate.
+        tokio::time::sleep(wait / self.weight).await;
+    }
+}
+
+#[derive(Debug)]
+/// A token bucket
+pub(crate) struct TokenBucket {
+    /// Sustained rate, in bytes per second.
+    rate: u64,
+
+    /// Bucket capacity, in bytes.
+    capacity: u64,
+
+    /// Available bytes, negative when more than available has been taken, to
+    /// be paid off later.
+    tokens: i128,
+
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create a new full [`TokenBucket`].
+    pub(crate) fn new(rate: u64, capacity: u64) -> Self {
+        Self {
+            rate: rate.max(1),
+            capacity,
+            tokens: i128::from(capacity),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Set available bytes, may exceed the capacity for an initial burst.
+    fn with_tokens(mut self, tokens: u64) -> Self {
+        self.tokens = i128::from(tokens);
+        self
+    }
+
+    #[cfg(feature = "upstream")]
+    /// Sustained rate, in bytes per second.
+    pub(crate) const fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Take `bytes` from the bucket, returning how long to wait before they
+    /// can be sent.
+    pub(crate) fn take(&mut self, bytes: u64) -> Duration {
+        self.refill();
+
+        self.tokens -= i128::from(bytes);
+
+        if self.tokens < 0 {
+            Duration::from_secs_f64(-self.tokens as f64 / self.rate as f64)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Put tokens accumulated since last refill into the bucket.
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill);
+        self.last_refill = now;
+
+        let refilled = elapsed.as_nanos() * u128::from(self.rate) / 1_000_000_000;
+
+        // Not clamping down an initial burst exceeding the capacity.
+        let capacity = i128::from(self.capacity).max(self.tokens);
+
+        self.tokens = self
+            .tokens
+            .saturating_add(i128::try_from(refilled).unwrap_or(i128::MAX))
+            .min(capacity);
+    }
+}
@@ -0,0 +1,166 @@
+//! `io_uring` backed file reads.
+//!
+//! A dedicated thread owns the ring. Reads are sent to it over a channel and
+//! completed through oneshot channels, so that file reads no longer go through
+//! tokio's blocking pool.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io,
+    os::fd::AsRawFd,
+    sync::{Arc, LazyLock, mpsc},
+    time::Duration,
+};
+
+use bytes::BytesMut;
+use io_uring::{IoUring, opcode, types};
+use tokio::sync::oneshot;
+
+/// Ring size
+const RING_ENTRIES: u32 = 256;
+
+/// The global ring, `None` when `io_uring` is not available, see
+/// [`available`].
+static URING: LazyLock<Option<mpsc::Sender<ReadOp>>> = LazyLock::new(|| {
+    let ring = IoUring::new(RING_ENTRIES)
+        .inspect_err(|e| tracing::error!("io_uring is not available: {e:?}"))
+        .ok()?;
+
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::Builder::new()
+        .name("io-uring".to_owned())
+        .spawn(move || drive(ring, &rx))
+        .inspect_err(|e| tracing::error!("Spawn io_uring thread error: {e:?}"))
+        .ok()?;
+
+    Some(tx)
+});
+
+#[derive(Debug)]
+/// A pending read
+struct ReadOp {
+    /// Keep the file open until the read completes even if the reader is gone.
+    file: Arc<File>,
+
+    offset: u64,
+
+    /// The buffer is moved into the op, so it stays valid until the read
+    /// completes.
+    buf: BytesMut,
+
+    done: oneshot::Sender<(io::Result<usize>, BytesMut)>,
+}
+
+/// Whether `io_uring` is available, set up on first call.
+pub(crate) fn available() -> bool {
+    URING.is_some()
+}
+
+/// Read into `buf` from `offset` of `file`, returning the buffer back with
+/// the number of bytes read.
+pub(crate) async fn read_at(
+    file: &Arc<File>,
+    offset: u64,
+    buf: BytesMut,
+) -> (io::Result<usize>, BytesMut) {
+    let Some(tx) = &*URING else {
+        return (Err(io::ErrorKind::Unsupported.into()), buf);
+    };
+
+    let (done, rx) = oneshot::channel();
+
+    if let Err(mpsc::SendError(op)) = tx.send(ReadOp {
+        file: file.clone(),
+        offset,
+        buf,
+        done,
+    }) {
+        return (Err(io::ErrorKind::BrokenPipe.into()), op.buf);
+    }
+
+    rx.await.unwrap_or_else(|_| {
+        (
+            Err(io::Error::other("io_uring thread exited")),
+            BytesMut::new(),
+        )
+    })
+}
+
+/// Drive the ring: submit incoming reads and dispatch completions.
+fn drive(mut ring: IoUring, rx: &mpsc::Receiver<ReadOp>) {
+    let mut next_id = 0u64;
+    let mut backlog = VecDeque::new();
+    let mut in_flight: HashMap<u64, ReadOp> = HashMap::new();
+
+    loop {
+        // Block only when there's nothing else to wait for.
+        if in_flight.is_empty() && backlog.is_empty() {
+            match rx.recv() {
+                Ok(op) => backlog.push_back(op),
+                Err(_) => return,
+            }
+        }
+        backlog.extend(rx.try_iter());
+
+        {
+            let mut submission = ring.submission();
+
+            while !submission.is_full() {
+                let Some(mut op) = backlog.pop_front() else {
+                    break;
+                };
+
+                #[allow(clippy::cast_possible_truncation, reason = "Buffers are small")]
+                let entry = opcode::Read::new(
+                    types::Fd(op.file.as_raw_fd()),
+                    op.buf.as_mut_ptr(),
+                    op.buf.len() as u32,
+                )
+                .offset(op.offset)
+                .build()
+                .user_data(next_id);
+
+                #[allow(unsafe_code, reason = "io_uring")]
+                // SAFETY: the fd and buffer are kept alive in `in_flight` until
+                // the read completes.
+                if unsafe { submission.push(&entry) }.is_err() {
+                    backlog.push_front(op);
+                    break;
+                }
+
+                in_flight.insert(next_id, op);
+                next_id = next_id.wrapping_add(1);
+            }
+        }
+
+        match ring.submit_and_wait(1) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                // Completion queue is full, reap first.
+            }
+            Err(e) => {
+                // In-flight reads must be waited for anyway, as the kernel
+                // owns the buffers now, so just retry later.
+                tracing::error!("io_uring submit error: {e:?}");
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        for cqe in ring.completion() {
+            let Some(op) = in_flight.remove(&cqe.user_data()) else {
+                continue;
+            };
+
+            let result = if cqe.result() < 0 {
+                Err(io::Error::from_raw_os_error(-cqe.result()))
+            } else {
+                Ok(cqe.result().unsigned_abs() as usize)
+            };
+
+            let _ = op.done.send((result, op.buf));
+        }
+    }
+}
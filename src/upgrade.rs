@@ -0,0 +1,89 @@
+//! Zero-downtime upgrade of the binary, on SIGUSR2.
+//!
+//! The binary at the path started from, i.e. the new one once replaced, is
+//! started with the same arguments, inheriting the listening sockets as of
+//! systemd socket activation, see [`listener`](crate::listener). Once the new
+//! process is accepting, see [`ready`], this one stops accepting and exits
+//! once the connections open are drained, so that transfers in flight are
+//! not interrupted. If the new process fails to start, this one keeps
+//! serving.
+//!
+//! The cache index and usage are persisted before handing over, and left to
+//! the new process since: objects cached by this one while draining are not
+//! indexed by the new one.
+//!
+//! Under systemd, the new process is to be tracked by `PIDFile=` and
+//! `--pidfile`.
+
+#[cfg(unix)]
+mod hand_over;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::listener::Listener;
+
+/// Environment variable telling the PID of the process upgraded from.
+const UPGRADE_FROM: &str = "BVC_UPGRADE_FROM";
+
+/// Environment variable telling the fd to write to once ready.
+const READY_FD: &str = "BVC_READY_FD";
+
+/// The PID of the process upgraded from, if started by upgrading.
+pub(crate) fn upgrading_from() -> Option<u32> {
+    std::env::var(UPGRADE_FROM).ok()?.parse().ok()
+}
+
+/// Tell the process upgraded from that this one is accepting, if started by
+/// upgrading.
+pub(crate) fn ready() {
+    #[cfg(unix)]
+    {
+        use std::{fs::File, io::Write, os::fd::FromRawFd};
+
+        let Some(fd) = std::env::var(READY_FD)
+            .ok()
+            .and_then(|fd| fd.parse::<i32>().ok())
+        else {
+            return;
+        };
+
+        #[allow(unsafe_code, reason = "FFI")]
+        // SAFETY: the fd is inherited for this only, owned by none else.
+        let mut file = unsafe { File::from_raw_fd(fd) };
+
+        if let Err(e) = file.write_all(b"1") {
+            tracing::error!("Tell ready for upgrading error: {e}");
+        }
+    }
+}
+
+/// Wait until upgraded, i.e. the new process started on SIGUSR2 accepting
+/// from `listeners`.
+pub(crate) async fn upgraded(listeners: &[Arc<Listener>]) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut user_defined2 = signal(SignalKind::user_defined2())?;
+
+        loop {
+            user_defined2.recv().await;
+
+            tracing::info!("Upgrading");
+
+            match hand_over::hand_over(listeners).await {
+                Ok(()) => return Ok(()),
+                Err(e) => tracing::error!("Upgrade error, keep serving: {e:?}"),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = listeners;
+
+        std::future::pending().await
+    }
+}
@@ -0,0 +1,146 @@
+//! Handing over the listeners to the new process, see
+//! [`upgraded`](super::upgraded).
+
+use std::{
+    io,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::CommandExt,
+    },
+    process::Command,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail};
+use tokio::{io::AsyncReadExt, net::unix::pipe};
+
+use super::{READY_FD, UPGRADE_FROM};
+use crate::{
+    cache::Cache,
+    listener::{Listener, SD_LISTEN_FDS_START, SD_PROXY_FD_NAME, SD_TLS_FD_NAME},
+    usage,
+};
+
+/// Start the new process inheriting `listeners`, and wait for it ready.
+pub(super) async fn hand_over(listeners: &[Arc<Listener>]) -> Result<()> {
+    // For the new process to load
+    if let Some(cache) = Cache::global() {
+        cache.persist().await?;
+    }
+    usage::persist().await?;
+
+    let (ready_reader, ready_writer) = pipe()?;
+
+    // Inherited as fds from `SD_LISTEN_FDS_START` on, then the ready one.
+    let count = RawFd::try_from(listeners.len())?;
+    let ready_fd = SD_LISTEN_FDS_START + count;
+
+    // Moved past those to inherit as, not to be overwritten in the child
+    let sources = listeners
+        .iter()
+        .map(|listener| listener.as_fd())
+        .chain([ready_writer.as_fd()])
+        .map(|fd| dup_from(fd, ready_fd + 1))
+        .collect::<io::Result<Vec<_>>>()?;
+    let raw_sources: Vec<RawFd> = sources.iter().map(AsRawFd::as_raw_fd).collect();
+
+    let names = listeners
+        .iter()
+        .map(|listener| {
+            if listener.proxy_protocol() {
+                SD_PROXY_FD_NAME
+            } else if listener.tls() {
+                SD_TLS_FD_NAME
+            } else {
+                "listen"
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(":");
+
+    let mut args = std::env::args_os();
+    let mut command = Command::new(args.next().context("No program path")?);
+    command
+        .args(args)
+        .env_remove("LISTEN_PID")
+        .env("LISTEN_FDS", count.to_string())
+        .env("LISTEN_FDNAMES", names)
+        .env(UPGRADE_FROM, std::process::id().to_string())
+        .env(READY_FD, ready_fd.to_string());
+
+    #[allow(unsafe_code, reason = "FFI")]
+    // SAFETY: only `dup2`, async-signal-safe, is called in the child.
+    unsafe {
+        command.pre_exec(move || {
+            for (target, source) in (SD_LISTEN_FDS_START..).zip(&raw_sources) {
+                // Without `FD_CLOEXEC`, inherited
+                if libc::dup2(*source, target) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    let mut child = command.spawn().context("Start the new process")?;
+
+    // Held by the child only, so that the reader sees EOF once it exited
+    drop(sources);
+    drop(ready_writer);
+
+    tracing::info!("Started process {}, waiting for it ready", child.id());
+
+    let mut ready_reader = pipe::Receiver::from_owned_fd(ready_reader)?;
+
+    if ready_reader.read(&mut [0; 1]).await? == 0 {
+        let status = tokio::task::spawn_blocking(move || child.wait()).await??;
+
+        bail!("The new process exited before ready, {status}")
+    }
+
+    tracing::info!("Upgraded, draining");
+
+    Ok(())
+}
+
+/// A pipe, of the reader and the writer, both with `FD_CLOEXEC`.
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+
+    #[allow(unsafe_code, reason = "FFI")]
+    // SAFETY: `fds` is of two fds as required, owned by none else once
+    // created.
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let fds = (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1]));
+
+        for fd in [fds.0.as_raw_fd(), fds.1.as_raw_fd()] {
+            if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(fds)
+    }
+}
+
+/// Duplicate `fd` as the lowest one not less than `min`, with
+/// `FD_CLOEXEC`.
+fn dup_from(fd: BorrowedFd<'_>, min: RawFd) -> io::Result<OwnedFd> {
+    #[allow(unsafe_code, reason = "FFI")]
+    // SAFETY: the fd is valid for the duration of the call, and the one
+    // duplicated owned by none else.
+    unsafe {
+        let duplicated = libc::fcntl(fd.as_raw_fd(), libc::F_DUPFD_CLOEXEC, min);
+
+        if duplicated == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(OwnedFd::from_raw_fd(duplicated))
+    }
+}
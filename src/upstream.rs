@@ -0,0 +1,541 @@
+//! Upstream CDN client, fetching resources not available locally.
+//!
+//! Requests go to one of the configured hosts, picked by weighted
+//! round-robin, failing over to the others in the configured order on
+//! connect errors, `5xx` responses or timeouts. A failing host is
+//! blacklisted, i.e. skipped for [`UpstreamConfig::blacklist_duration`].
+//! Hosts may also be checked actively, see [`health`].
+//!
+//! When all hosts fail, the request is retried up to
+//! [`UpstreamConfig::retries`] times with exponential backoff.
+//!
+//! Connections are kept alive and reused, see [`pool`], and over TLS if
+//! configured, see [`tls`], through an outbound proxy if configured, see
+//! [`proxy`]. Hosts may be pinned to addresses or resolved by
+//! DNS-over-HTTPS, see [`resolve`]. Fetches are limited apart from responses
+//! to clients, see [`limit`].
+
+mod health;
+mod limit;
+mod pool;
+mod proxy;
+mod resolve;
+mod tls;
+
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    io,
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use http::{
+    HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+    header::{CONNECTION, CONTENT_LENGTH, HOST, REFERER, TRANSFER_ENCODING, USER_AGENT},
+};
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    sync::OwnedSemaphorePermit,
+    time::timeout,
+};
+
+pub(crate) use self::{health::HostHealth, limit::Priority};
+use crate::{
+    alert,
+    buf::{Buf, Size},
+    config::{UpstreamConfig, UpstreamHostConfig, UpstreamLimitsConfig},
+    metrics, proto,
+    timing::{self, Phase},
+};
+
+/// Blacklisted hosts, by `host[:port]`, till when.
+static BLACKLIST: LazyLock<Mutex<HashMap<String, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Round-robin counter of requests.
+static ROUND_ROBIN: AtomicU64 = AtomicU64::new(0);
+
+/// Body of an upstream [`Response`].
+type Body = proto::BodyReader<BufReader<tls::Stream>>;
+
+#[derive(Debug)]
+/// Upstream response, with the body yet to be read.
+pub(crate) struct Response {
+    /// Status code
+    pub status: StatusCode,
+
+    /// Headers
+    pub headers: HeaderMap,
+
+    /// Host responded
+    host: UpstreamHostConfig,
+
+    /// How long the host is blacklisted for if stalled
+    blacklist_duration: Duration,
+
+    /// See [`UpstreamConfig::idle_timeout`].
+    idle_timeout: Duration,
+
+    /// Body, empty for `HEAD` requests. Taken when dropped.
+    body: Option<Body>,
+
+    /// Whether the connection can be reused once the body has been read, i.e.
+    /// not `Connection: close`.
+    keep_alive: bool,
+
+    /// Connection slot of the host, see [`pool::acquire`].
+    _slot: OwnedSemaphorePermit,
+
+    /// See [`UpstreamConfig::limits`].
+    limits: UpstreamLimitsConfig,
+
+    /// Whom the fetch is for, see [`Self::set_priority`].
+    priority: Priority,
+
+    /// Fetch slots, see [`limit::acquire`].
+    _permit: limit::Permit,
+}
+
+impl Response {
+    /// Body length if known beforehand, i.e. `Content-Length` of a body not
+    /// chunked.
+    pub(crate) fn content_length(&self) -> Option<u64> {
+        if self.headers.contains_key(TRANSFER_ENCODING) {
+            return None;
+        }
+
+        self.headers
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok())
+    }
+
+    /// URL of `path_and_query` on the host responded.
+    pub(crate) fn url(&self, path_and_query: &str) -> String {
+        let scheme = if self.host.tls { "https" } else { "http" };
+
+        format!("{scheme}://{}{path_and_query}", authority(&self.host))
+    }
+
+    /// Change whom the rest of the body is downloaded for, e.g. to
+    /// [`Priority::Background`] once the client has gone.
+    ///
+    /// Only the bandwidth limits follow, the slots taken stay.
+    pub(crate) const fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
+
+    /// Read the next piece of the body, `None` at the end, see
+    /// [`proto::BodyReader::next`].
+    ///
+    /// The host is blacklisted if stalled.
+    pub(crate) async fn next(&mut self) -> Result<Option<&[u8]>> {
+        let Some(body) = &mut self.body else {
+            return Ok(None);
+        };
+
+        match timeout(self.idle_timeout, body.next()).await {
+            Ok(Ok(Some(data))) => {
+                limit::throttle(&self.limits, self.priority, data.len()).await;
+
+                Ok(Some(data))
+            }
+            Ok(data) => data,
+            Err(_) => {
+                blacklist(&self.host, self.blacklist_duration);
+
+                Err(anyhow!("Upstream {} stalled", authority(&self.host)))
+            }
+        }
+    }
+}
+
+impl Drop for Response {
+    /// Put the connection back into the pool if the body has been read to the
+    /// end.
+    fn drop(&mut self) {
+        if let Some(body) = self.body.take() {
+            if self.keep_alive && body.is_finished() {
+                pool::put(&authority(&self.host), body.into_reader());
+            }
+        }
+    }
+}
+
+/// Spawn the health checker if configured, see [`health`].
+pub(crate) fn init(config: &UpstreamConfig) {
+    if let Some(health_check) = &config.health_check {
+        health::spawn(health_check.clone());
+    }
+}
+
+/// Health of the configured hosts, see [`HostHealth`].
+pub(crate) fn health(config: &UpstreamConfig) -> Vec<HostHealth> {
+    health::report(config.hosts.iter())
+}
+
+/// Send a request of `path_and_query` upstream with the given extra headers,
+/// e.g. `Range`, returning once the response head has been received.
+///
+/// `Host`, `Referer` and `User-Agent` are set according to the config, see
+/// [`UpstreamHostConfig::host_header`], then rewritten by
+/// [`UpstreamConfig::set_headers`] and [`UpstreamConfig::strip_headers`].
+///
+/// When all hosts and retries fail, the last `5xx` response is returned if
+/// any.
+///
+/// Waits for free slots first, see [`UpstreamConfig::limits`].
+pub(crate) async fn fetch(
+    config: &UpstreamConfig,
+    method: &Method,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    priority: Priority,
+) -> Result<Response> {
+    send(config, method, path_and_query, headers, &[], priority).await
+}
+
+/// [`fetch`] with a request `body`, sent with `Content-Length` unless empty.
+pub(crate) async fn send(
+    config: &UpstreamConfig,
+    method: &Method,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+    priority: Priority,
+) -> Result<Response> {
+    let _timer = timing::start(Phase::Upstream);
+
+    let permit = limit::acquire(&config.limits, priority).await?;
+
+    let mut result = fetch_any(config, method, path_and_query, headers, body).await;
+
+    for retry in 1..=config.retries {
+        if result
+            .as_ref()
+            .is_ok_and(|response| !response.status.is_server_error())
+        {
+            break;
+        }
+
+        let backoff = backoff(Duration::from_millis(config.retry_backoff), retry);
+
+        tracing::debug!("Retry fetching {path_and_query} in {backoff:?}");
+
+        tokio::time::sleep(backoff).await;
+
+        result = fetch_any(config, method, path_and_query, headers, body).await;
+    }
+
+    result.map(|mut response| {
+        response.limits = config.limits;
+        response.priority = priority;
+        response._permit = permit;
+
+        response
+    })
+}
+
+/// Try the hosts in turn, see [`candidates`].
+async fn fetch_any(
+    config: &UpstreamConfig,
+    method: &Method,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Response> {
+    let blacklist_duration = Duration::from_secs(config.blacklist_duration);
+
+    let mut last_result = None;
+
+    for host in candidates(config) {
+        let started = Instant::now();
+        let result = fetch_from(config, host, method, path_and_query, headers, body).await;
+
+        let failed = !result
+            .as_ref()
+            .is_ok_and(|response| !response.status.is_server_error());
+
+        metrics::upstream_fetch(started.elapsed(), failed);
+
+        if failed {
+            alert::upstream_error();
+        }
+
+        match &result {
+            Ok(response) if !response.status.is_server_error() => return result,
+            Ok(response) => {
+                tracing::warn!("Upstream {} responded {}", authority(host), response.status);
+            }
+            Err(e) => tracing::warn!("Fetch from upstream {} error: {e:#}", authority(host)),
+        }
+
+        blacklist(host, blacklist_duration);
+
+        if result.is_ok() || last_result.is_none() {
+            last_result = Some(result);
+        }
+    }
+
+    last_result.unwrap_or_else(|| Err(anyhow!("No upstream host configured")))
+}
+
+/// Exponential backoff with full jitter, i.e. random up to
+/// `base * 2 ^ (retry - 1)`.
+fn backoff(base: Duration, retry: u32) -> Duration {
+    let max = base.saturating_mul(1 << retry.saturating_sub(1).min(16));
+
+    // Randomly keyed, a different hash every time
+    let random = RandomState::new().hash_one(retry);
+
+    max.mul_f64(random as f64 / u64::MAX as f64)
+}
+
+/// Send the request to the given host, over an idle connection if any.
+///
+/// A reused connection may have been closed by the host meanwhile, in which
+/// case the request is sent again over a new one.
+async fn fetch_from(
+    config: &UpstreamConfig,
+    host: &UpstreamHostConfig,
+    method: &Method,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Response> {
+    let authority = authority(host);
+
+    let mut request = Buf::take(Size::Head);
+
+    request.extend_from_slice(method.as_str().as_bytes());
+    request.extend_from_slice(b" ");
+    request.extend_from_slice(path_and_query.as_bytes());
+    request.extend_from_slice(b" HTTP/1.1\r\nHost: ");
+    request.extend_from_slice(host.host_header.as_ref().unwrap_or(&authority).as_bytes());
+    request.extend_from_slice(b"\r\n");
+    for (header_name, header_value) in &request_headers(config, headers) {
+        request.extend_from_slice(header_name.as_str().as_bytes());
+        request.extend_from_slice(b": ");
+        request.extend_from_slice(header_value.as_bytes());
+        request.extend_from_slice(b"\r\n");
+    }
+    if !body.is_empty() {
+        request.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
+    }
+    request.extend_from_slice(b"\r\n");
+    request.extend_from_slice(body);
+
+    let slot = pool::acquire(&authority, config).await?;
+
+    let first_byte_timeout = Duration::from_secs(config.first_byte_timeout);
+
+    if let Some(mut reader) = pool::take(&authority, config) {
+        match timeout(first_byte_timeout, exchange(&mut reader, &request[..])).await {
+            Ok(Ok(head)) => return Ok(response(config, host, method, reader, head, slot)),
+            Ok(Err(e)) => {
+                tracing::debug!("Reused connection to upstream {authority} error: {e:#}");
+            }
+            Err(_) => bail!("Upstream {authority} timed out responding"),
+        }
+    }
+
+    let stream = timeout(
+        Duration::from_secs(config.connect_timeout),
+        tls::connect(config, host),
+    )
+    .await
+    .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+    .with_context(|| format!("Connect to upstream {authority}"))?;
+
+    let mut reader = BufReader::new(stream);
+
+    let head = timeout(first_byte_timeout, exchange(&mut reader, &request[..]))
+        .await
+        .map_err(|_| anyhow!("Upstream {authority} timed out responding"))??;
+
+    Ok(response(config, host, method, reader, head, slot))
+}
+
+/// Headers of a request upstream besides `Host`, the given ones rewritten
+/// according to the config.
+fn request_headers(config: &UpstreamConfig, headers: &HeaderMap) -> HeaderMap {
+    let mut request_headers = HeaderMap::with_capacity(headers.len() + 2);
+
+    if let Ok(referer) = HeaderValue::from_str(&config.referer) {
+        request_headers.insert(REFERER, referer);
+    }
+
+    if let Ok(user_agent) = HeaderValue::from_str(&config.user_agent) {
+        request_headers.insert(USER_AGENT, user_agent);
+    }
+
+    for (name, value) in headers {
+        request_headers.append(name, value.clone());
+    }
+
+    for (name, value) in &config.set_headers {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) if name != HOST => {
+                request_headers.insert(name, value);
+            }
+            _ => tracing::warn!("Invalid header to set upstream: {name}: {value}"),
+        }
+    }
+
+    for name in &config.strip_headers {
+        request_headers.remove(name.as_str());
+    }
+
+    request_headers.remove(HOST);
+
+    request_headers
+}
+
+/// Write the request and read the response head.
+async fn exchange(reader: &mut BufReader<tls::Stream>, request: &[u8]) -> Result<proto::Response> {
+    reader.get_mut().write_all(request).await?;
+
+    proto::Response::read_head(reader).await
+}
+
+/// The response of the given head, with the body to be read from `reader`.
+fn response(
+    config: &UpstreamConfig,
+    host: &UpstreamHostConfig,
+    method: &Method,
+    reader: BufReader<tls::Stream>,
+    head: proto::Response,
+    slot: OwnedSemaphorePermit,
+) -> Response {
+    let proto::Response {
+        status, headers, ..
+    } = head;
+
+    let has_body = *method != Method::HEAD
+        && !status.is_informational()
+        && status != StatusCode::NO_CONTENT
+        && status != StatusCode::NOT_MODIFIED;
+
+    let chunked = headers
+        .get(TRANSFER_ENCODING)
+        .and_then(|encoding| encoding.to_str().ok())
+        .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+
+    let length = if has_body {
+        headers
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok())
+    } else {
+        Some(0)
+    };
+
+    let keep_alive = !headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.to_ascii_lowercase().contains("close"));
+
+    Response {
+        status,
+        headers,
+        host: host.clone(),
+        blacklist_duration: Duration::from_secs(config.blacklist_duration),
+        idle_timeout: Duration::from_secs(config.idle_timeout),
+        body: Some(Body::new(reader, has_body && chunked, length)),
+        keep_alive,
+        _slot: slot,
+        limits: UpstreamLimitsConfig::default(),
+        priority: Priority::Interactive,
+        _permit: limit::Permit::default(),
+    }
+}
+
+/// Hosts to try in order: a weighted round-robin pick first, then the rest
+/// in the configured order, or from the fastest with health checks.
+///
+/// Blacklisted or unhealthy hosts are skipped, unless all are.
+fn candidates(config: &UpstreamConfig) -> Vec<&UpstreamHostConfig> {
+    let now = Instant::now();
+
+    let mut hosts: Vec<_> = {
+        let mut blacklist = BLACKLIST.lock().unwrap_or_else(|e| e.into_inner());
+
+        blacklist.retain(|_, until| *until > now);
+
+        config
+            .hosts
+            .iter()
+            .filter(|host| !blacklist.contains_key(&authority(host)))
+            .collect()
+    };
+
+    hosts.retain(|host| health::is_healthy(host));
+
+    if hosts.is_empty() {
+        hosts = config.hosts.iter().collect();
+    }
+
+    if config.health_check.is_some() {
+        // Stable, unprobed ones last in the configured order
+        hosts.sort_by(|a, b| match (health::latency(a), health::latency(b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        });
+    }
+
+    let total_weight: u64 = hosts.iter().map(|host| host.weight).sum();
+
+    if total_weight > 0 {
+        let mut pick = ROUND_ROBIN.fetch_add(1, Ordering::Relaxed) % total_weight;
+
+        if let Some(first) = hosts.iter().position(|host| {
+            if pick < host.weight {
+                return true;
+            }
+
+            pick -= host.weight;
+            false
+        }) {
+            let first = hosts.remove(first);
+            hosts.insert(0, first);
+        }
+    }
+
+    hosts
+}
+
+/// Skip the host for the given duration.
+fn blacklist(host: &UpstreamHostConfig, duration: Duration) {
+    tracing::warn!("Blacklist upstream {} for {duration:?}", authority(host));
+
+    BLACKLIST
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(authority(host), Instant::now() + duration);
+}
+
+/// Whether the host of `host[:port]` is blacklisted.
+fn is_blacklisted(authority: &str) -> bool {
+    BLACKLIST
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(authority)
+        .is_some_and(|until| *until > Instant::now())
+}
+
+/// `host[:port]`, omitting the default port of the scheme.
+fn authority(host: &UpstreamHostConfig) -> String {
+    match (host.tls, host.port()) {
+        (false, 80) | (true, 443) => host.host.clone(),
+        (_, port) => format!("{}:{port}", host.host),
+    }
+}
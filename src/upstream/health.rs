@@ -0,0 +1,214 @@
+//! Active health checks of upstream hosts.
+//!
+//! Each host is probed every [`HealthCheckConfig::interval`], by a `HEAD`
+//! request of [`HealthCheckConfig::path`] or just connecting when not set.
+//! Hosts failing the last probe are avoided like blacklisted ones, and the
+//! others are failed over to from the fastest.
+
+use std::{
+    collections::HashMap,
+    io,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+use http::{HeaderMap, Method};
+use serde::Serialize;
+use tokio::{net::TcpStream, time::timeout};
+
+use super::authority;
+use crate::config::{Config, HealthCheckConfig, UpstreamHostConfig};
+
+/// Weight of the latest probe in the moving averages.
+const SMOOTHING: f64 = 0.2;
+
+/// Probe results so far, by `host[:port]`.
+static STATS: LazyLock<Mutex<HashMap<String, Stats>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Probe results of a host
+struct Stats {
+    /// Whether the last probe succeeded
+    healthy: bool,
+
+    /// Moving average of probe latency, in milliseconds
+    latency: Option<f64>,
+
+    /// Moving average of probe failures, from 0 to 1
+    error_rate: f64,
+
+    checks: u64,
+    failures: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+/// Health of an upstream host, see [`report`].
+pub(crate) struct HostHealth {
+    /// `host[:port]`
+    pub host: String,
+
+    /// Whether the last probe succeeded, `true` before any probe.
+    pub healthy: bool,
+
+    /// Whether skipped for failing requests, see
+    /// [`UpstreamConfig::blacklist_duration`](crate::config::UpstreamConfig::blacklist_duration).
+    pub blacklisted: bool,
+
+    /// Moving average of probe latency, in milliseconds.
+    pub latency_ms: Option<f64>,
+
+    /// Moving average of probe failures, from 0 to 1.
+    pub error_rate: f64,
+
+    /// Number of probes
+    pub checks: u64,
+
+    /// Number of failed probes
+    pub failures: u64,
+}
+
+/// Spawn the health checker, probing the currently configured hosts every
+/// [`HealthCheckConfig::interval`].
+pub(super) fn spawn(config: HealthCheckConfig) {
+    tokio::spawn(async move {
+        loop {
+            let hosts = Config::current()
+                .upstream
+                .as_ref()
+                .map(|upstream_config| upstream_config.hosts.clone())
+                .unwrap_or_default();
+
+            let probes: Vec<_> = hosts
+                .into_iter()
+                .map(|host| {
+                    let path = config.path.clone();
+
+                    tokio::spawn(async move {
+                        let started = Instant::now();
+
+                        let result = probe(&host, path.as_deref()).await;
+
+                        record(&host, result.map(|()| started.elapsed()));
+                    })
+                })
+                .collect();
+
+            for probe in probes {
+                let _ = probe.await;
+            }
+
+            tokio::time::sleep(Duration::from_secs(config.interval)).await;
+        }
+    });
+}
+
+/// Probe the host once.
+async fn probe(host: &UpstreamHostConfig, path: Option<&str>) -> Result<()> {
+    let Some(upstream_config) = &Config::current().upstream else {
+        return Ok(());
+    };
+
+    let Some(path) = path else {
+        timeout(
+            Duration::from_secs(upstream_config.connect_timeout),
+            TcpStream::connect((host.host.as_str(), host.port())),
+        )
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))?;
+
+        return Ok(());
+    };
+
+    let status = super::fetch_from(
+        upstream_config,
+        host,
+        &Method::HEAD,
+        path,
+        &HeaderMap::new(),
+        &[],
+    )
+    .await?
+    .status;
+
+    if status.is_server_error() {
+        bail!("Responded {status}");
+    }
+
+    Ok(())
+}
+
+/// Record the result of a probe, the latency if succeeded.
+fn record(host: &UpstreamHostConfig, result: Result<Duration>) {
+    let mut all_stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+    let stats = all_stats.entry(authority(host)).or_default();
+
+    stats.checks += 1;
+
+    match result {
+        Ok(latency) => {
+            let latency = latency.as_secs_f64() * 1000.0;
+
+            stats.healthy = true;
+            stats.latency = Some(match stats.latency {
+                Some(average) => average + SMOOTHING * (latency - average),
+                None => latency,
+            });
+            stats.error_rate -= SMOOTHING * stats.error_rate;
+        }
+        Err(e) => {
+            if stats.healthy || stats.checks == 1 {
+                tracing::warn!("Upstream {} failed health check: {e:#}", authority(host));
+            }
+
+            stats.healthy = false;
+            stats.failures += 1;
+            stats.error_rate += SMOOTHING * (1.0 - stats.error_rate);
+        }
+    }
+}
+
+/// Whether the host passed the last probe, `true` before any probe.
+pub(super) fn is_healthy(host: &UpstreamHostConfig) -> bool {
+    STATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&authority(host))
+        .is_none_or(|stats| stats.healthy)
+}
+
+/// Moving average of probe latency of the host, `None` before any
+/// successful probe.
+pub(super) fn latency(host: &UpstreamHostConfig) -> Option<f64> {
+    STATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&authority(host))
+        .and_then(|stats| stats.latency)
+}
+
+/// Health of the given hosts.
+pub(crate) fn report<'h>(hosts: impl Iterator<Item = &'h UpstreamHostConfig>) -> Vec<HostHealth> {
+    let all_stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+
+    hosts
+        .map(|host| {
+            let authority = authority(host);
+            let stats = all_stats.get(&authority).copied().unwrap_or(Stats {
+                healthy: true,
+                ..Stats::default()
+            });
+
+            HostHealth {
+                blacklisted: super::is_blacklisted(&authority),
+                host: authority,
+                healthy: stats.healthy,
+                latency_ms: stats.latency,
+                error_rate: stats.error_rate,
+                checks: stats.checks,
+                failures: stats.failures,
+            }
+        })
+        .collect()
+}
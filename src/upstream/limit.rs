@@ -0,0 +1,183 @@
+//! Concurrency and bandwidth limits of fetches from upstream, see
+//! [`UpstreamLimitsConfig`].
+
+#[cfg(test)]
+mod tests;
+
+use std::{
+    pin::pin,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use anyhow::Result;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::{config::UpstreamLimitsConfig, transfer::TokenBucket};
+
+/// Semaphore of fetch slots, with the number configured.
+type Slots = Mutex<Option<(usize, Arc<Semaphore>)>>;
+
+/// Slots of all fetches.
+static SLOTS: LazyLock<Slots> = LazyLock::new(|| Mutex::new(None));
+
+/// Slots of background fetches.
+static BACKGROUND_SLOTS: LazyLock<Slots> = LazyLock::new(|| Mutex::new(None));
+
+/// Download bucket of all fetches.
+///
+/// [`tokio::sync::Mutex`] is fair, so fetches take turns drawing from it.
+static BUCKET: LazyLock<tokio::sync::Mutex<Option<TokenBucket>>> =
+    LazyLock::new(|| tokio::sync::Mutex::new(None));
+
+/// Download bucket of background fetches.
+static BACKGROUND_BUCKET: LazyLock<tokio::sync::Mutex<Option<TokenBucket>>> =
+    LazyLock::new(|| tokio::sync::Mutex::new(None));
+
+/// Interactive fetches drawing from [`BUCKET`], which background ones yield
+/// to, see [`Drawing`].
+static INTERACTIVE_DRAWING: AtomicUsize = AtomicUsize::new(0);
+
+/// Notified once no interactive fetches draw from [`BUCKET`].
+static INTERACTIVE_DRAWN: Notify = Notify::const_new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Whom a fetch is for.
+pub(crate) enum Priority {
+    /// A client waiting for the response
+    Interactive,
+
+    /// No client waiting, e.g. prefetching
+    Background,
+}
+
+#[derive(Debug, Default)]
+/// Slots taken by a fetch, freed once dropped.
+pub(super) struct Permit {
+    _slot: Option<OwnedSemaphorePermit>,
+    _background_slot: Option<OwnedSemaphorePermit>,
+}
+
+#[derive(Debug)]
+/// An interactive fetch drawing from [`BUCKET`], counted until dropped.
+struct Drawing(());
+
+impl Drawing {
+    fn new() -> Self {
+        INTERACTIVE_DRAWING.fetch_add(1, Ordering::AcqRel);
+
+        Self(())
+    }
+}
+
+impl Drop for Drawing {
+    fn drop(&mut self) {
+        if INTERACTIVE_DRAWING.fetch_sub(1, Ordering::AcqRel) == 1 {
+            INTERACTIVE_DRAWN.notify_waiters();
+        }
+    }
+}
+
+/// Wait for free slots for a fetch of `priority`.
+pub(super) async fn acquire(config: &UpstreamLimitsConfig, priority: Priority) -> Result<Permit> {
+    // The background slot first, not to hold one of all while waiting.
+    let background_slot = match config.max_background_fetches {
+        Some(max) if priority == Priority::Background => {
+            Some(slots(&BACKGROUND_SLOTS, max).acquire_owned().await?)
+        }
+        _ => None,
+    };
+
+    let slot = match config.max_fetches {
+        Some(max) => Some(slots(&SLOTS, max).acquire_owned().await?),
+        None => None,
+    };
+
+    Ok(Permit {
+        _slot: slot,
+        _background_slot: background_slot,
+    })
+}
+
+/// Wait until `bytes` more can be downloaded by a fetch of `priority`.
+///
+/// Background fetches draw from the bucket of all fetches only while no
+/// interactive ones do, never queueing ahead of them.
+pub(super) async fn throttle(config: &UpstreamLimitsConfig, priority: Priority, bytes: usize) {
+    let bytes = bytes as u64;
+
+    if let Some(rate) = config.background_rate {
+        if priority == Priority::Background {
+            draw(&BACKGROUND_BUCKET, rate, bytes).await;
+        }
+    }
+
+    if let Some(rate) = config.rate {
+        match priority {
+            Priority::Interactive => {
+                let _drawing = Drawing::new();
+
+                draw(&BUCKET, rate, bytes).await;
+            }
+            Priority::Background => loop {
+                yield_to_interactive().await;
+
+                // Queued before interactive ones came, let them go first
+                let bucket = BUCKET.lock().await;
+                if INTERACTIVE_DRAWING.load(Ordering::Acquire) == 0 {
+                    break take(bucket, rate, bytes).await;
+                }
+            },
+        }
+    }
+}
+
+/// Wait until no interactive fetches draw from [`BUCKET`].
+async fn yield_to_interactive() {
+    loop {
+        let mut drawn = pin!(INTERACTIVE_DRAWN.notified());
+        // Not to miss one notified before checking
+        drawn.as_mut().enable();
+
+        if INTERACTIVE_DRAWING.load(Ordering::Acquire) == 0 {
+            return;
+        }
+
+        drawn.await;
+    }
+}
+
+/// The semaphore of `max` slots, recreated once the config changed.
+fn slots(slots: &Slots, max: usize) -> Arc<Semaphore> {
+    let mut slots = slots.lock().unwrap_or_else(|e| e.into_inner());
+
+    match &*slots {
+        Some((configured, semaphore)) if *configured == max => semaphore.clone(),
+        _ => {
+            let semaphore = Arc::new(Semaphore::new(max.max(1)));
+
+            *slots = Some((max, semaphore.clone()));
+
+            semaphore
+        }
+    }
+}
+
+/// Draw `bytes` from the bucket of `rate`, waiting till available.
+async fn draw(bucket: &tokio::sync::Mutex<Option<TokenBucket>>, rate: u64, bytes: u64) {
+    take(bucket.lock().await, rate, bytes).await;
+}
+
+/// Draw `bytes` from the locked `bucket` of `rate`, see [`draw`].
+async fn take(mut bucket: tokio::sync::MutexGuard<'_, Option<TokenBucket>>, rate: u64, bytes: u64) {
+    let bucket = match &mut *bucket {
+        Some(bucket) if bucket.rate() == rate => bucket,
+        // Uninitialized or config changed, allow bursting up to 100ms.
+        bucket => bucket.insert(TokenBucket::new(rate, rate / 10)),
+    };
+
+    // Sleep with the lock held, others queue behind.
+    tokio::time::sleep(bucket.take(bytes)).await;
+}
@@ -0,0 +1,43 @@
+//! Interactive fetches not queued behind background ones saturating the
+//! bandwidth of all fetches.
+
+use std::time::{Duration, Instant};
+
+use super::{Priority, throttle};
+use crate::config::UpstreamLimitsConfig;
+
+/// Bandwidth of all fetches, in bytes per second.
+const RATE: u64 = 100_000;
+
+/// Bytes drawn by a background fetch at a time, taking 100ms.
+const PIECE: usize = 10_000;
+
+#[tokio::test]
+/// An interactive fetch waits for the piece being drawn at most, not for the
+/// background fetches queued.
+async fn interactive_ahead() {
+    let config = UpstreamLimitsConfig {
+        rate: Some(RATE),
+        ..Default::default()
+    };
+
+    for _ in 0..4 {
+        tokio::spawn(async move {
+            loop {
+                throttle(&config, Priority::Background, PIECE).await;
+            }
+        });
+    }
+
+    // Saturated, the burst allowed drawn
+    tokio::time::sleep(Duration::from_millis(250)).await;
+
+    let started = Instant::now();
+    throttle(&config, Priority::Interactive, PIECE / 10).await;
+
+    let waited = started.elapsed();
+    assert!(
+        waited < Duration::from_millis(250),
+        "Interactive fetch waited {waited:?}"
+    );
+}
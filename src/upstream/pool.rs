@@ -0,0 +1,92 @@
+//! Keep-alive connections to upstream hosts.
+//!
+//! A connection goes back into the pool once its response has been read to
+//! the end, and is reused by the next request to the same host unless idle
+//! for longer than [`UpstreamConfig::pool_idle_timeout`]. At most
+//! [`UpstreamConfig::max_connections`] requests are in flight to each host,
+//! and as many idle connections kept.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use tokio::{
+    io::BufReader,
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+
+use super::tls::Stream;
+use crate::config::{Config, UpstreamConfig};
+
+/// Connection to an upstream host.
+type Connection = BufReader<Stream>;
+
+/// An idle connection, with since when.
+type Idle = (Connection, Instant);
+
+/// Idle connections by `host[:port]`, the most recently used last.
+static IDLE: LazyLock<Mutex<HashMap<String, Vec<Idle>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Connection slots by `host[:port]`, see [`UpstreamConfig::max_connections`].
+static SLOTS: LazyLock<Mutex<HashMap<String, Arc<Semaphore>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Wait for a free slot to send a request to the host of `authority`, held
+/// till the response has been read.
+pub(super) async fn acquire(
+    authority: &str,
+    config: &UpstreamConfig,
+) -> Result<OwnedSemaphorePermit> {
+    let slots = SLOTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(authority.to_owned())
+        .or_insert_with(|| Arc::new(Semaphore::new(config.max_connections.max(1))))
+        .clone();
+
+    Ok(slots.acquire_owned().await?)
+}
+
+/// Take an idle connection to the host of `authority` if any.
+pub(super) fn take(authority: &str, config: &UpstreamConfig) -> Option<Connection> {
+    let idle_timeout = Duration::from_secs(config.pool_idle_timeout);
+
+    let mut idle = IDLE.lock().unwrap_or_else(|e| e.into_inner());
+    let connections = idle.get_mut(authority)?;
+
+    let (connection, since) = connections.pop()?;
+
+    if since.elapsed() >= idle_timeout {
+        // Older ones have expired as well
+        connections.clear();
+        return None;
+    }
+
+    Some(connection)
+}
+
+/// Put a connection to the host of `authority` back into the pool, ready to
+/// carry the next request.
+pub(super) fn put(authority: &str, connection: Connection) {
+    let Some(config) = &Config::current().upstream else {
+        return;
+    };
+
+    if config.pool_idle_timeout == 0 {
+        return;
+    }
+
+    let mut idle = IDLE.lock().unwrap_or_else(|e| e.into_inner());
+    let connections = idle.entry(authority.to_owned()).or_default();
+
+    if connections.len() >= config.max_connections {
+        // The least recently used one
+        connections.remove(0);
+    }
+
+    connections.push((connection, Instant::now()));
+}
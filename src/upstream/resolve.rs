@@ -0,0 +1,227 @@
+//! Resolving upstream hostnames, by the addresses pinned, see
+//! [`UpstreamConfig::resolve`], or a DNS-over-HTTPS resolver, see
+//! [`UpstreamConfig::doh`], falling back to the system resolver.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use fluent_uri::Uri;
+use http::{
+    StatusCode,
+    header::{CONTENT_LENGTH, TRANSFER_ENCODING},
+};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::{
+    config::{UpstreamConfig, UpstreamTlsConfig},
+    proto,
+};
+
+/// Min time an answer is cached for, regardless of its TTL.
+const MIN_TTL: Duration = Duration::from_secs(30);
+
+/// Max time an answer is cached for, regardless of its TTL.
+const MAX_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Max size of a DNS-over-HTTPS response body.
+const MAX_DOH_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// Addresses resolved by DNS-over-HTTPS and when they expire, by hostname.
+type Answers = HashMap<String, (Vec<IpAddr>, Instant)>;
+
+/// Answers cached.
+static ANSWERS: LazyLock<Mutex<Answers>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Deserialize)]
+/// Response of the JSON API of DNS-over-HTTPS resolvers.
+struct DohResponse {
+    #[serde(rename = "Status")]
+    /// DNS response code, 0 for no error
+    status: u32,
+
+    #[serde(rename = "Answer", default)]
+    answer: Vec<DohAnswer>,
+}
+
+#[derive(Debug, Deserialize)]
+/// A record of [`DohResponse`].
+struct DohAnswer {
+    #[serde(rename = "TTL")]
+    ttl: u64,
+
+    /// Address of A and AAAA records, a name of CNAME ones
+    data: String,
+}
+
+/// Connect to `host:port`, trying the addresses pinned or resolved by
+/// DNS-over-HTTPS in order, or as the system resolves.
+pub(super) async fn connect(
+    config: &UpstreamConfig,
+    host: &str,
+    port: u16,
+) -> io::Result<TcpStream> {
+    let ips = match (config.resolve.get(host), &config.doh) {
+        (Some(ips), _) if !ips.is_empty() => ips.clone(),
+        (_, Some(doh)) if host.parse::<IpAddr>().is_err() => resolve(doh, host).await?,
+        _ => return TcpStream::connect((host, port)).await,
+    };
+
+    let addrs: Vec<SocketAddr> = ips
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect();
+
+    TcpStream::connect(&addrs[..]).await
+}
+
+/// The first address pinned of `host`, if any.
+pub(super) fn pinned(config: &UpstreamConfig, host: &str) -> Option<IpAddr> {
+    config.resolve.get(host)?.first().copied()
+}
+
+/// Resolve `host` by the DNS-over-HTTPS resolver of URL `doh`, the answer
+/// cached by its TTL. IPv4 addresses are preferred, IPv6 ones are queried
+/// only if none.
+async fn resolve(doh: &str, host: &str) -> io::Result<Vec<IpAddr>> {
+    {
+        let mut answers = ANSWERS.lock().unwrap_or_else(|e| e.into_inner());
+
+        match answers.get(host) {
+            Some((ips, expires)) if *expires > Instant::now() => return Ok(ips.clone()),
+            Some(_) => {
+                answers.remove(host);
+            }
+            None => {}
+        }
+    }
+
+    let (mut ips, mut ttl) = query(doh, host, "A").await?;
+
+    if ips.is_empty() {
+        (ips, ttl) = query(doh, host, "AAAA").await?;
+    }
+
+    if ips.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No address of {host} resolved by DoH"),
+        ));
+    }
+
+    tracing::debug!("Resolved {host} by DoH: {ips:?}, TTL {ttl:?}");
+
+    ANSWERS.lock().unwrap_or_else(|e| e.into_inner()).insert(
+        host.to_owned(),
+        (ips.clone(), Instant::now() + ttl.clamp(MIN_TTL, MAX_TTL)),
+    );
+
+    Ok(ips)
+}
+
+/// Query the records of `record_type` of `host`, returning the addresses
+/// answered and their min TTL.
+///
+/// The resolver itself is resolved by the system, so better given by
+/// address.
+async fn query(doh: &str, host: &str, record_type: &str) -> io::Result<(Vec<IpAddr>, Duration)> {
+    let invalid = |reason: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid DoH resolver {doh:?}: {reason}"),
+        )
+    };
+
+    let uri = Uri::parse(doh).map_err(|e| invalid(&e.to_string()))?;
+
+    if !uri.scheme().as_str().eq_ignore_ascii_case("https") {
+        return Err(invalid("not HTTPS"));
+    }
+
+    let authority = uri.authority().ok_or_else(|| invalid("no host"))?;
+    let doh_host = authority
+        .host()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = authority
+        .port_to_u16()
+        .map_err(|e| invalid(&e.to_string()))?
+        .unwrap_or(443);
+
+    let tcp_stream = TcpStream::connect((doh_host, port)).await?;
+    let stream = super::tls::handshake(&UpstreamTlsConfig::default(), doh_host, tcp_stream).await?;
+
+    let mut reader = BufReader::new(stream);
+
+    let separator = if uri.query().is_some() { '&' } else { '?' };
+    let request = format!(
+        "GET {}{separator}name={host}&type={record_type} HTTP/1.1\r\nHost: {}\r\nAccept: \
+         application/dns-json\r\nConnection: close\r\n\r\n",
+        uri.path().as_str(),
+        authority.as_str(),
+    );
+
+    reader.get_mut().write_all(request.as_bytes()).await?;
+
+    let head = proto::Response::read_head(&mut reader)
+        .await
+        .map_err(io::Error::other)?;
+
+    if head.status != StatusCode::OK {
+        return Err(io::Error::other(format!(
+            "DoH resolver responded {}",
+            head.status
+        )));
+    }
+
+    let chunked = head
+        .headers
+        .get(TRANSFER_ENCODING)
+        .and_then(|encoding| encoding.to_str().ok())
+        .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+    let length = head
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse().ok());
+
+    let mut body = proto::BodyReader::new(reader, chunked, length);
+    let mut content = Vec::new();
+
+    while let Some(data) = body.next().await.map_err(io::Error::other)? {
+        if content.len() + data.len() > MAX_DOH_RESPONSE_SIZE {
+            return Err(io::Error::other("DoH response too large"));
+        }
+
+        content.extend_from_slice(data);
+    }
+
+    let response: DohResponse = serde_json::from_slice(&content).map_err(io::Error::other)?;
+
+    if response.status != 0 {
+        return Err(io::Error::other(format!(
+            "DoH resolver answered {host} with status {}",
+            response.status
+        )));
+    }
+
+    let mut ips = Vec::new();
+    let mut ttl = MAX_TTL;
+
+    for answer in response.answer {
+        if let Ok(ip) = answer.data.parse::<IpAddr>() {
+            ips.push(ip);
+            ttl = ttl.min(Duration::from_secs(answer.ttl));
+        }
+    }
+
+    Ok((ips, ttl))
+}
@@ -0,0 +1,273 @@
+//! Connecting to upstream hosts, over TLS if configured, see
+//! [`UpstreamTlsConfig`].
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, LazyLock, Mutex},
+    task::{Context, Poll},
+};
+
+use rustls::{
+    ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{CryptoProvider, ring},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{TlsConnector, client::TlsStream};
+
+use crate::config::{UpstreamConfig, UpstreamHostConfig, UpstreamTlsConfig};
+
+/// Client config built of an [`UpstreamTlsConfig`].
+type Built = (UpstreamTlsConfig, Arc<ClientConfig>);
+
+/// Client config built of the current [`UpstreamTlsConfig`], rebuilt once
+/// changed.
+static CLIENT_CONFIG: LazyLock<Mutex<Option<Built>>> = LazyLock::new(|| Mutex::new(None));
+
+#[derive(Debug)]
+/// Connection to an upstream host.
+pub(super) enum Stream {
+    /// Plain HTTP
+    Plain(TcpStream),
+
+    /// HTTPS
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => match Pin::new(stream).poll_read(cx, buf) {
+                // Hosts commonly close without `close_notify`, taken as the
+                // end like plain HTTP.
+                Poll::Ready(Err(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    Poll::Ready(Ok(()))
+                }
+                poll => poll,
+            },
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Connect to the host, through [`UpstreamConfig::proxy`] if configured,
+/// and complete the TLS handshake if [`UpstreamHostConfig::tls`].
+///
+/// The host is resolved as configured, see [`resolve`](super::resolve).
+/// Through a proxy, the host is resolved by the proxy unless pinned.
+pub(super) async fn connect(
+    config: &UpstreamConfig,
+    host: &UpstreamHostConfig,
+) -> io::Result<Stream> {
+    let tcp_stream = match &config.proxy {
+        Some(proxy) => {
+            let target = super::resolve::pinned(config, &host.host)
+                .map_or_else(|| host.host.clone(), |ip| ip.to_string());
+
+            super::proxy::connect(proxy, &target, host.port()).await?
+        }
+        None => super::resolve::connect(config, &host.host, host.port()).await?,
+    };
+
+    if !host.tls {
+        return Ok(Stream::Plain(tcp_stream));
+    }
+
+    handshake(
+        &config.tls,
+        host.sni.as_ref().unwrap_or(&host.host),
+        tcp_stream,
+    )
+    .await
+}
+
+/// Complete the TLS handshake over `tcp_stream`, the server verified as
+/// `server_name` according to `config`.
+pub(super) async fn handshake(
+    config: &UpstreamTlsConfig,
+    server_name: &str,
+    tcp_stream: TcpStream,
+) -> io::Result<Stream> {
+    let server_name = ServerName::try_from(server_name.to_owned())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let tls_stream = TlsConnector::from(client_config(config)?)
+        .connect(server_name, tcp_stream)
+        .await?;
+
+    Ok(Stream::Tls(Box::new(tls_stream)))
+}
+
+/// The client config of `config`, cached.
+fn client_config(config: &UpstreamTlsConfig) -> io::Result<Arc<ClientConfig>> {
+    let mut cached = CLIENT_CONFIG.lock().unwrap_or_else(|e| e.into_inner());
+
+    if let Some((cached_config, client_config)) = &*cached {
+        if cached_config == config {
+            return Ok(client_config.clone());
+        }
+    }
+
+    let provider = Arc::new(ring::default_provider());
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?;
+
+    let client_config = if config.verify && config.pinned.is_empty() {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+
+        builder.with_root_certificates(roots).with_no_client_auth()
+    } else {
+        let pinned = config
+            .pinned
+            .iter()
+            .map(|fingerprint| parse_fingerprint(fingerprint))
+            .collect::<io::Result<_>>()?;
+
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedVerifier { pinned, provider }))
+            .with_no_client_auth()
+    };
+
+    let client_config = Arc::new(client_config);
+
+    *cached = Some((config.clone(), client_config.clone()));
+
+    Ok(client_config)
+}
+
+/// Parse a SHA-256 fingerprint in hex, colons optional.
+fn parse_fingerprint(fingerprint: &str) -> io::Result<[u8; 32]> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid SHA-256 fingerprint {fingerprint:?}"),
+        )
+    };
+
+    let hex: Vec<u8> = fingerprint.bytes().filter(|&c| c != b':').collect();
+
+    if hex.len() != 64 {
+        return Err(invalid());
+    }
+
+    let mut parsed = [0; 32];
+
+    for (byte, pair) in parsed.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = std::str::from_utf8(pair)
+            .ok()
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or_else(invalid)?;
+    }
+
+    Ok(parsed)
+}
+
+#[derive(Debug)]
+/// Accepts certificates of the pinned fingerprints only, or any certificate
+/// when none is pinned, i.e. verification disabled.
+///
+/// Handshake signatures are verified still, so that the host does own the
+/// certificate.
+struct PinnedVerifier {
+    pinned: Vec<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        if self.pinned.is_empty() {
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        let fingerprint: [u8; 32] = Sha256::digest(end_entity.as_ref()).into();
+
+        if self.pinned.contains(&fingerprint) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(Error::General("Certificate not pinned".to_owned()))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
@@ -0,0 +1,239 @@
+//! Usage accounting per client address, see [`UsageConfig`], so that in a
+//! shared deployment who uses the bandwidth is known. Listed by the admin
+//! API, ranked.
+//!
+//! Requests and body bytes sent are counted per client, see [`observe`], and
+//! persisted periodically as JSON if configured, loaded back on start.
+
+#[cfg(feature = "admin")]
+use std::cmp::Reverse;
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client,
+    config::{Config, UsageConfig},
+    transfer,
+};
+
+/// Max clients accounted, past which the least recently seen is dropped.
+const MAX_CLIENTS: usize = 65536;
+
+/// Usage by client.
+static USAGE: LazyLock<Mutex<HashMap<IpAddr, Usage>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Whether [`USAGE`] has been changed since last persisted.
+static DIRTY: AtomicBool = AtomicBool::new(false);
+
+tokio::task_local! {
+    /// Whether the request being observed is parsed, see [`request`].
+    static REQUESTED: Cell<bool>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[derive(Serialize, Deserialize)]
+/// Usage of a client.
+struct Usage {
+    /// Number of requests
+    requests: u64,
+
+    /// Body bytes sent
+    bytes: u64,
+
+    /// Last seen at, in seconds since UNIX epoch
+    last_seen: u64,
+}
+
+#[cfg(feature = "admin")]
+#[derive(Debug)]
+#[derive(Serialize)]
+/// Usage of a client, see [`ranked`].
+pub(crate) struct ClientUsage {
+    client: IpAddr,
+
+    #[serde(flatten)]
+    usage: Usage,
+}
+
+#[cfg(feature = "admin")]
+#[derive(Debug, Clone, Copy)]
+/// Order of [`ranked`].
+pub(crate) enum RankBy {
+    /// Most bytes first
+    Bytes,
+
+    /// Most requests first
+    Requests,
+}
+
+/// Load the usage persisted if any, and spawn the persister of it.
+pub(crate) fn init(config: &UsageConfig) -> Result<()> {
+    let Some(path) = config.file.clone() else {
+        return Ok(());
+    };
+
+    match std::fs::read(&path) {
+        Ok(content) => {
+            let usage: HashMap<IpAddr, Usage> = serde_json::from_slice(&content)
+                .with_context(|| format!("Parse usage {}", path.display()))?;
+
+            tracing::info!("Usage loaded, {} clients", usage.len());
+
+            *USAGE.lock().unwrap_or_else(|e| e.into_inner()) = usage;
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("Read usage {}", path.display())),
+    }
+
+    let interval = Duration::from_secs(config.persist_interval.max(1));
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            if let Err(e) = persist_to(&path).await {
+                tracing::error!("Persist usage error: {e:?}");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Persist the usage if configured and changed, e.g. before exiting.
+pub(crate) async fn persist() -> io::Result<()> {
+    let Some(path) = Config::current()
+        .usage
+        .as_ref()
+        .and_then(|usage_config| usage_config.file.clone())
+    else {
+        return Ok(());
+    };
+
+    persist_to(&path).await
+}
+
+/// Persist the usage to `path` if changed.
+async fn persist_to(path: &Path) -> io::Result<()> {
+    if !DIRTY.swap(false, Ordering::AcqRel) {
+        return Ok(());
+    }
+
+    let content = {
+        let usage = USAGE.lock().unwrap_or_else(|e| e.into_inner());
+        serde_json::to_vec(&*usage)?
+    };
+
+    let mut tmp_path = PathBuf::from(path);
+    tmp_path.as_mut_os_string().push(".tmp");
+
+    tokio::fs::write(&tmp_path, &content).await?;
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+/// Run the handler `future` of a request from `client`, accounting it once
+/// done, to the client told by a trusted proxy if any, see
+/// [`client`](crate::client).
+pub(crate) async fn observe<F>(client: IpAddr, future: F) -> F::Output
+where
+    F: Future,
+{
+    if Config::current().usage.is_none() {
+        return future.await;
+    }
+
+    REQUESTED
+        .scope(Cell::new(false), async move {
+            let (output, bytes) = transfer::counted(future).await;
+            let requested = REQUESTED.with(Cell::get);
+
+            if requested || bytes != 0 {
+                record(
+                    client::current().unwrap_or(client),
+                    u64::from(requested),
+                    bytes,
+                );
+            }
+
+            output
+        })
+        .await
+}
+
+/// Mark the request being observed parsed, see [`observe`].
+pub(crate) fn request() {
+    let _ = REQUESTED.try_with(|requested| requested.set(true));
+}
+
+/// Add `requests` and `bytes` to the usage of `client`.
+fn record(client: IpAddr, requests: u64, bytes: u64) {
+    let mut usage = USAGE.lock().unwrap_or_else(|e| e.into_inner());
+
+    if usage.len() >= MAX_CLIENTS && !usage.contains_key(&client) {
+        if let Some(least_recent) = usage
+            .iter()
+            .min_by_key(|(_, usage)| usage.last_seen)
+            .map(|(client, _)| *client)
+        {
+            usage.remove(&least_recent);
+        }
+    }
+
+    let entry = usage.entry(client).or_default();
+    entry.requests += requests;
+    entry.bytes += bytes;
+    entry.last_seen = unix_timestamp();
+
+    DIRTY.store(true, Ordering::Release);
+}
+
+#[cfg(feature = "admin")]
+/// Usage of clients ranked by `by`, the top `limit` ones.
+pub(crate) fn ranked(by: RankBy, limit: usize) -> Vec<ClientUsage> {
+    let mut ranked: Vec<_> = USAGE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(client, usage)| ClientUsage {
+            client: *client,
+            usage: *usage,
+        })
+        .collect();
+
+    match by {
+        RankBy::Bytes => ranked.sort_unstable_by_key(|client| Reverse(client.usage.bytes)),
+        RankBy::Requests => ranked.sort_unstable_by_key(|client| Reverse(client.usage.requests)),
+    }
+
+    ranked.truncate(limit);
+
+    ranked
+}
+
+#[cfg(feature = "admin")]
+/// Reset the usage of all clients.
+pub(crate) fn reset() {
+    USAGE.lock().unwrap_or_else(|e| e.into_inner()).clear();
+
+    DIRTY.store(true, Ordering::Release);
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
@@ -1,10 +1,17 @@
 //! Utilities
 
 use std::{
+    any::Any,
+    panic::AssertUnwindSafe,
+    path::Path,
+    pin::pin,
     sync::{Arc, Mutex},
+    task::Poll,
+    thread,
     time::{Duration, Instant},
 };
 
+use anyhow::{Context, Result, bail};
 use tokio::task::yield_now;
 
 #[derive(Debug, Clone)]
@@ -64,3 +71,64 @@ impl Drop for IdleGuard<'_> {
         *self.idle_since.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
     }
 }
+
+/// Run `future`, catching panics in polling it, like
+/// [`std::panic::catch_unwind`].
+pub(crate) async fn catch_unwind<F>(future: F) -> thread::Result<F::Output>
+where
+    F: Future,
+{
+    let mut future = pin!(future);
+
+    std::future::poll_fn(|cx| {
+        match std::panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(panic) => Poll::Ready(Err(panic)),
+        }
+    })
+    .await
+}
+
+/// Message of a `panic` caught.
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
+
+#[cfg(feature = "admin")]
+/// Whether `a` equals `b`, in time depending on the length only, e.g. of
+/// secrets.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Check the file of secrets at `path`, told as `what`, is accessible by the
+/// owner only, refused otherwise.
+pub(crate) fn check_private(path: &Path, what: &str) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(path)
+            .with_context(|| format!("Stat {what} {}", path.display()))?
+            .permissions()
+            .mode();
+
+        if mode & 0o077 != 0 {
+            bail!(
+                "{what} {} is accessible by group or others (mode {:o}), `chmod 600` it",
+                path.display(),
+                mode & 0o777
+            );
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = (path, what);
+
+    Ok(())
+}
@@ -0,0 +1,184 @@
+//! WBI signing of requests to the web API of bilibili.
+//!
+//! The query of a request is signed like
+//!
+//! ```text
+//! {params, sorted}&wts={unix time}&w_rid={md5(query + mixin key)}
+//! ```
+//!
+//! where the mixin key is derived from the img and sub keys, i.e. the names
+//! of the images of `wbi_img` of `/x/web-interface/nav`, fetched from the API
+//! and kept for [`KEYS_TTL`], see [`sign`].
+
+use std::{
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{Context, Result, bail};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+
+use crate::{config::PlayurlConfig, playurl};
+
+/// How long the mixin key is kept, the keys being rotated daily.
+const KEYS_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Order of the characters of the img and sub keys in the mixin key.
+const MIXIN_KEY_ENC_TAB: [usize; 64] = [
+    46, 47, 18, 2, 53, 8, 23, 32, 15, 50, 10, 31, 58, 3, 45, 35, 27, 43, 5, 49, 33, 9, 42, 19, 29,
+    28, 14, 39, 12, 38, 41, 13, 37, 48, 7, 16, 24, 55, 40, 61, 26, 17, 0, 1, 60, 51, 30, 4, 22, 25,
+    54, 21, 56, 59, 6, 63, 57, 62, 11, 36, 20, 34, 44, 52,
+];
+
+/// Characters dropped from the values of params.
+const DROPPED_CHARS: [char; 5] = ['!', '\'', '(', ')', '*'];
+
+/// Mixin key and when fetched.
+static MIXIN_KEY: Mutex<Option<(Instant, String)>> = Mutex::new(None);
+
+#[derive(Debug, Deserialize)]
+/// `data` of the nav API, of which only `wbi_img` is taken, see
+/// [`playurl::NAV_PATH`].
+struct Nav {
+    wbi_img: WbiImg,
+}
+
+#[derive(Debug, Deserialize)]
+/// Images named after the img and sub keys.
+struct WbiImg {
+    /// Like `https://i0.hdslb.com/bfs/wbi/{img key}.png`
+    img_url: String,
+
+    /// Like `https://i0.hdslb.com/bfs/wbi/{sub key}.png`
+    sub_url: String,
+}
+
+/// Signed query of `params`, with `wts` and `w_rid` appended, the mixin key
+/// fetched from the API of `config` if not kept.
+pub(crate) async fn sign(config: &PlayurlConfig, params: &[(&str, String)]) -> Result<String> {
+    let mixin_key = match cached_mixin_key() {
+        Some(mixin_key) => mixin_key,
+        None => {
+            let mixin_key = fetch_mixin_key(config).await?;
+
+            *MIXIN_KEY.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some((Instant::now(), mixin_key.clone()));
+
+            mixin_key
+        }
+    };
+
+    let wts = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    Ok(signed_query(&mixin_key, params, wts))
+}
+
+/// Drop the mixin key kept, e.g. when the API rejects a signed request, so
+/// that it is fetched again.
+pub(crate) fn invalidate() {
+    *MIXIN_KEY.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// The mixin key kept, if not expired.
+fn cached_mixin_key() -> Option<String> {
+    MIXIN_KEY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .filter(|(fetched, _)| fetched.elapsed() < KEYS_TTL)
+        .map(|(_, mixin_key)| mixin_key.clone())
+}
+
+/// Fetch the img and sub keys and derive the mixin key of them.
+async fn fetch_mixin_key(config: &PlayurlConfig) -> Result<String> {
+    let response = playurl::forward(config, playurl::NAV_PATH).await?;
+
+    // `code` is -101 if not logged in, `data` given nevertheless
+    let Some(nav) = response
+        .get("data")
+        .filter(|data| !data.is_null())
+        .map(Nav::deserialize)
+        .transpose()
+        .context("Parse nav API response")?
+    else {
+        bail!("No WBI keys in nav API response");
+    };
+
+    let key = |url: &str| -> Option<String> {
+        let name = url.rsplit('/').next()?;
+        let key = name.split('.').next()?;
+        Some(key.to_owned()).filter(|key| key.len() == 32)
+    };
+
+    let (Some(img_key), Some(sub_key)) = (key(&nav.wbi_img.img_url), key(&nav.wbi_img.sub_url))
+    else {
+        bail!("Invalid WBI keys in nav API response");
+    };
+
+    Ok(mixin_key(&img_key, &sub_key))
+}
+
+/// Mixin key of `img_key` and `sub_key`.
+fn mixin_key(img_key: &str, sub_key: &str) -> String {
+    let keys = [img_key.as_bytes(), sub_key.as_bytes()].concat();
+
+    MIXIN_KEY_ENC_TAB
+        .iter()
+        .filter_map(|&index| keys.get(index))
+        .take(32)
+        .map(|&byte| char::from(byte))
+        .collect()
+}
+
+/// Query of `params` and `wts`, sorted, signed with `mixin_key`.
+fn signed_query(mixin_key: &str, params: &[(&str, String)], wts: u64) -> String {
+    let wts = wts.to_string();
+
+    let mut params: Vec<(&str, &str)> = params
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .chain([("wts", wts.as_str())])
+        .collect();
+    params.sort_unstable();
+
+    let query = params
+        .iter()
+        .map(|(name, value)| {
+            let value: String = value
+                .chars()
+                .filter(|c| !DROPPED_CHARS.contains(c))
+                .collect();
+            format!("{}={}", encode(name), encode(&value))
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let w_rid = Md5::digest(format!("{query}{mixin_key}")).iter().fold(
+        String::with_capacity(32),
+        |mut w_rid, byte| {
+            let _ = write!(w_rid, "{byte:02x}");
+            w_rid
+        },
+    );
+
+    format!("{query}&w_rid={w_rid}")
+}
+
+/// Percent-encode `value` like `encodeURIComponent`, but `!'()*` as well.
+fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+
+    for &byte in value.as_bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+
+    encoded
+}